@@ -1,6 +1,151 @@
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use tauri::Manager;
 
+const API_PORT: u16 = 3030;
+
+/// Where the running backend is listening, from its `runtime.json`.
+///
+/// Mirrors the fields the GUI needs of the backend's
+/// `runtime_info::RuntimeInfo`: this crate builds on its own and doesn't
+/// depend on `privacy_suite`, which would pull in arti.
+#[derive(Debug, Default, Deserialize)]
+struct RuntimeInfo {
+    #[serde(default)]
+    api_addr: Option<std::net::SocketAddr>,
+    /// The API serves HTTPS with the certificate next to the runtime file
+    #[serde(default)]
+    api_tls: bool,
+}
+
+/// `--config <path>` from the GUI's command line, passed on to the backend.
+/// Made absolute, as the elevated backend starts in another directory.
+fn config_arg() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            path = args.next().map(PathBuf::from);
+            break;
+        }
+        if let Some(arg) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            path = Some(PathBuf::from(arg));
+            break;
+        }
+    }
+    path.map(|path| std::path::absolute(&path).unwrap_or(path))
+}
+
+/// `value` as a single-quoted PowerShell string
+#[cfg(target_os = "windows")]
+fn powershell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// The backend's config directory, where it writes `runtime.json`: that of
+/// the `--config` file, as for the backend, or `~/.privacy_suite`
+fn backend_dir() -> Option<PathBuf> {
+    match config_arg() {
+        Some(path) => path.parent().filter(|dir| !dir.as_os_str().is_empty()).map(Path::to_path_buf),
+        None => dirs::home_dir().map(|home| home.join(".privacy_suite")),
+    }
+}
+
+/// What the backend recorded in its runtime file, defaults until it has
+fn runtime_info() -> RuntimeInfo {
+    backend_dir()
+        .and_then(|dir| std::fs::read_to_string(dir.join("runtime.json")).ok())
+        .and_then(|content| serde_json::from_str::<RuntimeInfo>(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Port the backend API is listening on, falling back to the default
+/// until the backend has written its runtime file
+fn api_port() -> u16 {
+    runtime_info().api_addr.map_or(API_PORT, |addr| addr.port())
+}
+
+fn backend_running() -> bool {
+    std::net::TcpStream::connect(("127.0.0.1", api_port())).is_ok()
+}
+
+/// Base URL of the backend API, with the scheme it serves
+fn api_base_url(info: &RuntimeInfo) -> String {
+    let port = info.api_addr.map_or(API_PORT, |addr| addr.port());
+    if info.api_tls {
+        format!("https://localhost:{}", port)
+    } else {
+        format!("http://127.0.0.1:{}", port)
+    }
+}
+
+/// Build a client for the backend API and return it with the API's base URL.
+///
+/// With `api_tls` enabled the backend serves a self-signed certificate from
+/// its config directory; only that certificate is trusted, and only for
+/// the localhost origin.
+fn api_client(timeout: Option<std::time::Duration>) -> Result<(reqwest::Client, String), String> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    
+    let info = runtime_info();
+    let base = api_base_url(&info);
+    
+    if let (true, Some(dir)) = (info.api_tls, backend_dir()) {
+        let pem = std::fs::read(dir.join("api_cert.pem"))
+            .map_err(|e| format!("Failed to read backend certificate: {}", e))?;
+        let cert = reqwest::Certificate::from_pem(&pem)
+            .map_err(|e| format!("Invalid backend certificate: {}", e))?;
+        let port = info.api_addr.map_or(API_PORT, |addr| addr.port());
+        let client = builder
+            .use_rustls_tls()
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(cert)
+            .resolve("localhost", std::net::SocketAddr::from(([127, 0, 0, 1], port)))
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+        return Ok((client, base));
+    }
+    
+    let client = builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    Ok((client, base))
+}
+
+/// An API response handed to the web view, which wraps it in a `Response`
+#[derive(Debug, Serialize)]
+struct ApiResponse {
+    status: u16,
+    body: String,
+}
+
+/// Make a backend API request for the web view. It goes through
+/// `api_client` because the web view doesn't trust the backend's
+/// self-signed certificate once `api_tls` is on.
+#[tauri::command]
+async fn api_fetch(method: String, path: String, body: Option<String>) -> Result<ApiResponse, String> {
+    let (client, base) = api_client(None)?;
+    let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|e| format!("Invalid method {}: {}", method, e))?;
+    
+    let mut request = client.request(method, format!("{}{}", base, path));
+    if let Some(body) = body {
+        request = request.header(reqwest::header::CONTENT_TYPE, "application/json").body(body);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Request to {} failed: {}", path, e))?;
+    let status = response.status().as_u16();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read the response to {}: {}", path, e))?;
+    Ok(ApiResponse { status, body })
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Stats {
     tor_connected: bool,
@@ -13,6 +158,8 @@ struct Stats {
     proxy_running: bool,
     auto_proxy_enabled: bool,
     uptime_seconds: u64,
+    #[serde(default)]
+    connected_since: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,7 +174,10 @@ struct LogEntry {
 async fn get_stats() -> Result<Stats, String> {
     println!("get_stats: Starting request to backend...");
     
-    let response = reqwest::get("http://127.0.0.1:3030/api/stats")
+    let (client, base) = api_client(None)?;
+    let response = client
+        .get(format!("{}/api/stats", base))
+        .send()
         .await
         .map_err(|e| {
             println!("get_stats: Request failed: {}", e);
@@ -56,7 +206,10 @@ async fn get_stats() -> Result<Stats, String> {
 
 #[tauri::command]
 async fn get_logs() -> Result<Vec<LogEntry>, String> {
-    let response = reqwest::get("http://127.0.0.1:3030/api/logs")
+    let (client, base) = api_client(None)?;
+    let response = client
+        .get(format!("{}/api/logs", base))
+        .send()
         .await
         .map_err(|e| format!("Failed to fetch logs: {}", e))?;
     
@@ -75,13 +228,10 @@ struct KillSwitchToggle {
 
 #[tauri::command]
 async fn toggle_kill_switch(enabled: bool) -> Result<Stats, String> {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(5))
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let (client, base) = api_client(Some(std::time::Duration::from_secs(5)))?;
     
     let response = client
-        .put("http://127.0.0.1:3030/api/killswitch")
+        .put(format!("{}/api/killswitch", base))
         .json(&KillSwitchToggle { enabled })
         .send()
         .await
@@ -102,13 +252,10 @@ struct ConnectionToggle {
 
 #[tauri::command]
 async fn toggle_connection(connect: bool) -> Result<Stats, String> {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let (client, base) = api_client(Some(std::time::Duration::from_secs(10)))?;
     
     let response = client
-        .post("http://127.0.0.1:3030/api/connection")
+        .post(format!("{}/api/connection", base))
         .json(&ConnectionToggle { connect })
         .send()
         .await
@@ -122,16 +269,157 @@ async fn toggle_connection(connect: bool) -> Result<Stats, String> {
     Ok(stats)
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct ElevationResponse {
+    elevated: bool,
+    relaunching: bool,
+    message: String,
+}
+
+#[tauri::command]
+async fn request_elevation() -> Result<ElevationResponse, String> {
+    // Long timeout - the backend waits for the user to answer the UAC prompt
+    let (client, base) = api_client(Some(std::time::Duration::from_secs(120)))?;
+    
+    let response = client
+        .post(format!("{}/api/elevate", base))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to request elevation: {}", e))?;
+    
+    let result = response
+        .json::<ElevationResponse>()
+        .await
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    
+    Ok(result)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PanicReport {
+    kill_switch_engaged: bool,
+    connections_aborted: usize,
+    proxy_stopped: bool,
+    dns_flushed: bool,
+}
+
+#[tauri::command]
+async fn panic_disconnect() -> Result<PanicReport, String> {
+    let (client, base) = api_client(Some(std::time::Duration::from_secs(5)))?;
+    
+    let response = client
+        .post(format!("{}/api/panic", base))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to trigger panic disconnect: {}", e))?;
+    
+    let report = response
+        .json::<PanicReport>()
+        .await
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    
+    Ok(report)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SelfTestCheck {
+    id: String,
+    passed: bool,
+    detail: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SelfTestReport {
+    passed: bool,
+    checks: Vec<SelfTestCheck>,
+}
+
+#[tauri::command]
+async fn run_self_test() -> Result<SelfTestReport, String> {
+    // The backend makes a request through Tor, which can take a while on a fresh circuit
+    let (client, base) = api_client(Some(std::time::Duration::from_secs(60)))?;
+    
+    let response = client
+        .post(format!("{}/api/test", base))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to run self-test: {}", e))?;
+    
+    let report = response
+        .json::<SelfTestReport>()
+        .await
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    
+    Ok(report)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetupInstructions {
+    platform: String,
+    title: String,
+    steps: Vec<String>,
+    proxy: String,
+    lan_proxy: Option<String>,
+    text: String,
+}
+
+/// Manual proxy setup steps for `os` (windows, macos, linux, firefox or android)
+#[tauri::command]
+async fn get_setup_instructions(os: String) -> Result<SetupInstructions, String> {
+    let (client, base) = api_client(Some(std::time::Duration::from_secs(5)))?;
+    
+    let response = client
+        .get(format!("{}/api/setup-instructions", base))
+        .query(&[("os", os)])
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to fetch setup instructions: {}", e))?;
+    
+    let instructions = response
+        .json::<SetupInstructions>()
+        .await
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    
+    Ok(instructions)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ConfigLocation {
+    path: std::path::PathBuf,
+}
+
+/// Show the backend's config.toml in the OS file manager, returning its path
+#[tauri::command]
+async fn open_config_dir(app: tauri::AppHandle) -> Result<String, String> {
+    use tauri_plugin_opener::OpenerExt;
+    
+    let (client, base) = api_client(Some(std::time::Duration::from_secs(5)))?;
+    
+    let location = client
+        .get(format!("{}/api/config-path", base))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to fetch config path: {}", e))?
+        .json::<ConfigLocation>()
+        .await
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    
+    app.opener()
+        .reveal_item_in_dir(&location.path)
+        .map_err(|e| format!("Failed to open {}: {}", location.path.display(), e))?;
+    
+    Ok(location.path.display().to_string())
+}
+
 #[tauri::command]
 async fn shutdown_backend() -> Result<(), String> {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(5))
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let (client, base) = api_client(Some(std::time::Duration::from_secs(5)))?;
     
     // Try to shutdown backend gracefully
     let _ = client
-        .post("http://127.0.0.1:3030/api/shutdown")
+        .post(format!("{}/api/shutdown", base))
         .send()
         .await;
     
@@ -143,14 +431,12 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_process::init())
-        .invoke_handler(tauri::generate_handler![get_stats, get_logs, toggle_kill_switch, toggle_connection, shutdown_backend])
+        .invoke_handler(tauri::generate_handler![api_fetch, get_stats, get_logs, toggle_kill_switch, toggle_connection, request_elevation, panic_disconnect, run_self_test, get_setup_instructions, open_config_dir, shutdown_backend])
         .setup(|app| {
             // Auto-start backend if not already running
             std::thread::spawn(move || {
                 // Check if backend is already running
-                let backend_running = std::net::TcpStream::connect("127.0.0.1:3030").is_ok();
-                
-                if !backend_running {
+                if !backend_running() {
                     println!("Backend not running, starting it...");
                     
                     // Get the directory where the GUI executable is located
@@ -167,10 +453,19 @@ pub fn run() {
                             use std::os::windows::process::CommandExt;
                             const CREATE_NO_WINDOW: u32 = 0x08000000;
                             
+                            // The backend gets the same --config, so it writes the runtime file we read
+                            // Start-Process joins the arguments with spaces, so the path gets its own double quotes
+                            let arguments = config_arg()
+                                .map(|path| format!(" -ArgumentList '--config',{}", powershell_quote(&format!("\"{}\"", path.display()))))
+                                .unwrap_or_default();
                             let result = std::process::Command::new("powershell")
                                 .args(&[
                                     "-Command",
-                                    &format!("Start-Process -FilePath '{}' -Verb RunAs -WindowStyle Hidden", backend_path.display())
+                                    &format!(
+                                        "Start-Process -FilePath {}{} -Verb RunAs -WindowStyle Hidden",
+                                        powershell_quote(&backend_path.to_string_lossy()),
+                                        arguments
+                                    )
                                 ])
                                 .creation_flags(CREATE_NO_WINDOW)
                                 .spawn();
@@ -181,7 +476,7 @@ pub fn run() {
                                     // Wait for backend to initialize
                                     for i in 0..30 {
                                         std::thread::sleep(std::time::Duration::from_millis(500));
-                                        if std::net::TcpStream::connect("127.0.0.1:3030").is_ok() {
+                                        if backend_running() {
                                             println!("Backend is ready after {} attempts", i + 1);
                                             break;
                                         }
@@ -194,7 +489,7 @@ pub fn run() {
                         println!("Backend executable not found at {:?}", backend_path);
                     }
                 } else {
-                    println!("Backend already running on port 3030");
+                    println!("Backend already running on port {}", api_port());
                 }
             });
             
@@ -1,5 +1,6 @@
 import { useState, useEffect } from "react";
 import Logs from "./components/Logs";
+import { apiFetch } from "./api";
 
 interface Stats {
   tor_connected: boolean;
@@ -55,7 +56,7 @@ function App() {
   useEffect(() => {
     const fetchStats = async () => {
       try {
-        const response = await fetch("http://127.0.0.1:3030/api/stats");
+        const response = await apiFetch("/api/stats");
         const data = await response.json();
         setStats(data);
       } catch (error) {
@@ -65,7 +66,7 @@ function App() {
 
     const fetchLogs = async () => {
       try {
-        const response = await fetch("http://127.0.0.1:3030/api/logs");
+        const response = await apiFetch("/api/logs");
         const data = await response.json();
         setLogs(data || []);
       } catch (error) {
@@ -86,7 +87,7 @@ function App() {
   const toggleConnection = async () => {
     setIsConnecting(true);
     try {
-      const response = await fetch("http://127.0.0.1:3030/api/connection", {
+      const response = await apiFetch("/api/connection", {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({
@@ -106,7 +107,7 @@ function App() {
 
   const toggleKillSwitch = async () => {
     try {
-      const response = await fetch("http://127.0.0.1:3030/api/kill-switch", {
+      const response = await apiFetch("/api/kill-switch", {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({ enabled: !stats.kill_switch_active }),
@@ -1,5 +1,6 @@
 import { useState, useEffect } from "react";
 import Logs from "./components/Logs";
+import { apiFetch } from "./api";
 
 interface Stats {
   tor_connected: boolean;
@@ -14,6 +15,8 @@ interface Stats {
   uptime_seconds: number;
   security_threats_detected: number;
   exit_country: string | null;
+  is_admin: boolean;
+  proxy_error?: string | null;
 }
 
 
@@ -32,6 +35,7 @@ function App() {
     uptime_seconds: 0,
     security_threats_detected: 0,
     exit_country: null,
+    is_admin: false,
   });
 
   const [logs, setLogs] = useState<any[]>([]);
@@ -40,6 +44,8 @@ function App() {
   const [showDonateModal, setShowDonateModal] = useState(false);
   const [copiedAddress, setCopiedAddress] = useState<string | null>(null);
   const [logFilter, setLogFilter] = useState<string>('all');
+  const [isElevating, setIsElevating] = useState(false);
+  const [elevationMessage, setElevationMessage] = useState<string | null>(null);
 
   const cryptoAddresses = {
     btc: "3CpHZqjxvQvXz64drZZxA6m3NWzKF1LCHX",
@@ -55,7 +61,7 @@ function App() {
   useEffect(() => {
     const fetchStats = async () => {
       try {
-        const response = await fetch("http://127.0.0.1:3030/api/stats");
+        const response = await apiFetch("/api/stats");
         const data = await response.json();
         setStats(data);
       } catch (error) {
@@ -65,7 +71,7 @@ function App() {
 
     const fetchLogs = async () => {
       try {
-        const response = await fetch("http://127.0.0.1:3030/api/logs");
+        const response = await apiFetch("/api/logs");
         const data = await response.json();
         setLogs(data || []);
       } catch (error) {
@@ -86,7 +92,7 @@ function App() {
   const toggleConnection = async () => {
     setIsConnecting(true);
     try {
-      const response = await fetch("http://127.0.0.1:3030/api/connection", {
+      const response = await apiFetch("/api/connection", {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({
@@ -106,7 +112,7 @@ function App() {
 
   const toggleKillSwitch = async () => {
     try {
-      const response = await fetch("http://127.0.0.1:3030/api/kill-switch", {
+      const response = await apiFetch("/api/kill-switch", {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({ enabled: !stats.kill_switch_active }),
@@ -118,6 +124,21 @@ function App() {
     }
   };
 
+  const requestElevation = async () => {
+    setIsElevating(true);
+    setElevationMessage(null);
+    try {
+      const response = await apiFetch("/api/elevate", { method: "POST" });
+      const data = await response.json();
+      // If declined, the backend keeps running in manual proxy mode
+      setElevationMessage(data.relaunching ? "Restarting with administrator privileges..." : data.message);
+    } catch (error) {
+      console.error("Failed to request elevation:", error);
+    } finally {
+      setIsElevating(false);
+    }
+  };
+
   const formatUptime = (seconds: number) => {
     const hours = Math.floor(seconds / 3600);
     const minutes = Math.floor((seconds % 3600) / 60);
@@ -371,6 +392,25 @@ function App() {
                   <span className="text-xs text-gray-300">Kill Switch</span>
                 </div>
               </div>
+              {stats.proxy_error && !stats.proxy_running && (
+                <div className="mt-4 px-3 py-2 bg-red-500/20 border border-red-500/30 rounded-xl text-xs text-red-300">
+                  {stats.proxy_error}
+                </div>
+              )}
+              {!stats.is_admin && (
+                <div className="mt-4">
+                  <button
+                    onClick={requestElevation}
+                    disabled={isElevating}
+                    className="w-full px-4 py-2 bg-purple-500/20 hover:bg-purple-500/30 border border-purple-500/30 rounded-xl text-xs font-medium text-purple-300 transition-all disabled:opacity-50"
+                  >
+                    {isElevating ? 'Waiting for approval...' : 'Enable system-wide protection (requires admin)'}
+                  </button>
+                  {elevationMessage && (
+                    <div className="mt-2 text-xs text-gray-400 text-center">{elevationMessage}</div>
+                  )}
+                </div>
+              )}
             </div>
             </div>
           </div>
@@ -0,0 +1,20 @@
+import { invoke } from "@tauri-apps/api/core";
+
+interface ApiResponse {
+  status: number;
+  body: string;
+}
+
+// Requests go through the Tauri side, which reads the backend's scheme and
+// port from its runtime file and trusts its certificate when `api_tls` is
+// on; the web view itself would refuse the self-signed certificate.
+// Bodies are sent as JSON.
+export async function apiFetch(path: string, init?: RequestInit): Promise<Response> {
+  const response = await invoke<ApiResponse>("api_fetch", {
+    method: init?.method ?? "GET",
+    path,
+    body: typeof init?.body === "string" ? init.body : null,
+  });
+  // A Response can't have a body with statuses such as 204
+  return new Response(response.body === "" ? null : response.body, { status: response.status });
+}
@@ -18,6 +18,7 @@ interface LogEntry {
   message: string;
   category: string;
   details?: LogDetails;
+  count?: number;
 }
 
 interface LogsProps {
@@ -229,6 +230,9 @@ function Logs({ logs, isDarkMode, initialFilter }: LogsProps) {
                     <span className={`text-xs px-2 py-0.5 rounded-lg ${isDarkMode ? 'bg-gray-700 text-gray-400' : 'bg-gray-200 text-gray-600'}`}>
                       {log.category}
                     </span>
+                    {(log.count ?? 1) > 1 && (
+                      <span className={`text-xs font-bold ${textSecondary}`}>×{log.count}</span>
+                    )}
                     {log.details && (
                       <span className={`text-xs ${isDarkMode ? 'text-blue-400' : 'text-blue-600'}`}>
                         🔍 {expandedLog === index ? 'Hide' : 'Details'}
@@ -0,0 +1,142 @@
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use rustls::ServerConfig;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info};
+
+/// Self-signed certificate for the local web API, kept next to the config file
+pub const CERT_FILE: &str = "api_cert.pem";
+pub const KEY_FILE: &str = "api_key.pem";
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Load the API certificate from `dir`, generating a self-signed one for
+/// `localhost`/`127.0.0.1` on first run
+pub fn load_or_generate(dir: &Path) -> Result<Arc<ServerConfig>, BoxError> {
+    let cert_path = dir.join(CERT_FILE);
+    let key_path = dir.join(KEY_FILE);
+
+    if !cert_path.exists() || !key_path.exists() {
+        let cert = rcgen::generate_simple_self_signed(vec![
+            "localhost".to_string(),
+            "127.0.0.1".to_string(),
+        ])?;
+        fs::create_dir_all(dir)?;
+        fs::write(&cert_path, cert.serialize_pem()?)?;
+        write_private(&key_path, &cert.serialize_private_key_pem())?;
+        info!("🔒 Generated self-signed web API certificate: {}", cert_path.display());
+    }
+
+    let certs = rustls_pemfile::certs(&mut fs::read(&cert_path)?.as_slice())
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut fs::read(&key_path)?.as_slice())?
+        .ok_or_else(|| format!("no private key in {}", key_path.display()))?;
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+/// Write the private key readable by the current user only
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(contents.as_bytes())
+    }
+
+    #[cfg(not(unix))]
+    {
+        fs::write(path, contents)
+    }
+}
+
+/// Serve `app` over TLS on `listener`
+pub async fn serve(
+    listener: TcpListener,
+    app: axum::Router,
+    tls_config: Arc<ServerConfig>,
+) -> Result<(), BoxError> {
+    let acceptor = TlsAcceptor::from(tls_config);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(app.clone());
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("Web API TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Web API connection from {} ended with error: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    #[tokio::test]
+    async fn test_serves_https_with_generated_cert() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_api_tls_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let tls_config = load_or_generate(&dir).unwrap();
+        let cert_pem = fs::read(dir.join(CERT_FILE)).unwrap();
+
+        // A second start reuses the stored certificate
+        load_or_generate(&dir).unwrap();
+        assert_eq!(fs::read(dir.join(CERT_FILE)).unwrap(), cert_pem);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new().route("/api/ping", get(|| async { "pong" }));
+        tokio::spawn(serve(listener, app, tls_config));
+
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&cert_pem).unwrap())
+            .resolve("localhost", addr)
+            .build()
+            .unwrap();
+        let body = client
+            .get(format!("https://localhost:{}/api/ping", addr.port()))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "pong");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
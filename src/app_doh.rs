@@ -0,0 +1,130 @@
+use hyper::header::{HeaderMap, ACCEPT, CONTENT_TYPE};
+
+/// Endpoints `Config::doh_endpoints` starts with. `host/path` matches that
+/// host (and its subdomains) under `path`; `/path` matches it on any host.
+pub const DEFAULT_DOH_ENDPOINTS: [&str; 4] = [
+    // RFC 8484's well-known path, used by Cloudflare, Quad9, AdGuard, OpenDNS, ...
+    "/dns-query",
+    // Google's JSON API
+    "dns.google/resolve",
+    // NextDNS puts the profile ID in the path, so the whole host is DoH
+    "dns.nextdns.io/",
+    "doh.mullvad.net/",
+];
+
+/// Hosts that serve nothing but DNS-over-HTTPS, recognized on tunnels too
+/// (`detect_host`), where the path can't be seen
+pub const KNOWN_DOH_HOSTS: [&str; 7] = [
+    "cloudflare-dns.com",
+    "dns.google",
+    "dns.quad9.net",
+    "dns.adguard-dns.com",
+    "doh.opendns.com",
+    "dns.nextdns.io",
+    "doh.mullvad.net",
+];
+
+/// Media types only DNS-over-HTTPS requests carry
+const DOH_MEDIA_TYPES: [&str; 2] = ["application/dns-message", "application/dns-json"];
+
+pub fn default_doh_endpoints() -> Vec<String> {
+    DEFAULT_DOH_ENDPOINTS.iter().map(|endpoint| endpoint.to_string()).collect()
+}
+
+/// Whether `path` lies under `prefix`, on a path-segment boundary
+fn under_path(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Whether `host` is `domain` or one of its subdomains
+fn on_domain(host: &str, domain: &str) -> bool {
+    host == domain || host.strip_suffix(domain).is_some_and(|rest| rest.ends_with('.'))
+}
+
+fn matches_endpoint(endpoint: &str, host: &str, path: &str) -> bool {
+    let endpoint = endpoint.trim().to_ascii_lowercase();
+    let (endpoint_host, endpoint_path) = match endpoint.find('/') {
+        Some(slash) => endpoint.split_at(slash),
+        None => (endpoint.as_str(), "/"),
+    };
+    (endpoint_host.is_empty() || on_domain(host, endpoint_host)) && under_path(&path.to_ascii_lowercase(), endpoint_path)
+}
+
+/// What marks a request to `host` (normalized) as an app's own DNS-over-HTTPS
+/// lookup: the entry of `endpoints` it matched, or the DoH media type it
+/// sends or asks for. None for any other request.
+pub fn detect(endpoints: &[String], host: &str, path: &str, headers: &HeaderMap) -> Option<String> {
+    if let Some(endpoint) = endpoints.iter().find(|endpoint| matches_endpoint(endpoint, host, path)) {
+        return Some(endpoint.trim().to_string());
+    }
+
+    [CONTENT_TYPE, ACCEPT]
+        .iter()
+        .filter_map(|name| headers.get(name)?.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|media_type| media_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+        .find(|media_type| DOH_MEDIA_TYPES.contains(&media_type.as_str()))
+}
+
+/// What marks a tunnel to `host` (normalized) as an app's own DoH: an entry
+/// of `endpoints` naming the host, whatever its path, or one of
+/// `KNOWN_DOH_HOSTS`. Path-only entries such as `/dns-query` can't match.
+pub fn detect_host(endpoints: &[String], host: &str) -> Option<String> {
+    let named = endpoints.iter().find(|endpoint| {
+        let endpoint = endpoint.trim().to_ascii_lowercase();
+        let endpoint_host = endpoint.split('/').next().unwrap_or_default();
+        !endpoint_host.is_empty() && on_domain(host, endpoint_host)
+    });
+    if let Some(endpoint) = named {
+        return Some(endpoint.trim().to_string());
+    }
+    KNOWN_DOH_HOSTS.iter().find(|known| on_domain(host, known)).map(|known| known.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_doh_endpoints_detected() {
+        let endpoints = default_doh_endpoints();
+        let none = HeaderMap::new();
+
+        assert_eq!(detect(&endpoints, "1.1.1.1", "/dns-query", &none).as_deref(), Some("/dns-query"));
+        assert_eq!(detect(&endpoints, "dns.google", "/resolve", &none).as_deref(), Some("dns.google/resolve"));
+        assert_eq!(detect(&endpoints, "dns.nextdns.io", "/abc123", &none).as_deref(), Some("dns.nextdns.io/"));
+        assert_eq!(detect(&endpoints, "base.doh.mullvad.net", "/dns-query", &none).as_deref(), Some("/dns-query"));
+
+        // The same hosts serve ordinary pages too
+        assert_eq!(detect(&endpoints, "1.1.1.1", "/", &none), None);
+        assert_eq!(detect(&endpoints, "dns.google", "/resolver-info", &none), None);
+        assert_eq!(detect(&endpoints, "example.com", "/dns-query-docs", &none), None);
+        assert_eq!(detect(&endpoints, "notdns.google", "/resolve", &none), None);
+    }
+
+    #[test]
+    fn test_doh_tunnels_detected_by_host() {
+        let endpoints = default_doh_endpoints();
+        assert_eq!(detect_host(&endpoints, "dns.google").as_deref(), Some("dns.google/resolve"));
+        assert_eq!(detect_host(&endpoints, "base.doh.mullvad.net").as_deref(), Some("doh.mullvad.net/"));
+        assert_eq!(detect_host(&endpoints, "mozilla.cloudflare-dns.com").as_deref(), Some("cloudflare-dns.com"));
+        assert_eq!(detect_host(&["resolver.example/q".to_string()], "resolver.example").as_deref(), Some("resolver.example/q"));
+
+        assert_eq!(detect_host(&endpoints, "1.1.1.1"), None);
+        assert_eq!(detect_host(&endpoints, "example.com"), None);
+        assert_eq!(detect_host(&endpoints, "notdns.google"), None);
+    }
+
+    #[test]
+    fn test_doh_media_type_detected() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, "text/html, Application/DNS-Message;q=0.9".parse().unwrap());
+        assert_eq!(detect(&[], "resolver.example", "/q", &headers).as_deref(), Some("application/dns-message"));
+
+        headers.insert(ACCEPT, "text/html".parse().unwrap());
+        assert_eq!(detect(&[], "resolver.example", "/q", &headers), None);
+    }
+}
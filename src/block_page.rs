@@ -0,0 +1,120 @@
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::{header, Response};
+
+/// What a block page tells the user about a blocked request
+#[derive(Debug, Clone, Copy)]
+pub struct BlockDetail<'a> {
+    pub host: &'a str,
+    /// The setting or list rule that matched, e.g. `||doubleclick.net^`
+    pub rule: &'a str,
+    /// The whole answer for clients that don't take HTML
+    pub message: &'a str,
+}
+
+/// Whether an `Accept` header asks for a page rather than data, as a
+/// browser navigating somewhere does
+pub fn wants_html(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| {
+        accept.split(',').any(|range| {
+            let mut params = range.split(';');
+            let media = params.next().unwrap_or_default().trim();
+            let refused = params.any(|param| matches!(param.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+            media.eq_ignore_ascii_case("text/html") && !refused
+        })
+    })
+}
+
+/// The 403 for a blocked request: an HTML page for browsers with an "allow
+/// once" button posting to `api_base`'s temporary allowlist, plain text for
+/// everything else
+pub fn response(category: &str, detail: BlockDetail<'_>, accept: Option<&str>, api_base: &str) -> Response<Full<Bytes>> {
+    let builder = Response::builder().status(403).header(header::CACHE_CONTROL, "no-store");
+    if wants_html(accept) {
+        builder
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Full::new(Bytes::from(render(category, detail, api_base))))
+            .unwrap()
+    } else {
+        builder
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(Full::new(Bytes::from(detail.message.to_string())))
+            .unwrap()
+    }
+}
+
+fn render(category: &str, detail: BlockDetail<'_>, api_base: &str) -> String {
+    let allow = |scope: &str, label: &str| {
+        format!(
+            r#"<form method="post" action="{}/api/allowlist/temp?domain={}&amp;scope={}"><button type="submit">{}</button></form>"#,
+            escape_html(api_base),
+            escape_html(&encode_query_value(detail.host)),
+            scope,
+            label
+        )
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Blocked by Privacy Suite</title>
+<style>
+body {{ font-family: system-ui, sans-serif; background: #10141c; color: #e6e9ef; display: flex; justify-content: center; padding: 10vh 1em; margin: 0; }}
+main {{ max-width: 36em; }}
+h1 {{ font-size: 1.4em; margin: 0 0 .2em; }}
+.category {{ color: #ff7a7a; text-transform: uppercase; letter-spacing: .08em; font-size: .8em; }}
+dl {{ display: grid; grid-template-columns: max-content 1fr; gap: .4em 1em; }}
+dt {{ color: #8d96a8; }}
+dd {{ margin: 0; font-family: ui-monospace, monospace; word-break: break-all; }}
+form {{ display: inline-block; margin-right: .5em; }}
+button {{ background: #2c3a55; color: inherit; border: 1px solid #4a5f8a; border-radius: 4px; padding: .5em 1em; cursor: pointer; }}
+</style>
+</head>
+<body>
+<main>
+<div class="category">{}</div>
+<h1>🛡️ Blocked by Privacy Suite</h1>
+<p>{}</p>
+<dl><dt>Host</dt><dd>{}</dd><dt>Rule</dt><dd>{}</dd></dl>
+{}{}
+</main>
+</body>
+</html>
+"#,
+        escape_html(category),
+        escape_html(detail.message),
+        escape_html(detail.host),
+        escape_html(detail.rule),
+        allow("once", "Allow once"),
+        allow("session", "Allow for this session"),
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
@@ -1,15 +1,208 @@
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
-use tracing::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::{info, warn};
+use crate::config::Config;
+use crate::domain_set::{CompactDomainSet, DomainSet};
 
 #[derive(Clone)]
 pub struct TrackerBlocker {
-    blocked_domains: HashSet<String>,
-    blocked_count: Arc<Mutex<u64>>,
+    /// Swapped as a whole on refresh so lookups always see one consistent set
+    blocked_domains: Arc<RwLock<Arc<DomainSet>>>,
+    /// `@@||domain^` exception rules from the fetched lists (swapped with `blocked_domains`)
+    exception_domains: Arc<RwLock<Arc<HashSet<String>>>>,
+    /// Domains the user never wants blocked (`Config::blocklist_allowlist`)
+    allowlist: Arc<HashSet<String>>,
+    /// Domains added with `import`, kept when the lists are refreshed
+    imported_domains: Arc<Mutex<HashSet<String>>>,
+    /// What the last successful refresh loaded from each list, by URL
+    list_status: Arc<Mutex<HashMap<String, ListStatus>>>,
+    /// What each list contributed when it was last loaded, so one list can
+    /// be reloaded without fetching the others. A checksummed list keeps
+    /// its last version that matched in place of a download that doesn't.
+    lists: Arc<Mutex<HashMap<String, Arc<ListContents>>>>,
+    /// Domains the user let through from a block page, never saved
+    temporary: Arc<Mutex<TemporaryAllowlist>>,
+    /// Where the domain set is kept as a `CompactDomainSet` between runs
+    /// (`Config::blocklist_cache`); None keeps it in a `HashSet`
+    disk_cache: Option<PathBuf>,
+    blocked_count: Arc<AtomicU64>,
+}
+
+/// How long a temporary allow lasts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AllowScope {
+    /// The next blocked request to the domain only
+    Once,
+    /// Until the session is disconnected
+    Session,
+}
+
+/// Domains allowed past the blocklist for now, as returned by `/api/allowlist/temp`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemporaryAllowlist {
+    pub once: HashSet<String>,
+    pub session: HashSet<String>,
+}
+
+/// What a refresh loaded from one list
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ListStatus {
+    /// RFC 3339 time of the refresh
+    updated: String,
+    domains: usize,
+}
+
+/// What one list contributed, kept compact as the lists are already
+/// merged into the domain set
+#[derive(Debug)]
+struct ListContents {
+    domains: CompactDomainSet,
+    exceptions: HashSet<String>,
+    /// Of the list as loaded
+    sha256: String,
+}
+
+/// Expected SHA-256 of a tracker list (`Config::tracker_list_checksums`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListChecksum {
+    pub url: String,
+    /// Hex digest of the list as downloaded
+    pub sha256: String,
+}
+
+/// A tracker list in `Config::tracker_lists`, as listed by `/api/subscriptions`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Subscription {
+    pub url: String,
+    /// Disabled lists stay subscribed but contribute nothing on the next refresh
+    pub enabled: bool,
+    /// When the list was last loaded, if it has been since startup
+    pub last_updated: Option<String>,
+    /// Domains it contained then
+    pub domain_count: Option<usize>,
+}
+
+/// Substrings that mark a host as tracking-related even if no list names it
+const TRACKING_PATTERNS: [&str; 6] = ["/tr", "analytics", "/ads", "doubleclick", "tracking", "pixel"];
+
+/// Why a domain is or isn't blocked, as reported by `/api/blocklist/check`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockCheck {
+    pub blocked: bool,
+    /// The rule that decided the outcome, in Adblock syntax
+    pub matched_rule: Option<String>,
+    /// "tracker" (list entry), "pattern" (keyword heuristic) or "allowlist"
+    pub category: Option<String>,
+}
+
+/// Outcome of a blocklist refresh
+#[derive(Debug, Clone, Serialize)]
+pub struct RefreshSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub total: usize,
+    /// Lists that didn't match their checksum; their previous verified
+    /// contents (if any) were kept instead
+    pub rejected: Vec<String>,
+}
+
+/// Text formats blocklists are imported from and exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlocklistFormat {
+    /// `0.0.0.0 domain` lines, as in `/etc/hosts` (plain domain lists count too)
+    Hosts,
+    /// `||domain^` rules, as in EasyList
+    Adblock,
+}
+
+impl BlocklistFormat {
+    /// Guess the format of a list: any Adblock syntax makes it an Adblock list
+    pub fn detect(text: &str) -> Self {
+        let is_adblock = text.lines().map(str::trim).any(|line| {
+            line.starts_with("||") || line.starts_with("@@") || line.starts_with('!') || line.starts_with("[Adblock")
+        });
+        if is_adblock {
+            BlocklistFormat::Adblock
+        } else {
+            BlocklistFormat::Hosts
+        }
+    }
+}
+
+/// Outcome of `TrackerBlocker::import`
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportSummary {
+    pub format: BlocklistFormat,
+    /// Domains in the import, after deduplication
+    pub parsed: usize,
+    /// Of those, the ones that weren't blocked already
+    pub added: usize,
+    pub total: usize,
 }
 
 impl TrackerBlocker {
     pub fn new() -> Self {
+        let blocked_domains = Self::builtin_domains();
+        
+        info!("Loaded {} tracking domains to block", blocked_domains.len());
+        
+        Self { 
+            blocked_domains: Arc::new(RwLock::new(Arc::new(DomainSet::Hashed(blocked_domains)))),
+            exception_domains: Arc::new(RwLock::new(Arc::new(HashSet::new()))),
+            allowlist: Arc::new(HashSet::new()),
+            imported_domains: Arc::new(Mutex::new(HashSet::new())),
+            list_status: Arc::new(Mutex::new(HashMap::new())),
+            lists: Arc::new(Mutex::new(HashMap::new())),
+            temporary: Arc::new(Mutex::new(TemporaryAllowlist::default())),
+            disk_cache: None,
+            blocked_count: Arc::new(AtomicU64::new(0)),
+        }
+    }
+    
+    /// The blocker `config` asks for: its allowlist, and the disk cache if
+    /// `Config::blocklist_cache` is on
+    pub fn for_config(config: &Config) -> Self {
+        let blocker = Self::new().with_allowlist(config.blocklist_allowlist.clone());
+        match config.blocklist_cache_path() {
+            Some(path) => blocker.with_disk_cache(path),
+            None => blocker,
+        }
+    }
+    
+    /// Never block these domains (or their subdomains), whatever the lists say
+    pub fn with_allowlist(mut self, domains: impl IntoIterator<Item = String>) -> Self {
+        self.allowlist = Arc::new(domains.into_iter().map(|d| normalize_host(&d)).collect());
+        self
+    }
+    
+    /// Keep the domain set compact and cached at `path`, starting from the
+    /// set cached there by the last run (if it can be read) instead of just
+    /// the built-in domains until the lists are refreshed
+    pub fn with_disk_cache(mut self, path: PathBuf) -> Self {
+        let domains = match CompactDomainSet::load(&path) {
+            Ok(cached) => {
+                info!("Loaded {} tracking domains from {}", cached.len(), path.display());
+                cached
+            }
+            Err(e) => {
+                if path.exists() {
+                    warn!("Ignoring blocklist cache: {}", e);
+                }
+                CompactDomainSet::from_domains(self.domains_snapshot().iter())
+            }
+        };
+        *self.blocked_domains.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(DomainSet::Compact(domains));
+        self.disk_cache = Some(path);
+        self
+    }
+    
+    /// Tracker domains that are always blocked, even before any list is fetched
+    fn builtin_domains() -> HashSet<String> {
         let mut blocked_domains = HashSet::new();
         
         // Common tracking and ad domains (comprehensive list)
@@ -117,51 +310,261 @@ impl TrackerBlocker {
             blocked_domains.insert(tracker.to_string());
         }
         
-        info!("Loaded {} tracking domains to block", blocked_domains.len());
+        blocked_domains
+    }
+    
+    /// Current domain set (cheap to clone, unaffected by later refreshes)
+    fn domains_snapshot(&self) -> Arc<DomainSet> {
+        self.blocked_domains.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+    
+    /// Atomically replace the domain set, returning what changed. With a
+    /// disk cache the compact set is built straight from `domains`, without
+    /// a `HashSet` of them all on the way.
+    fn swap_domains<I, S>(&self, domains: I) -> RefreshSummary
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let new_domains = Arc::new(match &self.disk_cache {
+            Some(path) => {
+                let compact = CompactDomainSet::from_domains(domains);
+                if let Err(e) = compact.save(path) {
+                    warn!("Failed to save blocklist cache: {}", e);
+                }
+                DomainSet::Compact(compact)
+            }
+            None => DomainSet::Hashed(domains.into_iter().map(|domain| domain.as_ref().to_string()).collect()),
+        });
+        let old_domains = {
+            let mut current = self.blocked_domains.write().unwrap_or_else(|e| e.into_inner());
+            std::mem::replace(&mut *current, new_domains.clone())
+        };
         
-        Self { 
-            blocked_domains,
-            blocked_count: Arc::new(Mutex::new(0)),
+        RefreshSummary {
+            added: new_domains.iter().filter(|domain| !old_domains.contains(domain)).count(),
+            removed: old_domains.iter().filter(|domain| !new_domains.contains(domain)).count(),
+            total: new_domains.len(),
+            rejected: Vec::new(),
         }
     }
     
-    /// Check if a domain should be blocked
-    pub fn should_block(&self, domain: &str) -> bool {
-        let should_block = {
-            // Check exact match
-            if self.blocked_domains.contains(domain) {
-                true
-            } else {
-                // Check if any parent domain matches (e.g., sub.google-analytics.com matches google-analytics.com)
-                let parts: Vec<&str> = domain.split('.').collect();
-                let mut found = false;
-                for i in 0..parts.len() {
-                    let subdomain = parts[i..].join(".");
-                    if self.blocked_domains.contains(&subdomain) {
-                        found = true;
-                        break;
-                    }
-                }
-                
-                // Also check if domain contains common tracking patterns
-                if !found {
-                    let lower_domain = domain.to_lowercase();
-                    found = lower_domain.contains("/tr") || 
-                            lower_domain.contains("analytics") ||
-                            lower_domain.contains("/ads") ||
-                            lower_domain.contains("doubleclick") ||
-                            lower_domain.contains("tracking") ||
-                            lower_domain.contains("pixel");
+    /// Re-fetch the given lists and swap them in alongside the built-in domains.
+    /// `file://` entries are read from disk instead of downloaded.
+    ///
+    /// If any list fails to load, the current set is kept unchanged.
+    pub async fn refresh(&self, urls: &[String]) -> Result<RefreshSummary, String> {
+        self.refresh_verified(urls, &[]).await
+    }
+    
+    /// `refresh`, checking the lists named in `checksums` against their
+    /// SHA-256. A list that doesn't match is not applied; the last version
+    /// that did stays in the set and its URL is reported in `rejected`.
+    pub async fn refresh_verified(&self, urls: &[String], checksums: &[ListChecksum]) -> Result<RefreshSummary, String> {
+        self.reload(urls, checksums, |_| true).await
+    }
+    
+    /// `refresh_verified`, loading only the lists in `changed` again. The
+    /// others keep what they contributed last time (a list not loaded yet
+    /// is loaded too), so a failing download can't hold back a local edit.
+    pub async fn refresh_changed(&self, urls: &[String], checksums: &[ListChecksum], changed: &[String]) -> Result<RefreshSummary, String> {
+        self.reload(urls, checksums, |url| changed.iter().any(|list| list == url)).await
+    }
+    
+    async fn reload(&self, urls: &[String], checksums: &[ListChecksum], changed: impl Fn(&str) -> bool) -> Result<RefreshSummary, String> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(60))
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+        
+        let mut contents = Vec::new();
+        let mut loaded = Vec::new();
+        let mut rejected = Vec::new();
+        
+        for url in urls {
+            let previous = self.lists.lock().unwrap_or_else(|e| e.into_inner()).get(url).cloned();
+            if let Some(previous) = previous.as_ref().filter(|_| !changed(url)) {
+                contents.push(previous.clone());
+                continue;
+            }
+            
+            let bytes = match local_list_path(url) {
+                Some(path) => tokio::fs::read(&path)
+                    .await
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+                None => client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| format!("Failed to fetch {}: {}", url, e))?
+                    .bytes()
+                    .await
+                    .map_err(|e| format!("Failed to read {}: {}", url, e))?
+                    .to_vec(),
+            };
+            
+            let sha256 = sha256_hex(&bytes);
+            if let Some(expected) = checksums.iter().find(|checksum| checksum.url == *url) {
+                let expected = expected.sha256.trim();
+                if !sha256.eq_ignore_ascii_case(expected) {
+                    warn!("Blocklist {} failed verification: expected sha256 {}, got {}", url, expected, sha256);
+                    rejected.push(url.clone());
+                    contents.extend(previous.filter(|previous| previous.sha256.eq_ignore_ascii_case(expected)));
+                    continue;
                 }
-                
-                found
             }
+            
+            let text = String::from_utf8_lossy(&bytes);
+            let list = Arc::new(ListContents {
+                domains: CompactDomainSet::from_domains(parse_blocklist(&text)),
+                exceptions: parse_exceptions(&text),
+                sha256,
+            });
+            contents.push(list.clone());
+            loaded.push((url.clone(), list));
+        }
+        
+        let builtin = Self::builtin_domains();
+        let imported = self.imported_domains.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let domains = builtin
+            .iter()
+            .chain(&imported)
+            .map(String::as_str)
+            .chain(contents.iter().flat_map(|list| list.domains.iter()));
+        let exceptions: HashSet<String> = contents.iter().flat_map(|list| list.exceptions.iter().cloned()).collect();
+        
+        let mut summary = self.swap_domains(domains);
+        summary.rejected = rejected;
+        *self.exception_domains.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(exceptions);
+        let updated = chrono::Local::now().to_rfc3339();
+        let mut list_status = self.list_status.lock().unwrap_or_else(|e| e.into_inner());
+        let mut lists = self.lists.lock().unwrap_or_else(|e| e.into_inner());
+        for (url, list) in loaded {
+            list_status.insert(url.clone(), ListStatus { updated: updated.clone(), domains: list.domains.len() });
+            lists.insert(url, list);
+        }
+        drop(lists);
+        drop(list_status);
+        info!(
+            "Blocklist refreshed: +{} / -{} domains ({} total)",
+            summary.added, summary.removed, summary.total
+        );
+        
+        Ok(summary)
+    }
+    
+    /// `lists` (all of `Config::tracker_lists`) with what the last refresh
+    /// loaded from each; `disabled` are the ones switched off
+    pub fn subscriptions(&self, lists: &[String], disabled: &[String]) -> Vec<Subscription> {
+        let list_status = self.list_status.lock().unwrap_or_else(|e| e.into_inner());
+        lists
+            .iter()
+            .map(|url| {
+                let status = list_status.get(url);
+                Subscription {
+                    url: url.clone(),
+                    enabled: !disabled.contains(url),
+                    last_updated: status.map(|status| status.updated.clone()),
+                    domain_count: status.map(|status| status.domains),
+                }
+            })
+            .collect()
+    }
+    
+    /// Merge a hosts-file or Adblock list (detected from its content) into
+    /// the blocklist. Imported domains survive later refreshes.
+    pub fn import(&self, text: &str) -> ImportSummary {
+        let format = BlocklistFormat::detect(text);
+        let parsed: HashSet<String> = parse_blocklist(text).iter().map(|domain| normalize_host(domain)).collect();
+        self.imported_domains.lock().unwrap_or_else(|e| e.into_inner()).extend(parsed.iter().cloned());
+        
+        let current = self.domains_snapshot();
+        let added = parsed.iter().filter(|domain| !current.contains(domain)).count();
+        let summary = if added == 0 {
+            RefreshSummary { added: 0, removed: 0, total: current.len(), rejected: Vec::new() }
+        } else {
+            self.swap_domains(current.iter().chain(parsed.iter().map(String::as_str)))
         };
+        info!("Imported {} blocklist domains ({} new, {} total)", parsed.len(), summary.added, summary.total);
+        
+        ImportSummary {
+            format,
+            parsed: parsed.len(),
+            added: summary.added,
+            total: summary.total,
+        }
+    }
+    
+    /// The current blocklist as a `format` list, sorted so exports diff cleanly.
+    /// Built-in path entries such as `google.com/ads` aren't domains and are left out.
+    pub fn export(&self, format: BlocklistFormat) -> String {
+        let mut domains: Vec<String> = self
+            .domains_snapshot()
+            .iter()
+            .filter(|domain| is_domain_entry(domain))
+            .map(str::to_string)
+            .collect();
+        domains.sort();
+        
+        let mut list = match format {
+            BlocklistFormat::Hosts => String::from("# DUL Privacy Suite blocklist\n"),
+            BlocklistFormat::Adblock => String::from("[Adblock Plus 2.0]\n! DUL Privacy Suite blocklist\n"),
+        };
+        for domain in domains {
+            let line = match format {
+                BlocklistFormat::Hosts => format!("0.0.0.0 {}\n", domain),
+                BlocklistFormat::Adblock => format!("||{}^\n", domain),
+            };
+            list.push_str(&line);
+        }
+        list
+    }
+    
+    /// Explain whether a domain would be blocked, without counting it as a block
+    pub fn check(&self, domain: &str) -> BlockCheck {
+        let exceptions = self.exception_domains.read().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Some(allowed) = find_suffix(&self.allowlist, domain).or_else(|| find_suffix(&exceptions, domain)) {
+            return BlockCheck {
+                blocked: false,
+                matched_rule: Some(format!("@@||{}^", allowed)),
+                category: Some("allowlist".to_string()),
+            };
+        }
+        
+        // Exact or parent domain match (e.g., sub.google-analytics.com matches google-analytics.com)
+        let domains = self.domains_snapshot();
+        if let Some(entry) = find_suffix_by(domain, |candidate| domains.contains(candidate)) {
+            return BlockCheck {
+                blocked: true,
+                matched_rule: Some(format!("||{}^", entry)),
+                category: Some("tracker".to_string()),
+            };
+        }
+        
+        // Also check if domain contains common tracking patterns
+        let lower_domain = domain.to_lowercase();
+        if let Some(pattern) = TRACKING_PATTERNS.iter().find(|p| lower_domain.contains(*p)) {
+            return BlockCheck {
+                blocked: true,
+                matched_rule: Some(format!("*{}*", pattern)),
+                category: Some("pattern".to_string()),
+            };
+        }
+        
+        BlockCheck {
+            blocked: false,
+            matched_rule: None,
+            category: None,
+        }
+    }
+    
+    /// Check if a domain should be blocked
+    pub fn should_block(&self, domain: &str) -> bool {
+        let should_block = self.check(domain).blocked;
         
         if should_block {
-            if let Ok(mut count) = self.blocked_count.lock() {
-                *count += 1;
-            }
+            self.blocked_count.fetch_add(1, Ordering::Relaxed);
         }
         
         should_block
@@ -169,13 +572,161 @@ impl TrackerBlocker {
     
     /// Get total number of domains in blocklist
     pub fn blocklist_size(&self) -> usize {
-        self.blocked_domains.len()
+        self.domains_snapshot().len()
     }
     
     /// Get total number of trackers blocked this session
     pub fn total_blocked(&self) -> u64 {
-        self.blocked_count.lock().unwrap_or_else(|e| e.into_inner()).clone()
+        self.blocked_count.load(Ordering::Relaxed)
+    }
+    
+    /// Let `domain` (and its subdomains) through once or for the session,
+    /// without touching the saved allowlist
+    pub fn allow_temporarily(&self, domain: &str, scope: AllowScope) -> TemporaryAllowlist {
+        let mut temporary = self.temporary.lock().unwrap_or_else(|e| e.into_inner());
+        let domain = normalize_host(domain);
+        match scope {
+            AllowScope::Once => temporary.once.insert(domain),
+            AllowScope::Session => temporary.session.insert(domain),
+        };
+        temporary.clone()
+    }
+    
+    /// Whether a temporary allow covers `domain`, using it up if it was a
+    /// once-only one
+    pub fn take_temporary_allow(&self, domain: &str) -> Option<AllowScope> {
+        let mut temporary = self.temporary.lock().unwrap_or_else(|e| e.into_inner());
+        if find_suffix(&temporary.session, domain).is_some() {
+            return Some(AllowScope::Session);
+        }
+        let once = find_suffix(&temporary.once, domain)?;
+        temporary.once.remove(&once);
+        Some(AllowScope::Once)
     }
+    
+    pub fn temporary_allowlist(&self) -> TemporaryAllowlist {
+        self.temporary.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+    
+    /// Forget every temporary allow, e.g. when the session ends
+    pub fn clear_temporary(&self) {
+        *self.temporary.lock().unwrap_or_else(|e| e.into_inner()) = TemporaryAllowlist::default();
+    }
+}
+
+/// Lowercase hex SHA-256 of `bytes`, as `ListChecksum::sha256` is written
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Path of a local list in `Config::tracker_lists`, written as a `file://` URL
+/// (`file:///home/me/block.txt`, or `file:///C:/lists/block.txt` on Windows)
+pub fn local_list_path(url: &str) -> Option<PathBuf> {
+    let path = url.strip_prefix("file://")?;
+    // Drop the slash before a Windows drive letter
+    let bytes = path.as_bytes();
+    let path = if bytes.len() >= 3 && bytes[0] == b'/' && bytes[1].is_ascii_alphabetic() && bytes[2] == b':' {
+        &path[1..]
+    } else {
+        path
+    };
+    Some(PathBuf::from(path))
+}
+
+/// Whether `host` is one of `domains` or a subdomain of one; both sides
+/// are expected to be normalized with `normalize_host`
+pub fn in_domain_set(domains: &HashSet<String>, host: &str) -> bool {
+    find_suffix(domains, host).is_some()
+}
+
+/// Find `domain` or its closest parent domain in `set`
+fn find_suffix(set: &HashSet<String>, domain: &str) -> Option<String> {
+    find_suffix_by(domain, |candidate| set.contains(candidate))
+}
+
+/// `find_suffix` in any set `contains` looks up
+fn find_suffix_by(domain: &str, contains: impl Fn(&str) -> bool) -> Option<String> {
+    let parts: Vec<&str> = domain.split('.').collect();
+    (0..parts.len())
+        .map(|i| parts[i..].join("."))
+        .find(|candidate| contains(candidate))
+}
+
+/// Canonicalize a host before matching it against blocklists and protections.
+///
+/// Strips userinfo (`user@host`) and trailing dots, lowercases, and converts
+/// IDN hosts to their punycode form so equivalent spellings match the same entry.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.rsplit('@').next().unwrap_or(host);
+    let host = host.trim_end_matches('.');
+    
+    // IPv6 literals aren't domain names
+    if host.starts_with('[') {
+        return host.to_ascii_lowercase();
+    }
+    
+    idna::domain_to_ascii(host).unwrap_or_else(|_| host.to_lowercase())
+}
+
+/// Extract domains from a hosts file, Adblock-style list, or plain domain list.
+///
+/// Adblock rules are only taken when they block a whole domain (`||domain^`);
+/// cosmetic, exception and path rules are skipped.
+pub fn parse_blocklist(text: &str) -> HashSet<String> {
+    let mut domains = HashSet::new();
+    
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('!') || line.starts_with('#') || line.starts_with('[') {
+            continue;
+        }
+        
+        let candidate = if let Some(rule) = line.strip_prefix("||") {
+            match rule.strip_suffix('^') {
+                Some(domain) => domain,
+                None => continue,
+            }
+        } else if line.starts_with("0.0.0.0 ") || line.starts_with("127.0.0.1 ") {
+            match line.split_whitespace().nth(1) {
+                Some(domain) => domain,
+                None => continue,
+            }
+        } else {
+            line
+        };
+        
+        if is_domain_entry(candidate) {
+            domains.insert(candidate.to_lowercase());
+        }
+    }
+    
+    if domains.is_empty() {
+        warn!("Blocklist contained no usable domain entries");
+    }
+    
+    domains
+}
+
+/// Whether a list entry names a whole domain (not a path or a local name)
+fn is_domain_entry(candidate: &str) -> bool {
+    candidate.contains('.')
+        && candidate
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+        && candidate != "localhost"
+}
+
+/// Extract whole-domain exception rules (`@@||domain^`) from an Adblock-style list
+pub fn parse_exceptions(text: &str) -> HashSet<String> {
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix("@@||")?.strip_suffix('^'))
+        .filter(|domain| domain.contains('.'))
+        .map(|domain| domain.to_lowercase())
+        .collect()
 }
 
 #[cfg(test)]
@@ -195,4 +746,237 @@ mod tests {
         assert!(!blocker.should_block("google.com"));
         assert!(!blocker.should_block("example.com"));
     }
+    
+    #[test]
+    fn test_normalized_hosts_match_blocklist() {
+        let blocker = TrackerBlocker::new();
+        
+        assert_eq!(normalize_host("GOOGLE-ANALYTICS.COM"), "google-analytics.com");
+        assert_eq!(normalize_host("google-analytics.com."), "google-analytics.com");
+        assert_eq!(normalize_host("user:pass@google-analytics.com"), "google-analytics.com");
+        
+        assert!(blocker.should_block(&normalize_host("GOOGLE-ANALYTICS.COM")));
+        assert!(blocker.should_block(&normalize_host("www.google-analytics.com.")));
+        assert!(blocker.should_block(&normalize_host("user@doubleclick.net")));
+        
+        // Fullwidth IDN spelling of a tracker maps to the ASCII domain
+        assert!(blocker.should_block(&normalize_host("ｃｒｉｔｅｏ.com")));
+        
+        // Non-ASCII hosts become punycode
+        assert_eq!(normalize_host("Bücher.example"), "xn--bcher-kva.example");
+        assert_eq!(normalize_host("[2001:DB8::1]"), "[2001:db8::1]");
+    }
+
+    #[test]
+    fn test_parse_blocklist_formats() {
+        let list = "! EasyList comment\n||ads.example.com^\n||example.org/path^\n##.banner\n0.0.0.0 tracker.example.net\n# hosts comment\nmetrics.example.io\n";
+        let domains = parse_blocklist(list);
+        
+        assert!(domains.contains("ads.example.com"));
+        assert!(domains.contains("tracker.example.net"));
+        assert!(domains.contains("metrics.example.io"));
+        assert_eq!(domains.len(), 3);
+    }
+    
+    #[test]
+    fn test_import_export_round_trip() {
+        let hosts = "# my hosts\n127.0.0.1 localhost\n0.0.0.0 ads.example.com\n0.0.0.0 Tracker.Example.net\n0.0.0.0 ads.example.com\n";
+        let adblock = "! my list\n||metrics.example.io^\n||ads.example.com^\n@@||fine.example.org^\n";
+        assert_eq!(BlocklistFormat::detect(hosts), BlocklistFormat::Hosts);
+        assert_eq!(BlocklistFormat::detect(adblock), BlocklistFormat::Adblock);
+        
+        let blocker = TrackerBlocker::new();
+        let before = blocker.blocklist_size();
+        let summary = blocker.import(hosts);
+        assert_eq!((summary.format, summary.parsed, summary.added), (BlocklistFormat::Hosts, 2, 2));
+        // Duplicates of domains already imported aren't added again
+        let summary = blocker.import(adblock);
+        assert_eq!((summary.format, summary.parsed, summary.added), (BlocklistFormat::Adblock, 2, 1));
+        assert_eq!(summary.total, before + 3);
+        assert!(blocker.check("tracker.example.net").blocked);
+        
+        // Each export parses back to exactly the current domains, in its own format
+        let domains: HashSet<String> = blocker.domains_snapshot().iter().filter(|d| is_domain_entry(d)).map(str::to_string).collect();
+        for format in [BlocklistFormat::Hosts, BlocklistFormat::Adblock] {
+            let exported = blocker.export(format);
+            assert_eq!(BlocklistFormat::detect(&exported), format);
+            assert_eq!(parse_blocklist(&exported), domains);
+            
+            let copy = TrackerBlocker::new();
+            assert_eq!(copy.import(&exported).added, 3);
+            assert_eq!(copy.domains_snapshot(), blocker.domains_snapshot());
+        }
+        assert!(blocker.export(BlocklistFormat::Adblock).contains("||metrics.example.io^\n"));
+        assert!(blocker.export(BlocklistFormat::Hosts).contains("0.0.0.0 tracker.example.net\n"));
+    }
+    
+    #[test]
+    fn test_refresh_swaps_set_atomically() {
+        let blocker = TrackerBlocker::new();
+        let old: HashSet<String> = ["old-a.com", "old-b.com"].iter().map(|d| d.to_string()).collect();
+        let new: HashSet<String> = ["new-a.com", "new-b.com"].iter().map(|d| d.to_string()).collect();
+        blocker.swap_domains(old);
+        
+        // A snapshot taken before the swap (an in-flight request) is unaffected by it
+        let in_flight = blocker.domains_snapshot();
+        
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let blocker = blocker.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let set = blocker.domains_snapshot();
+                        let is_old = set.contains("old-a.com") && set.contains("old-b.com") && !set.contains("new-a.com");
+                        let is_new = set.contains("new-a.com") && set.contains("new-b.com") && !set.contains("old-a.com");
+                        assert!(is_old || is_new, "observed a partially swapped blocklist");
+                    }
+                })
+            })
+            .collect();
+        
+        let summary = blocker.swap_domains(new);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        
+        assert_eq!(summary.added, 2);
+        assert_eq!(summary.removed, 2);
+        assert_eq!(summary.total, 2);
+        assert!(in_flight.contains("old-a.com"));
+        assert!(blocker.should_block("new-a.com"));
+        assert!(!blocker.should_block("old-a.com"));
+    }
+
+    #[test]
+    fn test_disk_cache_blocks_the_same_as_the_hash_set() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_blocklist_cache_{}", std::process::id()));
+        let path = dir.join("blocklist.cache");
+        let mut domains = TrackerBlocker::builtin_domains();
+        domains.extend(parse_blocklist("||ads.example.com^\n||metrics.example.io^\n0.0.0.0 pixel.example.net\n"));
+        
+        let hashed = TrackerBlocker::new();
+        hashed.swap_domains(domains.clone());
+        let cached = TrackerBlocker::new().with_disk_cache(path.clone());
+        cached.swap_domains(domains);
+        // The next run starts from the cache file
+        let restarted = TrackerBlocker::new().with_disk_cache(path.clone());
+        assert_eq!(restarted.blocklist_size(), hashed.blocklist_size());
+        
+        for host in ["ads.example.com", "cdn.ads.example.com", "example.com", "pixel.example.net", "doubleclick.net",
+            "stats.g.doubleclick.net", "metrics.example.io.evil.test", "analytics-collector.example.org", "example.org"]
+        {
+            assert_eq!(cached.check(host), hashed.check(host), "{}", host);
+            assert_eq!(restarted.check(host), hashed.check(host), "{}", host);
+        }
+        assert_eq!(restarted.export(BlocklistFormat::Adblock), hashed.export(BlocklistFormat::Adblock));
+        
+        // A corrupt cache is ignored rather than trusted
+        std::fs::write(&path, b"DULDS01\ngarbage").unwrap();
+        let fresh = TrackerBlocker::new().with_disk_cache(path);
+        assert_eq!(fresh.blocklist_size(), TrackerBlocker::new().blocklist_size());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn test_blocked_count_exact_across_threads() {
+        let blocker = TrackerBlocker::new();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let blocker = blocker.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        assert!(blocker.should_block("doubleclick.net"));
+                        assert!(!blocker.should_block("example.com"));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        
+        assert_eq!(blocker.total_blocked(), 8 * 1000);
+    }
+    
+    #[test]
+    fn test_check_explains_decision() {
+        let blocker = TrackerBlocker::new().with_allowlist(vec!["Sentry.io".to_string()]);
+        
+        let blocked = blocker.check("stats.doubleclick.net");
+        assert!(blocked.blocked);
+        assert_eq!(blocked.matched_rule.as_deref(), Some("||doubleclick.net^"));
+        assert_eq!(blocked.category.as_deref(), Some("tracker"));
+        
+        let allowed = blocker.check("example.com");
+        assert_eq!(allowed, BlockCheck { blocked: false, matched_rule: None, category: None });
+        
+        // sentry.io is on the built-in list, but the allowlist wins
+        let overridden = blocker.check("o123.ingest.sentry.io");
+        assert!(!overridden.blocked);
+        assert_eq!(overridden.matched_rule.as_deref(), Some("@@||sentry.io^"));
+        assert_eq!(overridden.category.as_deref(), Some("allowlist"));
+        
+        // Checking is diagnostic only and doesn't count as a block
+        assert_eq!(blocker.total_blocked(), 0);
+    }
+    
+    #[test]
+    fn test_allow_once_is_used_up() {
+        let blocker = TrackerBlocker::new();
+        blocker.allow_temporarily("DoubleClick.net", AllowScope::Once);
+        assert_eq!(blocker.temporary_allowlist().once, HashSet::from(["doubleclick.net".to_string()]));
+        
+        assert_eq!(blocker.take_temporary_allow("ad.doubleclick.net"), Some(AllowScope::Once));
+        assert_eq!(blocker.take_temporary_allow("ad.doubleclick.net"), None);
+        
+        blocker.allow_temporarily("doubleclick.net", AllowScope::Session);
+        for _ in 0..3 {
+            assert_eq!(blocker.take_temporary_allow("doubleclick.net"), Some(AllowScope::Session));
+        }
+        assert_eq!(blocker.take_temporary_allow("google-analytics.com"), None);
+        blocker.clear_temporary();
+        assert_eq!(blocker.take_temporary_allow("doubleclick.net"), None);
+        // Nothing the lists or the saved allowlist say changed
+        assert!(blocker.check("doubleclick.net").blocked);
+    }
+    
+    #[test]
+    fn test_list_exceptions_override_block() {
+        let blocker = TrackerBlocker::new();
+        let list = "||ads.example.com^\n@@||cdn.ads.example.com^\n";
+        let mut domains = TrackerBlocker::builtin_domains();
+        domains.extend(parse_blocklist(list));
+        blocker.swap_domains(domains);
+        *blocker.exception_domains.write().unwrap() = Arc::new(parse_exceptions(list));
+        
+        assert!(blocker.should_block("ads.example.com"));
+        assert!(!blocker.should_block("cdn.ads.example.com"));
+        assert_eq!(blocker.check("tracking.example.org").category.as_deref(), Some("pattern"));
+    }
+    
+    #[tokio::test]
+    async fn test_checksum_mismatch_keeps_verified_list() {
+        let path = std::env::temp_dir().join(format!("privacy_suite_checksum_list_{}.txt", std::process::id()));
+        let good = "0.0.0.0 good.example.com\n";
+        std::fs::write(&path, good).unwrap();
+        let url = format!("file://{}", path.display());
+        let checksums = vec![ListChecksum { url: url.clone(), sha256: sha256_hex(good.as_bytes()).to_uppercase() }];
+        
+        let blocker = TrackerBlocker::new();
+        let summary = blocker.refresh_verified(&[url.clone()], &checksums).await.unwrap();
+        assert!(summary.rejected.is_empty());
+        assert!(blocker.should_block("good.example.com"));
+        
+        // A tampered copy is refused and the verified one stays in force
+        std::fs::write(&path, "0.0.0.0 evil.example.com\n").unwrap();
+        let summary = blocker.refresh_verified(&[url.clone()], &checksums).await.unwrap();
+        assert_eq!(summary.rejected, [url.clone()]);
+        assert!(blocker.should_block("good.example.com"));
+        assert!(!blocker.should_block("evil.example.com"));
+        
+        // Without a checksum the list is taken as it is
+        blocker.refresh(&[url]).await.unwrap();
+        assert!(blocker.should_block("evil.example.com"));
+        let _ = std::fs::remove_file(&path);
+    }
 }
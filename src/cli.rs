@@ -0,0 +1,111 @@
+use clap::Parser;
+use std::path::PathBuf;
+use crate::config::Config;
+
+/// Command-line options. Anything given here overrides the config file.
+#[derive(Debug, Parser)]
+#[command(name = "privacy_suite", version, about = "DUL Privacy Suite backend")]
+pub struct Cli {
+    /// Use an alternate config file instead of ~/.privacy_suite/config.toml
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    
+    /// Port for the local web API (overrides `api_port`)
+    #[arg(long, value_name = "PORT")]
+    pub api_port: Option<u16>,
+    
+    /// Port for the proxy server (overrides the port in `proxy_addr`)
+    #[arg(long, value_name = "PORT")]
+    pub proxy_port: Option<u16>,
+    
+    /// Open the desktop window
+    #[arg(long)]
+    pub gui: bool,
+    
+    /// With --gui, attach to an already running backend instead of starting one
+    #[arg(long, requires = "gui")]
+    pub no_autostart: bool,
+    
+    /// Set on the instance started by `system_proxy::relaunch_elevated`
+    #[arg(long = "elevated-relaunch", hide = true)]
+    pub elevated_relaunch: bool,
+}
+
+impl Cli {
+    /// Load the config file selected by `--config` (or the default one)
+    /// and apply the command-line overrides to it
+    pub fn load_config(&self) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut config = match &self.config {
+            Some(path) => Config::load_or_create_at(path)?,
+            None => Config::load_or_create()?,
+        };
+        self.apply_overrides(&mut config);
+        Ok(config)
+    }
+    
+    pub fn apply_overrides(&self, config: &mut Config) {
+        if let Some(port) = self.api_port {
+            config.api_port = port;
+        }
+        if let Some(port) = self.proxy_port {
+            config.set_proxy_port(port);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("privacy_suite").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_parses_all_arguments() {
+        let cli = parse(&["--config", "/tmp/alt.toml", "--api-port", "4040", "--proxy-port", "9999", "--gui", "--no-autostart"]).unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("/tmp/alt.toml")));
+        assert_eq!(cli.api_port, Some(4040));
+        assert_eq!(cli.proxy_port, Some(9999));
+        assert!(cli.gui);
+        assert!(cli.no_autostart);
+        
+        let cli = parse(&[]).unwrap();
+        assert!(cli.config.is_none() && cli.api_port.is_none() && !cli.gui);
+    }
+
+    #[test]
+    fn test_rejects_invalid_arguments() {
+        assert!(parse(&["--api-port", "70000"]).is_err());
+        assert!(parse(&["--no-autostart"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["--elevated-relaunch"]).unwrap().elevated_relaunch);
+    }
+
+    #[test]
+    fn test_arguments_override_config_file() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_cli_test_{}", std::process::id()));
+        let path = dir.join("config.toml");
+        
+        let mut on_disk = Config::load_or_create_at(&path).unwrap();
+        on_disk.proxy_addr = "127.0.0.1:8118".to_string();
+        on_disk.api_port = 4000;
+        std::fs::write(&path, toml::to_string_pretty(&on_disk).unwrap()).unwrap();
+        
+        // Without flags the file's values win
+        let config = parse(&["--config", path.to_str().unwrap()]).unwrap().load_config().unwrap();
+        assert_eq!(config.api_port, 4000);
+        assert_eq!(config.proxy_addr(), "127.0.0.1:8118");
+        
+        // Flags take precedence and keep the bind address
+        let config = parse(&["--config", path.to_str().unwrap(), "--api-port", "5000", "--proxy-port", "9050"])
+            .unwrap()
+            .load_config()
+            .unwrap();
+        assert_eq!(config.api_port, 5000);
+        assert_eq!(config.proxy_addr(), "127.0.0.1:9050");
+        assert_eq!(config.config_path(), path.as_path());
+        
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,34 +1,533 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use crate::blocklist::ListChecksum;
+use crate::exit_selection::ExitPreference;
+use crate::onion_service::OnionServicePort;
+use crate::request_filter::RequestFilter;
+use crate::tor_network;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Local proxy server address
     pub proxy_addr: String,
     
-    /// Number of hops in multi-hop routing
+    /// Port of the local web API used by the GUI
+    #[serde(default = "default_api_port")]
+    pub api_port: u16,
+    
+    /// Serve the web API over HTTPS with a self-signed certificate. The
+    /// Tauri GUI sends its web view's requests through its own client,
+    /// which trusts the certificate; a browser or other tool opening the
+    /// API directly has to be given `api_cert.pem` first.
+    #[serde(default)]
+    pub api_tls: bool,
+    
+    /// Nodes per route for the experimental `RoutingBackend::Custom` only;
+    /// Tor circuits use `tor_path_length`
     pub num_hops: usize,
     
-    /// DNS server addresses
+    /// Relays in each Tor circuit. arti always builds 3-relay circuits, so
+    /// 3 is the only accepted value (see `tor_network::client_config`).
+    #[serde(default = "default_tor_path_length")]
+    pub tor_path_length: u8,
+    
+    /// Network used to carry plain HTTP requests
+    #[serde(default)]
+    pub routing_backend: RoutingBackend,
+    
+    /// DNS servers in order of preference, as `ip:port` or `ip:853#tls-name`
+    /// (see `dns::DnsServer`); later ones take over when earlier ones are down.
+    /// `DnsResolution::LocalDoH` asks the DoH service behind each DNS-over-TLS
+    /// entry, through Tor.
     pub dns_servers: Vec<String>,
     
+    /// Where hostnames are resolved for proxied connections
+    #[serde(default)]
+    pub dns_resolution: DnsResolution,
+    
+    /// Block apps' own DNS-over-HTTPS lookups (see `doh_endpoints`) so they
+    /// fall back to the system resolver; detected lookups are logged either way.
+    /// Only plain HTTP requests can be inspected, not HTTPS tunnels.
+    #[serde(default)]
+    pub block_app_doh: bool,
+    
+    /// DoH endpoints apps are detected using, as `host/path` or `/path` (any host)
+    #[serde(default = "default_doh_endpoints")]
+    pub doh_endpoints: Vec<String>,
+    
     /// Enable browser fingerprint randomization
     pub fingerprint_protection: bool,
     
-    /// Tracker blocking lists
+    /// Add Permissions-Policy headers to proxied HTML pages to limit WebRTC exposure
+    #[serde(default = "default_true")]
+    pub webrtc_policy_headers: bool,
+    
+    /// Tracker blocking lists: URLs, or `file://` paths to local lists that
+    /// are reloaded whenever the file changes
     pub tracker_lists: Vec<String>,
     
+    /// Entries of `tracker_lists` that are kept but not loaded
+    #[serde(default)]
+    pub disabled_tracker_lists: Vec<String>,
+    
+    /// Expected SHA-256 of entries of `tracker_lists`; a download that
+    /// doesn't match is refused and the last verified version kept
+    #[serde(default)]
+    pub tracker_list_checksums: Vec<ListChecksum>,
+    
+    /// Domains (and their subdomains) that are never blocked as trackers
+    #[serde(default)]
+    pub blocklist_allowlist: Vec<String>,
+    
+    /// How often the tracker lists are re-fetched (0 disables automatic refresh)
+    #[serde(default = "default_blocklist_refresh_hours")]
+    pub blocklist_refresh_hours: u64,
+    
+    /// Keep the merged tracker domains in a compact set, cached in
+    /// `blocklist.cache` next to the config file so the next start doesn't
+    /// wait for the lists. Worth it for very large lists.
+    #[serde(default)]
+    pub blocklist_cache: bool,
+    
     /// Blockchain configuration
     pub blockchain: BlockchainConfig,
     
     /// Node registry database path
     pub node_db_path: String,
     
+    /// Block requests to raw IP literals (independent of WebRTC protection)
+    #[serde(default)]
+    pub block_direct_ip: bool,
+    
+    /// Log what the protections and request filters would block, but let
+    /// every request through, to try out new rules before enforcing them
+    #[serde(default)]
+    pub learning_mode: bool,
+    
+    /// While Tor is down, the kill switch blocks LAN destinations too; turn
+    /// off to keep reaching local devices (printers, NAS) during an outage
+    #[serde(default = "default_true")]
+    pub killswitch_block_lan: bool,
+    
+    /// What happens to requests for `localhost`, `*.local` and private
+    /// addresses, which no Tor exit can reach
+    #[serde(default)]
+    pub private_hosts: PrivateHostPolicy,
+    
+    /// While Tor reconnects, hold requests for up to this many seconds and
+    /// send them once it is back, instead of failing them right away;
+    /// 0 turns holding off
+    #[serde(default)]
+    pub reconnect_hold_secs: u64,
+    
+    /// Requests held at once during a reconnect; any more fail right away
+    #[serde(default = "default_reconnect_hold_max")]
+    pub reconnect_hold_max: usize,
+    
+    /// Block WebRTC/STUN requests that could expose the real IP
+    #[serde(default = "default_true")]
+    pub webrtc_protection: bool,
+    
+    /// STUN/TURN servers WebRTC protection blocks: names (matched anywhere
+    /// in the host), wildcards like `*.stun.example.com`, or `/regex/`
+    #[serde(default = "default_stun_servers")]
+    pub stun_servers: Vec<String>,
+    
+    /// Block IPv6 destinations that could bypass Tor
+    #[serde(default = "default_true")]
+    pub ipv6_protection: bool,
+    
+    /// Protect against canvas fingerprinting
+    #[serde(default = "default_true")]
+    pub canvas_protection: bool,
+    
+    /// Block requests to known tracker domains
+    #[serde(default = "default_true")]
+    pub tracker_blocking: bool,
+    
+    /// Log credential leaks and tracking patterns seen in requests
+    #[serde(default = "default_true")]
+    pub security_detection: bool,
+    
+    /// Individual detectors to switch off, e.g. `tracking_pattern = false`
+    /// (see `SECURITY_DETECTORS`); detectors not listed stay on
+    #[serde(default)]
+    pub security_detectors: HashMap<String, bool>,
+    
+    /// IPv6 ranges exempt from IPv6 leak protection
+    #[serde(default)]
+    pub ipv6_allowlist: Ipv6AllowlistConfig,
+    
+    /// Least severe entry the in-app log keeps per category, e.g.
+    /// `network = "warn"`; categories not listed keep everything. The
+    /// events timeline and the webhook still get every entry.
+    #[serde(default)]
+    pub log_levels: HashMap<String, LogLevel>,
+    
+    /// In-memory cache for repeated GET requests
+    #[serde(default)]
+    pub response_cache: ResponseCacheConfig,
+    
+    /// Largest upstream response read into memory before the request is dropped
+    #[serde(default = "default_max_response_bytes")]
+    pub max_response_bytes: u64,
+    
+    /// Longest request line (`GET http://... HTTP/1.1`) a client may send
+    #[serde(default = "default_max_request_line_bytes")]
+    pub max_request_line_bytes: usize,
+    
+    /// Largest header block after the request line a client may send
+    #[serde(default = "default_max_request_header_bytes")]
+    pub max_request_header_bytes: usize,
+    
+    /// Let Tor exits connect to IPv6 addresses. Exits may always reach a
+    /// name over IPv6; IPv6 connections made from this machine stay blocked.
+    #[serde(default)]
+    pub allow_ipv6_over_tor: bool,
+    
+    /// Proxy used to reach the Tor network (`socks5://host:port` or `http://host:port`)
+    #[serde(default)]
+    pub upstream_proxy: Option<String>,
+    
+    /// Send all traffic through this SOCKS5 proxy (e.g. a system Tor at
+    /// `socks5://127.0.0.1:9050`) instead of the built-in Tor client
+    #[serde(default)]
+    pub socks_transport: Option<String>,
+    
+    /// Distinct hosts that must fail in a row before the Tor circuit is rebuilt (0 disables)
+    #[serde(default = "default_circuit_failure_threshold")]
+    pub circuit_failure_threshold: usize,
+    
+    /// Seconds a Tor circuit takes new streams before a fresh one is built,
+    /// like Tor's `MaxCircuitDirtiness` (0 disables)
+    #[serde(default = "default_max_circuit_age_secs")]
+    pub max_circuit_age_secs: u64,
+    
+    /// Reuse Tor streams and client connections across HTTP requests
+    #[serde(default = "default_true")]
+    pub http_keep_alive: bool,
+    
+    /// Hold back simultaneous CONNECTs to the same `host:port` until the
+    /// first has its stream, so they share its circuit instead of each
+    /// building one on page load
+    #[serde(default)]
+    pub coalesce_connects: bool,
+    
+    /// Bandwidth cap per LAN client in kilobits per second (0 = unlimited)
+    #[serde(default)]
+    pub rate_limit_kbps: u64,
+    
+    /// Give up connecting if Tor hasn't bootstrapped within this many seconds
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    
+    /// Requests routed at once across all LAN clients (0 = unlimited); when
+    /// all are busy, waiting clients take turns
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    
+    /// Engage the kill switch and close circuits on sleep/lock, reconnecting on resume
+    #[serde(default)]
+    pub disconnect_on_sleep: bool,
+    
+    /// Disconnect (kill switch engaged, system proxy restored) after this
+    /// many seconds without a proxied request; 0 never does
+    #[serde(default)]
+    pub idle_disconnect_secs: u64,
+    
+    /// Wi-Fi networks (by SSID) where protection is relaxed per
+    /// `trusted_network_mode`; it is engaged again on every other network.
+    /// An SSID only counts behind a gateway whose MAC address is listed in
+    /// `trusted_gateways`, as any hotspot can take a trusted network's name.
+    #[serde(default)]
+    pub trusted_ssids: Vec<String>,
+    
+    /// Networks trusted by their gateway's IP or (more reliably) MAC address
+    #[serde(default)]
+    pub trusted_gateways: Vec<String>,
+    
+    /// What happens on a trusted network
+    #[serde(default)]
+    pub trusted_network_mode: TrustedNetworkMode,
+    
+    /// How to pick an exit country when it is left on "Auto"
+    #[serde(default)]
+    pub exit_preference: ExitPreference,
+    
+    /// The user's country (e.g. "de"); detected from the OS locale if unset
+    #[serde(default)]
+    pub home_country: Option<String>,
+    
+    /// MaxMind country database (e.g. GeoLite2-Country.mmdb) used to show
+    /// which country the exit relay is in; lookups never leave the machine
+    #[serde(default)]
+    pub geoip_db_path: Option<String>,
+    
+    /// Where arti keeps its state and directory cache; `tor/` next to this
+    /// config file if unset, so separate instances never share one
+    #[serde(default)]
+    pub tor_data_dir: Option<PathBuf>,
+    
+    /// Look up exit countries the local database can't place with an online
+    /// service, queried through Tor. Off by default: the service learns
+    /// which exits this client uses.
+    #[serde(default)]
+    pub geoip_online_fallback: bool,
+    
+    /// Follow a site's `Onion-Location` header and load it from its onion
+    /// service, which never leaves the Tor network
+    #[serde(default)]
+    pub prefer_onion: bool,
+    
+    /// Local services to publish as onion services through the built-in Tor client
+    #[serde(default)]
+    pub onion_services: Vec<OnionServicePort>,
+    
+    /// Fail the connection rather than use exits elsewhere when no exit is
+    /// available in the exit country the user picked
+    #[serde(default)]
+    pub strict_exit: bool,
+    
+    /// Raise a security event when the exit relay turns out to be in another
+    /// country than the one the user picked
+    #[serde(default = "default_true")]
+    pub warn_exit_mismatch: bool,
+    
+    /// Exit relays never to carry traffic, by fingerprint (40 hex digits, as
+    /// in torrc's ExcludeExitNodes). Unlike torrc this is checked once a
+    /// stream is open, so an excluded exit still learns the destination of
+    /// the stream that is then dropped.
+    #[serde(default)]
+    pub exclude_exit_nodes: Vec<String>,
+    
+    /// torrc's ExitNodes. Not supported: arti can't pin exits, so a
+    /// non-empty list fails at connect with a configuration error.
+    #[serde(default)]
+    pub allow_exit_nodes: Vec<String>,
+    
+    /// Strip cookies from requests a page makes to other sites (and the
+    /// cookies those sites set), so embedded trackers can't follow the user
+    #[serde(default)]
+    pub first_party_isolation: bool,
+    
+    /// Response headers removed before responses reach the browser, e.g.
+    /// `Server-Timing`; an empty list turns scrubbing off
+    #[serde(default = "default_scrub_response_headers")]
+    pub scrub_response_headers: Vec<String>,
+    
+    /// User-Agents the session fingerprint picks from, replacing the built-in set
+    #[serde(default = "default_user_agents")]
+    pub user_agents: Vec<String>,
+    
+    /// Reroll the session fingerprint this often, in seconds (0 = only on a
+    /// new session or identity); a pinned fingerprint is kept
+    #[serde(default)]
+    pub fingerprint_rotation_secs: u64,
+    
+    /// `Allowlist` blocks every host outside `allowed_domains`
+    #[serde(default)]
+    pub mode: BrowsingMode,
+    
+    /// Domains (and their subdomains) reachable in `BrowsingMode::Allowlist`
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    
+    /// Method/path rules checked before a request is routed; the first match decides
+    #[serde(default)]
+    pub request_filters: Vec<RequestFilter>,
+    
+    /// URL that security errors (threats, kill switch blocks) are POSTed to
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    
+    /// Body posted to `webhook_url`
+    #[serde(default)]
+    pub webhook_format: WebhookFormat,
+    
     #[serde(skip)]
     config_path: PathBuf,
 }
 
+/// Network used to carry proxied HTTP requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoutingBackend {
+    /// Route through the Tor network via arti
+    #[default]
+    Tor,
+    
+    /// EXPERIMENTAL: the suite's own onion-routed node network.
+    ///
+    /// Requests are wrapped in layered encryption for `num_hops` nodes from
+    /// the node registry, but there is no relay protocol or deployed relay
+    /// nodes yet, so every request fails closed with an error instead of
+    /// being sent. HTTPS (CONNECT) tunnels always use Tor.
+    Custom,
+}
+
+/// Body of the `webhook_url` notifications
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// `{"event", "timestamp", "details"}`
+    #[default]
+    Json,
+    
+    /// A single message line as both `text` and `content`, which Slack and
+    /// Discord incoming webhooks accept as is
+    Chat,
+}
+
+/// Whether destinations are allowed unless blocked, or blocked unless allowed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BrowsingMode {
+    /// Everything is reachable except what the protections and blocklists stop
+    #[default]
+    Blocklist,
+    
+    /// Only `allowed_domains` are reachable; every other host is blocked
+    Allowlist,
+}
+
+/// Port the web API listens on unless `api_port` says otherwise
+pub const DEFAULT_API_PORT: u16 = 3030;
+
+/// Port of the default `proxy_addr`, also what `proxy_addr`s without a
+/// valid port are reported with
+pub const DEFAULT_PROXY_PORT: u16 = 8888;
+
+fn default_api_port() -> u16 {
+    DEFAULT_API_PORT
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_response_bytes() -> u64 {
+    tor_network::DEFAULT_MAX_RESPONSE_BYTES as u64
+}
+
+fn default_max_request_line_bytes() -> usize {
+    8 * 1024
+}
+
+fn default_max_request_header_bytes() -> usize {
+    32 * 1024
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    120
+}
+
+fn default_max_concurrent_requests() -> usize {
+    64
+}
+
+fn default_user_agents() -> Vec<String> {
+    crate::fingerprint::default_user_agents()
+}
+
+fn default_stun_servers() -> Vec<String> {
+    crate::webrtc_protection::default_stun_servers()
+}
+
+fn default_doh_endpoints() -> Vec<String> {
+    crate::app_doh::default_doh_endpoints()
+}
+
+fn default_scrub_response_headers() -> Vec<String> {
+    crate::header_scrubber::default_scrubbed_headers()
+}
+
+fn default_circuit_failure_threshold() -> usize {
+    crate::tor_network::DEFAULT_FAILURE_THRESHOLD
+}
+
+fn default_tor_path_length() -> u8 {
+    crate::tor_network::TOR_PATH_LENGTH
+}
+
+fn default_max_circuit_age_secs() -> u64 {
+    crate::tor_network::DEFAULT_MAX_CIRCUIT_AGE.as_secs()
+}
+
+fn default_blocklist_refresh_hours() -> u64 {
+    24
+}
+
+fn default_reconnect_hold_max() -> usize {
+    64
+}
+
+/// How protection is relaxed on a trusted network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrustedNetworkMode {
+    /// Disconnect from Tor; traffic goes out directly
+    #[default]
+    Off,
+    
+    /// Stay on Tor, but with the kill switch off so nothing is blocked if
+    /// Tor drops
+    Relaxed,
+}
+
+/// Severity threshold of an in-app log category, least severe first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    #[default]
+    Debug,
+    Info,
+    Warn,
+    Error,
+    /// Drop every entry of the category
+    Off,
+}
+
+impl LogLevel {
+    /// Level of a log entry's `level` string; unknown ones count as info
+    pub fn of_entry(level: &str) -> Self {
+        match level {
+            "debug" => LogLevel::Debug,
+            "warn" => LogLevel::Warn,
+            "error" => LogLevel::Error,
+            _ => LogLevel::Info,
+        }
+    }
+}
+
+/// How the router handles requests for private and local hosts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PrivateHostPolicy {
+    /// Connect to them directly from this machine, never through Tor.
+    /// This machine's own services (loopback) only answer clients on this
+    /// machine, and its control API never answers through the proxy.
+    Bypass,
+    
+    /// Refuse them, so nothing on the proxy reaches the local network
+    #[default]
+    Block,
+}
+
+/// Where hostnames of proxied connections are resolved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DnsResolution {
+    /// The Tor exit relay resolves the hostname (arti's default). The lookup
+    /// happens inside the circuit, so no resolver learns anything beyond what
+    /// the exit already sees.
+    #[default]
+    ExitDns,
+    
+    /// Resolve locally via DNS-over-HTTPS (the DoH query itself goes through
+    /// Tor) and connect to the resulting IP. Cached answers make repeat
+    /// connections faster, but the DoH provider sees every hostname visited
+    /// and lookups can be linked across circuits.
+    LocalDoH,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockchainConfig {
     /// Ethereum RPC endpoint
@@ -41,21 +540,134 @@ pub struct BlockchainConfig {
     pub wallet_address: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// Serve repeated GETs from memory instead of fetching them through Tor again
+    pub enabled: bool,
+    
+    /// Total size of cached bodies before least recently used ones are evicted
+    pub max_bytes: u64,
+    
+    /// Longest time a response is cached (an origin `max-age` can shorten it)
+    pub ttl_secs: u64,
+}
+
+/// Security detectors that can be switched off in `Config::security_detectors`
+pub const SECURITY_DETECTORS: [&str; 7] = [
+    "credential_leak",
+    "request_replay",
+    "tracking_pattern",
+    "malicious_domain",
+    "unencrypted",
+    "login_form",
+    "tls_downgrade",
+];
+
+/// Protections that can be switched on and off individually
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtectionSettings {
+    pub webrtc_protection: bool,
+    pub ipv6_protection: bool,
+    pub canvas_protection: bool,
+    pub tracker_blocking: bool,
+    pub security_detection: bool,
+    /// Every entry of `SECURITY_DETECTORS` and whether it is on
+    pub security_detectors: HashMap<String, bool>,
+    pub mode: BrowsingMode,
+    pub allowed_domains: Vec<String>,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: 16 * 1024 * 1024,
+            ttl_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ipv6AllowlistConfig {
+    /// Allow the loopback address (::1)
+    pub loopback: bool,
+    
+    /// Allow link-local addresses (fe80::/10)
+    pub link_local: bool,
+    
+    /// Allow unique local addresses (fc00::/7)
+    pub unique_local: bool,
+}
+
+impl Default for Ipv6AllowlistConfig {
+    fn default() -> Self {
+        Self {
+            loopback: true,
+            link_local: true,
+            unique_local: false,
+        }
+    }
+}
+
 impl Config {
     pub fn load_or_create() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_dir = Self::config_dir()?;
-        let config_path = config_dir.join("config.toml");
-        
+        let config_path = Self::config_dir()?.join("config.toml");
+        Self::load_or_create_at(&config_path)
+    }
+    
+    /// Write the config back to the file it was loaded from
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let toml_str = toml::to_string_pretty(self)?;
+        fs::write(&self.config_path, toml_str)?;
+        Ok(())
+    }
+    
+    pub fn protections(&self) -> ProtectionSettings {
+        ProtectionSettings {
+            webrtc_protection: self.webrtc_protection,
+            ipv6_protection: self.ipv6_protection,
+            canvas_protection: self.canvas_protection,
+            tracker_blocking: self.tracker_blocking,
+            security_detection: self.security_detection,
+            security_detectors: SECURITY_DETECTORS
+                .iter()
+                .map(|name| (name.to_string(), self.security_detector_enabled(name)))
+                .collect(),
+            mode: self.mode,
+            allowed_domains: self.allowed_domains.clone(),
+        }
+    }
+    
+    /// Whether a detector in `SECURITY_DETECTORS` is switched on
+    pub fn security_detector_enabled(&self, name: &str) -> bool {
+        self.security_detectors.get(name).copied().unwrap_or(true)
+    }
+    
+    pub fn set_protections(&mut self, settings: ProtectionSettings) {
+        self.webrtc_protection = settings.webrtc_protection;
+        self.ipv6_protection = settings.ipv6_protection;
+        self.canvas_protection = settings.canvas_protection;
+        self.tracker_blocking = settings.tracker_blocking;
+        self.security_detection = settings.security_detection;
+        self.security_detectors = settings.security_detectors;
+        self.mode = settings.mode;
+        self.allowed_domains = settings.allowed_domains;
+    }
+    
+    /// Load the config file at `config_path`, writing defaults there if it doesn't exist
+    pub fn load_or_create_at(config_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
         if config_path.exists() {
-            let content = fs::read_to_string(&config_path)?;
+            let content = fs::read_to_string(config_path)?;
             let mut config: Config = toml::from_str(&content)?;
-            config.config_path = config_path;
+            config.config_path = config_path.to_path_buf();
             Ok(config)
         } else {
-            fs::create_dir_all(&config_dir)?;
-            let config = Self::default_with_path(config_path.clone());
+            if let Some(config_dir) = config_path.parent() {
+                fs::create_dir_all(config_dir)?;
+            }
+            let config = Self::default_with_path(config_path.to_path_buf());
             let toml_str = toml::to_string_pretty(&config)?;
-            fs::write(&config_path, toml_str)?;
+            fs::write(config_path, toml_str)?;
             Ok(config)
         }
     }
@@ -76,31 +688,198 @@ impl Config {
         &self.config_path
     }
     
+    /// Whether any network is trusted, i.e. the trusted networks watcher is needed
+    pub fn has_trusted_networks(&self) -> bool {
+        !self.trusted_ssids.is_empty() || !self.trusted_gateways.is_empty()
+    }
+    
+    /// The `tracker_lists` a refresh loads
+    pub fn enabled_tracker_lists(&self) -> Vec<String> {
+        self.tracker_lists
+            .iter()
+            .filter(|url| !self.disabled_tracker_lists.contains(url))
+            .cloned()
+            .collect()
+    }
+    
+    /// Subscribe to another tracker list; false if it is already subscribed
+    pub fn add_tracker_list(&mut self, url: &str) -> bool {
+        if self.tracker_lists.iter().any(|list| list == url) {
+            return false;
+        }
+        self.tracker_lists.push(url.to_string());
+        true
+    }
+    
+    /// Unsubscribe from a tracker list; false if it wasn't subscribed
+    pub fn remove_tracker_list(&mut self, url: &str) -> bool {
+        let before = self.tracker_lists.len();
+        self.tracker_lists.retain(|list| list != url);
+        self.disabled_tracker_lists.retain(|list| list != url);
+        self.tracker_list_checksums.retain(|checksum| checksum.url != url);
+        self.tracker_lists.len() != before
+    }
+    
+    /// Switch a subscribed tracker list on or off; false if it isn't subscribed
+    pub fn set_tracker_list_enabled(&mut self, url: &str, enabled: bool) -> bool {
+        if !self.tracker_lists.iter().any(|list| list == url) {
+            return false;
+        }
+        self.disabled_tracker_lists.retain(|list| list != url);
+        if !enabled {
+            self.disabled_tracker_lists.push(url.to_string());
+        }
+        true
+    }
+    
+    /// `node_db_path` with a leading `~/` expanded to the home directory
+    pub fn resolved_node_db_path(&self) -> PathBuf {
+        match (self.node_db_path.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => PathBuf::from(&self.node_db_path),
+        }
+    }
+    
+    /// `geoip_db_path` with a leading `~/` expanded to the home directory
+    pub fn resolved_geoip_db_path(&self) -> Option<PathBuf> {
+        let path = self.geoip_db_path.as_deref()?;
+        match (path.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => Some(home.join(rest)),
+            _ => Some(PathBuf::from(path)),
+        }
+    }
+    
+    /// Where `blocklist_cache` keeps the domains, if it's on
+    pub fn blocklist_cache_path(&self) -> Option<PathBuf> {
+        let dir = match self.config_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => Self::config_dir().unwrap_or_default(),
+        };
+        self.blocklist_cache.then(|| dir.join("blocklist.cache"))
+    }
+    
+    /// `tor_data_dir` with a leading `~/` expanded, defaulting to `tor/`
+    /// in the config file's directory
+    pub fn resolved_tor_data_dir(&self) -> PathBuf {
+        match &self.tor_data_dir {
+            Some(path) => match (path.strip_prefix("~"), dirs::home_dir()) {
+                (Ok(rest), Some(home)) => home.join(rest),
+                _ => path.clone(),
+            },
+            None => match self.config_path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.join("tor"),
+                _ => Self::config_dir().unwrap_or_default().join("tor"),
+            },
+        }
+    }
+    
     pub fn proxy_addr(&self) -> &str {
         &self.proxy_addr
     }
+    
+    /// Port part of `proxy_addr`
+    pub fn proxy_port(&self) -> Option<u16> {
+        self.proxy_addr.rsplit_once(':').and_then(|(_, port)| port.parse().ok())
+    }
+    
+    /// Replace the port in `proxy_addr`, keeping the bind address
+    pub fn set_proxy_port(&mut self, port: u16) {
+        let host = self
+            .proxy_addr
+            .rsplit_once(':')
+            .map(|(host, _)| host)
+            .unwrap_or("0.0.0.0");
+        self.proxy_addr = format!("{}:{}", host, port);
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            proxy_addr: "0.0.0.0:8888".to_string(),
+            proxy_addr: format!("0.0.0.0:{}", DEFAULT_PROXY_PORT),
+            api_port: default_api_port(),
+            api_tls: false,
             num_hops: 3,
+            tor_path_length: default_tor_path_length(),
+            routing_backend: RoutingBackend::default(),
             dns_servers: vec![
                 "1.1.1.1:853".to_string(),
                 "8.8.8.8:853".to_string(),
             ],
+            dns_resolution: DnsResolution::default(),
+            block_app_doh: false,
+            doh_endpoints: default_doh_endpoints(),
             fingerprint_protection: true,
+            webrtc_policy_headers: true,
             tracker_lists: vec![
                 "https://easylist.to/easylist/easylist.txt".to_string(),
                 "https://easylist.to/easylist/easyprivacy.txt".to_string(),
             ],
+            disabled_tracker_lists: Vec::new(),
+            tracker_list_checksums: Vec::new(),
+            blocklist_allowlist: Vec::new(),
+            blocklist_refresh_hours: default_blocklist_refresh_hours(),
+            blocklist_cache: false,
             blockchain: BlockchainConfig {
                 eth_rpc: "https://mainnet.infura.io/v3/YOUR_PROJECT_ID".to_string(),
                 payment_contract: "0x0000000000000000000000000000000000000000".to_string(),
                 wallet_address: None,
             },
             node_db_path: "~/.privacy_suite/nodes.db".to_string(),
+            block_direct_ip: false,
+            learning_mode: false,
+            killswitch_block_lan: true,
+            private_hosts: PrivateHostPolicy::default(),
+            reconnect_hold_secs: 0,
+            reconnect_hold_max: default_reconnect_hold_max(),
+            webrtc_protection: true,
+            stun_servers: default_stun_servers(),
+            ipv6_protection: true,
+            canvas_protection: true,
+            tracker_blocking: true,
+            security_detection: true,
+            security_detectors: HashMap::new(),
+            ipv6_allowlist: Ipv6AllowlistConfig::default(),
+            log_levels: HashMap::new(),
+            response_cache: ResponseCacheConfig::default(),
+            max_response_bytes: default_max_response_bytes(),
+            max_request_line_bytes: default_max_request_line_bytes(),
+            max_request_header_bytes: default_max_request_header_bytes(),
+            allow_ipv6_over_tor: false,
+            upstream_proxy: None,
+            socks_transport: None,
+            circuit_failure_threshold: default_circuit_failure_threshold(),
+            max_circuit_age_secs: default_max_circuit_age_secs(),
+            http_keep_alive: true,
+            coalesce_connects: false,
+            rate_limit_kbps: 0,
+            connect_timeout_secs: default_connect_timeout_secs(),
+            max_concurrent_requests: default_max_concurrent_requests(),
+            disconnect_on_sleep: false,
+            idle_disconnect_secs: 0,
+            trusted_ssids: Vec::new(),
+            trusted_gateways: Vec::new(),
+            trusted_network_mode: TrustedNetworkMode::default(),
+            exit_preference: ExitPreference::default(),
+            home_country: None,
+            geoip_db_path: None,
+            tor_data_dir: None,
+            geoip_online_fallback: false,
+            prefer_onion: false,
+            onion_services: Vec::new(),
+            strict_exit: false,
+            exclude_exit_nodes: Vec::new(),
+            allow_exit_nodes: Vec::new(),
+            warn_exit_mismatch: true,
+            first_party_isolation: false,
+            scrub_response_headers: default_scrub_response_headers(),
+            user_agents: default_user_agents(),
+            fingerprint_rotation_secs: 0,
+            mode: BrowsingMode::default(),
+            allowed_domains: Vec::new(),
+            request_filters: Vec::new(),
+            webhook_url: None,
+            webhook_format: WebhookFormat::default(),
             config_path: PathBuf::new(),
         }
     }
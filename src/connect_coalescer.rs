@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Destinations with a stream being opened, and a receiver that wakes when
+/// it is done (or abandoned)
+type Pending = Arc<Mutex<HashMap<(String, u16), watch::Receiver<()>>>>;
+
+/// Holds back simultaneous CONNECTs to one `host:port` until the first of
+/// them has its stream (`Config::coalesce_connects`).
+///
+/// arti builds a circuit for a stream when none with matching isolation is
+/// open yet, so a page opening a dozen tunnels to one CDN at once can set
+/// off a dozen builds. Waiting for the first lets the others attach to the
+/// circuit it got, under the same isolation settings. Only tunnels to the
+/// same destination ever wait on each other.
+#[derive(Clone, Default)]
+pub struct ConnectCoalescer {
+    pending: Pending,
+}
+
+/// The first open to a destination; dropping it releases the waiting ones
+struct Opening {
+    pending: Pending,
+    key: (String, u16),
+    _done: watch::Sender<()>,
+}
+
+impl Drop for Opening {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.key);
+    }
+}
+
+impl ConnectCoalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `open` for `host:port`, first waiting for an open to the same
+    /// destination that is already under way. A failed first attempt
+    /// releases the others too; each then tries on its own.
+    pub async fn open<T, F, Fut>(&self, host: &str, port: u16, open: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let key = (host.to_ascii_lowercase(), port);
+        let waiting = {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            match pending.get(&key) {
+                Some(done) => Err(done.clone()),
+                None => {
+                    let (done, receiver) = watch::channel(());
+                    pending.insert(key.clone(), receiver);
+                    Ok(Opening { pending: self.pending.clone(), key, _done: done })
+                }
+            }
+        };
+
+        match waiting {
+            Ok(_opening) => open().await,
+            Err(mut done) => {
+                // Nothing is ever sent: the first open finishing drops the sender
+                let _ = done.changed().await;
+                open().await
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use tracing::{info, warn};
+use std::net::IpAddr;
+
+/// Direct IP Request Blocking
+/// 
+/// Requests to raw IP literals (e.g. `https://1.1.1.1`) skip DNS entirely and are
+/// a common way for scripts to reach infrastructure that isn't covered by domain
+/// blocklists. This is a strict, opt-in policy independent of WebRTC protection.
+#[derive(Clone)]
+pub struct DirectIpProtection {
+    enabled: bool,
+}
+
+impl DirectIpProtection {
+    pub fn new(enabled: bool) -> Self {
+        if enabled {
+            info!("🛡️ Direct IP request blocking enabled");
+        }
+        Self { enabled }
+    }
+
+    /// Check if a host is an IP literal that should be blocked
+    pub fn should_block(&self, host: &str) -> bool {
+        if self.would_block(host) {
+            warn!("🚫 Blocked direct IP connection attempt: {}", host);
+            return true;
+        }
+
+        false
+    }
+
+    /// Like `should_block`, without logging a block
+    pub fn would_block(&self, host: &str) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let unbracketed = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+        unbracketed.parse::<IpAddr>().is_ok()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webrtc_protection::WebRtcProtection;
+
+    #[test]
+    fn test_blocks_direct_ips_when_enabled() {
+        let protection = DirectIpProtection::new(true);
+        assert!(protection.should_block("192.168.1.1"));
+        assert!(protection.should_block("1.1.1.1"));
+        assert!(protection.should_block("[2001:db8::1]"));
+    }
+
+    #[test]
+    fn test_allows_direct_ips_when_disabled() {
+        let protection = DirectIpProtection::new(false);
+        assert!(!protection.should_block("1.1.1.1"));
+        assert!(!protection.should_block("[2001:db8::1]"));
+    }
+
+    #[test]
+    fn test_allows_domains() {
+        let protection = DirectIpProtection::new(true);
+        assert!(!protection.should_block("example.com"));
+        assert!(!protection.should_block("one.one.one.one"));
+    }
+
+    #[test]
+    fn test_independent_of_webrtc_protection() {
+        for webrtc_enabled in [true, false] {
+            for direct_ip_enabled in [true, false] {
+                let webrtc = WebRtcProtection::new(webrtc_enabled);
+                let direct_ip = DirectIpProtection::new(direct_ip_enabled);
+                let blocked = |host: &str, port: u16| {
+                    webrtc.should_block_request(host, port) || direct_ip.should_block(host)
+                };
+
+                assert_eq!(blocked("stun.l.google.com", 3478), webrtc_enabled);
+                assert_eq!(blocked("1.1.1.1", 443), direct_ip_enabled);
+                assert!(!blocked("example.com", 443));
+            }
+        }
+    }
+}
@@ -1,26 +1,225 @@
-use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
 use hickory_resolver::TokioAsyncResolver;
-use tracing::info;
+use http_body_util::BodyExt;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use crate::config::DnsResolution;
+use crate::transport::Transport;
 
+/// DoH JSON answers are tiny; anything bigger is not a real answer
+const DOH_MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// Upper bound on how long a DoH answer is cached, regardless of its TTL
+const MAX_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Server used when `Config::dns_servers` has no usable entry
+const FALLBACK_DNS_SERVER: &str = "1.1.1.1:853";
+
+/// How long a server gets to answer before the next one is tried
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// TLS names of well-known public DoT servers, for entries given without one
+const KNOWN_TLS_NAMES: [(&str, &str); 6] = [
+    ("1.1.1.1", "cloudflare-dns.com"),
+    ("1.0.0.1", "cloudflare-dns.com"),
+    ("8.8.8.8", "dns.google"),
+    ("8.8.4.4", "dns.google"),
+    ("9.9.9.9", "dns.quad9.net"),
+    ("149.112.112.112", "dns.quad9.net"),
+];
+
+/// DoH JSON endpoints (port and path) of providers that don't serve it at
+/// `https://<tls-name>/dns-query`
+const KNOWN_DOH_ENDPOINTS: [(&str, u16, &str); 2] = [
+    ("dns.google", 443, "/resolve"),
+    ("dns.quad9.net", 5053, "/dns-query"),
+];
+
+/// One entry of `Config::dns_servers`.
+///
+/// Written as `ip:port`, optionally followed by `#tls-name`. Port 853 is
+/// DNS-over-TLS and needs a TLS name, which is known for the big public
+/// resolvers; any other port is plain DNS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsServer {
+    pub addr: SocketAddr,
+    pub tls_name: Option<String>,
+}
+
+impl DnsServer {
+    pub fn parse(entry: &str) -> Result<Self, String> {
+        let (addr, tls_name) = match entry.trim().split_once('#') {
+            Some((addr, name)) => (addr, Some(name.to_string())),
+            None => (entry.trim(), None),
+        };
+        let addr: SocketAddr = addr.parse().map_err(|e| format!("Invalid DNS server {:?}: {}", entry, e))?;
+        
+        if addr.port() != 853 {
+            return Ok(Self { addr, tls_name: None });
+        }
+        let tls_name = tls_name.or_else(|| {
+            let ip = addr.ip().to_string();
+            KNOWN_TLS_NAMES.iter().find(|(known, _)| *known == ip).map(|(_, name)| name.to_string())
+        });
+        match tls_name {
+            Some(name) => Ok(Self { addr, tls_name: Some(name) }),
+            None => Err(format!("DNS-over-TLS server {} needs a TLS name, e.g. {}#dns.example", addr, addr)),
+        }
+    }
+    
+    /// Host, port and path `resolve_over_tor` sends this provider's DoH JSON
+    /// queries to. Plain DNS servers have none.
+    fn doh_endpoint(&self) -> Option<(&str, u16, &'static str)> {
+        let host = self.tls_name.as_deref()?;
+        let (port, path) = KNOWN_DOH_ENDPOINTS
+            .iter()
+            .find(|(known, _, _)| *known == host)
+            .map_or((443, "/dns-query"), |(_, port, path)| (*port, *path));
+        Some((host, port, path))
+    }
+    
+    fn name_server(&self) -> NameServerConfig {
+        let protocol = if self.tls_name.is_some() { Protocol::Tls } else { Protocol::Udp };
+        let mut server = NameServerConfig::new(self.addr, protocol);
+        server.tls_dns_name = self.tls_name.clone();
+        server
+    }
+}
+
+impl std::fmt::Display for DnsServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.tls_name {
+            Some(name) => write!(f, "{} ({})", self.addr, name),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+/// Configured servers in order of preference, and which one is in use
+#[derive(Debug)]
+pub struct ServerPool {
+    servers: Vec<DnsServer>,
+    active: AtomicUsize,
+}
+
+impl ServerPool {
+    /// `servers` must not be empty
+    pub fn new(servers: Vec<DnsServer>) -> Self {
+        assert!(!servers.is_empty(), "a DNS server pool needs at least one server");
+        Self { servers, active: AtomicUsize::new(0) }
+    }
+    
+    pub fn active(&self) -> &DnsServer {
+        &self.servers[self.active.load(Ordering::Relaxed)]
+    }
+    
+    fn switch_to(&self, index: usize) {
+        let previous = self.active.swap(index, Ordering::Relaxed);
+        if previous != index {
+            warn!("⚠️ DNS server {} unavailable - switched to {}", self.servers[previous], self.servers[index]);
+        }
+    }
+    
+    /// Run `query` against the active server, then the others in order while
+    /// `retry` says the error is the server's fault. The server that answers
+    /// becomes the active one.
+    pub async fn failover<T, E, F, Fut>(&self, mut query: F, retry: impl Fn(&E) -> bool) -> Result<T, E>
+    where
+        F: FnMut(usize, &DnsServer) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let start = self.active.load(Ordering::Relaxed);
+        let mut last_error = None;
+        
+        for offset in 0..self.servers.len() {
+            let index = (start + offset) % self.servers.len();
+            match query(index, &self.servers[index]).await {
+                Ok(answer) => {
+                    self.switch_to(index);
+                    return Ok(answer);
+                }
+                Err(e) if retry(&e) => {
+                    warn!("DNS server {} failed, trying the next one", self.servers[index]);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.expect("server pool is never empty"))
+    }
+}
+
+fn resolver_opts() -> ResolverOpts {
+    let mut opts = ResolverOpts::default();
+    // Fail fast so the next server gets a chance
+    opts.timeout = QUERY_TIMEOUT;
+    opts.attempts = 1;
+    opts
+}
+
+#[derive(Clone)]
 pub struct DnsResolver {
-    resolver: TokioAsyncResolver,
+    servers: Arc<ServerPool>,
+    /// One resolver per entry of `servers`, so failover is under our control
+    resolvers: Arc<Vec<TokioAsyncResolver>>,
+    doh_cache: Arc<Mutex<HashMap<String, (Vec<IpAddr>, Instant)>>>,
 }
 
 impl DnsResolver {
-    pub async fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        // Use DNS-over-TLS or DNS-over-HTTPS
-        let resolver = TokioAsyncResolver::tokio(
-            ResolverConfig::cloudflare(),
-            ResolverOpts::default(),
-        );
+    /// Build a resolver over `servers` (entries of `Config::dns_servers`).
+    ///
+    /// Servers aren't probed ahead of time: that would reach them directly,
+    /// off Tor. A server is found to be down when a lookup to it fails, and
+    /// the next one takes over.
+    pub async fn new(servers: &[String]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut parsed: Vec<DnsServer> = servers
+            .iter()
+            .filter_map(|entry| DnsServer::parse(entry).map_err(|e| warn!("Ignoring DNS server: {}", e)).ok())
+            .collect();
+        if parsed.is_empty() {
+            warn!("⚠️ No usable DNS servers configured, using {}", FALLBACK_DNS_SERVER);
+            parsed.push(DnsServer::parse(FALLBACK_DNS_SERVER)?);
+        }
         
-        Ok(Self { resolver })
+        let resolvers = parsed
+            .iter()
+            .map(|server| {
+                let mut config = ResolverConfig::new();
+                config.add_name_server(server.name_server());
+                TokioAsyncResolver::tokio(config, resolver_opts())
+            })
+            .collect();
+        
+        Ok(Self {
+            servers: Arc::new(ServerPool::new(parsed)),
+            resolvers: Arc::new(resolvers),
+            doh_cache: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+    
+    /// The DNS server lookups currently go to
+    pub fn active_server(&self) -> &DnsServer {
+        self.servers.active()
     }
     
     pub async fn resolve(&self, domain: &str) -> Result<Vec<std::net::IpAddr>, Box<dyn std::error::Error>> {
         info!("Resolving: {}", domain);
         
-        let response = self.resolver.lookup_ip(domain).await?;
+        let response = self
+            .servers
+            .failover(
+                |index, _| self.resolvers[index].lookup_ip(domain),
+                // A missing record is a real answer, not a broken server
+                |e| !matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }),
+            )
+            .await?;
         let ips: Vec<_> = response.iter().collect();
         
         info!("Resolved {} to {} addresses", domain, ips.len());
@@ -28,8 +227,282 @@ impl DnsResolver {
         Ok(ips)
     }
     
+    /// Resolve with a DNS-over-HTTPS query that is itself sent through Tor,
+    /// so the DoH provider sees the hostname but never this machine's IP.
+    ///
+    /// The query goes to the DoH service of the active DNS-over-TLS server
+    /// in `Config::dns_servers`, failing over to the next one like `resolve`
+    /// does; plain DNS entries are skipped.
+    pub async fn resolve_over_tor(
+        &self,
+        transport: &dyn Transport,
+        domain: &str,
+    ) -> Result<Vec<IpAddr>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(ips) = self.cached(domain) {
+            return Ok(ips);
+        }
+        
+        info!("Resolving {} via DoH over Tor", domain);
+        
+        // Any failure is the server's (or its circuit's); an empty answer is not a failure
+        let (ips, ttl) = self
+            .servers
+            .failover(
+                |_, server| {
+                    let server = server.clone();
+                    async move { query_doh(transport, &server, domain).await }
+                },
+                |_| true,
+            )
+            .await?;
+        if ips.is_empty() {
+            return Err(format!("No addresses found for {}", domain).into());
+        }
+        
+        info!("Resolved {} to {} addresses via DoH", domain, ips.len());
+        
+        self.doh_cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(domain.to_string(), (ips.clone(), Instant::now() + ttl.min(MAX_CACHE_TTL)));
+        
+        Ok(ips)
+    }
+    
+    fn cached(&self, domain: &str) -> Option<Vec<IpAddr>> {
+        let cache = self.doh_cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache
+            .get(domain)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(ips, _)| ips.clone())
+    }
+    
+    /// Drop all cached lookups
+    pub fn clear_cache(&self) {
+        for resolver in self.resolvers.iter() {
+            resolver.clear_cache();
+        }
+        self.doh_cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+    
     /// Resolve through multiple paths to prevent DNS manipulation
     pub async fn multi_path_resolve(&self, domain: &str) -> Result<Vec<std::net::IpAddr>, Box<dyn std::error::Error>> {
         self.resolve(domain).await
     }
 }
+
+/// Ask `server`'s DoH service over `transport` for the A records of `domain`
+async fn query_doh(
+    transport: &dyn Transport,
+    server: &DnsServer,
+    domain: &str,
+) -> Result<(Vec<IpAddr>, Duration), Box<dyn std::error::Error + Send + Sync>> {
+    let (host, port, path) = server
+        .doh_endpoint()
+        .ok_or_else(|| format!("{} is plain DNS, which has no DoH service", server))?;
+    let stream = transport.connect_stream(host, port).await?.stream;
+    
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let tls_config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(tls_config));
+    let server_name = rustls::pki_types::ServerName::try_from(host)?.to_owned();
+    let tls_stream = connector.connect(server_name, stream).await?;
+    
+    let request = format!(
+        "GET {}?name={}&type=A HTTP/1.1\r\nHost: {}\r\nAccept: application/dns-json\r\nConnection: close\r\n\r\n",
+        path, domain, host
+    );
+    let response = crate::tor_network::exchange(tls_stream, request.as_bytes(), DOH_MAX_RESPONSE_BYTES).await?;
+    if !response.status().is_success() {
+        return Err(format!("{} answered {}", host, response.status()).into());
+    }
+    let body = response.into_body().collect().await?.to_bytes();
+    parse_doh_json(&body)
+}
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "TTL", default)]
+    ttl: u64,
+    data: String,
+}
+
+/// Parse an `application/dns-json` DoH answer into addresses and the lowest TTL
+pub fn parse_doh_json(body: &[u8]) -> Result<(Vec<IpAddr>, Duration), Box<dyn std::error::Error + Send + Sync>> {
+    let response: DohResponse = serde_json::from_slice(body)?;
+    
+    // CNAME answers carry hostnames rather than addresses and are skipped
+    let ips = response.answer.iter().filter_map(|a| a.data.parse().ok()).collect();
+    let ttl = response.answer.iter().map(|a| a.ttl).min().unwrap_or(0);
+    
+    Ok((ips, Duration::from_secs(ttl)))
+}
+
+/// Choose the host handed to arti for a connection.
+///
+/// With `ExitDns` the hostname is passed through unchanged and the exit relay
+/// resolves it. With `LocalDoH` the hostname is resolved first via `resolve`
+/// and the first address is used instead.
+pub async fn select_connect_host<F, Fut>(
+    mode: DnsResolution,
+    host: &str,
+    resolve: F,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = Result<Vec<IpAddr>, Box<dyn std::error::Error + Send + Sync>>>,
+{
+    match mode {
+        DnsResolution::ExitDns => Ok(host.to_string()),
+        DnsResolution::LocalDoH => {
+            if host.parse::<IpAddr>().is_ok() {
+                return Ok(host.to_string());
+            }
+            
+            let ips = resolve(host.to_string()).await?;
+            ips.first()
+                .map(|ip| ip.to_string())
+                .ok_or_else(|| format!("No addresses found for {}", host).into())
+        }
+    }
+}
+
+/// Flush the operating system's DNS cache. Only Windows is supported;
+/// elsewhere this returns an error rather than pretend it was flushed.
+pub fn flush_system_cache() -> Result<(), String> {
+    #[cfg(target_os = "windows")]
+    {
+        let output = std::process::Command::new("ipconfig")
+            .arg("/flushdns")
+            .output()
+            .map_err(|e| format!("Failed to flush DNS cache: {}", e))?;
+        
+        if !output.status.success() {
+            return Err("ipconfig /flushdns failed".to_string());
+        }
+        
+        info!("✓ System DNS cache flushed");
+        Ok(())
+    }
+    
+    #[cfg(not(target_os = "windows"))]
+    {
+        Err("Flushing the system DNS cache is not supported on this platform".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_exit_dns_passes_hostname_through() {
+        let host = select_connect_host(DnsResolution::ExitDns, "example.com", |_| async {
+            panic!("ExitDns must not resolve locally");
+        })
+        .await
+        .unwrap();
+        assert_eq!(host, "example.com");
+    }
+
+    #[tokio::test]
+    async fn test_local_doh_connects_to_resolved_ip() {
+        let host = select_connect_host(DnsResolution::LocalDoH, "example.com", |domain| async move {
+            assert_eq!(domain, "example.com");
+            Ok(vec!["93.184.216.34".parse().unwrap()])
+        })
+        .await
+        .unwrap();
+        assert_eq!(host, "93.184.216.34");
+    }
+
+    #[tokio::test]
+    async fn test_local_doh_skips_ip_literals_and_reports_empty_answers() {
+        let host = select_connect_host(DnsResolution::LocalDoH, "1.1.1.1", |_| async {
+            panic!("IP literals need no lookup");
+        })
+        .await
+        .unwrap();
+        assert_eq!(host, "1.1.1.1");
+
+        let result = select_connect_host(DnsResolution::LocalDoH, "example.com", |_| async {
+            Ok(Vec::new())
+        })
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_dns_servers() {
+        let server = DnsServer::parse("8.8.8.8:853").unwrap();
+        assert_eq!(server.tls_name.as_deref(), Some("dns.google"));
+        
+        let server = DnsServer::parse("192.0.2.1:853#dns.example").unwrap();
+        assert_eq!(server.tls_name.as_deref(), Some("dns.example"));
+        assert_eq!(DnsServer::parse("192.0.2.1:53").unwrap().tls_name, None);
+        
+        // Lookups over Tor go to the same providers' DoH services
+        assert_eq!(DnsServer::parse("1.1.1.1:853").unwrap().doh_endpoint(), Some(("cloudflare-dns.com", 443, "/dns-query")));
+        assert_eq!(DnsServer::parse("8.8.8.8:853").unwrap().doh_endpoint(), Some(("dns.google", 443, "/resolve")));
+        assert_eq!(DnsServer::parse("192.0.2.1:53").unwrap().doh_endpoint(), None);
+        
+        // DoT without a name to check the certificate against is refused
+        assert!(DnsServer::parse("192.0.2.1:853").is_err());
+        assert!(DnsServer::parse("dns.google").is_err());
+    }
+
+    fn pool() -> ServerPool {
+        ServerPool::new(vec![
+            DnsServer::parse("192.0.2.1:853#primary.example").unwrap(),
+            DnsServer::parse("192.0.2.2:853#secondary.example").unwrap(),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_secondary_server() {
+        let servers = pool();
+        let primary_down = |_: usize, server: &DnsServer| {
+            let addr = server.addr;
+            async move {
+                if addr.ip().to_string() == "192.0.2.1" {
+                    Err("connection refused".to_string())
+                } else {
+                    Ok(vec!["93.184.216.34".parse::<IpAddr>().unwrap()])
+                }
+            }
+        };
+        
+        let ips = servers.failover(primary_down, |_| true).await.unwrap();
+        assert_eq!(ips, vec!["93.184.216.34".parse::<IpAddr>().unwrap()]);
+        assert_eq!(servers.active().tls_name.as_deref(), Some("secondary.example"));
+        
+        // Errors that aren't the server's fault are returned without trying others
+        let servers = pool();
+        let nxdomain = |_: usize, _: &DnsServer| async { Err::<(), _>("no such domain") };
+        assert!(servers.failover(nxdomain, |_| false).await.is_err());
+        assert_eq!(servers.active().tls_name.as_deref(), Some("primary.example"));
+    }
+
+    #[test]
+    fn test_parse_doh_json() {
+        let body = br#"{"Status":0,"Answer":[
+            {"name":"www.example.com","type":5,"TTL":600,"data":"example.com."},
+            {"name":"example.com","type":1,"TTL":120,"data":"93.184.216.34"}
+        ]}"#;
+        let (ips, ttl) = parse_doh_json(body).unwrap();
+        assert_eq!(ips, vec!["93.184.216.34".parse::<IpAddr>().unwrap()]);
+        assert_eq!(ttl, Duration::from_secs(120));
+
+        let (ips, _) = parse_doh_json(br#"{"Status":3}"#).unwrap();
+        assert!(ips.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+use thiserror::Error;
+
+/// Errors from the proxy hot path (Tor, DNS, routing)
+///
+/// Lets callers tell a Tor failure from a DNS failure or a blocked request,
+/// e.g. to pick an HTTP status code or a log category.
+#[derive(Debug, Error)]
+pub enum PrivacyError {
+    #[error("Tor bootstrap failed: {0}")]
+    TorBootstrap(String),
+    
+    #[error("Tor connection failed: {0}")]
+    TorConnect(String),
+    
+    #[error("DNS resolution failed: {0}")]
+    Dns(String),
+    
+    #[error("Request blocked: {0}")]
+    Blocked(String),
+    
+    /// Tor stayed down past `Config::reconnect_hold_secs` with the kill switch on
+    #[error("Kill switch: {0}")]
+    KillSwitch(String),
+    
+    #[error("Configuration error: {0}")]
+    Config(String),
+    
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    
+    #[error("Malformed HTTP: {0}")]
+    Http(String),
+    
+    /// The client sent a request we can't act on, e.g. a garbage CONNECT target
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    
+    /// The client's request line or headers went past `Config::max_request_line_bytes`
+    /// or `Config::max_request_header_bytes`
+    #[error("Request head too large: {0}")]
+    HeadTooLarge(String),
+    
+    #[error("Response exceeded the {limit} byte limit")]
+    ResponseTooLarge { limit: usize },
+    
+    #[error("No Tor exit available in {country}: {reason}")]
+    ExitUnavailable { country: String, reason: String },
+    
+    #[error("Port {port} is already in use - is another instance of Privacy Suite running?")]
+    PortInUse { port: u16 },
+}
+
+impl PrivacyError {
+    /// HTTP status to answer a proxied request that failed with this error
+    pub fn status_code(&self) -> u16 {
+        match self {
+            PrivacyError::BadRequest(_) => 400,
+            PrivacyError::Blocked(_) => 403,
+            PrivacyError::HeadTooLarge(_) => 431,
+            PrivacyError::TorBootstrap(_) | PrivacyError::KillSwitch(_) | PrivacyError::ExitUnavailable { .. } => 503,
+            PrivacyError::Config(_) | PrivacyError::PortInUse { .. } => 500,
+            PrivacyError::TorConnect(_)
+            | PrivacyError::Dns(_)
+            | PrivacyError::Io(_)
+            | PrivacyError::Http(_)
+            | PrivacyError::ResponseTooLarge { .. } => 502,
+        }
+    }
+    
+    /// Log category (as used by `ApiState::add_log`) this error belongs in
+    pub fn log_category(&self) -> &'static str {
+        match self {
+            PrivacyError::Blocked(_) | PrivacyError::KillSwitch(_) | PrivacyError::HeadTooLarge(_) => "security",
+            PrivacyError::Config(_) | PrivacyError::PortInUse { .. } => "general",
+            _ => "network",
+        }
+    }
+    
+    /// Translate a failure to bind a listener on `port`, calling out a port that is taken
+    pub fn from_bind(e: std::io::Error, port: u16) -> Self {
+        if e.kind() == std::io::ErrorKind::AddrInUse {
+            PrivacyError::PortInUse { port }
+        } else {
+            PrivacyError::Io(e)
+        }
+    }
+}
+
+impl From<hyper::http::Error> for PrivacyError {
+    fn from(e: hyper::http::Error) -> Self {
+        PrivacyError::Http(e.to_string())
+    }
+}
+
+impl From<std::net::AddrParseError> for PrivacyError {
+    fn from(e: std::net::AddrParseError) -> Self {
+        PrivacyError::Config(e.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, PrivacyError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variants_map_to_status_and_category() {
+        assert_eq!(PrivacyError::Blocked("tracker".into()).status_code(), 403);
+        assert_eq!(PrivacyError::Blocked("tracker".into()).log_category(), "security");
+        assert_eq!(PrivacyError::TorBootstrap("no consensus".into()).status_code(), 503);
+        let kill_switch = PrivacyError::KillSwitch("Tor disconnected".into());
+        assert_eq!((kill_switch.status_code(), kill_switch.log_category()), (503, "security"));
+        assert_eq!(PrivacyError::TorConnect("exit refused".into()).status_code(), 502);
+        assert_eq!(PrivacyError::Dns("NXDOMAIN".into()).log_category(), "network");
+        assert_eq!(PrivacyError::Config("bad proxy_addr".into()).log_category(), "general");
+        assert_eq!(PrivacyError::ResponseTooLarge { limit: 1 }.status_code(), 502);
+        assert_eq!(PrivacyError::BadRequest("CONNECT target".into()).status_code(), 400);
+        let too_large = PrivacyError::HeadTooLarge("request line".into());
+        assert_eq!((too_large.status_code(), too_large.log_category()), (431, "security"));
+        let no_exit = PrivacyError::ExitUnavailable { country: "de".into(), reason: "no exits".into() };
+        assert_eq!((no_exit.status_code(), no_exit.log_category()), (503, "network"));
+    }
+
+    #[test]
+    fn test_from_conversions() {
+        let io: PrivacyError = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "closed").into();
+        assert!(matches!(io, PrivacyError::Io(_)));
+        
+        let addr: PrivacyError = "not an addr".parse::<std::net::SocketAddr>().unwrap_err().into();
+        assert!(matches!(addr, PrivacyError::Config(_)));
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+
+/// How an exit country is chosen when the user leaves it on "Auto".
+///
+/// Exiting close to home is faster, since traffic doesn't cross an ocean twice,
+/// but an exit in the user's own country lets anyone watching that country's
+/// networks see both the entry and the exit side of their traffic. No choice is
+/// both the fastest and the most anonymous.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExitPreference {
+    /// Exit in the nearest region, a different country from the user's own.
+    /// Slightly slower than `Fastest`, but a local observer can't see both ends.
+    #[default]
+    AvoidHomeCountry,
+
+    /// Exit as close to the user as possible, including their own country
+    Fastest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Region {
+    NorthAmerica,
+    Europe,
+    AsiaPacific,
+}
+
+/// Exit countries offered in the GUI, roughly by Tor exit capacity
+const EXIT_COUNTRIES: [(&str, Region); 10] = [
+    ("de", Region::Europe),
+    ("us", Region::NorthAmerica),
+    ("nl", Region::Europe),
+    ("fr", Region::Europe),
+    ("se", Region::Europe),
+    ("ch", Region::Europe),
+    ("uk", Region::Europe),
+    ("ca", Region::NorthAmerica),
+    ("jp", Region::AsiaPacific),
+    ("au", Region::AsiaPacific),
+];
+
+/// The exit region with the lowest latency from `country`
+fn nearest_region(country: &str) -> Option<Region> {
+    let region = match country {
+        "us" | "ca" | "mx" | "br" | "ar" | "cl" | "co" | "pe" => Region::NorthAmerica,
+        "uk" | "ie" | "de" | "nl" | "fr" | "se" | "ch" | "at" | "be" | "dk" | "es" | "it"
+        | "no" | "fi" | "pl" | "pt" | "cz" | "ro" | "gr" | "ua" | "ru" | "tr" | "il" | "ae"
+        | "sa" | "eg" | "za" | "ng" | "ke" => Region::Europe,
+        "jp" | "au" | "nz" | "kr" | "sg" | "hk" | "tw" | "in" | "id" | "my" | "th" | "ph"
+        | "vn" | "cn" => Region::AsiaPacific,
+        _ => return None,
+    };
+    Some(region)
+}
+
+/// Pick an exit country for a user in `home`, or None to let Tor choose freely
+pub fn choose_exit(home: Option<&str>, preference: ExitPreference) -> Option<&'static str> {
+    let home = match home? {
+        "gb" => "uk",
+        home => home,
+    };
+    let region = nearest_region(home)?;
+    let nearby = EXIT_COUNTRIES.iter().filter(|(_, r)| *r == region).map(|(c, _)| *c);
+
+    match preference {
+        ExitPreference::Fastest => EXIT_COUNTRIES
+            .iter()
+            .map(|(c, _)| *c)
+            .find(|c| *c == home)
+            .or_else(|| nearby.clone().next()),
+        ExitPreference::AvoidHomeCountry => nearby
+            .clone()
+            .find(|c| *c != home)
+            .or_else(|| EXIT_COUNTRIES.iter().map(|(c, _)| *c).find(|c| *c != home)),
+    }
+}
+
+/// Whether two country codes name the same country, taking the GUI's
+/// "uk" and GeoIP's "gb" as one
+pub fn same_country(a: &str, b: &str) -> bool {
+    let canonical = |code: &str| match code.to_ascii_lowercase().as_str() {
+        "gb" => "uk".to_string(),
+        code => code.to_string(),
+    };
+    canonical(a) == canonical(b)
+}
+
+/// Country code from a locale such as `en_US.UTF-8` or `en-GB`, in the
+/// GUI's spelling (`uk` rather than `gb`)
+pub fn country_from_locale(locale: &str) -> Option<String> {
+    let base = locale.split(['.', '@']).next()?;
+    // Skip the language and any script subtag ("zh-Hans-CN")
+    let country = base
+        .split(['_', '-'])
+        .skip(1)
+        .find(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_alphabetic()))?
+        .to_ascii_lowercase();
+
+    Some(if country == "gb" { "uk".to_string() } else { country })
+}
+
+/// The user's country according to the OS locale
+pub fn system_country() -> Option<String> {
+    system_locale().as_deref().and_then(country_from_locale)
+}
+
+#[cfg(target_os = "windows")]
+fn system_locale() -> Option<String> {
+    use windows::Win32::Globalization::GetUserDefaultLocaleName;
+
+    let mut buf = [0u16; 85];
+    let len = unsafe { GetUserDefaultLocaleName(&mut buf) };
+    // The length includes the terminating NUL
+    (len > 1).then(|| String::from_utf16_lossy(&buf[..len as usize - 1]))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn system_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_avoids_home_country_by_default() {
+        let pick = |home| choose_exit(Some(home), ExitPreference::AvoidHomeCountry);
+
+        assert_eq!(pick("de"), Some("nl"));
+        assert_eq!(pick("us"), Some("ca"));
+        assert_eq!(pick("jp"), Some("au"));
+        assert_eq!(pick("pl"), Some("de"));
+        // Unknown regions and unknown users leave the choice to Tor
+        assert_eq!(pick("aq"), None);
+        assert_eq!(choose_exit(None, ExitPreference::AvoidHomeCountry), None);
+    }
+
+    #[test]
+    fn test_fastest_prefers_home_then_nearest_region() {
+        let pick = |home| choose_exit(Some(home), ExitPreference::Fastest);
+
+        assert_eq!(pick("de"), Some("de"));
+        assert_eq!(pick("uk"), Some("uk"));
+        assert_eq!(pick("br"), Some("us"));
+        assert_eq!(pick("sg"), Some("jp"));
+    }
+
+    #[test]
+    fn test_country_from_locale() {
+        assert_eq!(country_from_locale("en_US.UTF-8").as_deref(), Some("us"));
+        assert_eq!(country_from_locale("en-GB").as_deref(), Some("uk"));
+        assert_eq!(country_from_locale("de_DE@euro").as_deref(), Some("de"));
+        assert_eq!(country_from_locale("C"), None);
+        assert_eq!(country_from_locale("zh-Hans-CN").as_deref(), Some("cn"));
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Limits how many requests are routed at once and shares the slots fairly
+/// between LAN clients.
+///
+/// While a slot is free, requests start immediately. Once all are taken,
+/// new requests queue per client. A freed slot goes to the waiting client
+/// with the fewest requests in flight, and ties rotate round-robin, so one
+/// busy device can't take every slot.
+#[derive(Clone)]
+pub struct FairScheduler {
+    state: Arc<Mutex<SchedulerState>>,
+}
+
+struct SchedulerState {
+    /// Most slots in use at once; 0 means unlimited
+    limit: usize,
+    in_flight: usize,
+    per_client: HashMap<IpAddr, usize>,
+    waiting: HashMap<IpAddr, VecDeque<oneshot::Sender<Permit>>>,
+    /// Clients with queued requests, least recently served first
+    order: VecDeque<IpAddr>,
+}
+
+impl SchedulerState {
+    fn has_room(&self) -> bool {
+        self.limit == 0 || self.in_flight < self.limit
+    }
+
+    fn start(&mut self, client: IpAddr) {
+        self.in_flight += 1;
+        *self.per_client.entry(client).or_insert(0) += 1;
+    }
+
+    fn finish(&mut self, client: IpAddr) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        if let Some(count) = self.per_client.get_mut(&client) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_client.remove(&client);
+            }
+        }
+    }
+
+    /// Take the next waiter to hand a free slot to, counting it as started
+    fn next_waiter(&mut self) -> Option<(IpAddr, oneshot::Sender<Permit>)> {
+        if !self.has_room() {
+            return None;
+        }
+
+        // Fewest active requests wins; `min_by_key` keeps the first of equals
+        let position = self
+            .order
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, client)| self.per_client.get(client).copied().unwrap_or(0))
+            .map(|(position, _)| position)?;
+        let client = self.order.remove(position)?;
+
+        let queue = self.waiting.get_mut(&client)?;
+        let sender = queue.pop_front()?;
+        if queue.is_empty() {
+            self.waiting.remove(&client);
+        } else {
+            self.order.push_back(client);
+        }
+
+        self.start(client);
+        Some((client, sender))
+    }
+}
+
+/// A slot held by one request; dropping it lets the next one start
+pub struct Permit {
+    scheduler: FairScheduler,
+    client: IpAddr,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.scheduler.release(self.client);
+    }
+}
+
+impl FairScheduler {
+    /// Allow `limit` requests at once across all clients (0 for no limit)
+    pub fn new(limit: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SchedulerState {
+                limit,
+                in_flight: 0,
+                per_client: HashMap::new(),
+                waiting: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Wait for a slot for a request from `client`
+    pub async fn acquire(&self, client: IpAddr) -> Permit {
+        let receiver = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            // Don't jump the queue while others are waiting
+            if state.has_room() && state.order.is_empty() {
+                state.start(client);
+                return self.permit(client);
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            state.waiting.entry(client).or_default().push_back(sender);
+            if !state.order.contains(&client) {
+                state.order.push_back(client);
+            }
+            receiver
+        };
+
+        match receiver.await {
+            Ok(permit) => permit,
+            // Only happens if the queue was dropped, i.e. never in practice
+            Err(_) => {
+                self.state.lock().unwrap_or_else(|e| e.into_inner()).start(client);
+                self.permit(client)
+            }
+        }
+    }
+
+    /// Requests from `client` that hold a slot right now
+    pub fn in_flight(&self, client: IpAddr) -> usize {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.per_client.get(&client).copied().unwrap_or(0)
+    }
+
+    fn permit(&self, client: IpAddr) -> Permit {
+        Permit { scheduler: self.clone(), client }
+    }
+
+    fn release(&self, client: IpAddr) {
+        let next = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.finish(client);
+            state.next_waiter()
+        };
+
+        if let Some((next_client, sender)) = next {
+            // If the waiter gave up, the rejected permit drops here, outside
+            // the lock, and passes the slot on again
+            let _ = sender.send(self.permit(next_client));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const HEAVY: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 10));
+    const LIGHT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 20));
+
+    /// Queue a request from `client` that reports `client` once it gets a slot,
+    /// then holds the slot until `release` fires
+    fn queue(
+        scheduler: &FairScheduler,
+        client: IpAddr,
+        started: &tokio::sync::mpsc::UnboundedSender<IpAddr>,
+    ) -> oneshot::Sender<()> {
+        let (release, released) = oneshot::channel::<()>();
+        let scheduler = scheduler.clone();
+        let started = started.clone();
+        tokio::spawn(async move {
+            let _permit = scheduler.acquire(client).await;
+            started.send(client).unwrap();
+            let _ = released.await;
+        });
+        release
+    }
+
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn test_heavy_client_does_not_starve_light_one() {
+        let scheduler = FairScheduler::new(2);
+        let (started, mut order) = tokio::sync::mpsc::unbounded_channel();
+
+        // The heavy client fills both slots and queues three more requests
+        let first = scheduler.acquire(HEAVY).await;
+        let second = scheduler.acquire(HEAVY).await;
+        let _heavy: Vec<_> = (0..3).map(|_| queue(&scheduler, HEAVY, &started)).collect();
+        settle().await;
+        let light = queue(&scheduler, LIGHT, &started);
+        settle().await;
+        assert_eq!(scheduler.in_flight(HEAVY), 2);
+        assert!(order.try_recv().is_err());
+
+        // The light client queued last but has nothing in flight, so it goes next
+        drop(first);
+        assert_eq!(order.recv().await, Some(LIGHT));
+        assert_eq!((scheduler.in_flight(HEAVY), scheduler.in_flight(LIGHT)), (1, 1));
+
+        // With the light client idle again, the heavy one gets the freed slots
+        light.send(()).unwrap();
+        assert_eq!(order.recv().await, Some(HEAVY));
+        drop(second);
+        assert_eq!(order.recv().await, Some(HEAVY));
+        assert_eq!(scheduler.in_flight(HEAVY), 2);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_waiter_passes_its_slot_on() {
+        let scheduler = FairScheduler::new(1);
+        let first = scheduler.acquire(HEAVY).await;
+
+        // This request gives up before a slot frees
+        let abandoned = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire(LIGHT).await }
+        });
+        settle().await;
+        abandoned.abort();
+        let _ = abandoned.await;
+
+        let (started, mut order) = tokio::sync::mpsc::unbounded_channel();
+        let _waiting = queue(&scheduler, HEAVY, &started);
+        settle().await;
+        drop(first);
+        assert_eq!(order.recv().await, Some(HEAVY));
+        assert_eq!(scheduler.in_flight(LIGHT), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_never_waits() {
+        let scheduler = FairScheduler::new(0);
+        let permits: Vec<Permit> = futures::future::join_all((0..100).map(|_| scheduler.acquire(HEAVY))).await;
+        assert_eq!(scheduler.in_flight(HEAVY), permits.len());
+        drop(permits);
+        assert_eq!(scheduler.in_flight(HEAVY), 0);
+    }
+}
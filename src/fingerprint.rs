@@ -1,5 +1,40 @@
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// User-Agents fingerprints are drawn from unless `Config::user_agents` replaces them
+pub const DEFAULT_USER_AGENTS: [&str; 5] = [
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:121.0) Gecko/20100101 Firefox/121.0",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:121.0) Gecko/20100101 Firefox/121.0",
+];
+
+/// Content codings a fingerprint may advertise in `Accept-Encoding`. The
+/// proxy decodes none of them: a compressed response reaches the browser
+/// untouched, `Content-Encoding` included, and the browser decodes it. Only
+/// codings every supported browser can decode belong here.
+pub const FORWARDED_ENCODINGS: [&str; 4] = ["gzip", "deflate", "br", "identity"];
+
+pub fn default_user_agents() -> Vec<String> {
+    DEFAULT_USER_AGENTS.iter().map(|ua| ua.to_string()).collect()
+}
+
+/// Check a `Config::user_agents` list: at least one entry, each of them
+/// usable as a header value
+pub fn validate_user_agents(user_agents: &[String]) -> Result<(), String> {
+    if user_agents.is_empty() {
+        return Err("user_agents must list at least one User-Agent".to_string());
+    }
+    for user_agent in user_agents {
+        if user_agent.trim().is_empty() || hyper::header::HeaderValue::from_str(user_agent).is_err() {
+            return Err(format!("user_agents entry {:?} is not a valid header value", user_agent));
+        }
+    }
+    Ok(())
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BrowserFingerprint {
@@ -10,35 +45,150 @@ pub struct BrowserFingerprint {
     pub timezone: String,
     pub webgl_vendor: String,
     pub webgl_renderer: String,
+    /// User-agent client hints matching `user_agent` (None for browsers that don't send them)
+    pub client_hints: Option<ClientHints>,
+}
+
+/// `Sec-CH-UA*` values a Chromium browser sends alongside its User-Agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientHints {
+    /// `Sec-CH-UA`, e.g. `"Not_A Brand";v="8", "Chromium";v="120", "Google Chrome";v="120"`
+    pub brands: String,
+    /// `Sec-CH-UA-Platform`, e.g. `"Windows"`
+    pub platform: String,
+    /// `Sec-CH-UA-Mobile`, `?0` or `?1`
+    pub mobile: String,
+}
+
+impl ClientHints {
+    /// Derive the hints a browser with this User-Agent would send.
+    ///
+    /// Returns None for non-Chromium browsers (Firefox, Safari), which don't
+    /// send client hints at all.
+    pub fn from_user_agent(user_agent: &str) -> Option<Self> {
+        let version = user_agent.split("Chrome/").nth(1)?;
+        let major: String = version.chars().take_while(|c| c.is_ascii_digit()).collect();
+        if major.is_empty() {
+            return None;
+        }
+        
+        let brand = if user_agent.contains("Edg/") { "Microsoft Edge" } else { "Google Chrome" };
+        let platform = if user_agent.contains("Android") {
+            "Android"
+        } else if user_agent.contains("Windows") {
+            "Windows"
+        } else if user_agent.contains("Macintosh") {
+            "macOS"
+        } else if user_agent.contains("CrOS") {
+            "Chrome OS"
+        } else {
+            "Linux"
+        };
+        
+        Some(Self {
+            brands: format!("\"Not_A Brand\";v=\"8\", \"Chromium\";v=\"{0}\", \"{1}\";v=\"{0}\"", major, brand),
+            platform: format!("\"{}\"", platform),
+            mobile: if user_agent.contains("Mobile") { "?1" } else { "?0" }.to_string(),
+        })
+    }
 }
 
+/// Client hint header names, in the order Chrome sends them
+const CLIENT_HINT_HEADERS: [&str; 3] = ["sec-ch-ua", "sec-ch-ua-mobile", "sec-ch-ua-platform"];
+
 impl BrowserFingerprint {
     /// Generate a randomized but realistic browser fingerprint
     pub fn random() -> Self {
+        Self::random_from(&DEFAULT_USER_AGENTS)
+    }
+    
+    /// Generate a randomized fingerprint with one of `user_agents` (the
+    /// built-in set if it is empty)
+    pub fn random_from<S: AsRef<str>>(user_agents: &[S]) -> Self {
         let mut rng = rand::thread_rng();
         
-        let user_agents = vec![
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-            "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:121.0) Gecko/20100101 Firefox/121.0",
-            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:121.0) Gecko/20100101 Firefox/121.0",
-        ];
-        
         let languages = vec!["en-US,en;q=0.9", "en-GB,en;q=0.9", "en-US,en;q=0.5"];
         
         let resolutions = vec!["1920x1080", "2560x1440", "1366x768", "1536x864", "3840x2160"];
         
         let timezones = vec!["America/New_York", "America/Los_Angeles", "Europe/London", "Europe/Paris"];
         
+        let user_agent = if user_agents.is_empty() {
+            DEFAULT_USER_AGENTS[rng.gen_range(0..DEFAULT_USER_AGENTS.len())].to_string()
+        } else {
+            user_agents[rng.gen_range(0..user_agents.len())].as_ref().to_string()
+        };
+        let client_hints = ClientHints::from_user_agent(&user_agent);
+        
         Self {
-            user_agent: user_agents[rng.gen_range(0..user_agents.len())].to_string(),
+            user_agent,
             accept_language: languages[rng.gen_range(0..languages.len())].to_string(),
             accept_encoding: "gzip, deflate, br".to_string(),
             screen_resolution: resolutions[rng.gen_range(0..resolutions.len())].to_string(),
             timezone: timezones[rng.gen_range(0..timezones.len())].to_string(),
             webgl_vendor: "Google Inc. (NVIDIA)".to_string(),
             webgl_renderer: "ANGLE (NVIDIA, NVIDIA GeForce RTX 3070)".to_string(),
+            client_hints,
+        }
+    }
+    
+    /// Check that the fingerprint could come from a real browser.
+    ///
+    /// Every value must be a valid header value (they are written into raw
+    /// requests), and the client hints must be exactly the ones the
+    /// User-Agent implies - a Firefox UA with Chrome hints is a giveaway.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.user_agent.trim().is_empty() {
+            return Err("user_agent must not be empty".to_string());
+        }
+        
+        let mut values = vec![
+            ("user_agent", self.user_agent.as_str()),
+            ("accept_language", self.accept_language.as_str()),
+            ("accept_encoding", self.accept_encoding.as_str()),
+        ];
+        if let Some(hints) = &self.client_hints {
+            values.extend([
+                ("client_hints.brands", hints.brands.as_str()),
+                ("client_hints.platform", hints.platform.as_str()),
+                ("client_hints.mobile", hints.mobile.as_str()),
+            ]);
+        }
+        for (field, value) in values {
+            if hyper::header::HeaderValue::from_str(value).is_err() {
+                return Err(format!("{} is not a valid header value", field));
+            }
+        }
+        
+        if self.client_hints != ClientHints::from_user_agent(&self.user_agent) {
+            return Err("client_hints don't match the user_agent".to_string());
+        }
+        
+        if let Some(encoding) = self.accept_encodings().into_iter().find(|e| !FORWARDED_ENCODINGS.contains(&e.as_str())) {
+            return Err(format!("accept_encoding lists {:?}, which responses can't be forwarded in", encoding));
+        }
+        
+        Ok(())
+    }
+    
+    /// The content codings `accept_encoding` lists, lowercase and without q-values
+    pub fn accept_encodings(&self) -> Vec<String> {
+        self.accept_encoding
+            .split(',')
+            .map(|entry| entry.split(';').next().unwrap_or_default().trim().to_ascii_lowercase())
+            .filter(|encoding| !encoding.is_empty())
+            .collect()
+    }
+    
+    /// Client hint headers to send with this fingerprint (empty for Firefox)
+    pub fn client_hint_headers(&self) -> Vec<(&'static str, String)> {
+        match &self.client_hints {
+            Some(hints) => vec![
+                (CLIENT_HINT_HEADERS[0], hints.brands.clone()),
+                (CLIENT_HINT_HEADERS[1], hints.mobile.clone()),
+                (CLIENT_HINT_HEADERS[2], hints.platform.clone()),
+            ],
+            None => vec![],
         }
     }
     
@@ -56,6 +206,99 @@ impl BrowserFingerprint {
             hyper::header::ACCEPT_ENCODING,
             self.accept_encoding.parse().unwrap(),
         );
+        
+        // Drop the real browser's hints so they can't contradict the spoofed UA
+        for name in CLIENT_HINT_HEADERS {
+            headers.remove(name);
+        }
+        for (name, value) in self.client_hint_headers() {
+            if let Ok(value) = value.parse() {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// The fingerprint presented for the current session, shared between the
+/// router and the web API.
+///
+/// A new fingerprint is rolled for every connection (and on a timer, see
+/// `FingerprintRotation`) unless one is pinned, for sites that break when
+/// the fingerprint changes.
+#[derive(Clone)]
+pub struct SessionFingerprint {
+    inner: Arc<RwLock<(BrowserFingerprint, bool)>>,
+    user_agents: Arc<RwLock<Vec<String>>>,
+}
+
+impl SessionFingerprint {
+    pub fn new(fingerprint: BrowserFingerprint) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new((fingerprint, false))),
+            user_agents: Arc::new(RwLock::new(default_user_agents())),
+        }
+    }
+    
+    /// User-Agents later rerolls pick from
+    pub fn set_user_agents(&self, user_agents: Vec<String>) {
+        *self.user_agents.write().unwrap_or_else(|e| e.into_inner()) = user_agents;
+    }
+    
+    pub fn current(&self) -> BrowserFingerprint {
+        self.inner.read().unwrap_or_else(|e| e.into_inner()).0.clone()
+    }
+    
+    pub fn is_pinned(&self) -> bool {
+        self.inner.read().unwrap_or_else(|e| e.into_inner()).1
+    }
+    
+    /// Keep `fingerprint` across connections until the next reroll
+    pub fn pin(&self, fingerprint: BrowserFingerprint) -> Result<(), String> {
+        fingerprint.validate()?;
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = (fingerprint, true);
+        Ok(())
+    }
+    
+    /// Replace the fingerprint with a fresh random one and unpin it
+    pub fn reroll(&self) -> BrowserFingerprint {
+        let fingerprint = {
+            let user_agents = self.user_agents.read().unwrap_or_else(|e| e.into_inner());
+            BrowserFingerprint::random_from(user_agents.as_slice())
+        };
+        *self.inner.write().unwrap_or_else(|e| e.into_inner()) = (fingerprint.clone(), false);
+        fingerprint
+    }
+    
+    /// Start a new session: roll a new fingerprint unless one is pinned
+    pub fn rotate_unless_pinned(&self) {
+        if !self.is_pinned() {
+            self.reroll();
+        }
+    }
+}
+
+/// Rerolls a session fingerprint every `interval` (unless it is pinned)
+/// until dropped
+pub struct FingerprintRotation {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl FingerprintRotation {
+    pub fn spawn(session: SessionFingerprint, interval: Duration) -> Self {
+        let task = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            loop {
+                ticks.tick().await;
+                session.rotate_unless_pinned();
+            }
+        });
+        Self { task }
+    }
+}
+
+impl Drop for FingerprintRotation {
+    fn drop(&mut self) {
+        self.task.abort();
     }
 }
 
@@ -136,3 +379,152 @@ impl CanvasProtection {
         self.enabled
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHROME_WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+    const FIREFOX_MAC: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:121.0) Gecko/20100101 Firefox/121.0";
+
+    fn fingerprint_with(user_agent: &str) -> BrowserFingerprint {
+        BrowserFingerprint {
+            user_agent: user_agent.to_string(),
+            client_hints: ClientHints::from_user_agent(user_agent),
+            ..BrowserFingerprint::random()
+        }
+    }
+
+    #[test]
+    fn test_chrome_hints_match_user_agent() {
+        let hints = ClientHints::from_user_agent(CHROME_WINDOWS).unwrap();
+        assert_eq!(hints.brands, r#""Not_A Brand";v="8", "Chromium";v="120", "Google Chrome";v="120""#);
+        assert_eq!(hints.platform, r#""Windows""#);
+        assert_eq!(hints.mobile, "?0");
+
+        let mac = ClientHints::from_user_agent(
+            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+        ).unwrap();
+        assert_eq!(mac.platform, r#""macOS""#);
+    }
+
+    #[test]
+    fn test_firefox_sends_no_hints() {
+        assert!(ClientHints::from_user_agent(FIREFOX_MAC).is_none());
+
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("sec-ch-ua", r#""Chromium";v="119""#.parse().unwrap());
+        fingerprint_with(FIREFOX_MAC).apply_to_headers(&mut headers);
+
+        assert_eq!(headers[hyper::header::USER_AGENT], FIREFOX_MAC);
+        for name in CLIENT_HINT_HEADERS {
+            assert!(headers.get(name).is_none(), "{} should not be sent with a Firefox UA", name);
+        }
+    }
+
+    #[test]
+    fn test_apply_to_headers_emits_consistent_hints() {
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("sec-ch-ua-platform", r#""Linux""#.parse().unwrap());
+        fingerprint_with(CHROME_WINDOWS).apply_to_headers(&mut headers);
+
+        assert_eq!(headers[hyper::header::USER_AGENT], CHROME_WINDOWS);
+        assert!(headers["sec-ch-ua"].to_str().unwrap().contains(r#""Chromium";v="120""#));
+        assert_eq!(headers["sec-ch-ua-platform"], r#""Windows""#);
+        assert_eq!(headers["sec-ch-ua-mobile"], "?0");
+    }
+
+    #[test]
+    fn test_random_fingerprints_are_consistent() {
+        for _ in 0..50 {
+            let fingerprint = BrowserFingerprint::random();
+            let is_chrome = fingerprint.user_agent.contains("Chrome/");
+            assert_eq!(fingerprint.client_hints.is_some(), is_chrome, "{}", fingerprint.user_agent);
+        }
+    }
+
+    #[test]
+    fn test_validate_rejects_inconsistent_fingerprints() {
+        assert!(fingerprint_with(CHROME_WINDOWS).validate().is_ok());
+        assert!(fingerprint_with(FIREFOX_MAC).validate().is_ok());
+
+        let mismatched = BrowserFingerprint {
+            client_hints: ClientHints::from_user_agent(CHROME_WINDOWS),
+            ..fingerprint_with(FIREFOX_MAC)
+        };
+        assert!(mismatched.validate().is_err());
+
+        let injected = BrowserFingerprint {
+            accept_language: "en-US\r\nX-Real-IP: 203.0.113.7".to_string(),
+            ..fingerprint_with(FIREFOX_MAC)
+        };
+        assert!(injected.validate().is_err());
+    }
+
+    #[test]
+    fn test_advertised_encodings_are_forwarded_untouched() {
+        for _ in 0..20 {
+            let fingerprint = BrowserFingerprint::random();
+            for encoding in fingerprint.accept_encodings() {
+                assert!(FORWARDED_ENCODINGS.contains(&encoding.as_str()), "{}", encoding);
+            }
+        }
+
+        let zstd = BrowserFingerprint {
+            accept_encoding: "gzip, deflate, br, zstd;q=0.9".to_string(),
+            ..fingerprint_with(CHROME_WINDOWS)
+        };
+        assert_eq!(zstd.accept_encodings(), ["gzip", "deflate", "br", "zstd"]);
+        assert!(zstd.validate().unwrap_err().contains("zstd"));
+    }
+
+    #[test]
+    fn test_custom_user_agents_are_used_and_validated() {
+        let custom = vec![FIREFOX_MAC.to_string()];
+        let session = SessionFingerprint::new(BrowserFingerprint::random());
+        session.set_user_agents(custom.clone());
+        for _ in 0..10 {
+            assert_eq!(session.reroll().user_agent, FIREFOX_MAC);
+        }
+        assert!(session.current().validate().is_ok());
+
+        assert!(validate_user_agents(&custom).is_ok());
+        assert!(validate_user_agents(&default_user_agents()).is_ok());
+        assert!(validate_user_agents(&[]).is_err());
+        assert!(validate_user_agents(&["Bot/1.0\r\nX-Real-IP: 203.0.113.7".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rotation_rerolls_on_its_interval() {
+        let session = SessionFingerprint::new(fingerprint_with("Sentinel/1.0"));
+        session.set_user_agents(vec![CHROME_WINDOWS.to_string()]);
+
+        let rotation = FingerprintRotation::spawn(session.clone(), Duration::from_millis(100));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(session.current().user_agent, "Sentinel/1.0", "rotated before the interval");
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(session.current().user_agent, CHROME_WINDOWS);
+
+        // Dropping the rotation stops it
+        drop(rotation);
+        session.set_user_agents(vec![FIREFOX_MAC.to_string()]);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(session.current().user_agent, CHROME_WINDOWS);
+    }
+
+    #[test]
+    fn test_pinned_fingerprint_survives_rotation() {
+        let session = SessionFingerprint::new(BrowserFingerprint::random());
+        session.pin(fingerprint_with(FIREFOX_MAC)).unwrap();
+
+        for _ in 0..10 {
+            session.rotate_unless_pinned();
+            assert_eq!(session.current().user_agent, FIREFOX_MAC);
+        }
+        assert!(session.is_pinned());
+
+        let rerolled = session.reroll();
+        assert!(!session.is_pinned());
+        assert_eq!(session.current().user_agent, rerolled.user_agent);
+    }
+}
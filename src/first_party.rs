@@ -0,0 +1,106 @@
+use crate::blocklist::normalize_host;
+use hyper::header::{self, HeaderMap, HeaderName};
+
+/// Two-label public suffixes common enough that `site_of` must not treat
+/// them as a site of their own (e.g. `co.uk`)
+const MULTI_LABEL_SUFFIXES: [&str; 16] = [
+    "co.uk", "org.uk", "ac.uk", "gov.uk", "com.au", "net.au", "org.au", "co.jp",
+    "co.nz", "com.br", "com.cn", "co.in", "co.kr", "com.mx", "co.za", "com.tr",
+];
+
+/// The site a host belongs to: its registrable domain, e.g. `example.co.uk`
+/// for `www.example.co.uk`. IP addresses are a site of their own.
+pub fn site_of(host: &str) -> String {
+    let host = normalize_host(host);
+    if host.starts_with('[') || host.parse::<std::net::IpAddr>().is_ok() {
+        return host;
+    }
+
+    let labels: Vec<&str> = host.split('.').collect();
+    let suffix_labels = if labels.len() >= 3 && MULTI_LABEL_SUFFIXES.contains(&labels[labels.len() - 2..].join(".").as_str()) {
+        3
+    } else {
+        2
+    };
+    labels[labels.len().saturating_sub(suffix_labels)..].join(".")
+}
+
+/// Site of the page that made a request, from its `Referer` (or `Origin`)
+pub fn first_party(request_headers: &HeaderMap) -> Option<String> {
+    [header::REFERER, header::ORIGIN].iter().find_map(|name| {
+        let value = request_headers.get(name)?.to_str().ok()?;
+        let uri: hyper::Uri = value.trim().parse().ok()?;
+        uri.host().map(site_of)
+    })
+}
+
+/// The first party a request to `host` is embedded in, when that is a
+/// different site. Top-level navigations have no first party and are never
+/// third-party.
+pub fn third_party_of(host: &str, request_headers: &HeaderMap) -> Option<String> {
+    first_party(request_headers).filter(|first_party| *first_party != site_of(host))
+}
+
+/// Remove every `name` header (`Cookie` or `Set-Cookie`), returning how many cookies went
+pub fn strip_cookies(headers: &mut HeaderMap, name: HeaderName) -> usize {
+    let is_request = name == header::COOKIE;
+    let removed: usize = headers
+        .get_all(&name)
+        .iter()
+        .map(|value| {
+            // A request's cookies share one header; responses set one per header
+            if is_request {
+                value.as_bytes().split(|&b| b == b';').filter(|c| !c.trim_ascii().is_empty()).count()
+            } else {
+                1
+            }
+        })
+        .sum();
+    headers.remove(&name);
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse::<HeaderName>().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_site_of() {
+        assert_eq!(site_of("www.Example.com"), "example.com");
+        assert_eq!(site_of("static.cdn.example.com."), "example.com");
+        assert_eq!(site_of("shop.example.co.uk"), "example.co.uk");
+        assert_eq!(site_of("localhost"), "localhost");
+        assert_eq!(site_of("192.0.2.7"), "192.0.2.7");
+    }
+
+    #[test]
+    fn test_third_party_cookie_stripped_first_party_kept() {
+        let from_news = headers(&[("referer", "https://news.example.com/story"), ("cookie", "a=1; b=2")]);
+
+        // An ad server embedded in the page is a third party
+        let mut request = from_news.clone();
+        assert_eq!(third_party_of("ads.tracker.net", &request).as_deref(), Some("example.com"));
+        assert_eq!(strip_cookies(&mut request, header::COOKIE), 2);
+        assert!(request.get(header::COOKIE).is_none());
+
+        let mut response = headers(&[("set-cookie", "uid=42; Path=/"), ("set-cookie", "seen=1"), ("content-type", "text/html")]);
+        assert_eq!(strip_cookies(&mut response, header::SET_COOKIE), 2);
+        assert!(response.get(header::SET_COOKIE).is_none());
+        assert!(response.get(header::CONTENT_TYPE).is_some());
+
+        // The site's own subdomains, and top-level navigations, are first-party
+        assert_eq!(third_party_of("img.example.com", &from_news), None);
+        assert_eq!(third_party_of("ads.tracker.net", &headers(&[("cookie", "a=1")])), None);
+        assert_eq!(
+            third_party_of("api.example.com", &headers(&[("origin", "https://app.other.org")])).as_deref(),
+            Some("other.org")
+        );
+    }
+}
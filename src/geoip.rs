@@ -0,0 +1,249 @@
+use crate::config::Config;
+use crate::fingerprint::BrowserFingerprint;
+use crate::transport::Transport;
+use async_trait::async_trait;
+use http_body_util::BodyExt;
+use hyper::{Method, Uri};
+use maxminddb::geoip2;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Lookup service the online fallback asks, through Tor. Only plain HTTP is
+/// available on this path; the exit relay's address is all it reveals.
+pub const ONLINE_LOOKUP_URL: &str = "http://ip-api.com/json/";
+
+/// Addresses the online fallback remembers, so a busy exit is only looked up once
+const ONLINE_CACHE_SIZE: usize = 256;
+
+/// Finds the country an IP address is in, e.g. to show where the exit relay is
+#[async_trait]
+pub trait GeoIpProvider: Send + Sync {
+    /// Lowercase ISO 3166-1 alpha-2 code (e.g. `de`), if `ip` could be located
+    async fn country(&self, ip: IpAddr) -> Option<String>;
+}
+
+/// Lookups in a local MaxMind DB file (GeoLite2-Country or compatible).
+/// Nothing leaves the machine, so this is always tried first.
+pub struct MaxMindGeoIp {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl MaxMindGeoIp {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        maxminddb::Reader::open_readfile(path)
+            .map(|reader| Self { reader })
+            .map_err(|e| format!("Failed to open GeoIP database {}: {}", path.display(), e))
+    }
+
+    pub fn from_bytes(db: Vec<u8>) -> Result<Self, String> {
+        maxminddb::Reader::from_source(db)
+            .map(|reader| Self { reader })
+            .map_err(|e| format!("Invalid GeoIP database: {}", e))
+    }
+}
+
+#[async_trait]
+impl GeoIpProvider for MaxMindGeoIp {
+    async fn country(&self, ip: IpAddr) -> Option<String> {
+        let record = self.reader.lookup::<geoip2::Country>(ip).ok()?;
+        record.country?.iso_code.map(str::to_ascii_lowercase)
+    }
+}
+
+#[derive(Deserialize)]
+struct OnlineAnswer {
+    #[serde(rename = "countryCode")]
+    country_code: Option<String>,
+}
+
+/// Country code in an answer from `ONLINE_LOOKUP_URL`
+pub fn parse_online_answer(body: &[u8]) -> Option<String> {
+    let answer: OnlineAnswer = serde_json::from_slice(body).ok()?;
+    answer
+        .country_code
+        .filter(|code| code.len() == 2 && code.bytes().all(|b| b.is_ascii_alphabetic()))
+        .map(|code| code.to_ascii_lowercase())
+}
+
+/// Lookups at `ONLINE_LOOKUP_URL`, sent over `transport` (Tor) so the
+/// service never sees this machine's address
+pub struct OnlineGeoIp {
+    transport: Arc<dyn Transport>,
+    cache: Mutex<HashMap<IpAddr, Option<String>>>,
+}
+
+impl OnlineGeoIp {
+    pub fn new(transport: Arc<dyn Transport>) -> Self {
+        Self { transport, cache: Mutex::new(HashMap::new()) }
+    }
+}
+
+#[async_trait]
+impl GeoIpProvider for OnlineGeoIp {
+    async fn country(&self, ip: IpAddr) -> Option<String> {
+        if let Some(cached) = self.cache.lock().unwrap_or_else(|e| e.into_inner()).get(&ip) {
+            return cached.clone();
+        }
+
+        let uri: Uri = format!("{}{}?fields=countryCode", ONLINE_LOOKUP_URL, ip).parse().ok()?;
+        let host = uri.host()?.to_string();
+        let country = match self.transport.fetch(&Method::GET, &uri, &BrowserFingerprint::random(), &host).await {
+            Ok(response) if response.status().is_success() => {
+                let body = response.into_body().collect().await.ok()?.to_bytes();
+                parse_online_answer(&body)
+            }
+            Ok(response) => {
+                warn!("GeoIP lookup for {} failed: HTTP {}", ip, response.status());
+                return None;
+            }
+            Err(e) => {
+                // Not cached: the next circuit may get through
+                warn!("GeoIP lookup for {} failed: {}", ip, e);
+                return None;
+            }
+        };
+
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if cache.len() >= ONLINE_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(ip, country.clone());
+        country
+    }
+}
+
+/// Tries each provider in turn; the first that locates the address wins
+pub struct GeoIpChain {
+    providers: Vec<Arc<dyn GeoIpProvider>>,
+}
+
+impl GeoIpChain {
+    pub fn new(providers: Vec<Arc<dyn GeoIpProvider>>) -> Self {
+        Self { providers }
+    }
+}
+
+#[async_trait]
+impl GeoIpProvider for GeoIpChain {
+    async fn country(&self, ip: IpAddr) -> Option<String> {
+        for provider in &self.providers {
+            if let Some(country) = provider.country(ip).await {
+                return Some(country);
+            }
+        }
+        None
+    }
+}
+
+/// The providers `config` asks for: the local database at `geoip_db_path`,
+/// then (with `geoip_online_fallback`) an online lookup over `tor`. None when
+/// neither is set up.
+pub fn from_config(config: &Config, tor: Arc<dyn Transport>) -> Option<Arc<dyn GeoIpProvider>> {
+    let mut providers: Vec<Arc<dyn GeoIpProvider>> = Vec::new();
+    if let Some(path) = config.resolved_geoip_db_path() {
+        match MaxMindGeoIp::open(&path) {
+            Ok(db) => providers.push(Arc::new(db)),
+            Err(e) => warn!("{}", e),
+        }
+    }
+    if config.geoip_online_fallback {
+        providers.push(Arc::new(OnlineGeoIp::new(tor)));
+    }
+
+    match providers.len() {
+        0 => None,
+        1 => providers.pop(),
+        _ => Some(Arc::new(GeoIpChain::new(providers))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn string(db: &mut Vec<u8>, s: &str) {
+        db.push(0x40 | s.len() as u8);
+        db.extend_from_slice(s.as_bytes());
+    }
+
+    /// A minimal MaxMind DB (IPv4, 24-bit records) placing `network`/`prefix` in `iso_code`
+    fn test_db(network: Ipv4Addr, prefix: u32, iso_code: &str) -> Vec<u8> {
+        let node_count = prefix;
+        let bits = u32::from(network);
+        let mut db = Vec::new();
+
+        // One node per prefix bit; the other branch of each leads nowhere
+        for depth in 0..prefix {
+            let next = if depth + 1 == prefix { node_count + 16 } else { depth + 1 };
+            let (left, right) = if (bits >> (31 - depth)) & 1 == 0 { (next, node_count) } else { (node_count, next) };
+            db.extend_from_slice(&left.to_be_bytes()[1..]);
+            db.extend_from_slice(&right.to_be_bytes()[1..]);
+        }
+        db.extend_from_slice(&[0; 16]);
+
+        // {"country": {"iso_code": iso_code}}
+        db.push(0xE1);
+        string(&mut db, "country");
+        db.push(0xE1);
+        string(&mut db, "iso_code");
+        string(&mut db, iso_code);
+
+        db.extend_from_slice(b"\xAB\xCD\xEFMaxMind.com");
+        db.push(0xE9);
+        string(&mut db, "binary_format_major_version");
+        db.extend_from_slice(&[0xA1, 2]);
+        string(&mut db, "binary_format_minor_version");
+        db.push(0xA0);
+        string(&mut db, "build_epoch");
+        db.extend_from_slice(&[0x00, 0x02]);
+        string(&mut db, "database_type");
+        string(&mut db, "Test-Country");
+        string(&mut db, "description");
+        db.push(0xE0);
+        string(&mut db, "ip_version");
+        db.extend_from_slice(&[0xA1, 4]);
+        string(&mut db, "languages");
+        db.extend_from_slice(&[0x00, 0x04]);
+        string(&mut db, "node_count");
+        db.extend_from_slice(&[0xC1, node_count as u8]);
+        string(&mut db, "record_size");
+        db.extend_from_slice(&[0xA1, 24]);
+        db
+    }
+
+    struct Fixed(Option<&'static str>);
+
+    #[async_trait]
+    impl GeoIpProvider for Fixed {
+        async fn country(&self, _ip: IpAddr) -> Option<String> {
+            self.0.map(str::to_string)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_db_locates_known_ip() {
+        let db = MaxMindGeoIp::from_bytes(test_db(Ipv4Addr::new(81, 2, 69, 0), 24, "GB")).unwrap();
+        assert_eq!(db.country("81.2.69.142".parse().unwrap()).await.as_deref(), Some("gb"));
+        assert_eq!(db.country("81.2.70.1".parse().unwrap()).await, None);
+        assert_eq!(db.country("203.0.113.9".parse().unwrap()).await, None);
+
+        assert!(MaxMindGeoIp::from_bytes(b"not a database".to_vec()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chain_falls_back_in_order() {
+        let ip: IpAddr = "198.51.100.7".parse().unwrap();
+        let chain = GeoIpChain::new(vec![Arc::new(Fixed(None)), Arc::new(Fixed(Some("se"))), Arc::new(Fixed(Some("de")))]);
+        assert_eq!(chain.country(ip).await.as_deref(), Some("se"));
+        assert_eq!(GeoIpChain::new(vec![Arc::new(Fixed(None))]).country(ip).await, None);
+
+        assert_eq!(parse_online_answer(br#"{"countryCode":"NL"}"#).as_deref(), Some("nl"));
+        assert_eq!(parse_online_answer(br#"{"status":"fail"}"#), None);
+        assert_eq!(parse_online_answer(b"<html>"), None);
+    }
+}
@@ -1,6 +1,20 @@
 use eframe::egui;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
+use crate::config::DEFAULT_PROXY_PORT;
+use crate::runtime_info::RuntimeInfo;
+use crate::web_api::{ConnectionState, LogEntry, Stats};
+
+/// How often the log panel polls `/api/logs`
+const LOG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of log rows rendered at once
+const MAX_LOG_ROWS: usize = 200;
+
+/// Categories offered by the log panel filter (same values as `LogEntry::category`)
+const LOG_CATEGORIES: [&str; 6] = ["general", "network", "security", "tracker", "webrtc", "ipv6"];
 
 #[derive(Clone, Debug)]
 pub struct AppState {
@@ -13,6 +27,11 @@ pub struct AppState {
     pub total_requests: u64,
     pub proxy_running: bool,
     pub auto_proxy_enabled: bool,
+    pub connection_state: ConnectionState,
+    /// Relays per Tor circuit, for the "Multi-hop Tor" label
+    pub tor_path_length: u8,
+    /// Why the last connection attempt failed, e.g. no exit in a strict exit country
+    pub proxy_error: Option<String>,
 }
 
 impl AppState {
@@ -34,6 +53,22 @@ impl AppState {
     pub fn increment_request(&mut self) {
         self.total_requests += 1;
     }
+    
+    /// Copy the latest stats returned by the web API into the GUI state
+    pub fn apply_stats(&mut self, stats: &Stats) {
+        self.tor_connected = stats.tor_connected;
+        self.kill_switch_active = stats.kill_switch_active;
+        self.requests_blocked = stats.requests_blocked;
+        self.trackers_blocked = stats.trackers_blocked;
+        self.webrtc_blocked = stats.webrtc_blocked;
+        self.ipv6_blocked = stats.ipv6_blocked;
+        self.total_requests = stats.total_requests;
+        self.proxy_running = stats.proxy_running;
+        self.auto_proxy_enabled = stats.auto_proxy_enabled;
+        self.connection_state = stats.connection_state;
+        self.tor_path_length = stats.tor_path_length;
+        self.proxy_error = stats.proxy_error.clone();
+    }
 }
 
 impl Default for AppState {
@@ -48,17 +83,166 @@ impl Default for AppState {
             total_requests: 0,
             proxy_running: false,
             auto_proxy_enabled: false,
+            connection_state: ConnectionState::Disconnected,
+            tor_path_length: crate::tor_network::TOR_PATH_LENGTH,
+            proxy_error: None,
         }
     }
 }
 
+/// Control actions the GUI can send to the web API
+#[derive(Clone, Copy, Debug)]
+enum ControlRequest {
+    Connection(bool),
+    KillSwitch(bool),
+}
+
+impl ControlRequest {
+    async fn send(self, client: &reqwest::Client, api_base: &str) -> Result<Stats, String> {
+        let request = match self {
+            ControlRequest::Connection(connect) => client
+                .post(format!("{}/api/connection", api_base))
+                .json(&serde_json::json!({ "connect": connect })),
+            ControlRequest::KillSwitch(enabled) => client
+                .put(format!("{}/api/killswitch", api_base))
+                .json(&serde_json::json!({ "enabled": enabled })),
+        };
+        
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))?;
+        
+        response
+            .json::<Stats>()
+            .await
+            .map_err(|e| format!("Failed to parse response: {}", e))
+    }
+}
+
+/// State of the control request currently in flight (if any)
+#[derive(Default)]
+struct ControlStatus {
+    in_flight: bool,
+    error: Option<String>,
+}
+
 pub struct PrivacySuiteApp {
     state: Arc<RwLock<AppState>>,
+    control: Arc<Mutex<ControlStatus>>,
+    client: reqwest::Client,
+    runtime: tokio::runtime::Handle,
+    logs: Arc<Mutex<Vec<LogEntry>>>,
+    logs_polling: Arc<AtomicBool>,
+    last_log_poll: Option<Instant>,
+    log_category: Option<String>,
+    /// Endpoints the backend actually bound, re-read with every log poll
+    endpoints: RuntimeInfo,
 }
 
 impl PrivacySuiteApp {
     pub fn new(_cc: &eframe::CreationContext<'_>, state: Arc<RwLock<AppState>>) -> Self {
-        Self { state }
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+            .unwrap_or_default();
+        
+        Self {
+            state,
+            control: Arc::new(Mutex::new(ControlStatus::default())),
+            client,
+            runtime: tokio::runtime::Handle::current(),
+            logs: Arc::new(Mutex::new(Vec::new())),
+            logs_polling: Arc::new(AtomicBool::new(false)),
+            last_log_poll: None,
+            log_category: None,
+            endpoints: RuntimeInfo::load_default().unwrap_or_default(),
+        }
+    }
+    
+    /// Proxy address for apps on this machine
+    fn proxy_address(&self) -> String {
+        let port = self.endpoints.proxy_addr.map_or(DEFAULT_PROXY_PORT, |addr| addr.port());
+        format!("127.0.0.1:{}", port)
+    }
+    
+    /// Refresh the log buffer from the web API at most once per poll interval
+    fn poll_logs(&mut self, ctx: &egui::Context) {
+        if self.last_log_poll.map_or(false, |t| t.elapsed() < LOG_POLL_INTERVAL) {
+            return;
+        }
+        if self.logs_polling.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.last_log_poll = Some(Instant::now());
+        // The backend may have moved ports since the last poll
+        if let Some(endpoints) = RuntimeInfo::load_default() {
+            self.endpoints = endpoints;
+        }
+        
+        let api_base = self.endpoints.api_base();
+        let client = self.client.clone();
+        let logs = self.logs.clone();
+        let polling = self.logs_polling.clone();
+        let ctx = ctx.clone();
+        
+        self.runtime.spawn(async move {
+            let result = async {
+                client
+                    .get(format!("{}/api/logs", api_base))
+                    .send()
+                    .await?
+                    .json::<Vec<LogEntry>>()
+                    .await
+            }
+            .await;
+            
+            // Keep showing the previous logs if the backend is unreachable
+            if let Ok(entries) = result {
+                *logs.lock().unwrap_or_else(|e| e.into_inner()) = entries;
+                ctx.request_repaint();
+            }
+            
+            polling.store(false, Ordering::SeqCst);
+        });
+    }
+    
+    /// Send a control request in the background so the UI never blocks
+    fn send_control(&self, ctx: &egui::Context, request: ControlRequest) {
+        {
+            let mut control = self.control.lock().unwrap_or_else(|e| e.into_inner());
+            if control.in_flight {
+                return;
+            }
+            control.in_flight = true;
+            control.error = None;
+        }
+        
+        let api_base = self.endpoints.api_base();
+        let client = self.client.clone();
+        let state = self.state.clone();
+        let control = self.control.clone();
+        let ctx = ctx.clone();
+        
+        self.runtime.spawn(async move {
+            let result = request.send(&client, &api_base).await;
+            
+            let error = match result {
+                Ok(stats) => {
+                    state.write().await.apply_stats(&stats);
+                    None
+                }
+                Err(e) => Some(e),
+            };
+            
+            {
+                let mut control = control.lock().unwrap_or_else(|e| e.into_inner());
+                control.in_flight = false;
+                control.error = error;
+            }
+            
+            ctx.request_repaint();
+        });
     }
 }
 
@@ -70,6 +254,31 @@ impl eframe::App for PrivacySuiteApp {
         } else {
             AppState::default()
         };
+        
+        let (in_flight, control_error) = {
+            let control = self.control.lock().unwrap_or_else(|e| e.into_inner());
+            (control.in_flight, control.error.clone())
+        };
+        
+        self.poll_logs(ctx);
+        let proxy_address = self.proxy_address();
+        
+        // Filter the same way as `get_filtered_logs`: no category means all logs
+        let mut log_category = self.log_category.clone();
+        let visible_logs: Vec<LogEntry> = {
+            let logs = self.logs.lock().unwrap_or_else(|e| e.into_inner());
+            let filtered: Vec<&LogEntry> = logs
+                .iter()
+                .filter(|log| {
+                    log_category
+                        .as_ref()
+                        .map(|c| &log.category == c)
+                        .unwrap_or(true)
+                })
+                .collect();
+            let skip = filtered.len().saturating_sub(MAX_LOG_ROWS);
+            filtered.into_iter().skip(skip).cloned().collect()
+        };
 
         // Custom colors
         let green = egui::Color32::from_rgb(46, 204, 113);
@@ -91,6 +300,8 @@ impl eframe::App for PrivacySuiteApp {
                         ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                             if state.proxy_running {
                                 ui.colored_label(green, egui::RichText::new("● LIVE").size(14.0).strong());
+                            } else if state.connection_state == ConnectionState::Connecting {
+                                ui.colored_label(orange, egui::RichText::new("● CONNECTING").size(14.0).strong());
                             } else {
                                 ui.colored_label(red, egui::RichText::new("● OFF").size(14.0).strong());
                             }
@@ -130,6 +341,44 @@ impl eframe::App for PrivacySuiteApp {
                             });
                         });
 
+                    ui.add_space(8.0);
+
+                    // Connection controls
+                    ui.horizontal(|ui| {
+                        let connect_btn = egui::Button::new(egui::RichText::new("🔌 Connect").size(12.0))
+                            .fill(green)
+                            .min_size(egui::vec2(100.0, 30.0));
+                        let connecting = state.connection_state == ConnectionState::Connecting;
+                        if ui.add_enabled(!state.proxy_running && !connecting && !in_flight, connect_btn).clicked() {
+                            self.send_control(ctx, ControlRequest::Connection(true));
+                        }
+                        
+                        let disconnect_btn = egui::Button::new(egui::RichText::new("⏹ Disconnect").size(12.0))
+                            .fill(red)
+                            .min_size(egui::vec2(100.0, 30.0));
+                        if ui.add_enabled(!in_flight, disconnect_btn).clicked() {
+                            self.send_control(ctx, ControlRequest::Connection(false));
+                        }
+                        
+                        let mut kill_switch = state.kill_switch_active;
+                        if ui.add_enabled(!in_flight, egui::Checkbox::new(&mut kill_switch, "Kill switch")).changed() {
+                            self.send_control(ctx, ControlRequest::KillSwitch(kill_switch));
+                        }
+                        
+                        if in_flight {
+                            ui.spinner();
+                        }
+                    });
+                    
+                    if let Some(ref error) = control_error {
+                        ui.add_space(3.0);
+                        ui.colored_label(red, egui::RichText::new(format!("⚠ {}", error)).size(11.0));
+                    }
+                    if let (false, Some(error)) = (state.proxy_running, &state.proxy_error) {
+                        ui.add_space(3.0);
+                        ui.colored_label(red, egui::RichText::new(format!("⚠ {}", error)).size(11.0));
+                    }
+
                     ui.add_space(10.0);
 
                     // Compact statistics
@@ -233,8 +482,9 @@ impl eframe::App for PrivacySuiteApp {
                                 .num_columns(2)
                                 .spacing([5.0, 3.0])
                                 .show(ui, |ui| {
+                                    let multi_hop = format!("Multi-hop Tor ({})", state.tor_path_length);
                                     let features = [
-                                        ("🔄", "Multi-hop Tor (3)"),
+                                        ("🔄", multi_hop.as_str()),
                                         ("🎭", "Fingerprint randomization"),
                                         ("🚫", "Tracker blocking"),
                                         ("🔒", "DNS encryption"),
@@ -269,7 +519,7 @@ impl eframe::App for PrivacySuiteApp {
                         .show(ui, |ui| {
                             ui.horizontal(|ui| {
                                 ui.label(egui::RichText::new("Proxy:").strong());
-                                ui.label(egui::RichText::new("127.0.0.1:8888").monospace().color(blue));
+                                ui.label(egui::RichText::new(&proxy_address).monospace().color(blue));
                             });
                             ui.add_space(3.0);
                             if state.auto_proxy_enabled {
@@ -293,7 +543,7 @@ impl eframe::App for PrivacySuiteApp {
                             .fill(blue)
                             .min_size(egui::vec2(100.0, 30.0));
                         if ui.add(copy_btn).clicked() {
-                            ui.output_mut(|o| o.copied_text = "127.0.0.1:8888".to_string());
+                            ui.output_mut(|o| o.copied_text = proxy_address.clone());
                         }
                         
                         let help_btn = egui::Button::new(egui::RichText::new("❓ Help").size(12.0))
@@ -304,6 +554,67 @@ impl eframe::App for PrivacySuiteApp {
                         }
                     });
 
+                    ui.add_space(10.0);
+
+                    // Live logs
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new("Live Logs").size(16.0).strong());
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            egui::ComboBox::from_id_source("log_category_filter")
+                                .selected_text(log_category.as_deref().unwrap_or("all"))
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut log_category, None, "all");
+                                    for category in LOG_CATEGORIES {
+                                        ui.selectable_value(&mut log_category, Some(category.to_string()), category);
+                                    }
+                                });
+                        });
+                    });
+                    ui.add_space(5.0);
+                    
+                    egui::Frame::none()
+                        .fill(dark_bg)
+                        .rounding(6.0)
+                        .inner_margin(10.0)
+                        .show(ui, |ui| {
+                            // Sticks to the newest entry unless the user scrolls up
+                            egui::ScrollArea::vertical()
+                                .id_source("log_panel")
+                                .max_height(200.0)
+                                .auto_shrink([false, true])
+                                .stick_to_bottom(true)
+                                .show(ui, |ui| {
+                                    if visible_logs.is_empty() {
+                                        ui.label(egui::RichText::new("No logs yet").size(11.0).color(egui::Color32::GRAY));
+                                    }
+                                    
+                                    for log in &visible_logs {
+                                        let level_color = match log.level.as_str() {
+                                            "error" => red,
+                                            "warn" => orange,
+                                            _ => egui::Color32::from_gray(200),
+                                        };
+                                        let category_color = match log.category.as_str() {
+                                            "tracker" => red,
+                                            "webrtc" => orange,
+                                            "ipv6" => purple,
+                                            "security" => red,
+                                            "network" => blue,
+                                            _ => green,
+                                        };
+                                        
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new(&log.timestamp).size(10.0).monospace().color(egui::Color32::GRAY));
+                                            ui.label(egui::RichText::new(format!("[{}]", log.category)).size(10.0).color(category_color));
+                                            ui.label(egui::RichText::new(&log.message).size(11.0).color(level_color));
+                                            if log.count > 1 {
+                                                ui.label(egui::RichText::new(format!("×{}", log.count)).size(10.0).color(egui::Color32::GRAY));
+                                            }
+                                        });
+                                    }
+                                });
+                        });
+
                     ui.add_space(8.0);
                     ui.separator();
                     ui.add_space(5.0);
@@ -317,6 +628,8 @@ impl eframe::App for PrivacySuiteApp {
                 });
         });
 
+        self.log_category = log_category;
+
         // Request repaint to keep UI updated
         ctx.request_repaint_after(std::time::Duration::from_secs(1));
     }
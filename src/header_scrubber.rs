@@ -0,0 +1,70 @@
+use hyper::header::{HeaderMap, HeaderName};
+
+/// Response headers removed unless `Config::scrub_response_headers` says
+/// otherwise: both make the browser report back to the site (network errors,
+/// CSP violations) on its own, outside of any page load
+pub const DEFAULT_SCRUBBED_HEADERS: [&str; 2] = ["report-to", "nel"];
+
+pub fn default_scrubbed_headers() -> Vec<String> {
+    DEFAULT_SCRUBBED_HEADERS.iter().map(|name| name.to_string()).collect()
+}
+
+/// Remove every header named in `names` (case-insensitive), returning how
+/// many header lines went. Names that aren't valid header names are skipped.
+pub fn scrub_headers(headers: &mut HeaderMap, names: &[String]) -> usize {
+    names
+        .iter()
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .map(|name| {
+            let removed = headers.get_all(&name).iter().count();
+            headers.remove(&name);
+            removed
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_headers() -> HeaderMap {
+        [
+            ("content-type", "text/html"),
+            ("report-to", r#"{"group":"default","max_age":31536000}"#),
+            ("nel", r#"{"report_to":"default","max_age":31536000}"#),
+            ("server-timing", "db;dur=53"),
+            ("server-timing", "app;dur=47"),
+            ("set-cookie", "uid=42; Max-Age=31536000"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.parse::<HeaderName>().unwrap(), value.parse().unwrap()))
+        .collect()
+    }
+
+    #[test]
+    fn test_default_headers_scrubbed() {
+        let mut headers = response_headers();
+        assert_eq!(scrub_headers(&mut headers, &default_scrubbed_headers()), 2);
+        assert!(headers.get("report-to").is_none());
+        assert!(headers.get("nel").is_none());
+
+        // Everything else passes through untouched
+        assert_eq!(headers.get("content-type").unwrap(), "text/html");
+        assert_eq!(headers.get_all("server-timing").iter().count(), 2);
+        assert!(headers.get("set-cookie").is_some());
+    }
+
+    #[test]
+    fn test_configured_headers_scrubbed() {
+        let mut headers = response_headers();
+        let names = vec!["Server-Timing".to_string(), " NEL ".to_string(), "not a header".to_string()];
+        assert_eq!(scrub_headers(&mut headers, &names), 3);
+        assert!(headers.get("server-timing").is_none());
+        assert!(headers.get("nel").is_none());
+        assert!(headers.get("report-to").is_some());
+
+        // An empty list leaves responses alone
+        assert_eq!(scrub_headers(&mut headers, &[]), 0);
+        assert_eq!(headers.len(), 3);
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Hosts remembered per session; the least recently seen is dropped beyond this
+pub const MAX_TRACKED_HOSTS: usize = 500;
+
+/// Traffic to one destination host this session, as reported by `/api/hosts`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostStats {
+    pub host: String,
+    pub requests: u64,
+    pub bytes: u64,
+    pub blocked: u64,
+    /// Local time of the last request, e.g. `14:03:07.512`
+    pub last_seen: String,
+}
+
+struct HostEntry {
+    stats: HostStats,
+    /// Value of `Hosts::clock` when the host was last seen, for LRU eviction
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Hosts {
+    entries: HashMap<String, HostEntry>,
+    clock: u64,
+}
+
+/// Per-destination request, byte and block counts, bounded to the most
+/// recently seen `max_hosts` hosts
+#[derive(Clone)]
+pub struct HostTracker {
+    hosts: Arc<Mutex<Hosts>>,
+    max_hosts: usize,
+}
+
+impl HostTracker {
+    pub fn new(max_hosts: usize) -> Self {
+        Self {
+            hosts: Arc::new(Mutex::new(Hosts::default())),
+            max_hosts: max_hosts.max(1),
+        }
+    }
+
+    /// Count a request to `host`
+    pub fn record_request(&self, host: &str) {
+        self.update(host, |stats| stats.requests += 1);
+    }
+
+    /// Count a request to `host` that a protection or filter blocked
+    pub fn record_blocked(&self, host: &str) {
+        self.update(host, |stats| stats.blocked += 1);
+    }
+
+    /// Add transferred bytes to `host`
+    pub fn record_bytes(&self, host: &str, bytes: u64) {
+        self.update(host, |stats| stats.bytes += bytes);
+    }
+
+    fn update(&self, host: &str, change: impl FnOnce(&mut HostStats)) {
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        hosts.clock += 1;
+        let now = hosts.clock;
+
+        if !hosts.entries.contains_key(host) && hosts.entries.len() >= self.max_hosts {
+            let oldest = hosts
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(host, _)| host.clone());
+            if let Some(oldest) = oldest {
+                hosts.entries.remove(&oldest);
+            }
+        }
+
+        let entry = hosts.entries.entry(host.to_string()).or_insert_with(|| HostEntry {
+            stats: HostStats { host: host.to_string(), ..HostStats::default() },
+            last_used: now,
+        });
+        entry.last_used = now;
+        entry.stats.last_seen = chrono::Local::now().format("%H:%M:%S%.3f").to_string();
+        change(&mut entry.stats);
+    }
+
+    /// Up to `limit` hosts, heaviest bandwidth users first
+    pub fn top(&self, limit: usize) -> Vec<HostStats> {
+        let hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        let mut top: Vec<HostStats> = hosts.entries.values().map(|entry| entry.stats.clone()).collect();
+        top.sort_by(|a, b| {
+            b.bytes
+                .cmp(&a.bytes)
+                .then(b.requests.cmp(&a.requests))
+                .then_with(|| a.host.cmp(&b.host))
+        });
+        top.truncate(limit);
+        top
+    }
+
+    /// Forget every host, e.g. when a new session starts
+    pub fn clear(&self) {
+        self.hosts.lock().unwrap_or_else(|e| e.into_inner()).entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregates_per_host() {
+        let tracker = HostTracker::new(MAX_TRACKED_HOSTS);
+
+        tracker.record_request("example.com");
+        tracker.record_bytes("example.com", 1_000);
+        tracker.record_request("example.com");
+        tracker.record_bytes("example.com", 500);
+        tracker.record_request("doubleclick.net");
+        tracker.record_blocked("doubleclick.net");
+        tracker.record_request("news.example.org");
+        tracker.record_bytes("news.example.org", 20_000);
+
+        let top = tracker.top(10);
+        let hosts: Vec<&str> = top.iter().map(|stats| stats.host.as_str()).collect();
+        assert_eq!(hosts, ["news.example.org", "example.com", "doubleclick.net"]);
+        assert_eq!((top[1].requests, top[1].bytes, top[1].blocked), (2, 1_500, 0));
+        assert_eq!((top[2].requests, top[2].bytes, top[2].blocked), (1, 0, 1));
+        assert!(!top[0].last_seen.is_empty());
+
+        assert_eq!(tracker.top(1).len(), 1);
+        tracker.clear();
+        assert!(tracker.top(10).is_empty());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_seen_host() {
+        let tracker = HostTracker::new(2);
+
+        tracker.record_request("a.com");
+        tracker.record_request("b.com");
+        // Seeing a.com again makes b.com the least recently used
+        tracker.record_bytes("a.com", 10);
+        tracker.record_request("c.com");
+
+        let mut hosts: Vec<String> = tracker.top(10).into_iter().map(|stats| stats.host).collect();
+        hosts.sort();
+        assert_eq!(hosts, ["a.com", "c.com"]);
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+use crate::tor_network::Clock;
+use crate::web_api::ApiState;
+
+/// Longest wait between two idle checks
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// When the proxy last handled a request
+#[derive(Clone)]
+pub struct IdleTimer {
+    last_activity: Arc<Mutex<Instant>>,
+    clock: Clock,
+}
+
+impl IdleTimer {
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(Instant::now))
+    }
+
+    pub fn with_clock(clock: Clock) -> Self {
+        Self {
+            last_activity: Arc::new(Mutex::new(clock())),
+            clock,
+        }
+    }
+
+    /// Restart the idle time, e.g. on a proxied request
+    pub fn touch(&self) {
+        *self.last_activity.lock().unwrap_or_else(|e| e.into_inner()) = (self.clock)();
+    }
+
+    /// Time since the last `touch`
+    pub fn idle_for(&self) -> Duration {
+        let last_activity = *self.last_activity.lock().unwrap_or_else(|e| e.into_inner());
+        (self.clock)().saturating_duration_since(last_activity)
+    }
+}
+
+impl Default for IdleTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Disconnect the session once it has been `idle_after` without a proxied
+/// request. Returns whether it did.
+pub async fn check(state: &ApiState, idle_after: Duration) -> bool {
+    if !state.is_connected().await {
+        return false;
+    }
+    let idle = state.idle.idle_for();
+    if idle < idle_after {
+        return false;
+    }
+    state.disconnect_for_idle(idle).await;
+    true
+}
+
+/// Check for an idle session for as long as the app runs (`Config::idle_disconnect_secs`)
+pub async fn watch(state: ApiState, idle_after: Duration) {
+    info!("⏱️ Disconnecting after {}s without requests", idle_after.as_secs());
+    let mut interval = tokio::time::interval(CHECK_INTERVAL.min(idle_after));
+    loop {
+        interval.tick().await;
+        check(&state, idle_after).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[tokio::test]
+    async fn test_idle_session_disconnects_and_requests_reset_the_timer() {
+        let start = Instant::now();
+        let offset = Arc::new(Mutex::new(Duration::ZERO));
+        let clock_offset = offset.clone();
+        let clock: Clock = Arc::new(move || start + *clock_offset.lock().unwrap());
+        let advance_to = |secs| *offset.lock().unwrap() = Duration::from_secs(secs);
+
+        let state = ApiState::new(Config::default()).with_idle_timer(IdleTimer::with_clock(clock));
+        *state.proxy_handle.write().await = Some(tokio::spawn(std::future::pending()));
+        let idle_after = Duration::from_secs(60);
+
+        advance_to(45);
+        assert!(!check(&state, idle_after).await);
+
+        // A request 45s in puts the deadline at 105s
+        state.idle.touch();
+        advance_to(90);
+        assert!(!check(&state, idle_after).await);
+        assert!(state.is_connected().await);
+
+        advance_to(105);
+        assert!(check(&state, idle_after).await);
+        assert!(!state.is_connected().await);
+        assert!(state.stats_snapshot().await.kill_switch_active);
+        let logs = state.logs.read().await.clone();
+        assert!(logs.iter().any(|log| log.category == "security" && log.message.contains("No requests for 60s")), "{:?}", logs);
+
+        // Nothing more to do once disconnected
+        advance_to(500);
+        assert!(!check(&state, idle_after).await);
+    }
+}
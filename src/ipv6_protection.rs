@@ -1,50 +1,104 @@
 use tracing::{info, warn};
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
+use crate::config::Ipv6AllowlistConfig;
+
+/// Where an IPv6 connection would be made from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ipv6Origin {
+    /// The client machine connects itself, exposing its own IPv6 address
+    Client,
+    /// A Tor exit relay connects on our behalf; the client's address never
+    /// reaches the destination
+    TorExit,
+}
 
 /// IPv6 Leak Protection
 /// 
 /// Many VPNs/proxies only route IPv4, causing IPv6 traffic to leak the real IP.
-/// This module detects and blocks IPv6 requests.
+/// This module detects and blocks IPv6 requests. Local ranges (loopback,
+/// link-local and optionally unique local) can be allowlisted since they
+/// never leave the machine or LAN.
 #[derive(Clone)]
 pub struct Ipv6Protection {
     enabled: bool,
+    allowlist: Ipv6AllowlistConfig,
+    allow_over_tor: bool,
     blocked_count: std::sync::Arc<std::sync::atomic::AtomicU64>,
 }
 
 impl Ipv6Protection {
     pub fn new(enabled: bool) -> Self {
         if enabled {
-            info!("🛡️ IPv6 leak protection enabled - All IPv6 traffic will be blocked");
+            info!("🛡️ IPv6 leak protection enabled - IPv6 outside the allowlisted local ranges will be blocked");
         }
         Self {
             enabled,
+            allowlist: Ipv6AllowlistConfig::default(),
+            allow_over_tor: false,
             blocked_count: std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0)),
         }
     }
 
+    /// Set which local IPv6 ranges are exempt from blocking
+    pub fn with_allowlist(mut self, allowlist: Ipv6AllowlistConfig) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    /// Allow IPv6 destinations that are reached through a Tor exit
+    pub fn with_tor_exit_ipv6(mut self, allow_over_tor: bool) -> Self {
+        if allow_over_tor {
+            info!("🌐 IPv6 destinations allowed through Tor exits (client-side IPv6 still blocked)");
+        }
+        self.allow_over_tor = allow_over_tor;
+        self
+    }
+
+    /// Check if an IPv6 address falls in an allowlisted local range
+    pub fn is_allowed(&self, ip: &Ipv6Addr) -> bool {
+        let first_segment = ip.segments()[0];
+        
+        (self.allowlist.loopback && ip.is_loopback())
+            || (self.allowlist.link_local && (first_segment & 0xffc0) == 0xfe80)
+            || (self.allowlist.unique_local && (first_segment & 0xfe00) == 0xfc00)
+    }
+
+    /// Check if a connection to `host` should be blocked given who makes it.
+    ///
+    /// Client-origin IPv6 is always subject to blocking; exit-origin IPv6 is
+    /// only blocked when `with_tor_exit_ipv6` hasn't enabled it.
+    pub fn should_block(&self, host: &str, origin: Ipv6Origin) -> bool {
+        if origin == Ipv6Origin::TorExit && self.allow_over_tor {
+            return false;
+        }
+        self.should_block_ipv6(host)
+    }
+
+    /// Like `should_block`, without counting or logging a block
+    pub fn would_block(&self, host: &str, origin: Ipv6Origin) -> bool {
+        !(origin == Ipv6Origin::TorExit && self.allow_over_tor) && self.blocks(host)
+    }
+
     /// Check if an IP address or host is IPv6 and should be blocked
     pub fn should_block_ipv6(&self, host: &str) -> bool {
-        if !self.enabled {
+        if !self.blocks(host) {
             return false;
         }
+        self.blocked_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        warn!("🚫 Blocked IPv6 address: {}", host);
+        true
+    }
 
-        // Try to parse as IP address
-        if let Ok(ip_addr) = host.parse::<IpAddr>() {
-            if ip_addr.is_ipv6() {
-                self.blocked_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                warn!("🚫 Blocked IPv6 address: {}", host);
-                return true;
-            }
+    /// Whether `host` is an IPv6 address (with or without brackets and
+    /// port) outside the allowlisted ranges
+    fn blocks(&self, host: &str) -> bool {
+        if !self.enabled {
+            return false;
         }
-
-        // Check for IPv6 notation in host (e.g., [2001:db8::1])
-        if host.starts_with('[') && host.contains(':') {
-            self.blocked_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            warn!("🚫 Blocked IPv6 host notation: {}", host);
-            return true;
+        match strip_brackets_and_port(host).parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => !self.is_allowed(&ip),
+            _ => false,
         }
-
-        false
     }
 
     /// Get number of blocked IPv6 requests
@@ -79,6 +133,15 @@ impl Ipv6Protection {
     }
 }
 
+/// The address in `[2001:db8::1]` or `[2001:db8::1]:443`; anything else as it is
+fn strip_brackets_and_port(host: &str) -> &str {
+    match host.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((address, "")) => address,
+        Some((address, port)) if port.strip_prefix(':').is_some_and(|port| port.parse::<u16>().is_ok()) => address,
+        _ => host,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,14 +150,59 @@ mod tests {
     fn test_blocks_ipv6_addresses() {
         let protection = Ipv6Protection::new(true);
         assert!(protection.should_block_ipv6("2001:db8::1"));
-        assert!(protection.should_block_ipv6("::1"));
-        assert!(protection.should_block_ipv6("fe80::1"));
+        assert!(protection.should_block_ipv6("2606:4700::1111"));
     }
 
     #[test]
     fn test_blocks_ipv6_brackets() {
         let protection = Ipv6Protection::new(true);
         assert!(protection.should_block_ipv6("[2001:db8::1]"));
+        assert!(protection.should_block_ipv6("[2001:db8::1]:443"));
+    }
+
+    #[test]
+    fn test_allows_loopback_and_link_local() {
+        let protection = Ipv6Protection::new(true);
+        assert!(!protection.should_block_ipv6("::1"));
+        assert!(!protection.should_block_ipv6("[::1]"));
+        assert!(!protection.should_block_ipv6("[::1]:8080"));
+        assert!(!protection.should_block_ipv6("[fe80::1]:443"));
+        assert!(!protection.should_block_ipv6("fe80::1"));
+        assert!(!protection.should_block_ipv6("febf::1"));
+        assert_eq!(protection.get_blocked_count(), 0);
+    }
+
+    #[test]
+    fn test_unique_local_is_configurable() {
+        let protection = Ipv6Protection::new(true);
+        assert!(protection.should_block_ipv6("fd12:3456::1"));
+
+        let protection = Ipv6Protection::new(true).with_allowlist(Ipv6AllowlistConfig {
+            loopback: false,
+            link_local: false,
+            unique_local: true,
+        });
+        assert!(!protection.should_block_ipv6("fd12:3456::1"));
+        assert!(protection.should_block_ipv6("::1"));
+        assert!(protection.should_block_ipv6("fe80::1"));
+    }
+
+    #[test]
+    fn test_client_origin_ipv6_always_blocked() {
+        let protection = Ipv6Protection::new(true).with_tor_exit_ipv6(true);
+        assert!(protection.should_block("2001:db8::1", Ipv6Origin::Client));
+        assert!(protection.should_block("[2606:4700::1111]", Ipv6Origin::Client));
+    }
+
+    #[test]
+    fn test_tor_exit_ipv6_is_configurable() {
+        let protection = Ipv6Protection::new(true);
+        assert!(protection.should_block("2001:db8::1", Ipv6Origin::TorExit));
+
+        let protection = Ipv6Protection::new(true).with_tor_exit_ipv6(true);
+        assert!(!protection.should_block("2001:db8::1", Ipv6Origin::TorExit));
+        assert!(!protection.should_block("[2606:4700::1111]", Ipv6Origin::TorExit));
+        assert_eq!(protection.get_blocked_count(), 0);
     }
 
     #[test]
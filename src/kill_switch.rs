@@ -1,6 +1,9 @@
 use tracing::{info, warn};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{watch, RwLock};
 
 /// Kill Switch - Blocks all traffic if Tor connection fails
 /// 
@@ -9,13 +12,25 @@ use tokio::sync::RwLock;
 #[derive(Clone)]
 pub struct KillSwitch {
     state: Arc<RwLock<KillSwitchState>>,
+    /// Whether traffic flows (Tor up, or the kill switch off), for held
+    /// requests to wait on
+    flowing: Arc<watch::Sender<bool>>,
+    /// Requests currently held for a reconnect
+    held: Arc<AtomicUsize>,
 }
 
 #[derive(Debug, Clone)]
 struct KillSwitchState {
     tor_connected: bool,
     kill_switch_active: bool,
+    /// Block LAN destinations too while Tor is down (`Config::killswitch_block_lan`)
+    block_lan: bool,
     blocked_requests: u64,
+    /// How long a request blocked by an outage waits for Tor to come back
+    /// before it fails (`Config::reconnect_hold_secs`); zero fails at once
+    hold_timeout: Duration,
+    /// Requests held at the same time before new ones fail at once
+    hold_max: usize,
 }
 
 impl KillSwitch {
@@ -25,8 +40,13 @@ impl KillSwitch {
             state: Arc::new(RwLock::new(KillSwitchState {
                 tor_connected: false,
                 kill_switch_active: true,
+                block_lan: true,
                 blocked_requests: 0,
+                hold_timeout: Duration::ZERO,
+                hold_max: 0,
             })),
+            flowing: Arc::new(watch::channel(false).0),
+            held: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -34,6 +54,7 @@ impl KillSwitch {
     pub async fn set_tor_status(&self, connected: bool) {
         let mut state = self.state.write().await;
         state.tor_connected = connected;
+        self.flowing.send_replace(connected || !state.kill_switch_active);
         
         if connected {
             info!("✅ Kill switch: Tor connected, allowing traffic");
@@ -42,27 +63,97 @@ impl KillSwitch {
         }
     }
 
+    /// Choose whether LAN destinations stay reachable while Tor is down
+    pub async fn set_block_lan(&self, block_lan: bool) {
+        self.state.write().await.block_lan = block_lan;
+        if !block_lan {
+            info!("🏠 Kill switch: LAN destinations stay reachable during Tor outages");
+        }
+    }
+
+    /// Hold up to `max_requests` requests for up to `timeout` while Tor
+    /// reconnects, instead of failing them the moment it drops
+    pub async fn set_reconnect_hold(&self, timeout: Duration, max_requests: usize) {
+        let mut state = self.state.write().await;
+        state.hold_timeout = timeout;
+        state.hold_max = max_requests;
+        if !timeout.is_zero() && max_requests > 0 {
+            info!("⏳ Kill switch: holding up to {} requests for {}s during Tor reconnects", max_requests, timeout.as_secs());
+        }
+    }
+
     /// Check if traffic should be allowed
     pub async fn should_allow_traffic(&self) -> bool {
-        let mut state = self.state.write().await;
+        self.should_allow_traffic_to(None).await
+    }
+
+    /// Check if traffic to `host` (normalized, if known) should be allowed.
+    /// While Tor is down only LAN destinations may pass, and only when
+    /// `set_block_lan(false)` was chosen.
+    pub async fn should_allow_traffic_to(&self, host: Option<&str>) -> bool {
+        if self.passes(host).await {
+            return true;
+        }
+        self.record_block().await;
+        false
+    }
+
+    /// Like `should_allow_traffic_to`, but with a reconnect hold set a
+    /// blocked request first waits for Tor to come back, as long as there
+    /// is room in the hold queue
+    pub async fn allow_or_hold(&self, host: Option<&str>) -> bool {
+        if self.passes(host).await {
+            return true;
+        }
+        
+        let (timeout, max) = {
+            let state = self.state.read().await;
+            (state.hold_timeout, state.hold_max)
+        };
+        let slot = if timeout.is_zero() { None } else { HoldSlot::acquire(&self.held, max) };
+        if let Some(_slot) = slot {
+            info!("⏳ Kill switch: holding request until Tor reconnects ({}s at most)", timeout.as_secs());
+            let mut flowing = self.flowing.subscribe();
+            let resumed = matches!(tokio::time::timeout(timeout, flowing.wait_for(|flowing| *flowing)).await, Ok(Ok(_)));
+            if resumed && self.passes(host).await {
+                info!("✅ Kill switch: releasing held request, Tor is back");
+                return true;
+            }
+        }
+        
+        self.record_block().await;
+        false
+    }
+
+    async fn passes(&self, host: Option<&str>) -> bool {
+        let state = self.state.read().await;
         
         if !state.kill_switch_active {
             return true; // Kill switch disabled
         }
 
         if !state.tor_connected {
-            state.blocked_requests += 1;
-            warn!("🚫 Kill switch: Blocked request (Tor not connected) - Total blocked: {}", state.blocked_requests);
+            if !state.block_lan && host.is_some_and(is_lan_destination) {
+                info!("🏠 Kill switch: allowing LAN destination {} (Tor not connected)", host.unwrap_or_default());
+                return true;
+            }
             return false;
         }
 
         true
     }
 
+    async fn record_block(&self) {
+        let mut state = self.state.write().await;
+        state.blocked_requests += 1;
+        warn!("🚫 Kill switch: Blocked request (Tor not connected) - Total blocked: {}", state.blocked_requests);
+    }
+
     /// Enable or disable kill switch
     pub async fn set_enabled(&self, enabled: bool) {
         let mut state = self.state.write().await;
         state.kill_switch_active = enabled;
+        self.flowing.send_replace(state.tor_connected || !enabled);
         
         if enabled {
             info!("🔒 Kill switch ENABLED - Will block traffic if Tor disconnects");
@@ -86,6 +177,89 @@ impl KillSwitch {
         let state = self.state.read().await;
         state.tor_connected
     }
+
+    /// Keep the Tor status in step with `ready` (a transport's
+    /// `ready_for_traffic`) until the returned feed is dropped, so requests
+    /// made while the Tor client is cut off mid-session are held or blocked
+    pub fn follow(&self, mut ready: watch::Receiver<bool>) -> TorStatusFeed {
+        let kill_switch = self.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let connected = *ready.borrow_and_update();
+                if connected != kill_switch.is_tor_connected().await {
+                    kill_switch.set_tor_status(connected).await;
+                }
+                if ready.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+        TorStatusFeed { task }
+    }
+}
+
+/// Feeds a transport's readiness to a kill switch (`KillSwitch::follow`)
+/// until dropped
+pub struct TorStatusFeed {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for TorStatusFeed {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A place in the reconnect hold queue, given back on drop
+struct HoldSlot<'a>(&'a AtomicUsize);
+
+impl<'a> HoldSlot<'a> {
+    /// A slot if fewer than `max` requests are held
+    fn acquire(held: &'a AtomicUsize, max: usize) -> Option<Self> {
+        held.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| (count < max).then_some(count + 1))
+            .ok()
+            .map(|_| HoldSlot(held))
+    }
+}
+
+impl Drop for HoldSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Whether `host` is on the local network: a private, loopback or
+/// link-local address, or a name that only resolves locally
+pub fn is_lan_destination(host: &str) -> bool {
+    let unbracketed = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    match unbracketed.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        Ok(IpAddr::V6(ip)) => {
+            let segment = ip.segments()[0];
+            // Unique local (fc00::/7) and link-local (fe80::/10)
+            ip.is_loopback() || (segment & 0xfe00) == 0xfc00 || (segment & 0xffc0) == 0xfe80
+        }
+        Err(_) => {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            host == "localhost" || [".local", ".lan", ".home.arpa", ".localhost"].iter().any(|suffix| host.ends_with(suffix))
+        }
+    }
+}
+
+/// Whether `host` is this machine itself: a loopback or unspecified
+/// address, `localhost` or a name under `.localhost`
+pub fn is_loopback_destination(host: &str) -> bool {
+    let unbracketed = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    match unbracketed.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => ip.is_loopback() || ip.is_unspecified(),
+        Ok(IpAddr::V6(ip)) => {
+            ip.is_loopback() || ip.is_unspecified() || ip.to_ipv4_mapped().is_some_and(|v4| v4.is_loopback() || v4.is_unspecified())
+        }
+        Err(_) => {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            host == "localhost" || host.ends_with(".localhost")
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -100,3 +274,67 @@ impl Default for KillSwitch {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lan_destinations() {
+        for host in ["192.168.1.10", "10.0.0.1", "172.16.5.4", "127.0.0.1", "169.254.1.1", "[fe80::1]", "[fd00::7]", "nas.local", "printer.lan", "router.home.arpa"] {
+            assert!(is_lan_destination(host), "{}", host);
+        }
+        for host in ["8.8.8.8", "172.32.0.1", "[2001:db8::1]", "example.com", "local.example.com"] {
+            assert!(!is_lan_destination(host), "{}", host);
+        }
+        for host in ["127.0.0.1", "127.1.2.3", "0.0.0.0", "[::1]", "[::ffff:127.0.0.1]", "localhost", "app.localhost."] {
+            assert!(is_loopback_destination(host), "{}", host);
+        }
+        for host in ["192.168.1.10", "[fe80::1]", "nas.local", "localhost.example.com"] {
+            assert!(!is_loopback_destination(host), "{}", host);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outage_lan_policy() {
+        let kill_switch = KillSwitch::new();
+        kill_switch.set_tor_status(false).await;
+
+        // Total lockdown (the default): nothing passes, LAN included
+        assert!(!kill_switch.should_allow_traffic_to(Some("192.168.1.10")).await);
+        assert!(!kill_switch.should_allow_traffic_to(Some("example.com")).await);
+
+        // LAN preserved: only LAN destinations pass
+        kill_switch.set_block_lan(false).await;
+        assert!(kill_switch.should_allow_traffic_to(Some("192.168.1.10")).await);
+        assert!(!kill_switch.should_allow_traffic_to(Some("example.com")).await);
+        assert!(!kill_switch.should_allow_traffic().await);
+        assert_eq!(kill_switch.get_stats().await.blocked_requests, 3);
+
+        kill_switch.set_tor_status(true).await;
+        assert!(kill_switch.should_allow_traffic_to(Some("example.com")).await);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_hold_is_bounded() {
+        let kill_switch = KillSwitch::new();
+        kill_switch.set_tor_status(false).await;
+
+        // No hold set: blocked at once
+        assert!(!kill_switch.allow_or_hold(Some("example.com")).await);
+
+        // One slot: the first request waits, a second one fails right away
+        kill_switch.set_reconnect_hold(Duration::from_millis(100), 1).await;
+        let waiting = kill_switch.clone();
+        let held = tokio::spawn(async move { waiting.allow_or_hold(Some("example.com")).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let started = std::time::Instant::now();
+        assert!(!kill_switch.allow_or_hold(Some("example.com")).await);
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        // Tor stays down past the timeout, so the held request fails too
+        assert!(!held.await.unwrap());
+        assert_eq!(kill_switch.get_stats().await.blocked_requests, 3);
+        assert_eq!(kill_switch.held.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+
+/// An address of this machine that other devices on the LAN can reach
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LanAddress {
+    /// Interface name, e.g. `eth0` or `Wi-Fi`
+    pub interface: String,
+    pub ip: IpAddr,
+}
+
+/// What `/api/network-info` reports: where other devices can point their proxy settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkInfo {
+    pub addresses: Vec<LanAddress>,
+    pub proxy_port: u16,
+    /// `ip:port` for each address, ready to type into a device's proxy settings
+    pub proxy_endpoints: Vec<String>,
+}
+
+impl NetworkInfo {
+    pub fn new(addresses: Vec<LanAddress>, proxy_port: u16) -> Self {
+        let proxy_endpoints = addresses
+            .iter()
+            .map(|address| SocketAddr::new(address.ip, proxy_port).to_string())
+            .collect();
+        Self { addresses, proxy_port, proxy_endpoints }
+    }
+}
+
+/// Whether other LAN devices could reach this machine at `ip`
+fn is_lan_reachable(ip: &IpAddr) -> bool {
+    if ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() {
+        return false;
+    }
+    match ip {
+        IpAddr::V4(ip) => !ip.is_link_local() && !ip.is_broadcast(),
+        // Link-local (fe80::/10) addresses need a zone id to be usable
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) != 0xfe80,
+    }
+}
+
+/// The usable LAN addresses among `interfaces`, IPv4 first. IPv6 addresses
+/// are only included with `include_ipv6`.
+pub fn usable_addresses(
+    interfaces: impl IntoIterator<Item = (String, IpAddr)>,
+    include_ipv6: bool,
+) -> Vec<LanAddress> {
+    let mut addresses: Vec<LanAddress> = Vec::new();
+    for (interface, ip) in interfaces {
+        let wanted = is_lan_reachable(&ip) && (ip.is_ipv4() || include_ipv6);
+        if wanted && !addresses.iter().any(|address| address.ip == ip) {
+            addresses.push(LanAddress { interface, ip });
+        }
+    }
+    // Stable, so interfaces keep their order within each family
+    addresses.sort_by_key(|address| address.ip.is_ipv6());
+    addresses
+}
+
+/// Every usable LAN address of this machine, across all interfaces
+pub fn lan_addresses(include_ipv6: bool) -> Vec<LanAddress> {
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => usable_addresses(
+            interfaces.into_iter().map(|interface| {
+                let ip = interface.ip();
+                (interface.name, ip)
+            }),
+            include_ipv6,
+        ),
+        Err(e) => {
+            tracing::warn!("Failed to list network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interfaces() -> Vec<(String, IpAddr)> {
+        [
+            ("lo", "127.0.0.1"),
+            ("lo", "::1"),
+            ("eth0", "fe80::1c2b:3dff:fe4e:5f60"),
+            ("eth0", "2001:db8::42"),
+            ("eth0", "192.168.1.20"),
+            ("wlan0", "10.0.0.7"),
+            ("wlan0", "fd12:3456:789a::7"),
+            ("docker0", "169.254.3.1"),
+            // Reported twice, e.g. by a bridge
+            ("br0", "10.0.0.7"),
+        ]
+        .iter()
+        .map(|(name, ip)| (name.to_string(), ip.parse().unwrap()))
+        .collect()
+    }
+
+    #[test]
+    fn test_lists_every_usable_ipv4_address() {
+        let addresses = usable_addresses(interfaces(), false);
+        let found: Vec<(&str, String)> = addresses
+            .iter()
+            .map(|address| (address.interface.as_str(), address.ip.to_string()))
+            .collect();
+        assert_eq!(found, [("eth0", "192.168.1.20".to_string()), ("wlan0", "10.0.0.7".to_string())]);
+    }
+
+    #[test]
+    fn test_ipv6_addresses_only_when_enabled() {
+        let addresses = usable_addresses(interfaces(), true);
+        let ips: Vec<String> = addresses.iter().map(|address| address.ip.to_string()).collect();
+        assert_eq!(ips, ["192.168.1.20", "10.0.0.7", "2001:db8::42", "fd12:3456:789a::7"]);
+
+        let info = NetworkInfo::new(addresses, 8888);
+        assert_eq!(info.proxy_endpoints[0], "192.168.1.20:8888");
+        assert_eq!(info.proxy_endpoints[2], "[2001:db8::42]:8888");
+    }
+}
@@ -1,19 +1,54 @@
+pub mod api_tls;
+pub mod app_doh;
+pub mod cli;
 pub mod config;
+pub mod connect_coalescer;
 pub mod crypto;
+pub mod error;
+pub mod exit_selection;
+pub mod fair_scheduler;
 pub mod dns;
+pub mod domain_set;
+pub mod first_party;
 pub mod fingerprint;
+pub mod geoip;
+pub mod header_scrubber;
+pub mod host_stats;
+pub mod idle_timeout;
+pub mod lan_addresses;
+pub mod list_watcher;
 pub mod network;
+pub mod onion_service;
 pub mod blockchain;
+pub mod power_events;
 pub mod proxy;
+pub mod rate_limit;
+pub mod replay_detection;
+pub mod request_filter;
+pub mod response_cache;
 pub mod routing;
+pub mod runtime_info;
+pub mod self_test;
+pub mod session_history;
+pub mod setup_instructions;
+pub mod shutdown;
+pub mod sse;
 pub mod tor_network;
+pub mod trace;
+pub mod transport;
+pub mod trusted_networks;
+pub mod block_page;
 pub mod blocklist;
 pub mod webrtc_protection;
+pub mod direct_ip_protection;
 pub mod kill_switch;
 pub mod ipv6_protection;
 pub mod web_api;
+pub mod webhook;
 pub mod system_proxy;
+pub mod upstream_proxy;
 
 pub use config::Config;
+pub use error::PrivacyError;
 pub use proxy::ProxyServer;
 pub use web_api::{ApiState, start_web_api};
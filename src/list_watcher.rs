@@ -0,0 +1,120 @@
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::task::AbortHandle;
+use tracing::info;
+
+/// How often local blocklist files are checked for changes
+pub const LIST_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What a file looked like when last checked; `None` while it doesn't exist
+type FileState = Option<(SystemTime, u64)>;
+
+fn file_state(path: &Path) -> FileState {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Watches local blocklist files (the `file://` entries of
+/// `Config::tracker_lists`) and runs `on_change` with the ones edited,
+/// created or deleted since the last check. Stops when dropped.
+///
+/// Files are polled rather than watched with OS notifications, which behave
+/// differently on every platform and miss editors that save by rename. The
+/// size is compared as well as the modification time, which some file
+/// systems only keep to the second.
+pub struct ListWatcher {
+    task: AbortHandle,
+}
+
+impl ListWatcher {
+    pub fn spawn<F, Fut>(paths: Vec<PathBuf>, interval: Duration, on_change: F) -> Self
+    where
+        F: Fn(Vec<PathBuf>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let task = tokio::spawn(async move {
+            let mut states: Vec<FileState> = paths.iter().map(|path| file_state(path)).collect();
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let current: Vec<FileState> = paths.iter().map(|path| file_state(path)).collect();
+                if current == states {
+                    continue;
+                }
+
+                let changed: Vec<PathBuf> = paths
+                    .iter()
+                    .zip(&current)
+                    .zip(&states)
+                    .filter(|((_, now), before)| now != before)
+                    .map(|((path, _), _)| path.clone())
+                    .collect();
+                for path in &changed {
+                    info!("📝 Local blocklist changed: {}", path.display());
+                }
+                states = current;
+                on_change(changed).await;
+            }
+        })
+        .abort_handle();
+
+        Self { task }
+    }
+}
+
+impl Drop for ListWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocklist::TrackerBlocker;
+
+    #[tokio::test]
+    async fn test_local_list_reloads_on_change() {
+        let path = std::env::temp_dir().join(format!("privacy_suite_local_list_{}.txt", std::process::id()));
+        let other = std::env::temp_dir().join(format!("privacy_suite_other_list_{}.txt", std::process::id()));
+        fs::write(&path, "0.0.0.0 first.example.com\n").unwrap();
+        fs::write(&other, "0.0.0.0 other.example.net\n").unwrap();
+        let lists = vec![format!("file://{}", path.display()), format!("file://{}", other.display())];
+
+        let blocker = TrackerBlocker::new();
+        blocker.refresh(&lists).await.unwrap();
+        assert!(blocker.check("first.example.com").blocked);
+        assert!(!blocker.check("second.example.org").blocked);
+        // A list that can't be loaded any more doesn't hold the edit back
+        fs::remove_file(&other).unwrap();
+
+        let (reloaded, mut reloads) = tokio::sync::mpsc::unbounded_channel();
+        let _watcher = ListWatcher::spawn(vec![path.clone()], Duration::from_millis(20), {
+            let blocker = blocker.clone();
+            move |changed: Vec<PathBuf>| {
+                let blocker = blocker.clone();
+                let lists = lists.clone();
+                let reloaded = reloaded.clone();
+                async move {
+                    let changed: Vec<String> = changed.iter().map(|path| format!("file://{}", path.display())).collect();
+                    reloaded.send(blocker.refresh_changed(&lists, &[], &changed).await.is_ok()).unwrap();
+                }
+            }
+        });
+
+        // Nothing happens until the file changes
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(reloads.try_recv().is_err());
+
+        fs::write(&path, "0.0.0.0 first.example.com\n0.0.0.0 second.example.org\n").unwrap();
+        let reload = tokio::time::timeout(Duration::from_secs(5), reloads.recv()).await.unwrap();
+        assert_eq!(reload, Some(true));
+        assert!(blocker.check("second.example.org").blocked);
+        assert!(blocker.check("other.example.net").blocked);
+
+        let _ = fs::remove_file(&path);
+    }
+}
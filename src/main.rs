@@ -1,70 +1,157 @@
+use clap::Parser;
 use tracing::{info, warn, error};
 use tracing_subscriber;
 
+mod api_tls;
+mod app_doh;
+mod cli;
 mod config;
+mod connect_coalescer;
 mod crypto;
+mod error;
+mod exit_selection;
+mod fair_scheduler;
 mod dns;
+mod domain_set;
+mod first_party;
 mod fingerprint;
+mod geoip;
+mod header_scrubber;
+mod host_stats;
+mod idle_timeout;
+mod lan_addresses;
+mod list_watcher;
 mod network;
+mod onion_service;
 mod blockchain;
+mod power_events;
 mod proxy;
+mod rate_limit;
+mod replay_detection;
+mod request_filter;
+mod response_cache;
 mod routing;
+mod runtime_info;
+mod self_test;
+mod session_history;
+mod setup_instructions;
+mod shutdown;
+mod sse;
 mod tor_network;
+mod trace;
+mod transport;
+mod trusted_networks;
+mod block_page;
 mod blocklist;
 mod webrtc_protection;
+mod direct_ip_protection;
 mod kill_switch;
 mod ipv6_protection;
 mod web_api;
+mod webhook;
 mod system_proxy;
+mod upstream_proxy;
+#[cfg(feature = "gui")]
+mod gui;
 
-use config::Config;
+use cli::Cli;
+use shutdown::ShutdownReason;
 use web_api::ApiState;
 
-/// Get local LAN IP address for network-wide access
-fn get_lan_ip() -> Option<String> {
-    use std::net::UdpSocket;
-    
-    // Connect to a public DNS server (doesn't actually send data)
-    // This forces the OS to determine which network interface to use
-    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
-    socket.connect("8.8.8.8:80").ok()?;
-    socket.local_addr().ok().map(|addr| addr.ip().to_string())
+/// Wait for a previous backend instance to release the web API port.
+///
+/// Used after an elevated relaunch so the new instance doesn't race the old
+/// one for port 3030.
+async fn wait_for_port_release(port: u16) {
+    for _ in 0..50 {
+        if std::net::TcpListener::bind(("127.0.0.1", port)).is_ok() {
+            return;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+    }
+    warn!("Port {} still in use after waiting for previous instance", port);
+}
+
+/// Open the desktop window and block until it is closed
+#[cfg(feature = "gui")]
+async fn launch_gui() -> Result<(), Box<dyn std::error::Error>> {
+    let state = std::sync::Arc::new(tokio::sync::RwLock::new(gui::AppState::default()));
+    gui::run_gui(state)
+        .await
+        .map_err(|e| format!("GUI error: {}", e).into())
+}
+
+#[cfg(not(feature = "gui"))]
+async fn launch_gui() -> Result<(), Box<dyn std::error::Error>> {
+    Err("This build does not include the desktop window (rebuild with --features gui)".into())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    
     // Initialize logging
     tracing_subscriber::fmt()
         .with_env_filter("privacy_suite=info")
         .init();
 
+    // Attach to a backend that is already running elsewhere
+    if cli.no_autostart {
+        info!("🖥️ Opening Privacy Suite window (backend autostart disabled)");
+        return launch_gui().await;
+    }
+    
     info!("🚀 Starting Privacy Suite...");
     
+    // Undo a system proxy left pointing at us by a crashed or killed run
+    if let Some(backup) = system_proxy::default_backup_path() {
+        if let Err(e) = system_proxy::restore_leftover_backup(&backup) {
+            error!("Failed to restore leftover proxy settings: {}", e);
+        }
+    }
+    system_proxy::install_restore_panic_hook();
+    
     // Load configuration
-    let config = Config::load_or_create()?;
+    let config = cli.load_config()?;
     info!("Configuration loaded from: {}", config.config_path().display());
     
     // Initialize kill switch (disabled by default)
     let kill_switch = kill_switch::KillSwitch::new();
+    kill_switch.set_block_lan(config.killswitch_block_lan).await;
+    kill_switch.set_reconnect_hold(tokio::time::Duration::from_secs(config.reconnect_hold_secs), config.reconnect_hold_max).await;
     
     // Initialize system proxy manager
     let sys_proxy = std::sync::Arc::new(tokio::sync::RwLock::new(system_proxy::SystemProxy::new()));
     
     // Initialize API state with kill switch and config
-    let api_state = ApiState::new(config.clone())
+    let mut api_state = ApiState::new(config.clone())
         .with_kill_switch(kill_switch.clone())
-        .with_system_proxy(sys_proxy.clone());
+        .with_system_proxy(sys_proxy);
+    
+    // Open the node registry so reputation scores can be reported
+    match network::NodeRegistry::new(&config.resolved_node_db_path().to_string_lossy()) {
+        Ok(registry) => api_state = api_state.with_node_registry(std::sync::Arc::new(registry)),
+        Err(e) => warn!("Node registry unavailable: {}", e),
+    }
     api_state.add_log("info", "Privacy Suite starting...".to_string(), "general").await;
     api_state.add_log("info", "ℹ️ Click CONNECT button to start privacy protection".to_string(), "general").await;
+    // Say so up front, rather than on the first connect, if Tor can't run here
+    api_state.check_tor_support().await;
     
     // Check for admin rights for system proxy capability
     let is_admin = system_proxy::is_elevated();
-    let lan_ip = get_lan_ip();
+    let proxy_port = config.proxy_port().unwrap_or(config::DEFAULT_PROXY_PORT);
+    // IPv6 addresses are only worth offering when IPv6 can go out over Tor
+    let network_info = lan_addresses::NetworkInfo::new(lan_addresses::lan_addresses(config.allow_ipv6_over_tor), proxy_port);
+    let lan_endpoints = (!network_info.proxy_endpoints.is_empty()).then(|| network_info.proxy_endpoints.join(", "));
+    let api_port = config.api_port;
     info!("Admin status: {}", is_admin);
     
-    if let Some(ref ip) = lan_ip {
-        info!("🌐 LAN IP Address: {}", ip);
-        info!("📱 Other devices can use: {}:8888", ip);
+    for address in &network_info.addresses {
+        info!("🌐 LAN IP Address: {} ({})", address.ip, address.interface);
+    }
+    if let Some(ref endpoints) = lan_endpoints {
+        info!("📱 Other devices can use: {}", endpoints);
     }
     
     if is_admin {
@@ -72,26 +159,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         api_state.add_log("info", "✅ Running with administrator privileges - system-wide protection available".to_string(), "general").await;
     } else {
         info!("💡 Tip: Run as Administrator for automatic system-wide proxy");
-        if let Some(ref ip) = lan_ip {
-            info!("Or manually configure devices to use: {}:8888", ip);
-            api_state.add_log("warn", format!("⚠️ Not running as administrator - manually configure devices to use: {}:8888", ip), "general").await;
+        if let Some(ref endpoints) = lan_endpoints {
+            info!("Or manually configure devices to use: {}", endpoints);
+            api_state.add_log("warn", format!("⚠️ Not running as administrator - manually configure devices to use: {}", endpoints), "general").await;
         } else {
             info!("Or manually configure your browser to use: {}", config.proxy_addr());
             api_state.add_log("warn", "⚠️ Not running as administrator - manual browser setup required".to_string(), "general").await;
         }
     }
     
+    // An elevated relaunch starts while the previous instance is still exiting
+    if cli.elevated_relaunch {
+        info!("Relaunched with elevation - waiting for previous instance to exit...");
+        wait_for_port_release(api_port).await;
+    }
+    
     // Start web API server first (so UI can connect immediately)
-    info!("🌐 Starting Web API on http://127.0.0.1:3030");
-    // Start web API server
-    info!("🌐 Starting Web API on http://127.0.0.1:3030");
+    info!("🌐 Starting Web API on http://127.0.0.1:{}", api_port);
     let web_api_state = api_state.clone();
+    // The GUI can't reach a backend without its API, so losing it ends the process
+    let (fatal_tx, mut fatal_rx) = tokio::sync::mpsc::unbounded_channel();
     tokio::spawn(async move {
-        if let Err(e) = web_api::start_web_api(web_api_state, 3030).await {
-            eprintln!("Web API error: {}", e);
+        if let Err(e) = web_api::start_web_api(web_api_state, api_port).await {
+            error!("Web API error: {}", e);
+            let _ = fatal_tx.send(ShutdownReason::from_error(&*e));
         }
     });
     
+    // Keep tracker lists fresh in the background
+    if config.blocklist_refresh_hours > 0 {
+        let refresh_state = api_state.clone();
+        let refresh_every = tokio::time::Duration::from_secs(config.blocklist_refresh_hours * 3600);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(refresh_every);
+            loop {
+                interval.tick().await;
+                let _ = refresh_state.refresh_blocklist().await;
+            }
+        });
+    }
+    
+    // Reload local (file://) tracker lists as soon as they are edited
+    let local_lists: Vec<_> = config.enabled_tracker_lists().iter().filter_map(|url| blocklist::local_list_path(url)).collect();
+    let _list_watcher = (!local_lists.is_empty()).then(|| {
+        let watch_state = api_state.clone();
+        list_watcher::ListWatcher::spawn(local_lists, list_watcher::LIST_POLL_INTERVAL, move |changed| {
+            let watch_state = watch_state.clone();
+            async move {
+                let _ = watch_state.reload_local_lists(&changed).await;
+            }
+        })
+    });
+    
+    if config.disconnect_on_sleep {
+        tokio::spawn(power_events::watch(api_state.clone()));
+    }
+    
+    if config.idle_disconnect_secs > 0 {
+        tokio::spawn(idle_timeout::watch(api_state.clone(), tokio::time::Duration::from_secs(config.idle_disconnect_secs)));
+    }
+    
+    if config.has_trusted_networks() {
+        tokio::spawn(trusted_networks::watch(api_state.clone()));
+    }
+    
     // Wait for web API to start
     tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
     
@@ -99,37 +230,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("📊 Web GUI: http://127.0.0.1:1420");
     info!("🔌 Proxy: {} (disconnected - click Connect in GUI)", config.proxy_addr());
     
-    if let Some(ref ip) = lan_ip {
-        info!("🌐 Network-wide access: Configure devices to use {}:8888", ip);
-        api_state.add_log("info", format!("🌐 Network-wide proxy available at: {}:8888", ip), "general").await;
+    if let Some(ref endpoints) = lan_endpoints {
+        info!("🌐 Network-wide access: Configure devices to use {}", endpoints);
+        api_state.add_log("info", format!("🌐 Network-wide proxy available at: {}", endpoints), "general").await;
     }
     
-    info!("Press Ctrl+C to stop");
-    
-    // Keep running until interrupted
-    tokio::signal::ctrl_c().await?;
-    info!("Shutting down...");
-    api_state.add_log("info", "Shutting down Privacy Suite...".to_string(), "general").await;
+    // With --gui, keep running until the window is closed
+    let gui_closed = cli.gui && match launch_gui().await {
+        Ok(()) => true,
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    };
     
-    // CRITICAL: Disable kill switch before exiting to restore internet
-    if let Some(ref ks) = api_state.kill_switch {
-        info!("Disabling kill switch...");
-        ks.set_enabled(false).await;
-        api_state.add_log("info", "Kill switch disabled".to_string(), "general").await;
-    }
+    let reason = if gui_closed {
+        ShutdownReason::UserRequest
+    } else {
+        info!("Press Ctrl+C to stop");
+        
+        // Keep running until interrupted or the web API fails
+        shutdown::wait_for_shutdown(shutdown::termination_signal(), &mut fatal_rx).await
+    };
+    info!("Shutting down ({})...", reason);
+    api_state.add_log("info", format!("Shutting down Privacy Suite ({})...", reason), "general").await;
     
-    // Restore original proxy settings if we changed them
-    if system_proxy::is_elevated() {
-        info!("Restoring original proxy settings...");
-        let proxy = sys_proxy.read().await;
-        if let Err(e) = proxy.disable() {
-            error!("Failed to restore proxy: {}", e);
-        } else {
-            api_state.add_log("info", "Proxy settings restored".to_string(), "general").await;
-        }
+    // The same cleanup `/api/shutdown` runs: restore the system proxy, lift
+    // the kill switch, close connections and record the session
+    let report = api_state.shutdown().await;
+    for e in &report.errors {
+        error!("{}", e);
     }
     
     info!("✅ Shutdown complete");
     
-    Ok(())
+    reason.exit()
 }
@@ -1,13 +1,39 @@
 use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 // Module not fully implemented - placeholder for node management logic in the future
 
+/// Nodes below this reputation are banned from route selection
+pub const BAN_THRESHOLD: f32 = 0.5;
+
+/// Reputation gained for each successfully routed request
+const SUCCESS_REWARD: f32 = 0.05;
+
+/// Reputation lost when a node doesn't answer in time
+const TIMEOUT_PENALTY: f32 = 0.1;
+
+/// Reputation lost when a node answers with an error or drops the connection
+const FAILURE_PENALTY: f32 = 0.2;
+
+/// Reputation regained per hour without incidents, so banned nodes get another chance
+const RECOVERY_PER_HOUR: f32 = 0.05;
+
+/// How a node let a request down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeFailure {
+    Timeout,
+    Error,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Node {
     pub address: String,
     pub public_key: Option<Vec<u8>>,
     pub reputation: f32,
     pub latency_ms: Option<u64>,
+    /// Unix time the reputation was last changed, used for recovery
+    #[serde(default)]
+    pub reputation_updated: u64,
 }
 
 impl Node {
@@ -17,6 +43,7 @@ impl Node {
             public_key: None,
             reputation: 1.0,
             latency_ms: None,
+            reputation_updated: unix_now(),
         }
     }
     
@@ -26,10 +53,49 @@ impl Node {
     
     pub fn is_available(&self) -> bool {
         // Check if node is responsive and has good reputation
-        self.reputation > 0.5
+        !self.is_banned()
+    }
+    
+    pub fn is_banned(&self) -> bool {
+        self.reputation < BAN_THRESHOLD
+    }
+    
+    /// Credit a successfully routed request
+    pub fn record_success(&mut self, now: u64) {
+        self.recover(now);
+        self.reputation = (self.reputation + SUCCESS_REWARD).min(1.0);
+    }
+    
+    /// Penalize a timeout or failure
+    pub fn record_failure(&mut self, failure: NodeFailure, now: u64) {
+        self.recover(now);
+        let penalty = match failure {
+            NodeFailure::Timeout => TIMEOUT_PENALTY,
+            NodeFailure::Error => FAILURE_PENALTY,
+        };
+        self.reputation = (self.reputation - penalty).max(0.0);
+    }
+    
+    /// Slowly restore reputation for the time passed since the last change
+    pub fn recover(&mut self, now: u64) {
+        if self.reputation_updated == 0 {
+            self.reputation_updated = now;
+            return;
+        }
+        
+        let hours = now.saturating_sub(self.reputation_updated) as f32 / 3600.0;
+        self.reputation = (self.reputation + hours * RECOVERY_PER_HOUR).min(1.0);
+        self.reputation_updated = now.max(self.reputation_updated);
     }
 }
 
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 pub struct NodeRegistry {
     // Database handle for node registry
     db: sled::Db,
@@ -48,17 +114,138 @@ impl NodeRegistry {
         Ok(())
     }
     
+    pub fn get_node(&self, address: &str) -> Result<Option<Node>, Box<dyn std::error::Error>> {
+        match self.db.get(address.as_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+    
+    /// Nodes that may be selected for a route (banned nodes are left out)
     pub fn get_all_nodes(&self) -> Result<Vec<Node>, Box<dyn std::error::Error>> {
+        Ok(self
+            .all_nodes_at(unix_now())?
+            .into_iter()
+            .filter(Node::is_available)
+            .collect())
+    }
+    
+    /// Every registered node, banned or not, with recovery applied
+    pub fn all_nodes(&self) -> Result<Vec<Node>, Box<dyn std::error::Error>> {
+        self.all_nodes_at(unix_now())
+    }
+    
+    fn all_nodes_at(&self, now: u64) -> Result<Vec<Node>, Box<dyn std::error::Error>> {
         let mut nodes = Vec::new();
         
         for item in self.db.iter() {
             let (_key, value) = item?;
-            let node: Node = serde_json::from_slice(&value)?;
-            if node.is_available() {
-                nodes.push(node);
-            }
+            let mut node: Node = serde_json::from_slice(&value)?;
+            node.recover(now);
+            nodes.push(node);
         }
         
         Ok(nodes)
     }
+    
+    /// Credit a node for a successfully routed request
+    pub fn record_success(&self, address: &str) -> Result<Option<Node>, Box<dyn std::error::Error>> {
+        self.update_node(address, |node| node.record_success(unix_now()))
+    }
+    
+    /// Penalize a node for a timeout or failure, banning it once it drops below `BAN_THRESHOLD`
+    pub fn record_failure(&self, address: &str, failure: NodeFailure) -> Result<Option<Node>, Box<dyn std::error::Error>> {
+        self.update_node(address, |node| node.record_failure(failure, unix_now()))
+    }
+    
+    fn update_node<F>(&self, address: &str, update: F) -> Result<Option<Node>, Box<dyn std::error::Error>>
+    where
+        F: FnOnce(&mut Node),
+    {
+        let Some(mut node) = self.get_node(address)? else {
+            return Ok(None);
+        };
+        
+        update(&mut node);
+        self.add_node(&node)?;
+        Ok(Some(node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const HOUR: u64 = 3600;
+    
+    fn node_at(now: u64) -> Node {
+        Node {
+            reputation_updated: now,
+            ..Node::new("node.example.com:9000".to_string())
+        }
+    }
+    
+    #[test]
+    fn test_failures_decay_reputation_until_banned() {
+        let mut node = node_at(1_000);
+        
+        node.record_failure(NodeFailure::Timeout, 1_000);
+        assert!((node.reputation - 0.9).abs() < 1e-6);
+        assert!(node.is_available());
+        
+        for _ in 0..3 {
+            node.record_failure(NodeFailure::Error, 1_000);
+        }
+        assert!(node.is_banned());
+        assert!(!node.is_available());
+        
+        // Successes help, but a single one isn't enough to lift the ban
+        node.record_success(1_000);
+        assert!(node.is_banned());
+    }
+    
+    #[test]
+    fn test_banned_node_recovers_over_time() {
+        let mut node = node_at(0);
+        node.reputation = 0.3;
+        node.reputation_updated = 1_000;
+        
+        node.recover(1_000 + HOUR);
+        assert!(node.is_banned());
+        
+        node.recover(1_000 + 5 * HOUR);
+        assert!(node.is_available());
+        
+        node.recover(1_000 + 100 * HOUR);
+        assert_eq!(node.reputation, 1.0);
+    }
+    
+    #[test]
+    fn test_registry_persists_reputation_and_excludes_banned_nodes() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_nodes_{}", std::process::id()));
+        let path = dir.to_string_lossy().to_string();
+        
+        {
+            let registry = NodeRegistry::new(&path).unwrap();
+            registry.add_node(&Node::new("good.example.com:9000".to_string())).unwrap();
+            registry.add_node(&Node::new("flaky.example.com:9000".to_string())).unwrap();
+            
+            for _ in 0..3 {
+                registry.record_failure("flaky.example.com:9000", NodeFailure::Error).unwrap();
+            }
+            registry.record_success("good.example.com:9000").unwrap();
+            assert!(registry.record_success("unknown.example.com:9000").unwrap().is_none());
+        }
+        
+        let registry = NodeRegistry::new(&path).unwrap();
+        let selectable: Vec<String> = registry.get_all_nodes().unwrap().into_iter().map(|n| n.address).collect();
+        assert_eq!(selectable, vec!["good.example.com:9000".to_string()]);
+        
+        let flaky = registry.get_node("flaky.example.com:9000").unwrap().unwrap();
+        assert!(flaky.is_banned());
+        assert_eq!(registry.all_nodes().unwrap().len(), 2);
+        
+        drop(registry);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
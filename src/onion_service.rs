@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// A local service to publish as an onion service (`Config::onion_services`):
+/// connections to the onion address on `virtual_port` are forwarded to
+/// `127.0.0.1:local_port`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OnionServicePort {
+    pub local_port: u16,
+    pub virtual_port: u16,
+}
+
+impl OnionServicePort {
+    /// Name arti keeps the service's keys under. It only depends on the
+    /// ports, so the service keeps its `.onion` address across restarts.
+    pub fn nickname(&self) -> String {
+        format!("privacy-suite-{}-{}", self.virtual_port, self.local_port)
+    }
+}
+
+/// A published onion service, as reported by `/api/onion-services`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OnionServiceInfo {
+    /// e.g. `abc…xyz.onion`
+    pub hostname: String,
+    pub virtual_port: u16,
+    pub local_port: u16,
+}
+
+/// Check `Config::onion_services` before anything is published
+pub fn validate(services: &[OnionServicePort]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for service in services {
+        if service.local_port == 0 || service.virtual_port == 0 {
+            return Err(format!(
+                "onion service ports must be non-zero (local_port {}, virtual_port {})",
+                service.local_port, service.virtual_port
+            ));
+        }
+        if !seen.insert(service) {
+            return Err(format!(
+                "onion service {} -> {} is listed twice",
+                service.virtual_port, service.local_port
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Copy an inbound onion stream to and from `127.0.0.1:local_port` until
+/// either side closes, returning the bytes sent each way
+pub async fn forward_to_local<S>(mut onion: S, local_port: u16) -> io::Result<(u64, u64)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut local = TcpStream::connect(("127.0.0.1", local_port)).await?;
+    tokio::io::copy_bidirectional(&mut onion, &mut local).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_validate_onion_services() {
+        let web = OnionServicePort { local_port: 8080, virtual_port: 80 };
+        let ssh = OnionServicePort { local_port: 22, virtual_port: 22 };
+        assert_eq!(validate(&[web, ssh]), Ok(()));
+        assert!(validate(&[web, web]).unwrap_err().contains("twice"));
+        assert!(validate(&[OnionServicePort { local_port: 0, virtual_port: 80 }]).is_err());
+
+        // Keys are found again under the same name on the next start
+        assert_eq!(web.nickname(), "privacy-suite-80-8080");
+        assert_ne!(web.nickname(), ssh.nickname());
+    }
+
+    #[tokio::test]
+    async fn test_onion_stream_forwarded_to_local_port() {
+        let local = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_port = local.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = local.accept().await.unwrap();
+            let mut request = [0u8; 4];
+            socket.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"ping");
+            socket.write_all(b"pong").await.unwrap();
+        });
+
+        // Stands in for the stream arti hands over for an inbound onion connection
+        let (mut visitor, onion) = tokio::io::duplex(1024);
+        let forwarding = tokio::spawn(forward_to_local(onion, local_port));
+        visitor.write_all(b"ping").await.unwrap();
+        let mut reply = [0u8; 4];
+        visitor.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"pong");
+
+        drop(visitor);
+        assert_eq!(forwarding.await.unwrap().unwrap(), (4, 4));
+    }
+}
@@ -0,0 +1,244 @@
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use crate::web_api::ApiState;
+
+/// A system sleep or session lock change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerEvent {
+    Sleep,
+    Wake,
+    Lock,
+    Unlock,
+}
+
+/// What the suite should do about a power event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    Disconnect,
+    Reconnect,
+    Nothing,
+}
+
+/// Tracks sleep and lock state so the suite disconnects once when the
+/// machine goes away and reconnects once it is both awake and unlocked
+#[derive(Debug, Default)]
+pub struct SleepTracker {
+    asleep: bool,
+    locked: bool,
+    /// The user was connected when the machine went away
+    reconnect: bool,
+}
+
+impl SleepTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_event(&mut self, event: PowerEvent, connected: bool) -> PowerAction {
+        let was_away = self.asleep || self.locked;
+
+        match event {
+            PowerEvent::Sleep => self.asleep = true,
+            PowerEvent::Wake => self.asleep = false,
+            PowerEvent::Lock => self.locked = true,
+            PowerEvent::Unlock => self.locked = false,
+        }
+        let away = self.asleep || self.locked;
+
+        match (was_away, away) {
+            (false, true) => {
+                self.reconnect = connected;
+                if connected {
+                    PowerAction::Disconnect
+                } else {
+                    PowerAction::Nothing
+                }
+            }
+            (true, false) if std::mem::take(&mut self.reconnect) => PowerAction::Reconnect,
+            _ => PowerAction::Nothing,
+        }
+    }
+}
+
+/// Disconnect on sleep/lock and reconnect on resume for as long as the app runs
+pub async fn watch(state: ApiState) {
+    let mut events = subscribe();
+    let mut tracker = SleepTracker::new();
+    info!("💤 Disconnect on sleep/lock enabled");
+
+    while let Some(event) = events.recv().await {
+        let connected = state.is_connected().await;
+        match tracker.on_event(event, connected) {
+            PowerAction::Disconnect => {
+                let reason = match event {
+                    PowerEvent::Lock => "Session locked",
+                    _ => "System going to sleep",
+                };
+                state.disconnect_for_sleep(reason).await;
+            }
+            PowerAction::Reconnect => state.reconnect_after_sleep().await,
+            PowerAction::Nothing => {}
+        }
+    }
+
+    warn!("Power event source stopped - disconnect on sleep/lock is no longer active");
+}
+
+/// Deliver OS power and session notifications from a hidden window's message loop
+#[cfg(target_os = "windows")]
+fn subscribe() -> mpsc::UnboundedReceiver<PowerEvent> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    if windows_events::SENDER.set(tx).is_err() {
+        warn!("Power event listener already running");
+        return rx;
+    }
+
+    std::thread::spawn(|| {
+        if let Err(e) = windows_events::run_message_loop() {
+            warn!("Power event listener failed: {}", e);
+        }
+    });
+
+    rx
+}
+
+/// Without OS notifications, spot a suspend when the wall clock jumps ahead of
+/// the monotonic clock (which stops while the machine sleeps)
+#[cfg(not(target_os = "windows"))]
+fn subscribe() -> mpsc::UnboundedReceiver<PowerEvent> {
+    use std::time::{Duration, Instant, SystemTime};
+
+    const CHECK_EVERY: Duration = Duration::from_secs(5);
+    const SLEEP_GAP: Duration = Duration::from_secs(30);
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut last_wall = SystemTime::now();
+        let mut last_mono = Instant::now();
+        loop {
+            tokio::time::sleep(CHECK_EVERY).await;
+
+            let wall = SystemTime::now();
+            let mono = Instant::now();
+            let wall_elapsed = wall.duration_since(last_wall).unwrap_or_default();
+            if wall_elapsed > mono.duration_since(last_mono) + SLEEP_GAP {
+                // Only noticed after waking, so drop the stale session and start a new one
+                if tx.send(PowerEvent::Sleep).is_err() || tx.send(PowerEvent::Wake).is_err() {
+                    return;
+                }
+            }
+            last_wall = wall;
+            last_mono = mono;
+        }
+    });
+
+    rx
+}
+
+#[cfg(target_os = "windows")]
+mod windows_events {
+    use super::PowerEvent;
+    use std::sync::OnceLock;
+    use tokio::sync::mpsc::UnboundedSender;
+    use windows::core::w;
+    use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+    use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+    use windows::Win32::System::RemoteDesktop::{
+        WTSRegisterSessionNotification, NOTIFY_FOR_THIS_SESSION, WTS_SESSION_LOCK, WTS_SESSION_UNLOCK,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, RegisterClassW,
+        TranslateMessage, MSG, PBT_APMRESUMEAUTOMATIC, PBT_APMSUSPEND, WINDOW_EX_STYLE,
+        WM_POWERBROADCAST, WM_WTSSESSION_CHANGE, WNDCLASSW, WS_OVERLAPPED,
+    };
+
+    pub static SENDER: OnceLock<UnboundedSender<PowerEvent>> = OnceLock::new();
+
+    extern "system" fn window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        let event = match (msg, wparam.0 as u32) {
+            (WM_POWERBROADCAST, PBT_APMSUSPEND) => Some(PowerEvent::Sleep),
+            (WM_POWERBROADCAST, PBT_APMRESUMEAUTOMATIC) => Some(PowerEvent::Wake),
+            (WM_WTSSESSION_CHANGE, WTS_SESSION_LOCK) => Some(PowerEvent::Lock),
+            (WM_WTSSESSION_CHANGE, WTS_SESSION_UNLOCK) => Some(PowerEvent::Unlock),
+            _ => None,
+        };
+        if let (Some(event), Some(sender)) = (event, SENDER.get()) {
+            let _ = sender.send(event);
+        }
+
+        unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) }
+    }
+
+    /// Create a hidden (not message-only, which misses broadcasts) window and pump its messages
+    pub fn run_message_loop() -> windows::core::Result<()> {
+        unsafe {
+            let instance = GetModuleHandleW(None)?;
+            let class_name = w!("PrivacySuitePowerEvents");
+            let class = WNDCLASSW {
+                lpfnWndProc: Some(window_proc),
+                hInstance: instance.into(),
+                lpszClassName: class_name,
+                ..Default::default()
+            };
+            RegisterClassW(&class);
+
+            let hwnd = CreateWindowExW(
+                WINDOW_EX_STYLE::default(),
+                class_name,
+                w!("Privacy Suite power events"),
+                WS_OVERLAPPED,
+                0,
+                0,
+                0,
+                0,
+                None,
+                None,
+                instance,
+                None,
+            );
+            WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION)?;
+
+            let mut message = MSG::default();
+            while GetMessageW(&mut message, None, 0, 0).as_bool() {
+                TranslateMessage(&message);
+                DispatchMessageW(&message);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sleep_disconnects_and_wake_reconnects() {
+        let mut tracker = SleepTracker::new();
+
+        assert_eq!(tracker.on_event(PowerEvent::Sleep, true), PowerAction::Disconnect);
+        assert_eq!(tracker.on_event(PowerEvent::Wake, false), PowerAction::Reconnect);
+        // Duplicate resume notifications don't reconnect twice
+        assert_eq!(tracker.on_event(PowerEvent::Wake, true), PowerAction::Nothing);
+    }
+
+    #[test]
+    fn test_stays_disconnected_until_awake_and_unlocked() {
+        let mut tracker = SleepTracker::new();
+
+        assert_eq!(tracker.on_event(PowerEvent::Lock, true), PowerAction::Disconnect);
+        assert_eq!(tracker.on_event(PowerEvent::Sleep, false), PowerAction::Nothing);
+        assert_eq!(tracker.on_event(PowerEvent::Wake, false), PowerAction::Nothing);
+        assert_eq!(tracker.on_event(PowerEvent::Unlock, false), PowerAction::Reconnect);
+    }
+
+    #[test]
+    fn test_no_reconnect_if_user_was_disconnected() {
+        let mut tracker = SleepTracker::new();
+
+        assert_eq!(tracker.on_event(PowerEvent::Sleep, false), PowerAction::Nothing);
+        assert_eq!(tracker.on_event(PowerEvent::Wake, false), PowerAction::Nothing);
+        // Waking without a matching sleep notification is ignored
+        assert_eq!(tracker.on_event(PowerEvent::Wake, true), PowerAction::Nothing);
+    }
+}
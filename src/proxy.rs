@@ -1,37 +1,164 @@
+use crate::blocklist::normalize_host;
 use crate::config::Config;
-use crate::routing::Router;
-use crate::web_api::ApiState;
-use hyper::server::conn::http1;
+use crate::error::{PrivacyError, Result};
+use crate::fair_scheduler::FairScheduler;
+use crate::rate_limit::{self, RateLimiter};
+use crate::routing::{ClientAddr, Router};
+use crate::trace;
+use crate::web_api::{ApiState, LogDetails};
+use hyper::server::conn::{http1, http2};
 use hyper::service::service_fn;
 use hyper::{Request, Response, body::Bytes};
 use http_body_util::Full;
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioExecutor, TokioIo};
 use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tracing::{info, error};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::task::{AbortHandle, JoinHandle};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn, error};
+
+/// Registry of in-flight client connection tasks so they can be severed at once
+#[derive(Clone, Default)]
+pub struct ConnectionRegistry {
+    next_id: Arc<AtomicU64>,
+    handles: Arc<Mutex<HashMap<u64, AbortHandle>>>,
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Reserve an id for a connection task before it is spawned
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+    
+    /// Track a spawned connection task
+    pub fn insert<T>(&self, id: u64, handle: &JoinHandle<T>) {
+        self.handles
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, handle.abort_handle());
+        
+        // The task may have finished (and tried to remove itself) before it was inserted
+        if handle.is_finished() {
+            self.remove(id);
+        }
+    }
+    
+    /// Stop tracking a finished connection task
+    pub fn remove(&self, id: u64) {
+        self.handles.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    }
+    
+    /// Abort every tracked connection, returning how many were aborted
+    pub fn abort_all(&self) -> usize {
+        let handles: Vec<AbortHandle> = self
+            .handles
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain()
+            .map(|(_, handle)| handle)
+            .collect();
+        
+        for handle in &handles {
+            handle.abort();
+        }
+        handles.len()
+    }
+    
+    /// Number of connections currently tracked
+    pub fn len(&self) -> usize {
+        self.handles.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Bind a listener, reporting an occupied port as `PrivacyError::PortInUse`
+pub async fn bind_listener(addr: SocketAddr) -> Result<TcpListener> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| PrivacyError::from_bind(e, addr.port()))
+}
+
+/// Ports after the configured one the proxy tries when it is already taken
+const PORT_FALLBACK_ATTEMPTS: u16 = 10;
+
+/// Bind the proxy on `addr`, moving to one of the next few ports if it is in use.
+/// The GUIs find the port actually bound through `runtime_info`.
+pub async fn bind_with_fallback(addr: SocketAddr) -> Result<TcpListener> {
+    let in_use = match bind_listener(addr).await {
+        Ok(listener) => return Ok(listener),
+        Err(e @ PrivacyError::PortInUse { .. }) => e,
+        Err(e) => return Err(e),
+    };
+    
+    for offset in 1..=PORT_FALLBACK_ATTEMPTS {
+        let Some(port) = addr.port().checked_add(offset) else {
+            break;
+        };
+        if let Ok(listener) = bind_listener(SocketAddr::new(addr.ip(), port)).await {
+            return Ok(listener);
+        }
+    }
+    Err(in_use)
+}
 
 pub struct ProxyServer {
-    config: Config,
+    listener: TcpListener,
     router: Router,
     app_state: Option<ApiState>,
+    keep_alive: bool,
+    head_limits: HeadLimits,
+    rate_limiter: RateLimiter,
+    scheduler: FairScheduler,
 }
 
 impl ProxyServer {
-    pub async fn new(config: Config, app_state: Option<ApiState>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn new(config: Config, app_state: Option<ApiState>) -> Result<Self> {
+        let addr: SocketAddr = config.proxy_addr().parse()?;
+        let listener = bind_with_fallback(addr).await?;
+        Self::with_listener(listener, config, app_state).await
+    }
+    
+    /// Build the server on an already bound listener; binding before
+    /// bootstrapping Tor lets callers learn the real port early
+    pub async fn with_listener(listener: TcpListener, config: Config, app_state: Option<ApiState>) -> Result<Self> {
         let router = Router::new(config.clone(), app_state.clone()).await?;
+        Ok(Self::with_router(listener, &config, router, app_state))
+    }
+    
+    /// Serve `router`, which may use any `Transport`, on `listener`
+    pub fn with_router(listener: TcpListener, config: &Config, router: Router, app_state: Option<ApiState>) -> Self {
+        // Share the API's limiter so per-client throughput shows up in the stats
+        let rate_limiter = app_state
+            .as_ref()
+            .map(|state| state.rate_limiter.clone())
+            .unwrap_or_else(|| RateLimiter::new(config.rate_limit_kbps));
         
-        Ok(Self {
-            config,
+        Self {
+            listener,
             router,
             app_state,
-        })
+            keep_alive: config.http_keep_alive,
+            head_limits: HeadLimits::from_config(config),
+            rate_limiter,
+            scheduler: FairScheduler::new(config.max_concurrent_requests),
+        }
     }
     
-    pub async fn run(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let addr: std::net::SocketAddr = self.config.proxy_addr().parse()?;
-        let listener = TcpListener::bind(addr).await?;
+    pub async fn run(self) -> Result<()> {
+        let listener = self.listener;
         
-        info!("Proxy server listening on {}", addr);
+        info!("Proxy server listening on {}", listener.local_addr()?);
         
         // Spawn statistics reporter
         let stats_router = self.router.clone();
@@ -46,52 +173,92 @@ impl ProxyServer {
         
         loop {
             match listener.accept().await {
-                Ok((stream, client_addr)) => {
+                Ok((mut stream, client_addr)) => {
                     info!("🔌 New connection from: {}", client_addr);
                     
                     if let Some(ref state) = self.app_state {
                         state.add_log("info", format!("🔌 New connection from: {}", client_addr), "network").await;
+                        state.counters.active_connections.inc();
+                        state.idle.touch();
                     }
                     
                     let router = self.router.clone();
                     let app_state = self.app_state.clone();
+                    let registry = self.app_state.as_ref().map(|s| s.connections.clone());
+                    let connection_id = registry.as_ref().map(|r| r.next_id()).unwrap_or(0);
+                    let task_registry = registry.clone();
+                    let keep_alive = self.keep_alive;
+                    let head_limits = self.head_limits;
+                    let rate_limiter = self.rate_limiter.clone();
+                    let scheduler = self.scheduler.clone();
+                    let client_ip = client_addr.ip();
                     
-                    tokio::spawn(async move {
-                        // Read first line to check if it's CONNECT
-                        let mut buffer = vec![0u8; 8192];
-                        match stream.peek(&mut buffer).await {
-                            Ok(n) if n > 0 => {
-                                let request_start = String::from_utf8_lossy(&buffer[..n]);
-                                
-                                if request_start.starts_with("CONNECT ") {
+                    let handle = tokio::spawn(async move {
+                        // Wait for the first line to check if it's CONNECT
+                        match peek_protocol(&stream).await {
+                            Some(protocol) => match protocol {
+                                ClientProtocol::Connect => {
                                     // Handle HTTPS tunnel
-                                    if let Err(e) = handle_connect_tunnel(stream, router, app_state).await {
+                                    let tunnel = handle_connect_tunnel(stream, router, app_state.clone(), head_limits, rate_limiter, scheduler, client_ip);
+                                    if let Err(e) = trace::scope(trace::new_id(), tunnel).await {
                                         error!("CONNECT tunnel error: {}", e);
                                     }
-                                } else {
+                                }
+                                // An oversized first request was answered with a 431
+                                ClientProtocol::Http1 if check_http1_head(&mut stream, head_limits, app_state.as_ref(), client_ip).await.is_err() => {}
+                                protocol => {
                                     // Handle regular HTTP with hyper
                                     let io = TokioIo::new(stream);
                                     
+                                    let idle = app_state.as_ref().map(|state| state.idle.clone());
                                     let service = service_fn(move |req| {
-                                        let router = router.clone();
-                                        async move {
-                                            handle_request(req, router).await
+                                        // Requests on a kept-alive connection count as activity too
+                                        if let Some(ref idle) = idle {
+                                            idle.touch();
                                         }
+                                        let router = router.clone();
+                                        let rate_limiter = rate_limiter.clone();
+                                        let scheduler = scheduler.clone();
+                                        // One trace per request, even when a connection carries several
+                                        trace::scope(trace::new_id(), handle_request(req, router, rate_limiter, scheduler, client_ip))
                                     });
                                     
-                                    if let Err(e) = http1::Builder::new()
-                                        .serve_connection(io, service)
-                                        .await
-                                    {
-                                        error!("Error serving connection: {}", e);
+                                    let served = if protocol == ClientProtocol::Http2 {
+                                        // Plaintext HTTP/2 with prior knowledge, e.g. `curl --http2-prior-knowledge`
+                                        http2::Builder::new(TokioExecutor::new())
+                                            .max_header_list_size(head_limits.headers.try_into().unwrap_or(u32::MAX))
+                                            .serve_connection(io, service)
+                                            .await
+                                    } else {
+                                        // Later requests on a kept-alive connection get hyper's own 431
+                                        http1::Builder::new()
+                                            .keep_alive(keep_alive)
+                                            .max_buf_size(head_limits.max_head().max(MIN_HYPER_BUF_SIZE))
+                                            .serve_connection(io, service)
+                                            .await
+                                    };
+                                    if let Err(e) = served {
+                                        error!("Error serving {:?} connection: {}", protocol, e);
                                     }
                                 }
-                            }
-                            _ => {
+                            },
+                            None => {
                                 error!("Failed to peek stream data");
                             }
                         }
+                        
+                        if let Some(ref state) = app_state {
+                            state.counters.active_connections.dec();
+                        }
+                        
+                        if let Some(ref registry) = task_registry {
+                            registry.remove(connection_id);
+                        }
                     });
+                    
+                    if let Some(ref registry) = registry {
+                        registry.insert(connection_id, &handle);
+                    }
                 }
                 Err(e) => {
                     error!("Error accepting connection: {}", e);
@@ -101,43 +268,378 @@ impl ProxyServer {
     }
 }
 
-async fn handle_connect_tunnel(
-    mut client_stream: tokio::net::TcpStream,
-    router: Router,
-    app_state: Option<ApiState>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Read CONNECT request
-    let mut buffer = vec![0u8; 8192];
-    let n = client_stream.read(&mut buffer).await?;
-    let request = String::from_utf8_lossy(&buffer[..n]);
+/// What a client speaks, judged from the first bytes it sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientProtocol {
+    /// An HTTP/1.x `CONNECT` tunnel request
+    Connect,
+    /// HTTP/2 with prior knowledge, opening with the connection preface
+    Http2,
+    /// Anything else is served as HTTP/1.0 or 1.1
+    Http1,
+}
+
+/// Start of the connection preface every HTTP/2 client sends first
+const H2_PREFACE_START: &[u8] = b"PRI * HTTP/2.0";
+
+impl ClientProtocol {
+    pub fn detect(peeked: &[u8]) -> Self {
+        if peeked.starts_with(b"CONNECT ") {
+            ClientProtocol::Connect
+        } else if peeked.starts_with(H2_PREFACE_START) {
+            ClientProtocol::Http2
+        } else {
+            ClientProtocol::Http1
+        }
+    }
+}
+
+/// How much of a client's first bytes are peeked at to tell its protocol
+const PEEK_BUFFER: usize = 8192;
+
+/// How long a client gets to finish its first line before it is judged on
+/// what it sent so far
+const FIRST_LINE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between peeks while the first line is still incomplete; peeking
+/// again straight away would spin on the same bytes
+const PEEK_RETRY: Duration = Duration::from_millis(5);
+
+/// Peek at `stream` until its first line is complete (or the peek buffer is
+/// full, or `FIRST_LINE_TIMEOUT` passes) and tell its protocol from that, so
+/// a slow client's `CONN` isn't mistaken for HTTP. None if the client closed
+/// or failed before sending anything.
+async fn peek_protocol(stream: &tokio::net::TcpStream) -> Option<ClientProtocol> {
+    let mut buffer = vec![0u8; PEEK_BUFFER];
+    let peeked = peek_until(stream, &mut buffer, |peeked| peeked.contains(&b'\n')).await?;
+    Some(ClientProtocol::detect(&buffer[..peeked]))
+}
+
+/// Peek into `buffer` until `done` says what arrived is enough, the buffer
+/// is full or `FIRST_LINE_TIMEOUT` passes. Returns how much was peeked, None
+/// if the client closed or failed before sending anything.
+async fn peek_until(stream: &tokio::net::TcpStream, buffer: &mut [u8], done: impl Fn(&[u8]) -> bool) -> Option<usize> {
+    let deadline = tokio::time::Instant::now() + FIRST_LINE_TIMEOUT;
+    let mut peeked = 0;
+    loop {
+        match tokio::time::timeout_at(deadline, stream.peek(buffer)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => return None,
+            Ok(Ok(n)) => peeked = n,
+            Err(_) => break,
+        }
+        if done(&buffer[..peeked]) || peeked == buffer.len() {
+            break;
+        }
+        if tokio::time::timeout_at(deadline, tokio::time::sleep(PEEK_RETRY)).await.is_err() {
+            break;
+        }
+    }
+    (peeked > 0).then_some(peeked)
+}
+
+/// Smallest read buffer hyper's HTTP/1 server accepts
+const MIN_HYPER_BUF_SIZE: usize = 8192;
+
+/// Caps on a client's request head (`Config::max_request_line_bytes` and
+/// `Config::max_request_header_bytes`), enforced before any Tor work
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadLimits {
+    pub request_line: usize,
+    /// The header block after the request line, ending blank line included
+    pub headers: usize,
+}
+
+impl HeadLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            request_line: config.max_request_line_bytes,
+            headers: config.max_request_header_bytes,
+        }
+    }
+    
+    /// Most bytes a head within the limits can take
+    pub fn max_head(&self) -> usize {
+        self.request_line + 1 + self.headers
+    }
+    
+    /// Check the start of a request head, which may still be incomplete
+    pub fn check(&self, head: &[u8]) -> Result<()> {
+        let line_end = head.iter().position(|&b| b == b'\n');
+        let request_line = &head[..line_end.unwrap_or(head.len())];
+        if request_line.strip_suffix(b"\r").unwrap_or(request_line).len() > self.request_line {
+            return Err(PrivacyError::HeadTooLarge(format!("request line over {} bytes", self.request_line)));
+        }
+        
+        if let Some(line_end) = line_end {
+            let rest = &head[line_end + 1..];
+            let headers = match rest.windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(pos) => pos + 4,
+                None if rest.starts_with(b"\r\n") => 2,
+                None => rest.len(),
+            };
+            if headers > self.headers {
+                return Err(PrivacyError::HeadTooLarge(format!("headers over {} bytes", self.headers)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hold an HTTP/1 client's first request head to `limits` before hyper
+/// reads it, answering 431 and logging a security event if it's over
+async fn check_http1_head(
+    stream: &mut tokio::net::TcpStream,
+    limits: HeadLimits,
+    app_state: Option<&ApiState>,
+    client_ip: IpAddr,
+) -> Result<()> {
+    let mut head = vec![0u8; limits.max_head() + 1];
+    let finished = |peeked: &[u8]| peeked.windows(4).any(|w| w == b"\r\n\r\n") || limits.check(peeked).is_err();
+    let Some(peeked) = peek_until(stream, &mut head, finished).await else {
+        return Ok(());
+    };
+    let Err(e) = limits.check(&head[..peeked]) else {
+        return Ok(());
+    };
+    report_oversized_head(app_state, client_ip, &e).await;
+    reject_head(stream, &e).await;
+    Err(e)
+}
+
+async fn report_oversized_head(app_state: Option<&ApiState>, client_ip: IpAddr, e: &PrivacyError) {
+    warn!("🚫 Rejected request from {}: {}", client_ip, e);
+    if let Some(state) = app_state {
+        state.add_log("warn", format!("🚫 Rejected request from {}: {}", client_ip, e), "security").await;
+    }
+}
+
+/// Most of an oversized request read (and dropped) after answering it, so
+/// closing doesn't reset the connection before the client reads the answer
+const REJECTED_DRAIN_BYTES: u64 = 1024 * 1024;
+
+/// Answer a request head refused before routing with `e`'s status (400 or
+/// 431) and close the connection
+async fn reject_head(stream: &mut tokio::net::TcpStream, e: &PrivacyError) {
+    let status = hyper::StatusCode::from_u16(e.status_code()).unwrap_or(hyper::StatusCode::BAD_REQUEST);
+    let body = e.to_string();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default(),
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+    let drain = tokio::io::copy(&mut (&mut *stream).take(REJECTED_DRAIN_BYTES), &mut tokio::io::sink());
+    let _ = tokio::time::timeout(Duration::from_secs(1), drain).await;
+}
+
+/// Most headers accepted on a CONNECT request
+const MAX_CONNECT_HEADERS: usize = 64;
+
+/// A parsed `CONNECT host:port HTTP/1.1` request head
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectRequest {
+    pub host: String,
+    pub port: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+impl ConnectRequest {
+    /// Parse a complete request head (everything up to and including the blank line)
+    pub fn parse(head: &[u8]) -> Result<Self> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_CONNECT_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(head) {
+            Ok(httparse::Status::Complete(_)) => {}
+            Ok(httparse::Status::Partial) => {
+                return Err(PrivacyError::Http("Incomplete CONNECT request".to_string()))
+            }
+            Err(e) => return Err(PrivacyError::BadRequest(format!("Invalid CONNECT request: {}", e))),
+        }
+        
+        if request.method != Some("CONNECT") {
+            return Err(PrivacyError::Http(format!(
+                "Expected CONNECT request, got {}",
+                request.method.unwrap_or("nothing")
+            )));
+        }
+        
+        let (host, port) = parse_connect_target(request.path.unwrap_or_default())?;
+        
+        let headers = request
+            .headers
+            .iter()
+            .map(|h| (h.name.to_string(), String::from_utf8_lossy(h.value).into_owned()))
+            .collect();
+        
+        Ok(Self { host, port, headers })
+    }
     
-    // Parse CONNECT target (e.g., "CONNECT example.com:443 HTTP/1.1")
-    let first_line = request.lines().next().ok_or("Empty request")?;
-    let parts: Vec<&str> = first_line.split_whitespace().collect();
+    /// First value of a header (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
     
-    if parts.len() < 2 {
-        return Err("Invalid CONNECT request".into());
+    pub fn proxy_authorization(&self) -> Option<&str> {
+        self.header("Proxy-Authorization")
     }
     
-    let target = parts[1];
-    info!("🔐 HTTPS tunnel request: {}", target);
+    /// `host:port` as the client asked for it
+    pub fn target(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
     
-    if let Some(ref state) = app_state {
-        state.add_log("info", format!("🔐 HTTPS tunnel request: {}", target), "network").await;
-        state.update_stats(|s| s.total_requests += 1).await;
+    /// Headers formatted for the log, with credentials redacted
+    pub fn loggable_headers(&self) -> Vec<String> {
+        self.headers
+            .iter()
+            .map(|(name, value)| {
+                if name.eq_ignore_ascii_case("proxy-authorization") {
+                    format!("{}: [redacted]", name)
+                } else {
+                    format!("{}: {}", name, value)
+                }
+            })
+            .collect()
     }
+}
+
+/// Port a CONNECT target without one is assumed to mean
+const DEFAULT_CONNECT_PORT: u16 = 443;
+
+/// Split a CONNECT target in authority form, e.g. `example.com:443` or
+/// `[::1]:443`, into host and port. IPv6 hosts come back without brackets
+/// and a missing port means `DEFAULT_CONNECT_PORT`.
+pub fn parse_connect_target(target: &str) -> Result<(String, u16)> {
+    let invalid = || PrivacyError::BadRequest(format!("Invalid host:port in CONNECT: {:?}", target));
+    
+    let (host, port) = match target.strip_prefix('[') {
+        Some(rest) => {
+            let (host, after) = rest.split_once(']').ok_or_else(invalid)?;
+            host.parse::<std::net::Ipv6Addr>().map_err(|_| invalid())?;
+            let port = match after {
+                "" => None,
+                after => Some(after.strip_prefix(':').ok_or_else(invalid)?),
+            };
+            (host, port)
+        }
+        None => match target.split_once(':') {
+            // More than one `:` is an IPv6 address missing its brackets
+            Some((_, port)) if port.contains(':') => return Err(invalid()),
+            Some((host, port)) => (host, Some(port)),
+            None => (target, None),
+        },
+    };
     
-    // Parse host:port
-    let host_port: Vec<&str> = target.split(':').collect();
-    if host_port.len() != 2 {
-        return Err("Invalid host:port in CONNECT".into());
+    let is_bracketed = target.starts_with('[');
+    let valid_host = is_bracketed
+        || (!host.is_empty()
+            && host.len() <= 253
+            && host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_')));
+    if !valid_host {
+        return Err(invalid());
     }
     
-    let host = host_port[0];
-    let port: u16 = host_port[1].parse()?;
+    let port = match port {
+        None => DEFAULT_CONNECT_PORT,
+        Some(port) => port.parse::<u16>().ok().filter(|&port| port != 0).ok_or_else(invalid)?,
+    };
     
-    // Connect through Tor
-    let tor_stream = router.connect_through_tor(host, port).await?;
+    Ok((host.to_string(), port))
+}
+
+/// Read a CONNECT request head that may arrive over several reads.
+///
+/// Returns the request and any bytes the client already sent after the head
+/// (e.g. an eager TLS ClientHello), which belong to the tunnel.
+pub async fn read_connect_request<R>(reader: &mut R, limits: HeadLimits) -> Result<(ConnectRequest, Vec<u8>)>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    
+    loop {
+        limits.check(&buffer)?;
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head_end = pos + 4;
+            let request = ConnectRequest::parse(&buffer[..head_end])?;
+            return Ok((request, buffer[head_end..].to_vec()));
+        }
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(PrivacyError::Http("Client closed connection during CONNECT request".to_string()));
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+}
+
+async fn handle_connect_tunnel(
+    mut client_stream: tokio::net::TcpStream,
+    router: Router,
+    app_state: Option<ApiState>,
+    head_limits: HeadLimits,
+    rate_limiter: RateLimiter,
+    scheduler: FairScheduler,
+    client_ip: IpAddr,
+) -> Result<()> {
+    // Read the full CONNECT request head (e.g., "CONNECT example.com:443 HTTP/1.1")
+    let (request, early_data) = match read_connect_request(&mut client_stream, head_limits).await {
+        Ok(read) => read,
+        Err(e @ (PrivacyError::BadRequest(_) | PrivacyError::HeadTooLarge(_))) => {
+            if matches!(e, PrivacyError::HeadTooLarge(_)) {
+                report_oversized_head(app_state.as_ref(), client_ip, &e).await;
+            }
+            // Tell the client what was wrong instead of just dropping the connection
+            reject_head(&mut client_stream, &e).await;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+    let target = request.target();
+    info!("🔐 HTTPS tunnel request: {}", target);
+    
+    if let Some(ref state) = app_state {
+        let details = LogDetails {
+            domain: Some(request.host.clone()),
+            port: Some(request.port),
+            method: Some("CONNECT".to_string()),
+            client_ip: client_stream.peer_addr().ok().map(|addr| addr.ip().to_string()),
+            request_headers: Some(request.loggable_headers()),
+            ..LogDetails::default()
+        };
+        state.add_log_with_details("info", format!("🔐 HTTPS tunnel request: {}", target), "network", Some(details)).await;
+        state.counters.total_requests.inc();
+        state.hosts.record_request(&normalize_host(&request.host));
+    }
+    
+    // Connect through Tor, taking turns with other clients for a slot while the stream opens
+    let permit = scheduler.acquire(client_ip).await;
+    let opened = router.connect_through_tor(&request.host, request.port, Some(client_ip)).await;
+    drop(permit);
+    let mut tor_stream = match opened {
+        Ok(stream) => stream,
+        Err(e) => {
+            // A refused or failed tunnel gets a status too, e.g. a 503 from the kill switch
+            reject_head(&mut client_stream, &e).await;
+            return Err(e);
+        }
+    };
+    
+    // Forward anything the client sent before waiting for our response
+    if !early_data.is_empty() {
+        tor_stream.write_all(&early_data).await?;
+        tor_stream.flush().await?;
+    }
     
     // Send success response to client
     client_stream.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await?;
@@ -153,47 +655,820 @@ async fn handle_connect_tunnel(
     let (mut client_read, mut client_write) = client_stream.split();
     let (mut tor_read, mut tor_write) = tokio::io::split(tor_stream);
     
-    let client_to_tor = tokio::io::copy(&mut client_read, &mut tor_write);
-    let tor_to_client = tokio::io::copy(&mut tor_read, &mut client_write);
+    // Both directions draw on the client's bandwidth budget, and bytes
+    // moving either way keep the session from counting as idle
+    let idle = app_state.as_ref().map(|state| state.idle.clone());
+    let touch = || {
+        if let Some(ref idle) = idle {
+            idle.touch();
+        }
+    };
+    let (mut sent, mut received) = (early_data.len() as u64, 0u64);
     
-    // Run both directions concurrently
-    tokio::select! {
-        result = client_to_tor => {
-            if let Err(e) = result {
-                error!("Client->Tor copy error: {}", e);
-            }
+    // Each direction runs until its side closes, then passes the close on,
+    // like `tokio::io::copy_bidirectional`
+    let client_to_tor = async {
+        let copied = rate_limit::copy_throttled_with_progress(&mut client_read, &mut tor_write, &rate_limiter, client_ip, |n| {
+            sent += n as u64;
+            touch();
+        }).await;
+        if let Err(e) = copied {
+            error!("Client->Tor copy error: {}", e);
         }
-        result = tor_to_client => {
-            if let Err(e) = result {
-                error!("Tor->Client copy error: {}", e);
-            }
+        let _ = tor_write.shutdown().await;
+    };
+    let tor_to_client = async {
+        let copied = rate_limit::copy_throttled_with_progress(&mut tor_read, &mut client_write, &rate_limiter, client_ip, |n| {
+            received += n as u64;
+            touch();
+        }).await;
+        if let Err(e) = copied {
+            error!("Tor->Client copy error: {}", e);
         }
-    }
+        let _ = client_write.shutdown().await;
+    };
+    tokio::join!(client_to_tor, tor_to_client);
+    let bytes_copied = sent + received;
     
     info!("🔌 HTTPS tunnel closed: {}", target);
     
     if let Some(ref state) = app_state {
         state.add_log("info", format!("🔌 HTTPS tunnel closed: {}", target), "network").await;
+        state.counters.bytes_transferred.add(bytes_copied);
+        state.hosts.record_bytes(&normalize_host(&request.host), bytes_copied);
     }
     
     Ok(())
 }
 
 async fn handle_request(
-    req: Request<hyper::body::Incoming>,
+    mut req: Request<hyper::body::Incoming>,
     router: Router,
-) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    rate_limiter: RateLimiter,
+    scheduler: FairScheduler,
+    client_ip: IpAddr,
+) -> std::result::Result<Response<Full<Bytes>>, hyper::Error> {
+    use hyper::body::Body;
+    
     let method = req.method().clone();
     let uri = req.uri().clone();
     
     info!("📡 HTTP Request: {} {}", method, uri);
+    req.extensions_mut().insert(ClientAddr(client_ip));
     
-    // Route through multi-hop network
-    match router.route_request(req).await {
-        Ok(response) => Ok(response),
+    // Route through multi-hop network once it's this client's turn
+    let permit = scheduler.acquire(client_ip).await;
+    let result = router.route_request(req).await;
+    drop(permit);
+    match result {
+        Ok(response) => {
+            // Hold the response back until it fits the client's bandwidth budget
+            let body_size = response.body().size_hint().exact().unwrap_or(0);
+            rate_limiter.acquire(client_ip, body_size as usize).await;
+            Ok(response)
+        }
         Err(e) => {
             error!("Routing error: {}", e);
-            Ok(Response::new(Full::new(Bytes::from("Error processing request"))))
+            Ok(Response::builder()
+                .status(e.status_code())
+                .body(Full::new(Bytes::from(format!("Privacy Suite could not complete the request: {}", e))))
+                .unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::DirectTransport;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_port_in_use_is_reported_clearly() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = taken.local_addr().unwrap();
+
+        let err = bind_listener(addr).await.unwrap_err();
+        assert!(matches!(err, PrivacyError::PortInUse { port } if port == addr.port()));
+        assert!(err.to_string().contains(&format!("Port {} is already in use", addr.port())));
+    }
+
+    #[tokio::test]
+    async fn test_taken_port_falls_back_to_next_free_one() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = taken.local_addr().unwrap();
+        
+        let listener = bind_with_fallback(addr).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(port > addr.port() && port <= addr.port() + PORT_FALLBACK_ATTEMPTS);
+    }
+
+    /// Feed `chunks` to `read_connect_request` as separate writes
+    async fn read_chunked(chunks: Vec<&'static [u8]>) -> Result<(ConnectRequest, Vec<u8>)> {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        tokio::spawn(async move {
+            for chunk in chunks {
+                client.write_all(chunk).await.unwrap();
+                client.flush().await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+        read_connect_request(&mut server, HeadLimits::from_config(&Config::default())).await
+    }
+
+    #[tokio::test]
+    async fn test_connect_split_across_reads() {
+        let (request, early_data) = read_chunked(vec![
+            b"CONN",
+            b"ECT example.com:4",
+            b"43 HTTP/1.1\r\nHost: exam",
+            b"ple.com:443\r\n",
+            b"\r\n",
+        ])
+        .await
+        .unwrap();
+        
+        assert_eq!(request.host, "example.com");
+        assert_eq!(request.port, 443);
+        assert_eq!(request.header("host"), Some("example.com:443"));
+        assert!(early_data.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_trailing_headers_and_early_data() {
+        let (request, early_data) = read_chunked(vec![
+            b"CONNECT [2001:db8::1]:8443 HTTP/1.1\r\n",
+            b"Host: [2001:db8::1]:8443\r\nUser-Agent: curl/8.0\r\n",
+            b"Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n\x16\x03\x01",
+        ])
+        .await
+        .unwrap();
+        
+        assert_eq!(request.host, "2001:db8::1");
+        assert_eq!(request.port, 8443);
+        assert_eq!(request.target(), "[2001:db8::1]:8443");
+        assert_eq!(request.proxy_authorization(), Some("Basic dXNlcjpwYXNz"));
+        assert!(request.loggable_headers().contains(&"Proxy-Authorization: [redacted]".to_string()));
+        assert_eq!(early_data, vec![0x16, 0x03, 0x01]);
+    }
+
+    #[test]
+    fn test_connect_target_parsing() {
+        let parsed = |target| parse_connect_target(target).unwrap();
+        assert_eq!(parsed("example.com:8443"), ("example.com".to_string(), 8443));
+        assert_eq!(parsed("[::1]:443"), ("::1".to_string(), 443));
+        assert_eq!(parsed("192.0.2.7:993"), ("192.0.2.7".to_string(), 993));
+        // No port means the HTTPS default
+        assert_eq!(parsed("example.com"), ("example.com".to_string(), 443));
+        assert_eq!(parsed("[2001:db8::1]"), ("2001:db8::1".to_string(), 443));
+        
+        for garbage in ["", ":443", "example.com:", "example.com:https", "example.com:70000", "example.com:0",
+            "::1:443", "[::1", "[not-an-ip]:443", "[::1]443", "exa mple.com:443", "exa/mple.com:443"]
+        {
+            assert!(
+                matches!(parse_connect_target(garbage), Err(PrivacyError::BadRequest(_))),
+                "accepted {:?}",
+                garbage
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_connect_requests() {
+        let (request, _) = read_chunked(vec![b"CONNECT example.com HTTP/1.1\r\n\r\n"]).await.unwrap();
+        assert_eq!(request.port, 443);
+        assert!(read_chunked(vec![b"CONNECT example.com:abc HTTP/1.1\r\n\r\n"]).await.is_err());
+        assert!(read_chunked(vec![b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"]).await.is_err());
+        assert!(read_chunked(vec![b"CONNECT example.com:443 HTTP/1.1\r\n"]).await.is_err());
+    }
+
+    /// Local server answering every request with its method, target and Host header
+    async fn echo_server() -> SocketAddr {
+        let app = axum::Router::new().fallback(|req: axum::extract::Request| async move {
+            let host = req
+                .headers()
+                .get(hyper::header::HOST)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            format!("{} {} host={}", req.method(), req.uri(), host)
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    /// The default config, except that the test servers on this machine are
+    /// served directly (`PrivateHostPolicy::Bypass`) to the test clients,
+    /// which are on this machine too
+    fn local_config() -> Config {
+        let mut config = Config::default();
+        config.private_hosts = crate::config::PrivateHostPolicy::Bypass;
+        config
+    }
+
+    /// Run the full proxy, with every protection at its default but private
+    /// hosts, over `DirectTransport`
+    async fn start_proxy() -> SocketAddr {
+        let config = local_config();
+        let router = Router::with_transport(config.clone(), None, Arc::new(DirectTransport::new())).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(ProxyServer::with_router(listener, &config, router, None).run());
+        addr
+    }
+
+    /// Read from `stream` until the end of a response head
+    async fn read_head(stream: &mut tokio::net::TcpStream) -> String {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            assert_eq!(stream.read(&mut byte).await.unwrap(), 1, "closed mid-head");
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    async fn send_and_read_all(stream: &mut tokio::net::TcpStream, request: &str) -> String {
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_http_get_is_forwarded_and_trackers_blocked() {
+        let echo = echo_server().await;
+        let proxy = start_proxy().await;
+
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        let response = send_and_read_all(
+            &mut client,
+            &format!("GET http://{}/hello?x=1 HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", echo, echo),
+        ).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        // The upstream gets an origin-form request with the fingerprinted headers
+        assert!(response.ends_with(&format!("GET /hello?x=1 host={}", echo)), "{}", response);
+
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        let response = send_and_read_all(
+            &mut client,
+            "GET http://doubleclick.net/ad.js HTTP/1.1\r\nHost: doubleclick.net\r\nConnection: close\r\n\r\n",
+        ).await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        assert!(response.ends_with("Tracker blocked by Privacy Suite"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_connect_target_gets_400() {
+        let proxy = start_proxy().await;
+        
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        let response = send_and_read_all(&mut client, "CONNECT exa$mple.com:443 HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{}", response);
+        assert!(response.contains("Invalid host:port"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_connect_tunnel_to_ipv6_literal() {
+        let Ok(listener) = TcpListener::bind("[::1]:0").await else {
+            // No IPv6 loopback on this machine
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket.write_all(b"hello over ipv6").await.unwrap();
+        });
+        let proxy = start_proxy().await;
+        
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        client.write_all(format!("CONNECT [::1]:{} HTTP/1.1\r\n\r\n", port).as_bytes()).await.unwrap();
+        let response = send_and_read_all(&mut client, "").await;
+        assert_eq!(response, "HTTP/1.1 200 Connection Established\r\n\r\nhello over ipv6");
+    }
+
+    #[tokio::test]
+    async fn test_learning_mode_forwards_would_be_blocks() {
+        use crate::request_filter::{FilterAction, RequestFilter};
+        
+        let echo = echo_server().await;
+        let mut config = local_config();
+        // The echo server is a raw IP, and `/admin` matches a blocking filter
+        config.block_direct_ip = true;
+        config.request_filters = vec![RequestFilter {
+            host_glob: None,
+            path_glob: Some("/admin*".to_string()),
+            method: None,
+            action: FilterAction::Block,
+        }];
+        
+        for learning_mode in [true, false] {
+            config.learning_mode = learning_mode;
+            let state = ApiState::new(config.clone());
+            let router = Router::with_transport(config.clone(), Some(state.clone()), Arc::new(DirectTransport::new())).await.unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = listener.local_addr().unwrap();
+            tokio::spawn(ProxyServer::with_router(listener, &config, router, Some(state.clone())).run());
+            
+            let mut statuses = Vec::new();
+            for path in ["/admin", "/page"] {
+                let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+                let request = format!("GET http://{}{} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", echo, path, echo);
+                let response = send_and_read_all(&mut client, &request).await;
+                statuses.push(response[9..12].to_string());
+            }
+            
+            let stats = state.stats_snapshot().await;
+            let logs = state.logs.read().await.clone();
+            let would_block: Vec<&str> = logs
+                .iter()
+                .filter(|log| log.message.contains("Would block"))
+                .map(|log| log.message.as_str())
+                .collect();
+            if learning_mode {
+                assert_eq!(statuses, ["200", "200"]);
+                assert_eq!((stats.would_block, stats.requests_blocked), (2, 0));
+                assert_eq!(would_block.len(), 2, "{:?}", would_block);
+                assert!(would_block[0].contains("request filter * */admin*"), "{}", would_block[0]);
+                assert!(would_block[1].contains("block_direct_ip"), "{}", would_block[1]);
+            } else {
+                assert_eq!(statuses, ["403", "403"]);
+                assert_eq!((stats.would_block, stats.requests_blocked), (0, 2));
+                assert!(would_block.is_empty());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_logs_share_one_trace_id() {
+        let echo = echo_server().await;
+        let config = local_config();
+        let state = ApiState::new(config.clone());
+        let router = Router::with_transport(config.clone(), Some(state.clone()), Arc::new(DirectTransport::new())).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        tokio::spawn(ProxyServer::with_router(listener, &config, router, Some(state.clone())).run());
+
+        for path in ["/first", "/second"] {
+            let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+            let request = format!("GET http://{}{} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", echo, path, echo);
+            send_and_read_all(&mut client, &request).await;
+        }
+
+        // Everything but the connection notices belongs to one of the two requests
+        let logs = state.logs.read().await.clone();
+        let mut traces: Vec<(String, Vec<String>)> = Vec::new();
+        for log in logs.iter().filter(|log| !log.message.contains("New connection")) {
+            let trace_id = log.details.as_ref().and_then(|d| d.trace_id.clone()).unwrap_or_else(|| panic!("untraced: {}", log.message));
+            match traces.last_mut() {
+                Some((id, messages)) if *id == trace_id => messages.push(log.message.clone()),
+                _ => traces.push((trace_id, vec![log.message.clone()])),
+            }
+        }
+
+        assert_eq!(traces.len(), 2, "{:?}", traces);
+        assert_ne!(traces[0].0, traces[1].0);
+        assert!(traces[0].1.len() > 1 && traces[0].1[0].ends_with("/first"), "{:?}", traces[0].1);
+        assert!(traces[1].1[0].ends_with("/second"), "{:?}", traces[1].1);
+    }
+
+    /// Stands in for a Tor exit that can't reach anything, so any answer
+    /// came over a direct connection
+    #[derive(Default)]
+    struct UnreachableExit {
+        asked: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::transport::Transport for UnreachableExit {
+        async fn connect_stream(&self, host: &str, _port: u16) -> Result<crate::transport::OpenedStream> {
+            self.asked.lock().unwrap().push(host.to_string());
+            Err(PrivacyError::TorConnect(format!("the exit can't reach {}", host)))
+        }
+
+        async fn route_request(
+            &self,
+            _req: Request<hyper::body::Incoming>,
+            _fingerprint: &crate::fingerprint::BrowserFingerprint,
+            connect_host: &str,
+        ) -> Result<Response<Full<Bytes>>> {
+            self.connect_stream(connect_host, 80).await?;
+            unreachable!("the exit never connects")
+        }
+
+        async fn fetch(
+            &self,
+            _method: &hyper::Method,
+            _uri: &hyper::Uri,
+            _fingerprint: &crate::fingerprint::BrowserFingerprint,
+            connect_host: &str,
+        ) -> Result<Response<Full<Bytes>>> {
+            self.connect_stream(connect_host, 80).await?;
+            unreachable!("the exit never connects")
+        }
+    }
+
+    /// An `UnreachableExit` that loses and regains the network when told to
+    struct DroppingNetwork {
+        exit: UnreachableExit,
+        ready: tokio::sync::watch::Sender<bool>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::transport::Transport for DroppingNetwork {
+        async fn connect_stream(&self, host: &str, port: u16) -> Result<crate::transport::OpenedStream> {
+            self.exit.connect_stream(host, port).await
+        }
+
+        async fn route_request(
+            &self,
+            req: Request<hyper::body::Incoming>,
+            fingerprint: &crate::fingerprint::BrowserFingerprint,
+            connect_host: &str,
+        ) -> Result<Response<Full<Bytes>>> {
+            self.exit.route_request(req, fingerprint, connect_host).await
+        }
+
+        async fn fetch(
+            &self,
+            method: &hyper::Method,
+            uri: &hyper::Uri,
+            fingerprint: &crate::fingerprint::BrowserFingerprint,
+            connect_host: &str,
+        ) -> Result<Response<Full<Bytes>>> {
+            self.exit.fetch(method, uri, fingerprint, connect_host).await
+        }
+
+        fn ready_for_traffic(&self) -> Option<tokio::sync::watch::Receiver<bool>> {
+            Some(self.ready.subscribe())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_private_hosts_are_kept_off_tor() {
+        use crate::config::PrivateHostPolicy;
+        
+        let echo = echo_server().await;
+        let localhost = format!("localhost:{}", echo.port());
+        let mut config = Config::default();
+        
+        for policy in [PrivateHostPolicy::Bypass, PrivateHostPolicy::Block] {
+            config.private_hosts = policy;
+            let exit = Arc::new(UnreachableExit::default());
+            let state = ApiState::new(config.clone());
+            let router = Router::with_transport(config.clone(), Some(state.clone()), exit.clone()).await.unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = listener.local_addr().unwrap();
+            tokio::spawn(ProxyServer::with_router(listener, &config, router, Some(state.clone())).run());
+            
+            // Connecting directly to these would wait on the network, so only blocking is tried
+            let hosts = match policy {
+                PrivateHostPolicy::Bypass => vec![localhost.as_str()],
+                PrivateHostPolicy::Block => vec![localhost.as_str(), "printer.local", "10.1.2.3"],
+            };
+            for host in &hosts {
+                let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+                let request = format!("GET http://{}/status HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", host, host);
+                let response = send_and_read_all(&mut client, &request).await;
+                match policy {
+                    PrivateHostPolicy::Bypass => assert!(response.ends_with(&format!("GET /status host={}", localhost)), "{}", response),
+                    PrivateHostPolicy::Block => assert!(response.starts_with("HTTP/1.1 403"), "{}: {}", host, response),
+                }
+            }
+            
+            assert!(exit.asked.lock().unwrap().is_empty(), "{:?}", exit.asked.lock().unwrap());
+            let logs = state.logs.read().await.clone();
+            let blocked = logs.iter().filter(|log| log.category == "security" && log.message.contains("private host")).count();
+            assert_eq!(blocked, if policy == PrivateHostPolicy::Block { 3 } else { 0 });
         }
     }
+
+    #[tokio::test]
+    async fn test_bypass_never_reaches_the_control_api_and_tunnels_stay_local() {
+        let echo = echo_server().await;
+        let api = echo_server().await;
+        let mut config = local_config();
+        config.api_port = api.port();
+        let exit = Arc::new(UnreachableExit::default());
+        let state = ApiState::new(config.clone());
+        let router = Router::with_transport(config.clone(), Some(state.clone()), exit.clone()).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        tokio::spawn(ProxyServer::with_router(listener, &config, router, Some(state.clone())).run());
+        
+        // Even from this machine, the API port is refused over plain HTTP and CONNECT
+        for host in [format!("127.0.0.1:{}", api.port()), format!("localhost:{}", api.port())] {
+            let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+            let request = format!("POST http://{}/api/shutdown HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", host, host);
+            let response = send_and_read_all(&mut client, &request).await;
+            assert!(response.starts_with("HTTP/1.1 403"), "{}: {}", host, response);
+            
+            let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+            let response = send_and_read_all(&mut client, &format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", host, host)).await;
+            assert!(!response.contains("200 Connection Established"), "{}: {}", host, response);
+        }
+        
+        // Other local services are tunneled directly, never via the exit
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        client.write_all(format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", echo, echo).as_bytes()).await.unwrap();
+        assert_eq!(read_head(&mut client).await, "HTTP/1.1 200 Connection Established\r\n\r\n");
+        let response = send_and_read_all(&mut client, "GET /local HTTP/1.1\r\nHost: echo.test\r\nConnection: close\r\n\r\n").await;
+        assert!(response.ends_with("GET /local host=echo.test"), "{}", response);
+        assert!(exit.asked.lock().unwrap().is_empty(), "{:?}", exit.asked.lock().unwrap());
+        
+        let logs = state.logs.read().await.clone();
+        assert_eq!(logs.iter().filter(|log| log.category == "security" && log.message.contains("private host")).count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_heads_get_431_before_tor() {
+        let config = Config::default();
+        let exit = Arc::new(UnreachableExit::default());
+        let state = ApiState::new(config.clone());
+        let router = Router::with_transport(config.clone(), Some(state.clone()), exit.clone()).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        tokio::spawn(ProxyServer::with_router(listener, &config, router, Some(state.clone())).run());
+        
+        let long_path = "a".repeat(config.max_request_line_bytes);
+        let big_header = "b".repeat(config.max_request_header_bytes);
+        let requests = [
+            (format!("GET http://example.com/{} HTTP/1.1\r\nHost: example.com\r\n\r\n", long_path), "request line"),
+            (format!("GET http://example.com/ HTTP/1.1\r\nHost: example.com\r\nX-Big: {}\r\n\r\n", big_header), "headers"),
+            (format!("CONNECT example.com:443 HTTP/1.1\r\nX-Big: {}\r\n\r\n", big_header), "headers"),
+        ];
+        for (request, reason) in &requests {
+            let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+            let response = send_and_read_all(&mut client, request).await;
+            assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"), "{}", response);
+            assert!(response.contains(reason), "{}", response);
+        }
+        assert!(exit.asked.lock().unwrap().is_empty());
+        
+        // Heads just under the limits still reach the exit
+        let fits = format!("GET http://example.com/{} HTTP/1.1\r\nHost: example.com\r\n\r\n", &long_path[64..]);
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        client.write_all(fits.as_bytes()).await.unwrap();
+        assert!(!read_head(&mut client).await.starts_with("HTTP/1.1 431"));
+        assert!(exit.asked.lock().unwrap().contains(&"example.com".to_string()));
+        
+        let logs = state.logs.read().await.clone();
+        let rejected = logs.iter().filter(|log| log.category == "security" && log.message.contains("Request head too large")).count();
+        assert_eq!(rejected, requests.len());
+    }
+
+    #[tokio::test]
+    async fn test_request_held_during_outage_succeeds_after_reconnect() {
+        let echo = echo_server().await;
+        let mut config = local_config();
+        config.reconnect_hold_secs = 5;
+        let router = Router::with_transport(config.clone(), None, Arc::new(DirectTransport::new())).await.unwrap();
+        let kill_switch = router.kill_switch().clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        tokio::spawn(ProxyServer::with_router(listener, &config, router, None).run());
+
+        kill_switch.set_tor_status(false).await;
+        let request = tokio::spawn(async move {
+            let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+            send_and_read_all(&mut client, &format!("GET http://{}/held HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", echo, echo)).await
+        });
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        assert!(!request.is_finished(), "request wasn't held");
+
+        kill_switch.set_tor_status(true).await;
+        let response = request.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("GET /held"), "{}", response);
+        assert_eq!(kill_switch.get_stats().await.blocked_requests, 0);
+    }
+
+    #[tokio::test]
+    async fn test_the_app_kill_switch_guards_the_session_router() {
+        let echo = echo_server().await;
+        
+        for block_lan in [true, false] {
+            let mut config = local_config();
+            config.killswitch_block_lan = block_lan;
+            let exit = Arc::new(UnreachableExit::default());
+            let state = ApiState::new(config.clone()).with_kill_switch(crate::kill_switch::KillSwitch::new());
+            let router = Router::with_transport(config.clone(), Some(state.clone()), exit.clone()).await.unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = listener.local_addr().unwrap();
+            tokio::spawn(ProxyServer::with_router(listener, &config, router, Some(state.clone())).run());
+            
+            // The session's Tor client going away is what the app's kill switch hears about
+            state.kill_switch.as_ref().unwrap().set_tor_status(false).await;
+            for host in [echo.to_string(), "example.com".to_string()] {
+                let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+                let request = format!("GET http://{}/outage HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", host, host);
+                let response = send_and_read_all(&mut client, &request).await;
+                let lan_passes = !block_lan && host == echo.to_string();
+                if lan_passes {
+                    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+                } else {
+                    assert!(response.starts_with("HTTP/1.1 503"), "{}: {}", host, response);
+                }
+            }
+            
+            assert!(exit.asked.lock().unwrap().is_empty(), "{:?}", exit.asked.lock().unwrap());
+            let blocked = state.kill_switch.as_ref().unwrap().get_stats().await.blocked_requests;
+            assert_eq!(blocked, if block_lan { 2 } else { 1 });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_held_while_the_transport_reconnects() {
+        let echo = echo_server().await;
+        let mut config = local_config();
+        config.reconnect_hold_secs = 5;
+        let network = Arc::new(DroppingNetwork { exit: UnreachableExit::default(), ready: tokio::sync::watch::channel(true).0 });
+        let state = ApiState::new(config.clone()).with_kill_switch(crate::kill_switch::KillSwitch::new());
+        let router = Router::with_transport(config.clone(), Some(state.clone()), network.clone()).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        tokio::spawn(ProxyServer::with_router(listener, &config, router, Some(state.clone())).run());
+        
+        // The session keeps running; only the transport loses the network
+        let kill_switch = state.kill_switch.clone().unwrap();
+        network.ready.send_replace(false);
+        for _ in 0..100 {
+            if !kill_switch.is_tor_connected().await {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        assert!(!kill_switch.is_tor_connected().await);
+        
+        let tunnel = tokio::spawn(async move {
+            let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+            client.write_all(format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", echo, echo).as_bytes()).await.unwrap();
+            let head = read_head(&mut client).await;
+            (client, head)
+        });
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        assert!(!tunnel.is_finished(), "tunnel wasn't held");
+        
+        network.ready.send_replace(true);
+        let (mut client, head) = tunnel.await.unwrap();
+        assert_eq!(head, "HTTP/1.1 200 Connection Established\r\n\r\n");
+        let response = send_and_read_all(&mut client, "GET /held HTTP/1.1\r\nHost: echo.test\r\nConnection: close\r\n\r\n").await;
+        assert!(response.ends_with("GET /held host=echo.test"), "{}", response);
+        assert_eq!(kill_switch.get_stats().await.blocked_requests, 0);
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_gets_503_once_the_hold_runs_out() {
+        let echo = echo_server().await;
+        let mut config = local_config();
+        config.reconnect_hold_secs = 1;
+        let network = Arc::new(DroppingNetwork { exit: UnreachableExit::default(), ready: tokio::sync::watch::channel(true).0 });
+        let state = ApiState::new(config.clone()).with_kill_switch(crate::kill_switch::KillSwitch::new());
+        let router = Router::with_transport(config.clone(), Some(state.clone()), network.clone()).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        tokio::spawn(ProxyServer::with_router(listener, &config, router, Some(state.clone())).run());
+        
+        let kill_switch = state.kill_switch.clone().unwrap();
+        network.ready.send_replace(false);
+        for _ in 0..100 {
+            if !kill_switch.is_tor_connected().await {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        let response = send_and_read_all(&mut client, &format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", echo, echo)).await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert_eq!(kill_switch.get_stats().await.blocked_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_refused_tunnels_get_403() {
+        let mut config = local_config();
+        config.mode = crate::config::BrowsingMode::Allowlist;
+        config.allowed_domains = vec!["example.com".to_string(), "dns.google".to_string()];
+        config.block_app_doh = true;
+        let router = Router::with_transport(config.clone(), None, Arc::new(DirectTransport::new())).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        tokio::spawn(ProxyServer::with_router(listener, &config, router, None).run());
+        
+        // Not allowlisted, and an app's own DNS-over-HTTPS
+        for target in ["tracker.example.net:443", "dns.google:443"] {
+            let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+            let response = send_and_read_all(&mut client, &format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target)).await;
+            assert!(response.starts_with("HTTP/1.1 403"), "{}: {}", target, response);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_counts_bytes_both_ways() {
+        let echo = echo_server().await;
+        let config = local_config();
+        let state = ApiState::new(config.clone());
+        let router = Router::with_transport(config.clone(), Some(state.clone()), Arc::new(DirectTransport::new())).await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        tokio::spawn(ProxyServer::with_router(listener, &config, router, Some(state.clone())).run());
+        
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        client.write_all(format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", echo, echo).as_bytes()).await.unwrap();
+        read_head(&mut client).await;
+        let request = "GET /both-ways HTTP/1.1\r\nHost: echo.test\r\nConnection: close\r\n\r\n";
+        let response = send_and_read_all(&mut client, request).await;
+        drop(client);
+        
+        // Counted once the tunnel has closed on both sides
+        let expected = (request.len() + response.len()) as u64;
+        for _ in 0..100 {
+            if state.counters.bytes_transferred.get() != 0 {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(state.counters.bytes_transferred.get(), expected);
+    }
+
+    #[test]
+    fn test_client_protocol_detection() {
+        assert_eq!(ClientProtocol::detect(b"CONNECT example.com:443 HTTP/1.1\r\n"), ClientProtocol::Connect);
+        assert_eq!(ClientProtocol::detect(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n"), ClientProtocol::Http2);
+        assert_eq!(ClientProtocol::detect(b"GET http://example.com/ HTTP/1.0\r\n"), ClientProtocol::Http1);
+        assert_eq!(ClientProtocol::detect(b"POST http://example.com/ HTTP/1.1\r\n"), ClientProtocol::Http1);
+    }
+
+    #[tokio::test]
+    async fn test_http10_and_http2_clients_are_served() {
+        use http_body_util::{BodyExt, Empty};
+        
+        let echo = echo_server().await;
+        let proxy = start_proxy().await;
+        
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        let response = send_and_read_all(&mut client, &format!("GET http://{}/old HTTP/1.0\r\n\r\n", echo)).await;
+        assert!(response.starts_with("HTTP/1.0 200"), "{}", response);
+        assert!(response.ends_with(&format!("GET /old host={}", echo)), "{}", response);
+        
+        // An HTTP/2 client that skips the upgrade and opens with the preface
+        let stream = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .uri(format!("http://{}/h2", echo))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.version(), hyper::Version::HTTP_2);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, format!("GET /h2 host={}", echo));
+    }
+
+    #[tokio::test]
+    async fn test_connect_sent_byte_by_byte_is_tunneled() {
+        let echo = echo_server().await;
+        let proxy = start_proxy().await;
+        
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        client.set_nodelay(true).unwrap();
+        // Slower than `PEEK_RETRY`, so the proxy sees "C", "CO", ... first
+        for byte in format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", echo, echo).bytes() {
+            client.write_all(&[byte]).await.unwrap();
+            tokio::time::sleep(PEEK_RETRY * 2).await;
+        }
+        assert_eq!(read_head(&mut client).await, "HTTP/1.1 200 Connection Established\r\n\r\n");
+        
+        let response = send_and_read_all(
+            &mut client,
+            "GET /slow HTTP/1.1\r\nHost: echo.test\r\nConnection: close\r\n\r\n",
+        ).await;
+        assert!(response.ends_with("GET /slow host=echo.test"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_connect_tunnel_reaches_destination() {
+        let echo = echo_server().await;
+        let proxy = start_proxy().await;
+
+        let mut client = tokio::net::TcpStream::connect(proxy).await.unwrap();
+        client
+            .write_all(format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", echo, echo).as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(read_head(&mut client).await, "HTTP/1.1 200 Connection Established\r\n\r\n");
+
+        // Inside the tunnel the client talks to the destination directly
+        let response = send_and_read_all(
+            &mut client,
+            "GET /tunneled HTTP/1.1\r\nHost: echo.test\r\nConnection: close\r\n\r\n",
+        ).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("GET /tunneled host=echo.test"), "{}", response);
+    }
 }
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Clients idle this long are forgotten
+const CLIENT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Period over which per-client throughput is measured
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Current transfer rate of one client, as reported by the stats API
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientThroughput {
+    pub client: String,
+    pub bytes_per_sec: u64,
+}
+
+struct ClientBucket {
+    /// Bytes the client may send right now; negative while it is being throttled
+    tokens: f64,
+    last_refill: Instant,
+    window_start: Instant,
+    window_bytes: u64,
+    bytes_per_sec: u64,
+}
+
+impl ClientBucket {
+    fn new(capacity: f64, now: Instant) -> Self {
+        Self {
+            tokens: capacity,
+            last_refill: now,
+            window_start: now,
+            window_bytes: 0,
+            bytes_per_sec: 0,
+        }
+    }
+
+    fn record(&mut self, bytes: usize, now: Instant) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= THROUGHPUT_WINDOW {
+            self.bytes_per_sec = (self.window_bytes as f64 / elapsed.as_secs_f64()) as u64;
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+        self.window_bytes += bytes as u64;
+    }
+
+    /// Throughput of the last full window, or zero once the client has gone quiet
+    fn throughput(&self, now: Instant) -> u64 {
+        if now.duration_since(self.window_start) >= THROUGHPUT_WINDOW * 2 {
+            0
+        } else {
+            self.bytes_per_sec
+        }
+    }
+}
+
+/// Per-client-IP token bucket shared by every connection from that client.
+///
+/// Over-budget clients are slowed down by delaying their transfers rather
+/// than having data dropped. With no limit configured it still measures
+/// per-client throughput for the stats API.
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// Bytes per second per client, or None for unlimited
+    rate: Option<f64>,
+    clients: Arc<Mutex<HashMap<IpAddr, ClientBucket>>>,
+}
+
+impl RateLimiter {
+    /// `rate_limit_kbps` is in kilobits per second; 0 disables throttling
+    pub fn new(rate_limit_kbps: u64) -> Self {
+        Self {
+            rate: (rate_limit_kbps > 0).then(|| rate_limit_kbps as f64 * 1000.0 / 8.0),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Account `bytes` transferred for `client`, waiting if it is over budget
+    pub async fn acquire(&self, client: IpAddr, bytes: usize) {
+        let delay = self.consume(client, bytes, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Take `bytes` from the client's bucket and return how long it must wait
+    fn consume(&self, client: IpAddr, bytes: usize, now: Instant) -> Duration {
+        let capacity = self.rate.unwrap_or(0.0);
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        if !clients.contains_key(&client) {
+            clients.retain(|_, bucket| now.duration_since(bucket.last_refill) < CLIENT_IDLE_TIMEOUT);
+        }
+        let bucket = clients
+            .entry(client)
+            .or_insert_with(|| ClientBucket::new(capacity, now));
+        bucket.record(bytes, now);
+
+        let Some(rate) = self.rate else {
+            bucket.last_refill = now;
+            return Duration::ZERO;
+        };
+
+        // Refill for the time since the last transfer, allowing at most one second of burst
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.last_refill = now;
+
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+
+    /// Current throughput of every recently active client, busiest first
+    pub fn throughput(&self) -> Vec<ClientThroughput> {
+        let now = Instant::now();
+        let clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut throughput: Vec<ClientThroughput> = clients
+            .iter()
+            .map(|(client, bucket)| ClientThroughput {
+                client: client.to_string(),
+                bytes_per_sec: bucket.throughput(now),
+            })
+            .filter(|entry| entry.bytes_per_sec > 0)
+            .collect();
+        throughput.sort_by(|a, b| b.bytes_per_sec.cmp(&a.bytes_per_sec));
+        throughput
+    }
+}
+
+/// Like `tokio::io::copy`, but charges every chunk to `client`'s budget
+/// before writing it, so a throttled client's stream slows down.
+pub async fn copy_throttled<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    limiter: &RateLimiter,
+    client: IpAddr,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    copy_throttled_with_progress(reader, writer, limiter, client, |_| {}).await
+}
+
+/// `copy_throttled`, calling `progress` with the size of every chunk written
+pub async fn copy_throttled_with_progress<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    limiter: &RateLimiter,
+    client: IpAddr,
+    mut progress: impl FnMut(usize),
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = [0u8; 16 * 1024];
+    let mut total = 0u64;
+
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            writer.flush().await?;
+            return Ok(total);
+        }
+
+        limiter.acquire(client, n).await;
+        writer.write_all(&buf[..n]).await?;
+        progress(n);
+        total += n as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 20));
+    const OTHER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 21));
+
+    #[tokio::test]
+    async fn test_high_rate_client_is_slowed_to_cap() {
+        // 400 kbps = 50,000 bytes/s, with up to one second of burst
+        let limiter = RateLimiter::new(400);
+        let data = vec![0u8; 100_000];
+
+        let started = Instant::now();
+        let copied = copy_throttled(&mut data.as_slice(), &mut tokio::io::sink(), &limiter, CLIENT)
+            .await
+            .unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(copied, 100_000);
+        // The first 50,000 bytes are the burst; the rest take a second at the cap
+        assert!(elapsed >= Duration::from_millis(900), "finished too fast: {:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(3), "throttled too hard: {:?}", elapsed);
+
+        // Other clients have their own budget
+        assert_eq!(limiter.consume(OTHER, 50_000, Instant::now()), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_progress_reported_per_chunk() {
+        let data = vec![0u8; 40 * 1024];
+        let mut chunks = Vec::new();
+        let copied = copy_throttled_with_progress(&mut data.as_slice(), &mut tokio::io::sink(), &RateLimiter::new(0), CLIENT, |n| chunks.push(n))
+            .await
+            .unwrap();
+
+        assert_eq!(copied, 40 * 1024);
+        assert_eq!(chunks.iter().sum::<usize>(), 40 * 1024);
+        assert!(chunks.len() >= 3, "{:?}", chunks);
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = RateLimiter::new(80); // 10,000 bytes/s
+        let start = Instant::now();
+
+        assert_eq!(limiter.consume(CLIENT, 10_000, start), Duration::ZERO);
+        assert_eq!(limiter.consume(CLIENT, 5_000, start), Duration::from_millis(500));
+        // Half a second later the debt is paid off, and a second later there's budget again
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(limiter.consume(CLIENT, 10_000, later), Duration::ZERO);
+
+        let unlimited = RateLimiter::new(0);
+        assert_eq!(unlimited.consume(CLIENT, usize::MAX / 2, start), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_reports_per_client_throughput() {
+        let limiter = RateLimiter::new(0);
+        let start = Instant::now();
+
+        limiter.consume(CLIENT, 30_000, start);
+        limiter.consume(OTHER, 1_000, start);
+        limiter.consume(CLIENT, 0, start + THROUGHPUT_WINDOW);
+        limiter.consume(OTHER, 0, start + THROUGHPUT_WINDOW);
+
+        let clients = limiter.clients.lock().unwrap();
+        let now = start + THROUGHPUT_WINDOW;
+        assert_eq!(clients[&CLIENT].throughput(now), 30_000);
+        assert_eq!(clients[&OTHER].throughput(now), 1_000);
+        assert_eq!(clients[&CLIENT].throughput(now + THROUGHPUT_WINDOW * 2), 0);
+    }
+}
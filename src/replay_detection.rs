@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Repeats of a sensitive URL closer together than this are reported
+pub const REPLAY_WINDOW: Duration = Duration::from_secs(30);
+
+/// Sensitive URLs remembered at once; the least recently requested is dropped beyond this
+pub const MAX_TRACKED_URLS: usize = 256;
+
+/// SHA-256 of a tracked URL, kept instead of the URL so the credentials
+/// in it aren't held in memory any longer than the request
+type UrlKey = [u8; 32];
+
+fn url_key(url: &str) -> UrlKey {
+    let mut key = [0; 32];
+    key.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, url.as_bytes()).as_ref());
+    key
+}
+
+struct UrlEntry {
+    last_seen: Instant,
+    /// Requests in the current run of repeats, each within `window` of the previous
+    repeats: u32,
+}
+
+/// Recently requested URLs that carry credentials or tokens, to spot the
+/// same one being sent again and again (a replayed token or a retry storm)
+#[derive(Clone)]
+pub struct ReplayTracker {
+    urls: Arc<Mutex<HashMap<UrlKey, UrlEntry>>>,
+    window: Duration,
+    max_urls: usize,
+}
+
+impl ReplayTracker {
+    pub fn new(window: Duration, max_urls: usize) -> Self {
+        Self {
+            urls: Arc::new(Mutex::new(HashMap::new())),
+            window,
+            max_urls: max_urls.max(1),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Note a request for `url`, returning how many times in a row it has
+    /// been requested within the window when this is a repeat
+    pub fn record(&self, url: &str) -> Option<u32> {
+        self.record_at(url, Instant::now())
+    }
+
+    pub fn record_at(&self, url: &str, now: Instant) -> Option<u32> {
+        let key = url_key(url);
+        let mut urls = self.urls.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(entry) = urls.get_mut(&key) {
+            let repeated = now.saturating_duration_since(entry.last_seen) <= self.window;
+            entry.repeats = if repeated { entry.repeats + 1 } else { 1 };
+            entry.last_seen = now;
+            return repeated.then_some(entry.repeats);
+        }
+
+        if urls.len() >= self.max_urls {
+            // Expired entries can't produce a repeat, so they go first
+            let window = self.window;
+            urls.retain(|_, entry| now.saturating_duration_since(entry.last_seen) <= window);
+        }
+        if urls.len() >= self.max_urls {
+            let oldest = urls.iter().min_by_key(|(_, entry)| entry.last_seen).map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                urls.remove(&oldest);
+            }
+        }
+        urls.insert(key, UrlEntry { last_seen: now, repeats: 1 });
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.urls.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_within_window_escalate() {
+        let tracker = ReplayTracker::new(Duration::from_secs(30), 8);
+        let start = Instant::now();
+        let url = "api.example.com/v1/me?access_token=abc";
+
+        assert_eq!(tracker.record_at(url, start), None);
+        assert_eq!(tracker.record_at(url, start + Duration::from_secs(5)), Some(2));
+        assert_eq!(tracker.record_at(url, start + Duration::from_secs(10)), Some(3));
+
+        // Spaced out, the same URL is just another request
+        let spaced = "api.example.com/v1/me?access_token=xyz";
+        assert_eq!(tracker.record_at(spaced, start), None);
+        assert_eq!(tracker.record_at(spaced, start + Duration::from_secs(60)), None);
+        assert_eq!(tracker.record_at(spaced, start + Duration::from_secs(120)), None);
+    }
+
+    #[test]
+    fn test_tracked_urls_are_bounded() {
+        let tracker = ReplayTracker::new(Duration::from_secs(30), 4);
+        let start = Instant::now();
+        for i in 0..10u64 {
+            tracker.record_at(&format!("example.com/?token={}", i), start + Duration::from_secs(i));
+        }
+        assert_eq!(tracker.len(), 4);
+
+        // The most recent URLs are the ones kept
+        assert_eq!(tracker.record_at("example.com/?token=9", start + Duration::from_secs(11)), Some(2));
+        assert_eq!(tracker.record_at("example.com/?token=0", start + Duration::from_secs(11)), None);
+    }
+}
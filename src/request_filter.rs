@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+/// What to do with a request that matches a `RequestFilter`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    #[default]
+    Block,
+    /// Let the request through without consulting later filters
+    Allow,
+}
+
+/// A user-defined rule matching requests by host, path and method.
+///
+/// Omitted fields match anything. Globs support `*` (any run of characters)
+/// and `?` (one character); hosts match case-insensitively, paths exactly.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestFilter {
+    #[serde(default)]
+    pub host_glob: Option<String>,
+    #[serde(default)]
+    pub path_glob: Option<String>,
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub action: FilterAction,
+}
+
+impl RequestFilter {
+    pub fn matches(&self, method: &str, host: &str, path: &str) -> bool {
+        self.method.as_ref().is_none_or(|m| m.eq_ignore_ascii_case(method))
+            && self.host_glob.as_ref().is_none_or(|glob| {
+                glob_match(&glob.to_ascii_lowercase(), &host.to_ascii_lowercase())
+            })
+            && self.path_glob.as_ref().is_none_or(|glob| glob_match(glob, path))
+    }
+
+    /// Short description for logs, e.g. `TRACE *` or `* *.example.com/wp-admin*`
+    pub fn describe(&self) -> String {
+        format!(
+            "{} {}{}",
+            self.method.as_deref().unwrap_or("*"),
+            self.host_glob.as_deref().unwrap_or("*"),
+            self.path_glob.as_deref().unwrap_or(""),
+        )
+    }
+}
+
+/// The first filter matching a request, which decides what happens to it
+pub fn first_match<'a>(
+    filters: &'a [RequestFilter],
+    method: &str,
+    host: &str,
+    path: &str,
+) -> Option<&'a RequestFilter> {
+    filters.iter().find(|filter| filter.matches(method, host, path))
+}
+
+/// Match `text` against a glob of `*` and `?` wildcards
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the last `*` swallow one more character and retry
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(host_glob: Option<&str>, path_glob: Option<&str>, method: Option<&str>) -> RequestFilter {
+        RequestFilter {
+            host_glob: host_glob.map(str::to_string),
+            path_glob: path_glob.map(str::to_string),
+            method: method.map(str::to_string),
+            action: FilterAction::Block,
+        }
+    }
+
+    #[test]
+    fn test_method_only_rule() {
+        let filters = [block(None, None, Some("TRACE"))];
+
+        assert!(first_match(&filters, "TRACE", "example.com", "/").is_some());
+        assert!(first_match(&filters, "trace", "other.org", "/any/path").is_some());
+        assert!(first_match(&filters, "GET", "example.com", "/").is_none());
+    }
+
+    #[test]
+    fn test_path_glob_rule() {
+        let filters = [block(None, Some("/wp-admin*"), None)];
+
+        assert!(first_match(&filters, "GET", "blog.example.com", "/wp-admin").is_some());
+        assert!(first_match(&filters, "POST", "shop.example.com", "/wp-admin/install.php").is_some());
+        assert!(first_match(&filters, "GET", "example.com", "/blog/wp-admin").is_none());
+        assert!(first_match(&filters, "GET", "example.com", "/WP-ADMIN").is_none());
+    }
+
+    #[test]
+    fn test_host_and_path_rule_with_allow_override() {
+        let filters = [
+            RequestFilter { action: FilterAction::Allow, ..block(Some("admin.example.com"), None, None) },
+            block(Some("*.example.com"), Some("/admin/*"), None),
+        ];
+
+        let decide = |host, path| first_match(&filters, "GET", host, path).map(|f| f.action);
+        assert_eq!(decide("www.Example.com", "/admin/users"), Some(FilterAction::Block));
+        assert_eq!(decide("www.example.com", "/about"), None);
+        assert_eq!(decide("example.org", "/admin/users"), None);
+        // The earlier allow rule wins for its host
+        assert_eq!(decide("admin.example.com", "/admin/users"), Some(FilterAction::Allow));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b?d", "axxbcd"));
+        assert!(glob_match("*.php", "/x/y.php"));
+        assert!(!glob_match("*.php", "/x/y.php5"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+}
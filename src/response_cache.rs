@@ -0,0 +1,305 @@
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, header, HeaderMap, Method, Response, StatusCode, Uri};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::config::ResponseCacheConfig;
+
+/// A response body and the headers needed to replay it
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<String, CachedResponse>,
+    /// Least recently used key first
+    order: VecDeque<String>,
+    total_bytes: usize,
+}
+
+impl CacheInner {
+    fn touch(&mut self, key: &str) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            if let Some(k) = self.order.remove(pos) {
+                self.order.push_back(k);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.total_bytes -= entry.body.len();
+            self.order.retain(|k| k != key);
+        }
+    }
+}
+
+/// In-memory LRU cache for idempotent GET responses
+///
+/// Only responses that are safe to share are stored: no cookies being set,
+/// no credentials on the request, nothing the origin marked as
+/// `no-store`, `no-cache` or `private`, and no `Vary` on anything but
+/// `Accept-Encoding`. Entries are keyed on the URL alone and shared by
+/// every client, so a response that varies by another request header could
+/// reach a client it wasn't meant for.
+#[derive(Clone)]
+pub struct ResponseCache {
+    inner: Arc<Mutex<CacheInner>>,
+    max_bytes: usize,
+    ttl: Duration,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl ResponseCache {
+    pub fn new(max_bytes: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CacheInner::default())),
+            max_bytes,
+            ttl,
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Build a cache from config, or None if caching is disabled
+    pub fn from_config(config: &ResponseCacheConfig) -> Option<Self> {
+        config.enabled.then(|| {
+            Self::new(
+                config.max_bytes as usize,
+                Duration::from_secs(config.ttl_secs),
+            )
+        })
+    }
+
+    pub fn key(method: &Method, uri: &Uri) -> String {
+        format!("{} {}", method, uri)
+    }
+
+    /// Whether a request may be answered from (and stored in) the cache
+    pub fn is_cacheable_request(method: &Method, headers: &HeaderMap) -> bool {
+        method == Method::GET
+            && !headers.contains_key(header::AUTHORIZATION)
+            && !headers.contains_key(header::PROXY_AUTHORIZATION)
+            && !headers.contains_key(header::COOKIE)
+            && !cache_control_forbids(headers)
+    }
+
+    /// Look up a fresh entry, counting the hit or miss
+    pub fn get(&self, key: &str) -> Option<Response<Full<Bytes>>> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        let fresh = inner.entries.get(key).map(|entry| entry.expires > Instant::now());
+        match fresh {
+            Some(true) => {
+                inner.touch(key);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+
+                let entry = &inner.entries[key];
+                let mut response = Response::new(Full::new(entry.body.clone()));
+                *response.status_mut() = entry.status;
+                *response.headers_mut() = entry.headers.clone();
+                Some(response)
+            }
+            Some(false) => {
+                inner.remove(key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Cache `response` under `key` if it is shareable, and hand it back
+    pub async fn store(&self, key: String, response: Response<Full<Bytes>>) -> Response<Full<Bytes>> {
+        let (parts, body) = response.into_parts();
+        let body = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(never) => match never {},
+        };
+
+        if let Some(ttl) = self.ttl_for(parts.status, &parts.headers) {
+            if body.len() <= self.max_bytes {
+                self.insert(key, CachedResponse {
+                    status: parts.status,
+                    headers: parts.headers.clone(),
+                    body: body.clone(),
+                    expires: Instant::now() + ttl,
+                });
+            }
+        }
+
+        Response::from_parts(parts, Full::new(body))
+    }
+
+    fn insert(&self, key: String, entry: CachedResponse) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.remove(&key);
+
+        inner.total_bytes += entry.body.len();
+        inner.entries.insert(key.clone(), entry);
+        inner.order.push_back(key);
+
+        // Evict least recently used entries until we're back under the limit
+        while inner.total_bytes > self.max_bytes {
+            match inner.order.front().cloned() {
+                Some(oldest) => inner.remove(&oldest),
+                None => break,
+            }
+        }
+    }
+
+    /// How long a response may be cached, or None if it must not be
+    fn ttl_for(&self, status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+        if status != StatusCode::OK
+            || headers.contains_key(header::SET_COOKIE)
+            || cache_control_forbids(headers)
+            || varies_beyond_encoding(headers)
+        {
+            return None;
+        }
+
+        let max_age = cache_control_directives(headers).find_map(|directive| {
+            directive
+                .strip_prefix("max-age=")
+                .and_then(|secs| secs.trim_matches('"').parse::<u64>().ok())
+        });
+
+        match max_age {
+            Some(0) => None,
+            Some(secs) => Some(self.ttl.min(Duration::from_secs(secs))),
+            None => Some(self.ttl),
+        }
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Number of cached responses
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn cache_control_directives(headers: &HeaderMap) -> impl Iterator<Item = String> + '_ {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+}
+
+fn cache_control_forbids(headers: &HeaderMap) -> bool {
+    cache_control_directives(headers)
+        .any(|d| d == "no-store" || d == "no-cache" || d == "private")
+}
+
+/// Whether `Vary` names a request header other than `Accept-Encoding`, or `*`
+fn varies_beyond_encoding(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::VARY)
+        .iter()
+        .flat_map(|value| value.to_str().map_or(vec!["*"], |value| value.split(',').collect()))
+        .map(str::trim)
+        .any(|name| !name.is_empty() && !name.eq_ignore_ascii_case("accept-encoding"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &'static [u8], headers: &[(&str, &str)]) -> Response<Full<Bytes>> {
+        let mut builder = Response::builder().status(200);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Full::new(Bytes::from_static(body))).unwrap()
+    }
+
+    async fn body_of(response: Response<Full<Bytes>>) -> Bytes {
+        response.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit() {
+        let cache = ResponseCache::new(1024, Duration::from_secs(60));
+        let key = ResponseCache::key(&Method::GET, &"http://example.com/logo.png".parse().unwrap());
+
+        assert!(cache.get(&key).is_none());
+        let returned = cache.store(key.clone(), response(b"png-bytes", &[("content-type", "image/png")])).await;
+        assert_eq!(body_of(returned).await, "png-bytes");
+
+        let cached = cache.get(&key).expect("second request is served from cache");
+        assert_eq!(cached.headers()["content-type"], "image/png");
+        assert_eq!(body_of(cached).await, "png-bytes");
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_no_store_and_cookies_bypass_cache() {
+        let cache = ResponseCache::new(1024, Duration::from_secs(60));
+
+        cache.store("GET /a".into(), response(b"a", &[("cache-control", "public, no-store")])).await;
+        cache.store("GET /b".into(), response(b"b", &[("set-cookie", "session=1")])).await;
+        cache.store("GET /c".into(), response(b"c", &[("cache-control", "max-age=0")])).await;
+        assert!(cache.is_empty());
+
+        let mut headers = HeaderMap::new();
+        assert!(ResponseCache::is_cacheable_request(&Method::GET, &headers));
+        assert!(!ResponseCache::is_cacheable_request(&Method::POST, &headers));
+        headers.insert(header::AUTHORIZATION, "Bearer token".parse().unwrap());
+        assert!(!ResponseCache::is_cacheable_request(&Method::GET, &headers));
+    }
+
+    #[tokio::test]
+    async fn test_responses_varying_by_client_headers_bypass_cache() {
+        let cache = ResponseCache::new(1024, Duration::from_secs(60));
+
+        for vary in ["Cookie", "Authorization", "accept-encoding, Accept-Language", "*"] {
+            cache.store(format!("GET /{}", vary), response(b"per-client", &[("vary", vary)])).await;
+        }
+        assert!(cache.is_empty());
+
+        // The Accept-Encoding sent upstream is the fingerprint's, the same for every client
+        cache.store("GET /gz".into(), response(b"gzipped", &[("vary", "Accept-Encoding")])).await;
+        assert!(cache.get("GET /gz").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used_under_size_pressure() {
+        let cache = ResponseCache::new(10, Duration::from_secs(60));
+
+        cache.store("GET /1".into(), response(b"1111", &[])).await;
+        cache.store("GET /2".into(), response(b"2222", &[])).await;
+        // Touch /1 so /2 becomes the least recently used entry
+        assert!(cache.get("GET /1").is_some());
+        cache.store("GET /3".into(), response(b"3333", &[])).await;
+
+        assert!(cache.get("GET /1").is_some());
+        assert!(cache.get("GET /2").is_none());
+        assert!(cache.get("GET /3").is_some());
+
+        // Bodies larger than the whole cache are never stored
+        cache.store("GET /big".into(), response(b"much too large", &[])).await;
+        assert!(cache.get("GET /big").is_none());
+        assert_eq!(cache.len(), 2);
+    }
+}
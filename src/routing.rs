@@ -1,79 +1,879 @@
-use crate::config::Config;
+use crate::app_doh;
+use crate::config::{BrowsingMode, Config, PrivateHostPolicy, RoutingBackend};
+use crate::connect_coalescer::ConnectCoalescer;
+use crate::dns::{self, DnsResolver};
+use crate::exit_selection;
 use crate::network::Node;
+use crate::onion_service;
 use crate::crypto::CryptoLayer;
-use crate::tor_network::TorNetwork;
-use crate::fingerprint::{BrowserFingerprint, CanvasProtection};
-use crate::blocklist::TrackerBlocker;
+use crate::tor_network::{CircuitHop, ExitRelays, HostedOnionService, TorNetwork};
+use crate::transport::{DirectTransport, Socks5Transport, Transport, TransportStream};
+use crate::first_party;
+use crate::geoip::GeoIpProvider;
+use crate::header_scrubber;
+use crate::fingerprint::{self, BrowserFingerprint, CanvasProtection, FingerprintRotation, SessionFingerprint};
+use crate::blocklist::{self, normalize_host, AllowScope, TrackerBlocker};
+use crate::block_page::{self, BlockDetail};
 use crate::webrtc_protection::WebRtcProtection;
-use crate::kill_switch::KillSwitch;
-use crate::ipv6_protection::Ipv6Protection;
+use crate::direct_ip_protection::DirectIpProtection;
+use crate::kill_switch::{self, KillSwitch, TorStatusFeed};
+use crate::ipv6_protection::{Ipv6Origin, Ipv6Protection};
 use crate::web_api::{ApiState, LogDetails};
-use hyper::{Request, Response, body::Bytes};
+use crate::error::{PrivacyError, Result};
+use crate::upstream_proxy::{UpstreamProxy, UpstreamScheme};
+use crate::request_filter::{self, FilterAction};
+use crate::response_cache::ResponseCache;
+use hyper::{Method, Request, Response, body::{Body, Bytes}};
 use http_body_util::Full;
-use tracing::{info, warn};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// The request-blocking protections selected in `Config`.
+///
+/// A protection switched off in the config is left out entirely instead of
+/// being built in a disabled state, so the router never consults it.
+#[derive(Clone)]
+struct Protections {
+    ipv6: Option<Ipv6Protection>,
+    webrtc: Option<WebRtcProtection>,
+    direct_ip: DirectIpProtection,
+    tracker_blocker: Option<TrackerBlocker>,
+    /// The only reachable domains, in `BrowsingMode::Allowlist`
+    allowed_domains: Option<Arc<HashSet<String>>>,
+    /// Private hosts are connected to from this machine
+    /// (`PrivateHostPolicy::Bypass`) rather than through an exit
+    direct_private_hosts: bool,
+}
+
+/// The protection that stopped a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockedBy {
+    NotAllowlisted,
+    Ipv6,
+    WebRtc,
+    DirectIp,
+    Tracker,
+}
+
+impl BlockedBy {
+    /// Log category of the block, as used for real blocks
+    fn log_category(self) -> &'static str {
+        match self {
+            BlockedBy::NotAllowlisted | BlockedBy::DirectIp => "security",
+            BlockedBy::Ipv6 => "ipv6",
+            BlockedBy::WebRtc => "webrtc",
+            BlockedBy::Tracker => "tracker",
+        }
+    }
+}
+
+/// A warning sign in a plaintext HTTP response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ResponseRisk {
+    /// Entry of `SECURITY_DETECTORS` that reports this risk
+    detector: &'static str,
+    threat: &'static str,
+    reason: &'static str,
+}
+
+const LOGIN_FORM_OVER_HTTP: ResponseRisk = ResponseRisk {
+    detector: "login_form",
+    threat: "Login form over HTTP",
+    reason: "Page served over plain HTTP asks for a password - anyone on the path (including the Tor exit) can read it. Do not log in; use the https:// address instead",
+};
+
+const HSTS_OVER_HTTP: ResponseRisk = ResponseRisk {
+    detector: "tls_downgrade",
+    threat: "Possible TLS downgrade",
+    reason: "Site sends Strict-Transport-Security, so it normally requires HTTPS, but this copy arrived over plain HTTP - the HTTPS version may have been stripped. Reload with https:// and don't enter credentials",
+};
+
+const SECURE_COOKIE_OVER_HTTP: ResponseRisk = ResponseRisk {
+    detector: "tls_downgrade",
+    threat: "Possible TLS downgrade",
+    reason: "Secure (HTTPS-only) cookie delivered over plain HTTP - the connection may have been downgraded. Reload with https:// and don't enter credentials",
+};
+
+/// Check a plaintext response for login forms and signs of a stripped HTTPS connection
+fn response_risks(headers: &hyper::HeaderMap, body: &[u8]) -> Vec<ResponseRisk> {
+    use hyper::header;
+    
+    let mut risks = Vec::new();
+    
+    if headers.contains_key(header::STRICT_TRANSPORT_SECURITY) {
+        risks.push(HSTS_OVER_HTTP);
+    }
+    
+    let secure_cookie = headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|cookie| cookie.split(';').skip(1).any(|attr| attr.trim().eq_ignore_ascii_case("secure")));
+    if secure_cookie {
+        risks.push(SECURE_COOKIE_OVER_HTTP);
+    }
+    
+    let is_html = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.to_ascii_lowercase().contains("html"));
+    if is_html {
+        match scannable_body(headers, body) {
+            Some(html) if has_password_input(&html) => risks.push(LOGIN_FORM_OVER_HTTP),
+            Some(_) => {}
+            None => info!("Login form check skipped: {} body can't be decoded here", content_encodings(headers).join(", ")),
+        }
+    }
+    
+    risks
+}
+
+/// The `Content-Encoding`s applied to a body, lowercased, without identity
+fn content_encodings(headers: &hyper::HeaderMap) -> Vec<String> {
+    headers
+        .get_all(hyper::header::CONTENT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+        .collect()
+}
+
+/// The start of a body as the browser will read it, for `has_password_input`.
+///
+/// A gzip or deflate body is decoded into a scan-only copy of at most
+/// `PASSWORD_SCAN_LIMIT` bytes; the response itself is forwarded as it
+/// came. None for any other encoding, such as br, or a stack of them.
+fn scannable_body<'a>(headers: &hyper::HeaderMap, body: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+    use std::io::Read;
+    
+    let limit = PASSWORD_SCAN_LIMIT as u64;
+    let mut decoded = Vec::new();
+    let _ = match content_encodings(headers).as_slice() {
+        [] => return Some(Cow::Borrowed(body)),
+        [encoding] if encoding == "gzip" || encoding == "x-gzip" => {
+            flate2::read::MultiGzDecoder::new(body).take(limit).read_to_end(&mut decoded)
+        }
+        // Meant to be zlib-wrapped, but some servers send raw deflate
+        [encoding] if encoding == "deflate" => match flate2::read::ZlibDecoder::new(body).take(limit).read_to_end(&mut decoded) {
+            Err(_) if decoded.is_empty() => flate2::read::DeflateDecoder::new(body).take(limit).read_to_end(&mut decoded),
+            zlib => zlib,
+        },
+        _ => return None,
+    };
+    // A truncated or corrupt body is still scanned as far as it decoded
+    Some(Cow::Owned(decoded))
+}
+
+/// Bytes at the start of an HTML body searched for a password field; a
+/// login form sits near the top of its page
+const PASSWORD_SCAN_LIMIT: usize = 256 * 1024;
+
+/// Whether an HTML body has an `<input type="password">` in its first
+/// `PASSWORD_SCAN_LIMIT` bytes, which must already be decoded (see
+/// `scannable_body`).
+fn has_password_input(body: &[u8]) -> bool {
+    let mut html = &body[..body.len().min(PASSWORD_SCAN_LIMIT)];
+    
+    while let Some(start) = find_ignore_ascii_case(html, b"<input") {
+        html = &html[start + b"<input".len()..];
+        let tag = html.split(|&b| b == b'>').next().unwrap_or_default();
+        if input_type_is_password(tag) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether the attributes of an input tag include `type=password`, in any
+/// case, quoted or not, with or without spaces around the `=`
+fn input_type_is_password(mut attributes: &[u8]) -> bool {
+    while let Some(start) = find_ignore_ascii_case(attributes, b"type") {
+        attributes = &attributes[start + b"type".len()..];
+        let Some(value) = attributes.trim_ascii_start().strip_prefix(b"=") else {
+            continue;
+        };
+        let value = value.trim_ascii_start();
+        let value = value.strip_prefix(b"\"").or_else(|| value.strip_prefix(b"'")).unwrap_or(value);
+        if value.get(..b"password".len()).is_some_and(|value| value.eq_ignore_ascii_case(b"password")) {
+            return true;
+        }
+    }
+    false
+}
+
+fn find_ignore_ascii_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+/// Detect security risks and malicious tracking patterns in a request,
+/// skipping detectors switched off in `Config::security_detectors`
+async fn detect_security_risks(config: &Config, state: &ApiState, host: &str, path: &str, method: &str) {
+    let full_url = format!("{}{}", host, path);
+    
+    // Detect credential leaks in URL
+    if config.security_detector_enabled("credential_leak") {
+        let credential_patterns = vec![
+            ("password", "Password in URL"),
+            ("pwd", "Password in URL"),
+            ("api_key", "API Key in URL"),
+            ("apikey", "API Key in URL"),
+            ("token", "Token in URL"),
+            ("access_token", "Access Token in URL"),
+            ("secret", "Secret in URL"),
+            ("private", "Private data in URL"),
+            ("auth", "Auth data in URL"),
+            ("session", "Session ID in URL"),
+        ];
+        
+        let mut sensitive = false;
+        for (pattern, threat) in credential_patterns {
+            if path.to_lowercase().contains(pattern) {
+                sensitive = true;
+                let details = LogDetails {
+                    url: Some(full_url.clone()),
+                    domain: Some(host.to_string()),
+                    path: Some(path.to_string()),
+                    method: Some(method.to_string()),
+                    threat_type: Some(threat.to_string()),
+                    reason: Some("Sensitive data detected in URL - potential credential leak".to_string()),
+                    ..LogDetails::default()
+                };
+                warn!("⚠️ SECURITY: {} - {}", threat, full_url);
+                state.counters.security_threats_detected.inc();
+                state.add_log_with_details("error", format!("⚠️ SECURITY: {} - {}", threat, host), "security", Some(details)).await;
+            }
+        }
+        
+        // The same credentials sent again and again: a replayed token or a retry storm
+        if sensitive && config.security_detector_enabled("request_replay") {
+            if let Some(repeats) = state.replay_tracker.record(&full_url) {
+                let window = state.replay_tracker.window().as_secs();
+                let details = LogDetails {
+                    url: Some(full_url.clone()),
+                    domain: Some(host.to_string()),
+                    path: Some(path.to_string()),
+                    method: Some(method.to_string()),
+                    threat_type: Some("Repeated sensitive request".to_string()),
+                    reason: Some(format!(
+                        "Same URL with credentials requested {} times within {} seconds - possible token replay or retry storm",
+                        repeats, window
+                    )),
+                    ..LogDetails::default()
+                };
+                warn!("🔁 SECURITY: Sensitive URL repeated {} times within {}s - {}", repeats, window, host);
+                state.counters.security_threats_detected.inc();
+                state.add_log_with_details("error", format!("🔁 SECURITY: Sensitive request repeated {} times - {}", repeats, host), "security", Some(details)).await;
+            }
+        }
+    }
+    
+    // Detect suspicious tracking patterns
+    if config.security_detector_enabled("tracking_pattern") {
+        let tracking_patterns = vec![
+            ("/track", "Tracking endpoint"),
+            ("/collect", "Data collection endpoint"),
+            ("/analytics", "Analytics tracking"),
+            ("/beacon", "Tracking beacon"),
+            ("/pixel", "Tracking pixel"),
+            ("/impression", "Ad impression tracking"),
+            ("/conversion", "Conversion tracking"),
+            ("/telemetry", "Telemetry data collection"),
+            ("/fingerprint", "Browser fingerprinting"),
+        ];
+        
+        for (pattern, tracking_type) in tracking_patterns {
+            if path.to_lowercase().contains(pattern) {
+                let details = LogDetails {
+                    url: Some(full_url.clone()),
+                    domain: Some(host.to_string()),
+                    path: Some(path.to_string()),
+                    method: Some(method.to_string()),
+                    threat_type: Some(tracking_type.to_string()),
+                    reason: Some("Suspicious tracking pattern detected".to_string()),
+                    ..LogDetails::default()
+                };
+                warn!("🔍 TRACKING: {} detected - {}", tracking_type, full_url);
+                state.counters.security_threats_detected.inc();
+                state.add_log_with_details("warn", format!("🔍 {} detected: {}", tracking_type, host), "security", Some(details)).await;
+            }
+        }
+    }
+    
+    // Detect malicious domains patterns
+    if config.security_detector_enabled("malicious_domain") {
+        let malicious_patterns = vec![
+            ("analytics", "Analytics service"),
+            ("doubleclick", "Ad network"),
+            ("adserver", "Ad server"),
+            ("tracker", "Tracking service"),
+            ("metric", "Metrics collection"),
+            ("stats", "Statistics collection"),
+            ("tag-manager", "Tag management"),
+            ("remarketing", "Remarketing service"),
+        ];
+        
+        for (pattern, service_type) in malicious_patterns {
+            if host.to_lowercase().contains(pattern) {
+                let details = LogDetails {
+                    url: Some(full_url.clone()),
+                    domain: Some(host.to_string()),
+                    path: Some(path.to_string()),
+                    method: Some(method.to_string()),
+                    threat_type: Some(service_type.to_string()),
+                    reason: Some("Suspicious domain pattern - likely tracking/advertising".to_string()),
+                    ..LogDetails::default()
+                };
+                info!("🕵️ {} detected in domain: {}", service_type, host);
+                state.counters.security_threats_detected.inc();
+                state.add_log_with_details("info", format!("🕵️ {} detected: {}", service_type, host), "security", Some(details)).await;
+            }
+        }
+    }
+    
+    // Detect unencrypted connections
+    if config.security_detector_enabled("unencrypted") && host.starts_with("http://") {
+        let details = LogDetails {
+            url: Some(full_url.clone()),
+            domain: Some(host.to_string()),
+            path: Some(path.to_string()),
+            method: Some(method.to_string()),
+            threat_type: Some("Unencrypted connection".to_string()),
+            reason: Some("HTTP connection detected - data transmitted in plain text".to_string()),
+            ..LogDetails::default()
+        };
+        warn!("⚠️ SECURITY: Unencrypted HTTP request to: {}", host);
+        state.counters.security_threats_detected.inc();
+        state.add_log_with_details("warn", format!("⚠️ Unencrypted HTTP: {}", host), "security", Some(details)).await;
+    }
+}
+
+impl Protections {
+    /// Fails on an invalid `Config::stun_servers` entry
+    fn from_config(config: &Config, tracker_blocker: &TrackerBlocker) -> std::result::Result<Self, String> {
+        let webrtc = if config.webrtc_protection {
+            Some(
+                WebRtcProtection::new(true)
+                    .with_header_injection(config.webrtc_policy_headers)
+                    .with_stun_servers(&config.stun_servers)?,
+            )
+        } else {
+            None
+        };
+        Ok(Self {
+            // Through an exit, IPv6 depends on `allow_ipv6_over_tor`; see `ipv6_origin`
+            ipv6: config.ipv6_protection.then(|| {
+                Ipv6Protection::new(true)
+                    .with_allowlist(config.ipv6_allowlist.clone())
+                    .with_tor_exit_ipv6(config.allow_ipv6_over_tor)
+            }),
+            webrtc,
+            direct_ip: DirectIpProtection::new(config.block_direct_ip),
+            tracker_blocker: config.tracker_blocking.then(|| tracker_blocker.clone()),
+            allowed_domains: (config.mode == BrowsingMode::Allowlist).then(|| {
+                Arc::new(config.allowed_domains.iter().map(|domain| normalize_host(domain.trim())).collect())
+            }),
+            direct_private_hosts: config.private_hosts == PrivateHostPolicy::Bypass,
+        })
+    }
+    
+    /// Who connects to `host`: this machine for a private host served
+    /// directly, otherwise the Tor exit
+    fn ipv6_origin(&self, host: &str) -> Ipv6Origin {
+        if self.direct_private_hosts && kill_switch::is_lan_destination(host) {
+            Ipv6Origin::Client
+        } else {
+            Ipv6Origin::TorExit
+        }
+    }
+    
+    /// Whether allowlist mode lets `host` through at all
+    fn is_allowlisted(&self, host: &str) -> bool {
+        self.allowed_domains.as_ref().is_none_or(|domains| blocklist::in_domain_set(domains, host))
+    }
+    
+    /// The setting or list rule behind a `check` result, for logs
+    fn rule(&self, blocked_by: BlockedBy, host: &str) -> String {
+        match blocked_by {
+            BlockedBy::NotAllowlisted => "allowed_domains".to_string(),
+            BlockedBy::Ipv6 => "ipv6_protection".to_string(),
+            BlockedBy::WebRtc => "webrtc_protection".to_string(),
+            BlockedBy::DirectIp => "block_direct_ip".to_string(),
+            BlockedBy::Tracker => self
+                .tracker_blocker
+                .as_ref()
+                .and_then(|blocker| blocker.check(host).matched_rule)
+                .unwrap_or_else(|| "tracker_blocking".to_string()),
+        }
+    }
+    
+    /// Run the enabled protections against a destination; the first match wins
+    fn check(&self, host: &str, port: u16) -> Option<BlockedBy> {
+        if !self.is_allowlisted(host) {
+            Some(BlockedBy::NotAllowlisted)
+        } else if let Some(leak) = self.check_leaks(host, port) {
+            Some(leak)
+        } else if self.tracker_blocker.as_ref().is_some_and(|b| b.should_block(host)) {
+            Some(BlockedBy::Tracker)
+        } else {
+            None
+        }
+    }
+    
+    /// The protections against leaking the real IP, which no allowlist overrides
+    fn check_leaks(&self, host: &str, port: u16) -> Option<BlockedBy> {
+        if self.ipv6.as_ref().is_some_and(|p| p.should_block(host, self.ipv6_origin(host))) {
+            Some(BlockedBy::Ipv6)
+        } else if self.webrtc.as_ref().is_some_and(|p| p.should_block_request(host, port)) {
+            Some(BlockedBy::WebRtc)
+        } else if self.direct_ip.should_block(host) {
+            Some(BlockedBy::DirectIp)
+        } else {
+            None
+        }
+    }
+    
+    /// What `check` would return, without counting or logging a block, for
+    /// reporting requests that are let through anyway
+    fn would_block(&self, host: &str, port: u16) -> Option<BlockedBy> {
+        if !self.is_allowlisted(host) {
+            Some(BlockedBy::NotAllowlisted)
+        } else if self.ipv6.as_ref().is_some_and(|p| p.would_block(host, self.ipv6_origin(host))) {
+            Some(BlockedBy::Ipv6)
+        } else if self.webrtc.as_ref().is_some_and(|p| p.would_block_request(host, port)) {
+            Some(BlockedBy::WebRtc)
+        } else if self.direct_ip.would_block(host) {
+            Some(BlockedBy::DirectIp)
+        } else if self.tracker_blocker.as_ref().is_some_and(|b| b.check(host).blocked) {
+            Some(BlockedBy::Tracker)
+        } else {
+            None
+        }
+    }
+    
+    /// Whether the allowlist mode or the tracker lists would block `host`
+    fn lists_block(&self, host: &str) -> bool {
+        !self.is_allowlisted(host) || self.tracker_blocker.as_ref().is_some_and(|b| b.check(host).blocked)
+    }
+}
+
+/// Rule that `config`'s protections would block `host:port` with, if any,
+/// e.g. for the self-test's leak checks
+pub fn blocking_rule(config: &Config, tracker_blocker: &TrackerBlocker, host: &str, port: u16) -> Option<String> {
+    // An invalid config never gets a router, so there is nothing to check against
+    let protections = Protections::from_config(config, tracker_blocker).ok()?;
+    protections.would_block(host, port).map(|blocked_by| protections.rule(blocked_by, host))
+}
 
 #[derive(Clone)]
 pub struct Router {
     config: Config,
     crypto: CryptoLayer,
     nodes: Vec<Node>,
-    tor: TorNetwork,
-    fingerprint: BrowserFingerprint,
+    transport: Arc<dyn Transport>,
+    /// Reaches private and local hosts under `PrivateHostPolicy::Bypass`
+    local: DirectTransport,
+    dns: DnsResolver,
+    fingerprint: SessionFingerprint,
+    /// Rerolls `fingerprint` every `fingerprint_rotation_secs`, stopped with the last clone
+    _fingerprint_rotation: Option<Arc<FingerprintRotation>>,
+    /// `Config::onion_services`, published for as long as the router lives
+    _onion_services: Arc<Vec<HostedOnionService>>,
     tracker_blocker: TrackerBlocker,
-    webrtc_protection: WebRtcProtection,
+    protections: Protections,
     kill_switch: KillSwitch,
-    ipv6_protection: Ipv6Protection,
+    /// Marks Tor down in `kill_switch` while the transport has lost the
+    /// network, stopped with the last clone
+    _tor_status: Option<Arc<TorStatusFeed>>,
+    /// Brings back an exit country `check_exit` had to give up, stopped
+    /// with the last clone
+    _exit_reprobe: Option<Arc<ExitReprobe>>,
+    /// Circuit rebuilds and age rotations `report_circuit_changes` has logged
+    reported_circuits: Arc<Mutex<(u64, u64)>>,
     canvas_protection: CanvasProtection,
+    response_cache: Option<ResponseCache>,
+    /// `Config::coalesce_connects`, shared by every clone
+    connect_coalescer: Option<ConnectCoalescer>,
+    /// Places the exit of each circuit reported to `app_state`
+    geoip: Option<Arc<dyn GeoIpProvider>>,
     app_state: Option<ApiState>,
 }
 
+/// The client a proxied request came from, put in the request's extensions
+/// by the proxy
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddr(pub IpAddr);
+
+/// Why a private or local destination can't be served directly to `client`,
+/// as the rule and the message for the block page; None when it can.
+///
+/// The proxy usually listens on the LAN, so loopback is only reachable by
+/// clients on this machine, and the control API not at all: it has no
+/// authentication, and through the proxy any device could shut the suite
+/// down or read its logs.
+pub fn private_host_refusal(config: &Config, host: &str, port: u16, client: Option<IpAddr>) -> Option<(&'static str, &'static str)> {
+    if config.private_hosts == PrivateHostPolicy::Block {
+        return Some(("private_hosts", "Private and local hosts are not sent through Tor"));
+    }
+    if !kill_switch::is_loopback_destination(host) {
+        return None;
+    }
+    if port == config.api_port {
+        return Some(("api_port", "The Privacy Suite API can't be reached through the proxy"));
+    }
+    if !client.is_some_and(|ip| ip.is_loopback()) {
+        return Some(("loopback", "This computer's own services only answer clients on this computer"));
+    }
+    None
+}
+
+/// The `http://….onion` URL a site advertises in its `Onion-Location` header.
+///
+/// HTTPS onion URLs are skipped: this path speaks plain HTTP to the
+/// destination and can't do the TLS handshake they need.
+pub fn onion_location(headers: &hyper::HeaderMap) -> Option<hyper::Uri> {
+    let value = headers.get("onion-location")?.to_str().ok()?;
+    let uri: hyper::Uri = value.trim().parse().ok()?;
+    let is_onion = uri.host()?.to_ascii_lowercase().ends_with(".onion");
+    (is_onion && uri.scheme_str() == Some("http")).then_some(uri)
+}
+
+/// Decide whether to keep an exit restriction to `country` from the outcome of `probe`.
+///
+/// Returns true if the restriction stays, and false if the connection
+/// should fall back to any exit. Only a definite `ExitUnavailable` falls
+/// back: a probe that failed for another reason keeps the country, whose
+/// streams will retry. With `strict` the fallback is refused and the
+/// connection fails instead.
+async fn check_exit(
+    country: &str,
+    strict: bool,
+    app_state: Option<&ApiState>,
+    probe: impl std::future::Future<Output = Result<()>>,
+) -> Result<bool> {
+    let reason = match probe.await {
+        Ok(()) => return Ok(true),
+        Err(PrivacyError::ExitUnavailable { reason, .. }) => reason,
+        Err(e) => {
+            warn!("⚠️ Couldn't check for exits in {} ({}) - keeping the exit country", country, e);
+            return Ok(true);
+        }
+    };
+    
+    if strict {
+        error!("🛑 No exit available in {} ({}) - strict exit mode refuses other exits", country, reason);
+        if let Some(state) = app_state {
+            state.update_stats(|s| s.tor_connected = false).await;
+            state.add_log("error", format!("🛑 No exit available in {} - strict exit mode refused to use another country", country), "network").await;
+        }
+        return Err(PrivacyError::ExitUnavailable { country: country.to_string(), reason });
+    }
+    
+    warn!("⚠️ No exit available in {} ({}) - using any exit", country, reason);
+    if let Some(state) = app_state {
+        state.add_log("warn", format!("⚠️ No exit available in {} - traffic exits in another country", country), "network").await;
+    }
+    Ok(false)
+}
+
+/// After `check_exit` fell back to any exit, probes the exit country again
+/// each time Tor reconnects and restores it once an exit there is
+/// reachable. Stops when dropped.
+struct ExitReprobe {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ExitReprobe {
+    fn spawn(tor: TorNetwork, country: String, app_state: Option<ApiState>) -> Self {
+        let task = tokio::spawn(async move {
+            let Some(mut ready) = tor.ready_for_traffic() else {
+                return;
+            };
+            loop {
+                // Wait for the network to go away and come back
+                if ready.wait_for(|ready| !*ready).await.is_err() || ready.wait_for(|ready| *ready).await.is_err() {
+                    return;
+                }
+                if tor.probe_exit().await.is_ok() {
+                    tor.use_any_exit(false);
+                    info!("🌍 Exits in {} reachable again - exit country restored", country);
+                    if let Some(state) = &app_state {
+                        state.add_log("info", format!("🌍 Exits in {} reachable again - traffic exits there", country), "network").await;
+                    }
+                    return;
+                }
+            }
+        });
+        Self { task }
+    }
+}
+
+impl Drop for ExitReprobe {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
 impl Router {
-    pub async fn new(config: Config, app_state: Option<ApiState>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let crypto = CryptoLayer::new();
+    /// Exit country to use when the user left it on "Auto", from their home
+    /// country and `Config::exit_preference`
+    pub fn choose_default_exit(config: &Config) -> Option<String> {
+        let home = config
+            .home_country
+            .as_ref()
+            .map(|country| country.to_ascii_lowercase())
+            .or_else(exit_selection::system_country);
+        exit_selection::choose_exit(home.as_deref(), config.exit_preference).map(str::to_string)
+    }
+    
+    pub async fn new(config: Config, app_state: Option<ApiState>) -> Result<Self> {
+        // The GUI's exit choice, if the user made one
+        let requested_exit = match &app_state {
+            Some(state) => state.requested_exit_country().await,
+            None => None,
+        };
         
-        // Load available nodes from database/registry
-        let nodes = Self::load_nodes(&config).await?;
+        if let Some(url) = config.socks_transport.clone() {
+            if let (true, Some(country)) = (config.strict_exit, &requested_exit) {
+                let unsupported = async {
+                    Err(PrivacyError::ExitUnavailable {
+                        country: country.clone(),
+                        reason: "the SOCKS5 transport can't choose its exit".to_string(),
+                    })
+                };
+                check_exit(country, true, app_state.as_ref(), unsupported).await?;
+            }
+            let transport = Self::socks_transport(&url, &config)?;
+            return Self::with_transport(config, app_state, Arc::new(transport)).await;
+        }
+        
+        onion_service::validate(&config.onion_services).map_err(PrivacyError::Config)?;
+        
+        // Validate the upstream proxy before spending time on anything else
+        let upstream_proxy = config
+            .upstream_proxy
+            .as_deref()
+            .map(UpstreamProxy::parse)
+            .transpose()
+            .map_err(PrivacyError::Config)?;
+        
+        // Strict mode only guards a country the user asked for, not one picked for "Auto"
+        let strict_exit = config.strict_exit && requested_exit.is_some();
+        let exit_country = match requested_exit {
+            Some(country) => Some(country),
+            None => {
+                let default = Self::choose_default_exit(&config);
+                if let (Some(country), Some(state)) = (&default, &app_state) {
+                    state.update_stats(|s| s.exit_country = Some(country.clone())).await;
+                    state.add_log("info", format!("🌍 Auto exit location: {} ({:?})", country, config.exit_preference), "network").await;
+                }
+                default
+            }
+        };
+        
+        // Checked before bootstrapping, which a typo would otherwise wait out
+        let exit_relays = ExitRelays::new(&config.exclude_exit_nodes, &config.allow_exit_nodes)?;
         
         // Initialize Tor connection
         info!("Connecting to Tor network...");
-        let tor = TorNetwork::new().await?;
+        let mut tor = TorNetwork::new(upstream_proxy, config.tor_path_length, &config.resolved_tor_data_dir())
+            .await?
+            .with_max_response_bytes(config.max_response_bytes as usize)
+            .with_failure_threshold(config.circuit_failure_threshold)
+            .with_max_circuit_age(Duration::from_secs(config.max_circuit_age_secs))
+            .with_keep_alive(config.http_keep_alive)
+            .with_onion_services(config.prefer_onion)
+            .with_exit_relays(exit_relays);
+        let mut exit_reprobe = None;
+        if let Some(country) = &exit_country {
+            match tor.clone().with_exit_country(country) {
+                Ok(restricted) => {
+                    tor = restricted;
+                    if !check_exit(country, strict_exit, app_state.as_ref(), tor.probe_exit()).await? {
+                        tor.use_any_exit(true);
+                        exit_reprobe = Some(Arc::new(ExitReprobe::spawn(tor.clone(), country.clone(), app_state.clone())));
+                    }
+                }
+                Err(e) => {
+                    check_exit(country, strict_exit, app_state.as_ref(), async { Err(e) }).await?;
+                }
+            }
+        }
+        // The online lookup goes over this same client, without GeoIP of its own
+        let geoip = crate::geoip::from_config(&config, Arc::new(tor.clone()));
         info!("✅ Connected to Tor! Using 6,000+ volunteer nodes");
         
+        let onion_services = config
+            .onion_services
+            .iter()
+            .map(|service| tor.launch_onion_service(*service))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(state) = &app_state {
+            for hosted in &onion_services {
+                state.add_log(
+                    "info",
+                    format!("🧅 Onion service published: {}:{} -> local port {}", hosted.info.hostname, hosted.info.virtual_port, hosted.info.local_port),
+                    "network",
+                ).await;
+            }
+            state.set_onion_services(onion_services.iter().map(|hosted| hosted.info.clone()).collect()).await;
+        }
+        
+        let mut router = Self::with_transport(config, app_state, Arc::new(tor)).await?;
+        router._onion_services = Arc::new(onion_services);
+        router._exit_reprobe = exit_reprobe;
+        router.geoip = geoip;
+        Ok(router)
+    }
+    
+    /// The external SOCKS5 proxy named by `Config::socks_transport`
+    fn socks_transport(url: &str, config: &Config) -> Result<Socks5Transport> {
+        let proxy = UpstreamProxy::parse(url).map_err(PrivacyError::Config)?;
+        if proxy.scheme != UpstreamScheme::Socks5 {
+            return Err(PrivacyError::Config(format!("socks_transport must be a socks5:// URL, got {}", proxy)));
+        }
+        if config.upstream_proxy.is_some() {
+            warn!("⚠️ upstream_proxy is ignored when socks_transport is set");
+        }
+        warn!("⚠️ Using external SOCKS5 transport {} - exit country and circuit settings don't apply", proxy);
+        if !config.onion_services.is_empty() {
+            warn!("⚠️ onion_services need the built-in Tor client and are not published with socks_transport");
+        }
+        
+        Ok(Socks5Transport::new(proxy.host, proxy.port)
+            .with_max_response_bytes(config.max_response_bytes as usize))
+    }
+    
+    /// Build a router that reaches destinations through `transport` instead of
+    /// bootstrapping Tor
+    pub async fn with_transport(
+        config: Config,
+        app_state: Option<ApiState>,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Self> {
+        let crypto = CryptoLayer::new();
+        
+        // Load available nodes from database/registry
+        let nodes = Self::load_nodes(&config).await?;
+        
         // Initialize privacy features
-        let fingerprint = BrowserFingerprint::random();
-        info!("✅ Browser fingerprint randomization enabled");
+        // Share the app-wide fingerprint so it can be inspected and pinned from the API
+        fingerprint::validate_user_agents(&config.user_agents).map_err(PrivacyError::Config)?;
+        let fingerprint = app_state
+            .as_ref()
+            .map(|state| state.fingerprint.clone())
+            .unwrap_or_else(|| SessionFingerprint::new(BrowserFingerprint::random()));
+        fingerprint.set_user_agents(config.user_agents.clone());
+        fingerprint.rotate_unless_pinned();
+        if fingerprint.is_pinned() {
+            info!("✅ Using pinned browser fingerprint");
+        } else {
+            info!("✅ Browser fingerprint randomization enabled ({} User-Agents)", config.user_agents.len());
+        }
+        let fingerprint_rotation = (config.fingerprint_rotation_secs > 0).then(|| {
+            info!("✅ Rotating browser fingerprint every {}s", config.fingerprint_rotation_secs);
+            Arc::new(FingerprintRotation::spawn(
+                fingerprint.clone(),
+                Duration::from_secs(config.fingerprint_rotation_secs),
+            ))
+        });
         
-        let tracker_blocker = TrackerBlocker::new();
-        info!("✅ Tracker blocking enabled ({} domains)", tracker_blocker.blocklist_size());
+        // Share the app-wide blocker so scheduled refreshes apply to this session
+        let tracker_blocker = app_state
+            .as_ref()
+            .map(|s| s.tracker_blocker.clone())
+            .unwrap_or_else(|| TrackerBlocker::for_config(&config));
+        if config.tracker_blocking {
+            info!("✅ Tracker blocking enabled ({} domains)", tracker_blocker.blocklist_size());
+        } else {
+            warn!("⚠️ Tracker blocking disabled in config");
+        }
         
-        info!("✅ DNS-over-HTTPS encryption enabled");
+        let dns = DnsResolver::new(&config.dns_servers)
+            .await
+            .map_err(|e| PrivacyError::Dns(e.to_string()))?;
+        info!("✅ DNS-over-HTTPS encryption enabled ({:?}, DNS server {})", config.dns_resolution, dns.active_server());
         
         // Initialize advanced security features
-        let webrtc_protection = WebRtcProtection::new(true);
-        let ipv6_protection = Ipv6Protection::new(true);
-        let canvas_protection = CanvasProtection::new(true);
-        info!("✅ Canvas fingerprinting protection enabled");
+        let protections = Protections::from_config(&config, &tracker_blocker).map_err(PrivacyError::Config)?;
+        if !config.webrtc_protection {
+            warn!("⚠️ WebRTC leak protection disabled in config");
+        }
+        if !config.ipv6_protection {
+            warn!("⚠️ IPv6 leak protection disabled in config");
+        }
+        let canvas_protection = CanvasProtection::new(config.canvas_protection);
+        if config.canvas_protection {
+            info!("✅ Canvas fingerprinting protection enabled");
+        } else {
+            warn!("⚠️ Canvas fingerprinting protection disabled in config");
+        }
+        if !config.security_detection {
+            warn!("⚠️ Security risk detection disabled in config");
+        }
+        if config.learning_mode {
+            warn!("⚠️ Learning mode on - requests that would be blocked are logged and forwarded");
+        }
+        
+        if config.routing_backend == RoutingBackend::Custom {
+            warn!("⚠️ Experimental custom routing backend selected - HTTP requests will fail closed");
+        }
+        
+        let response_cache = ResponseCache::from_config(&config.response_cache);
+        if response_cache.is_some() {
+            info!("✅ Response cache enabled ({} bytes, {}s TTL)", config.response_cache.max_bytes, config.response_cache.ttl_secs);
+        }
         
-        let kill_switch = KillSwitch::new();
+        // The app's kill switch when there is one, so the session and the API
+        // decide what this router lets through during an outage
+        let kill_switch = app_state
+            .as_ref()
+            .and_then(|s| s.kill_switch.clone())
+            .unwrap_or_else(KillSwitch::new);
         kill_switch.set_tor_status(true).await;
+        kill_switch.set_block_lan(config.killswitch_block_lan).await;
+        kill_switch.set_reconnect_hold(Duration::from_secs(config.reconnect_hold_secs), config.reconnect_hold_max).await;
+        let tor_status = transport.ready_for_traffic().map(|ready| Arc::new(kill_switch.follow(ready)));
+        let reported_circuits = Arc::new(Mutex::new((transport.circuit_rebuilds(), transport.circuit_rotations())));
         info!("✅ Kill switch enabled");
         
+        let connect_coalescer = config.coalesce_connects.then(ConnectCoalescer::new);
+        if connect_coalescer.is_some() {
+            info!("✅ Simultaneous tunnels to one host share a circuit");
+        }
+        
+        let local = DirectTransport::new().with_max_response_bytes(config.max_response_bytes as usize);
+        
         Ok(Self {
             config,
             crypto,
             nodes,
-            tor,
+            transport,
+            local,
+            dns,
             fingerprint,
+            _fingerprint_rotation: fingerprint_rotation,
+            _onion_services: Arc::new(Vec::new()),
             tracker_blocker,
-            webrtc_protection,
+            protections,
             kill_switch,
-            ipv6_protection,
+            _tor_status: tor_status,
+            _exit_reprobe: None,
+            reported_circuits,
             canvas_protection,
+            response_cache,
+            connect_coalescer,
+            geoip: None,
             app_state,
         })
     }
     
-    async fn load_nodes(_config: &Config) -> Result<Vec<Node>, Box<dyn std::error::Error + Send + Sync>> {
+    async fn load_nodes(_config: &Config) -> Result<Vec<Node>> {
         Ok(vec![
             Node::new("node1.example.com:9000".to_string()),
             Node::new("node2.example.com:9000".to_string()),
@@ -81,136 +881,75 @@ impl Router {
         ])
     }
     
+    /// Resolve the host arti should connect to according to `Config::dns_resolution`
+    async fn connect_host(&self, host: &str) -> Result<String> {
+        dns::select_connect_host(self.config.dns_resolution, host, |domain| async move {
+            self.dns.resolve_over_tor(self.transport.as_ref(), &domain).await
+        })
+        .await
+        .map_err(|e| PrivacyError::Dns(format!("{}: {}", host, e)))
+    }
+    
     /// Detect security risks and malicious tracking patterns
     async fn detect_security_risks(&self, host: &str, path: &str, method: &str) {
         if let Some(state) = &self.app_state {
-            let full_url = format!("{}{}", host, path);
-            
-            // Detect credential leaks in URL
-            let credential_patterns = vec![
-                ("password", "Password in URL"),
-                ("pwd", "Password in URL"),
-                ("api_key", "API Key in URL"),
-                ("apikey", "API Key in URL"),
-                ("token", "Token in URL"),
-                ("access_token", "Access Token in URL"),
-                ("secret", "Secret in URL"),
-                ("private", "Private data in URL"),
-                ("auth", "Auth data in URL"),
-                ("session", "Session ID in URL"),
-            ];
-            
-            for (pattern, threat) in credential_patterns {
-                if path.to_lowercase().contains(pattern) {
-                    let details = LogDetails {
-                        url: Some(full_url.clone()),
-                        domain: Some(host.to_string()),
-                        path: Some(path.to_string()),
-                        port: None,
-                        method: Some(method.to_string()),
-                        client_ip: None,
-                        threat_type: Some(threat.to_string()),
-                        reason: Some("Sensitive data detected in URL - potential credential leak".to_string()),
-                        request_headers: None,
-                    };
-                    warn!("⚠️ SECURITY: {} - {}", threat, full_url);
-                    state.update_stats(|s| s.security_threats_detected += 1).await;
-                    state.add_log_with_details("error", format!("⚠️ SECURITY: {} - {}", threat, host), "security", Some(details)).await;
-                }
-            }
-            
-            // Detect suspicious tracking patterns
-            let tracking_patterns = vec![
-                ("/track", "Tracking endpoint"),
-                ("/collect", "Data collection endpoint"),
-                ("/analytics", "Analytics tracking"),
-                ("/beacon", "Tracking beacon"),
-                ("/pixel", "Tracking pixel"),
-                ("/impression", "Ad impression tracking"),
-                ("/conversion", "Conversion tracking"),
-                ("/telemetry", "Telemetry data collection"),
-                ("/fingerprint", "Browser fingerprinting"),
-            ];
-            
-            for (pattern, tracking_type) in tracking_patterns {
-                if path.to_lowercase().contains(pattern) {
-                    let details = LogDetails {
-                        url: Some(full_url.clone()),
-                        domain: Some(host.to_string()),
-                        path: Some(path.to_string()),
-                        port: None,
-                        method: Some(method.to_string()),
-                        client_ip: None,
-                        threat_type: Some(tracking_type.to_string()),
-                        reason: Some("Suspicious tracking pattern detected".to_string()),
-                        request_headers: None,
-                    };
-                    warn!("🔍 TRACKING: {} detected - {}", tracking_type, full_url);
-                    state.update_stats(|s| s.security_threats_detected += 1).await;
-                    state.add_log_with_details("warn", format!("🔍 {} detected: {}", tracking_type, host), "security", Some(details)).await;
-                }
-            }
-            
-            // Detect malicious domains patterns
-            let malicious_patterns = vec![
-                ("analytics", "Analytics service"),
-                ("doubleclick", "Ad network"),
-                ("adserver", "Ad server"),
-                ("tracker", "Tracking service"),
-                ("metric", "Metrics collection"),
-                ("stats", "Statistics collection"),
-                ("tag-manager", "Tag management"),
-                ("remarketing", "Remarketing service"),
-            ];
-            
-            for (pattern, service_type) in malicious_patterns {
-                if host.to_lowercase().contains(pattern) {
-                    let details = LogDetails {
-                        url: Some(full_url.clone()),
-                        domain: Some(host.to_string()),
-                        path: Some(path.to_string()),
-                        port: None,
-                        method: Some(method.to_string()),
-                        client_ip: None,
-                        threat_type: Some(service_type.to_string()),
-                        reason: Some("Suspicious domain pattern - likely tracking/advertising".to_string()),
-                        request_headers: None,
-                    };
-                    info!("🕵️ {} detected in domain: {}", service_type, host);
-                    state.update_stats(|s| s.security_threats_detected += 1).await;
-                    state.add_log_with_details("info", format!("🕵️ {} detected: {}", service_type, host), "security", Some(details)).await;
-                }
-            }
+            detect_security_risks(&self.config, state, host, path, method).await;
+        }
+    }
+    
+    /// Response-side counterpart of `detect_security_risks` for the plaintext HTTP path
+    async fn detect_response_risks(
+        &self,
+        uri: &hyper::Uri,
+        method: &str,
+        response: Response<Full<Bytes>>,
+    ) -> Response<Full<Bytes>> {
+        use http_body_util::BodyExt;
+        
+        let (parts, body) = response.into_parts();
+        let body = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(never) => match never {},
+        };
+        
+        if let Some(state) = &self.app_state {
+            let host = normalize_host(uri.host().unwrap_or_default());
+            let full_url = format!("{}{}", host, uri.path());
             
-            // Detect unencrypted connections
-            if host.starts_with("http://") {
+            let risks = response_risks(&parts.headers, &body)
+                .into_iter()
+                .filter(|risk| self.config.security_detector_enabled(risk.detector));
+            for risk in risks {
                 let details = LogDetails {
                     url: Some(full_url.clone()),
-                    domain: Some(host.to_string()),
-                    path: Some(path.to_string()),
-                    port: None,
+                    domain: Some(host.clone()),
+                    path: Some(uri.path().to_string()),
+                    port: uri.port_u16(),
                     method: Some(method.to_string()),
-                    client_ip: None,
-                    threat_type: Some("Unencrypted connection".to_string()),
-                    reason: Some("HTTP connection detected - data transmitted in plain text".to_string()),
-                    request_headers: None,
+                    threat_type: Some(risk.threat.to_string()),
+                    reason: Some(risk.reason.to_string()),
+                    ..LogDetails::default()
                 };
-                warn!("⚠️ SECURITY: Unencrypted HTTP request to: {}", host);
-                state.update_stats(|s| s.security_threats_detected += 1).await;
-                state.add_log_with_details("warn", format!("⚠️ Unencrypted HTTP: {}", host), "security", Some(details)).await;
+                warn!("⚠️ SECURITY: {} - {}", risk.threat, full_url);
+                state.counters.security_threats_detected.inc();
+                state.add_log_with_details("error", format!("⚠️ SECURITY: {} - {}", risk.threat, host), "security", Some(details)).await;
             }
         }
+        
+        Response::from_parts(parts, Full::new(body))
     }
     
     pub async fn route_request(
         &self,
-        req: Request<hyper::body::Incoming>,
-    ) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error + Send + Sync>> {
+        mut req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Full<Bytes>>> {
         let method = req.method().clone();
         let uri = req.uri().clone();
+        let accept = req.headers().get(hyper::header::ACCEPT).and_then(|v| v.to_str().ok()).map(str::to_string);
         
         // Check kill switch first
-        if !self.kill_switch.should_allow_traffic().await {
+        let destination = uri.host().map(normalize_host);
+        if !self.kill_switch.allow_or_hold(destination.as_deref()).await {
             warn!("🚫 Kill switch: Blocking request (Tor disconnected)");
             if let Some(state) = &self.app_state {
                 let details = LogDetails {
@@ -219,16 +958,13 @@ impl Router {
                     path: Some(uri.path().to_string()),
                     port: uri.port_u16(),
                     method: Some(method.to_string()),
-                    client_ip: None,
                     threat_type: Some("Kill Switch Block".to_string()),
                     reason: Some("Tor connection lost - blocking traffic to prevent IP leaks".to_string()),
-                    request_headers: None,
+                    ..LogDetails::default()
                 };
                 state.add_log_with_details("error", "🚫 Kill switch blocked request - Tor disconnected!".to_string(), "security", Some(details)).await;
-                state.update_stats(|s| {
-                    s.requests_blocked += 1;
-                    s.security_threats_detected += 1;
-                }).await;
+                state.counters.requests_blocked.inc();
+                state.counters.security_threats_detected.inc();
             }
             return Ok(Response::builder()
                 .status(503)
@@ -238,11 +974,14 @@ impl Router {
         
         // Increment total requests
         if let Some(state) = &self.app_state {
-            state.update_stats(|s| s.total_requests += 1).await;
+            state.counters.total_requests.inc();
         }
         
         // Log all domains being accessed
-        if let Some(host) = uri.host() {
+        if let Some(raw_host) = uri.host() {
+            // Normalize so case, trailing dots, IDN and userinfo can't evade the checks below
+            let normalized_host = normalize_host(raw_host);
+            let host = normalized_host.as_str();
             let path = uri.path();
             let port = uri.port_u16().unwrap_or(443);
             let full_url = format!("{}{}", host, path);
@@ -255,20 +994,29 @@ impl Router {
                     path: Some(path.to_string()),
                     port: Some(port),
                     method: Some(method.to_string()),
-                    client_ip: None,
-                    threat_type: None,
-                    reason: None,
-                    request_headers: None,
+                    ..LogDetails::default()
                 };
                 state.add_log_with_details("info", format!("🌐 {}", full_url), "network", Some(details)).await;
+                state.hosts.record_request(host);
             }
             
             // Detect security risks and malicious tracking patterns
-            self.detect_security_risks(host, path, method.as_str()).await;
+            if self.config.security_detection {
+                self.detect_security_risks(host, path, method.as_str()).await;
+            }
             
-            // Check IPv6 protection
-            if self.ipv6_protection.should_block_ipv6(host) {
-                warn!("🚫 Blocked IPv6 request: {}", host);
+            // User-defined method/path rules
+            let filter = request_filter::first_match(&self.config.request_filters, method.as_str(), host, path)
+                .filter(|f| f.action == FilterAction::Block);
+            
+            // Learning mode reports what would have been blocked and lets it through
+            let learning = self.config.learning_mode;
+            if learning {
+                self.report_would_block(host, port, &full_url, method.as_str(), filter).await;
+            }
+            
+            if let Some(filter) = filter.filter(|_| !learning) {
+                warn!("🚫 Blocked by request filter ({}): {} {}", filter.describe(), method, full_url);
                 if let Some(state) = &self.app_state {
                     let details = LogDetails {
                         url: Some(full_url.clone()),
@@ -276,27 +1024,77 @@ impl Router {
                         path: Some(path.to_string()),
                         port: Some(port),
                         method: Some(method.to_string()),
-                        client_ip: None,
-                        threat_type: Some("IPv6 Leak Attempt".to_string()),
-                        reason: Some("IPv6 connection blocked to prevent real IP address exposure".to_string()),
-                        request_headers: None,
+                        threat_type: Some("Request Filter".to_string()),
+                        reason: Some(format!("Matched request filter {}", filter.describe())),
+                        ..LogDetails::default()
                     };
-                    state.update_stats(|s| {
-                        s.ipv6_blocked += 1;
-                        s.requests_blocked += 1;
-                    }).await;
-                    state.add_log_with_details("warn", format!("🚫 Blocked IPv6 leak: {}{}", host, path), "ipv6", Some(details)).await;
-                    info!("IPv6 protection prevented potential IP leak");
+                    state.counters.requests_blocked.inc();
+                    state.add_log_with_details("warn", format!("🚫 Blocked by request filter: {} {}", method, full_url), "security", Some(details)).await;
+                    state.hosts.record_blocked(host);
                 }
-                return Ok(Response::builder()
-                    .status(403)
-                    .body(Full::new(Bytes::from("IPv6 blocked for privacy protection")))
-                    .unwrap());
+                let detail = BlockDetail { host, rule: &filter.describe(), message: "Request blocked by a Privacy Suite request filter" };
+                return Ok(self.block_response("Request filter", detail, accept.as_deref()));
             }
             
-            // Check WebRTC protection
-            if self.webrtc_protection.should_block_request(host, port) {
-                warn!("🚫 Blocked WebRTC/STUN request: {}:{}", host, port);
+            if let Some(blocked) = self.check_app_doh(&method, host, port, path, req.headers()).await {
+                return Ok(blocked);
+            }
+            
+            let (blocked_by, allowed_for_now) = if learning { (None, None) } else { self.check_protections(host, port) };
+            if let (Some(state), Some(_)) = (&self.app_state, blocked_by) {
+                state.hosts.record_blocked(host);
+            }
+            if let Some(scope) = allowed_for_now {
+                self.report_temporary_allow(host, scope).await;
+            }
+            
+            // In allowlist mode, anything not explicitly allowed is blocked
+            if blocked_by == Some(BlockedBy::NotAllowlisted) {
+                warn!("🚫 Blocked non-allowlisted host: {}{}", host, path);
+                if let Some(state) = &self.app_state {
+                    let details = LogDetails {
+                        url: Some(full_url.clone()),
+                        domain: Some(host.to_string()),
+                        path: Some(path.to_string()),
+                        port: Some(port),
+                        method: Some(method.to_string()),
+                        threat_type: Some("Not Allowlisted".to_string()),
+                        reason: Some("Allowlist mode is on and this domain is not in allowed_domains".to_string()),
+                        ..LogDetails::default()
+                    };
+                    state.counters.requests_blocked.inc();
+                    state.add_log_with_details("warn", format!("🚫 Blocked (not allowlisted): {}{}", host, path), "security", Some(details)).await;
+                }
+                let detail = BlockDetail { host, rule: &self.protections.rule(BlockedBy::NotAllowlisted, host), message: "Domain not on the Privacy Suite allowlist" };
+                return Ok(self.block_response("Not allowlisted", detail, accept.as_deref()));
+            }
+            
+            // Check IPv6 protection
+            if blocked_by == Some(BlockedBy::Ipv6) {
+                warn!("🚫 Blocked IPv6 request: {}", host);
+                if let Some(state) = &self.app_state {
+                    let details = LogDetails {
+                        url: Some(full_url.clone()),
+                        domain: Some(host.to_string()),
+                        path: Some(path.to_string()),
+                        port: Some(port),
+                        method: Some(method.to_string()),
+                        threat_type: Some("IPv6 Leak Attempt".to_string()),
+                        reason: Some("IPv6 connection blocked to prevent real IP address exposure".to_string()),
+                        ..LogDetails::default()
+                    };
+                    state.counters.ipv6_blocked.inc();
+                    state.counters.requests_blocked.inc();
+                    state.add_log_with_details("warn", format!("🚫 Blocked IPv6 leak: {}{}", host, path), "ipv6", Some(details)).await;
+                    info!("IPv6 protection prevented potential IP leak");
+                }
+                let detail = BlockDetail { host, rule: &self.protections.rule(BlockedBy::Ipv6, host), message: "IPv6 blocked for privacy protection" };
+                return Ok(self.block_response("IPv6 leak", detail, accept.as_deref()));
+            }
+            
+            // Check WebRTC protection
+            if blocked_by == Some(BlockedBy::WebRtc) {
+                warn!("🚫 Blocked WebRTC/STUN request: {}:{}", host, port);
                 if let Some(state) = &self.app_state {
                     let details = LogDetails {
                         url: Some(full_url.clone()),
@@ -304,26 +1102,42 @@ impl Router {
                         path: Some(path.to_string()),
                         port: Some(port),
                         method: Some(method.to_string()),
-                        client_ip: None,
                         threat_type: Some("WebRTC Leak Attempt".to_string()),
                         reason: Some("WebRTC/STUN connection blocked to prevent real IP address exposure via peer connections".to_string()),
-                        request_headers: None,
+                        ..LogDetails::default()
                     };
-                    state.update_stats(|s| {
-                        s.webrtc_blocked += 1;
-                        s.requests_blocked += 1;
-                    }).await;
+                    state.counters.webrtc_blocked.inc();
+                    state.counters.requests_blocked.inc();
                     state.add_log_with_details("warn", format!("🚫 Blocked WebRTC leak attempt: {}:{}", host, port), "webrtc", Some(details)).await;
                     info!("WebRTC protection prevented potential IP leak");
                 }
-                return Ok(Response::builder()
-                    .status(403)
-                    .body(Full::new(Bytes::from("WebRTC blocked for privacy protection")))
-                    .unwrap());
+                let detail = BlockDetail { host, rule: &self.protections.rule(BlockedBy::WebRtc, host), message: "WebRTC blocked for privacy protection" };
+                return Ok(self.block_response("WebRTC leak", detail, accept.as_deref()));
+            }
+            
+            // Check direct IP policy
+            if blocked_by == Some(BlockedBy::DirectIp) {
+                warn!("🚫 Blocked direct IP request: {}:{}", host, port);
+                if let Some(state) = &self.app_state {
+                    let details = LogDetails {
+                        url: Some(full_url.clone()),
+                        domain: Some(host.to_string()),
+                        path: Some(path.to_string()),
+                        port: Some(port),
+                        method: Some(method.to_string()),
+                        threat_type: Some("Direct IP Request".to_string()),
+                        reason: Some("Request to a raw IP address blocked by the direct IP policy".to_string()),
+                        ..LogDetails::default()
+                    };
+                    state.counters.requests_blocked.inc();
+                    state.add_log_with_details("warn", format!("🚫 Blocked direct IP request: {}:{}", host, port), "security", Some(details)).await;
+                }
+                let detail = BlockDetail { host, rule: &self.protections.rule(BlockedBy::DirectIp, host), message: "Direct IP requests blocked by Privacy Suite" };
+                return Ok(self.block_response("Direct IP", detail, accept.as_deref()));
             }
             
             // Check if domain should be blocked
-            if self.tracker_blocker.should_block(host) {
+            if blocked_by == Some(BlockedBy::Tracker) {
                 warn!("🚫 Blocked tracker: {}{}", host, path);
                 if let Some(state) = &self.app_state {
                     let details = LogDetails {
@@ -332,47 +1146,538 @@ impl Router {
                         path: Some(path.to_string()),
                         port: Some(port),
                         method: Some(method.to_string()),
-                        client_ip: None,
                         threat_type: Some("Known Tracker".to_string()),
                         reason: Some("Domain matched against known tracker database - preventing data collection".to_string()),
-                        request_headers: None,
+                        ..LogDetails::default()
                     };
-                    state.update_stats(|s| {
-                        s.trackers_blocked += 1;
-                        s.requests_blocked += 1;
-                    }).await;
+                    state.counters.trackers_blocked.inc();
+                    state.counters.requests_blocked.inc();
                     state.add_log_with_details("warn", format!("🚫 Blocked tracker: {}{}", host, path), "tracker", Some(details)).await;
                     info!("Tracker blocker prevented data collection attempt");
                 }
-                return Ok(Response::builder()
-                    .status(403)
-                    .body(Full::new(Bytes::from("Tracker blocked by Privacy Suite")))
-                    .unwrap());
+                let detail = BlockDetail { host, rule: &self.protections.rule(BlockedBy::Tracker, host), message: "Tracker blocked by Privacy Suite" };
+                return Ok(self.block_response("Tracker", detail, accept.as_deref()));
+            }
+            
+            // No exit can reach these, and asking one reveals what is on the local network
+            if kill_switch::is_lan_destination(host) {
+                let client = req.extensions().get::<ClientAddr>().map(|client| client.0);
+                return self.route_private_host(req, host, port, &full_url, method.as_str(), client).await;
             }
         }
         
+        if self.config.routing_backend == RoutingBackend::Custom {
+            let route = self.select_route();
+            let encrypted_request = self.crypto
+                .build_onion_layers(req, &route)
+                .map_err(|e| PrivacyError::Config(format!("Custom routing backend: {}", e)))?;
+            return self.send_through_route(encrypted_request, &route).await;
+        }
+        
+        // Serve repeated GETs for shareable resources without a new Tor round trip
+        let cache_key = self
+            .response_cache
+            .as_ref()
+            .filter(|_| ResponseCache::is_cacheable_request(&method, req.headers()))
+            .map(|_| ResponseCache::key(&method, &uri));
+        if let (Some(cache), Some(key)) = (&self.response_cache, &cache_key) {
+            let cached = cache.get(key);
+            if let Some(state) = &self.app_state {
+                let counter = if cached.is_some() { &state.counters.cache_hits } else { &state.counters.cache_misses };
+                counter.inc();
+            }
+            if let Some(response) = cached {
+                info!("💾 Served from cache: {}", uri);
+                return Ok(response);
+            }
+        }
+        
+        // Don't let sites embedded in a page read or set cookies there
+        let third_party_of = self
+            .config
+            .first_party_isolation
+            .then(|| first_party::third_party_of(uri.host().unwrap_or_default(), req.headers()))
+            .flatten();
+        if third_party_of.is_some() {
+            first_party::strip_cookies(req.headers_mut(), hyper::header::COOKIE);
+        }
+        
         // Route through Tor's existing 3-hop circuit with randomized fingerprint
-        let response = self.tor.route_request(req, &self.fingerprint).await?;
+        let connect_host = self.connect_host(uri.host().unwrap_or_default()).await?;
+        let fingerprint = self.fingerprint.current();
+        let result = self.transport.route_request(req, &fingerprint, &connect_host).await;
+        self.report_circuit_changes().await;
+        let mut response = match result {
+            Ok(response) => response,
+            Err(PrivacyError::ResponseTooLarge { limit }) => {
+                return Ok(self.response_too_large(&uri, limit).await);
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(first_party) = &third_party_of {
+            // Count only what the site tried to set: the request's cookies
+            // are dropped before it is known whether it goes out at all
+            let cookies_stripped = first_party::strip_cookies(response.headers_mut(), hyper::header::SET_COOKIE);
+            self.report_stripped_cookies(&uri, first_party, cookies_stripped).await;
+        }
+        if self.config.prefer_onion {
+            response = self.follow_onion_location(&method, &uri, response).await;
+        }
+        let scrubbed = header_scrubber::scrub_headers(response.headers_mut(), &self.config.scrub_response_headers);
+        if let Some(state) = self.app_state.as_ref().filter(|_| scrubbed > 0) {
+            state.counters.headers_scrubbed.add(scrubbed as u64);
+        }
+        if self.config.security_detection {
+            response = self.detect_response_risks(&uri, method.as_str(), response).await;
+        }
+        if let Some(webrtc) = &self.protections.webrtc {
+            webrtc.apply_to_response(&mut response);
+        }
+        
+        if let (Some(cache), Some(key)) = (&self.response_cache, cache_key) {
+            response = cache.store(key, response).await;
+        }
         
         if let Some(state) = &self.app_state {
+            let body_size = response.body().size_hint().exact().unwrap_or(0);
+            state.counters.bytes_transferred.add(body_size);
+            state.hosts.record_bytes(&normalize_host(uri.host().unwrap_or_default()), body_size);
             state.add_log("info", "✅ Routed through Tor (3 encrypted hops)".to_string(), "network").await;
         }
         
         Ok(response)
     }
     
+    /// Serve a request for a private or local host directly, or refuse it,
+    /// per `Config::private_hosts` and `private_host_refusal`
+    async fn route_private_host(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        host: &str,
+        port: u16,
+        full_url: &str,
+        method: &str,
+        client: Option<IpAddr>,
+    ) -> Result<Response<Full<Bytes>>> {
+        if let Some((rule, message)) = private_host_refusal(&self.config, host, port, client) {
+            self.report_private_host_refused(host, port, full_url, method, client, rule).await;
+            let accept = req.headers().get(hyper::header::ACCEPT).and_then(|v| v.to_str().ok());
+            let detail = BlockDetail { host, rule, message };
+            return Ok(self.block_response("Private host", detail, accept));
+        }
+        
+        info!("🏠 Private host {} - connecting directly instead of through Tor", full_url);
+        let uri = req.uri().clone();
+        let connect_host = host.trim_start_matches('[').trim_end_matches(']');
+        let fingerprint = self.fingerprint.current();
+        let response = match self.local.route_request(req, &fingerprint, connect_host).await {
+            Ok(response) => response,
+            Err(PrivacyError::ResponseTooLarge { limit }) => return Ok(self.response_too_large(&uri, limit).await),
+            Err(e) => return Err(e),
+        };
+        if let Some(state) = &self.app_state {
+            let body_size = response.body().size_hint().exact().unwrap_or(0);
+            state.counters.bytes_transferred.add(body_size);
+            state.hosts.record_bytes(host, body_size);
+            state.add_log("info", format!("🏠 Served directly (private host): {}", full_url), "network").await;
+        }
+        Ok(response)
+    }
+    
+    async fn report_private_host_refused(&self, host: &str, port: u16, target: &str, method: &str, client: Option<IpAddr>, rule: &str) {
+        warn!("🚫 Blocked request to private host ({}): {}", rule, target);
+        if let Some(state) = &self.app_state {
+            let reason = match rule {
+                "api_port" => "The suite's own API is never reachable through the proxy".to_string(),
+                "loopback" => "Only clients on this computer may reach its local services".to_string(),
+                _ => "Local and private hosts can't be reached through a Tor exit (private_hosts = Block)".to_string(),
+            };
+            let details = LogDetails {
+                url: Some(target.to_string()),
+                domain: Some(host.to_string()),
+                port: Some(port),
+                method: Some(method.to_string()),
+                client_ip: client.map(|ip| ip.to_string()),
+                threat_type: Some("Private Host".to_string()),
+                reason: Some(reason),
+                ..LogDetails::default()
+            };
+            state.counters.requests_blocked.inc();
+            state.add_log_with_details("warn", format!("🚫 Blocked private host: {}", target), "security", Some(details)).await;
+            state.hosts.record_blocked(host);
+        }
+    }
+    
+    /// Repeat a GET or HEAD at the onion address the site advertises with
+    /// `Onion-Location`, keeping the original response if that fails
+    async fn follow_onion_location(
+        &self,
+        method: &Method,
+        uri: &hyper::Uri,
+        response: Response<Full<Bytes>>,
+    ) -> Response<Full<Bytes>> {
+        if !matches!(*method, Method::GET | Method::HEAD) {
+            return response;
+        }
+        let Some(onion) = onion_location(response.headers()) else {
+            return response;
+        };
+        let origin = normalize_host(uri.host().unwrap_or_default());
+        let onion_host = onion.host().unwrap_or_default().to_string();
+        
+        // Onion addresses are never resolved locally, whatever `dns_resolution` says
+        let fingerprint = self.fingerprint.current();
+        match self.transport.fetch(method, &onion, &fingerprint, &onion_host).await {
+            Ok(upgraded) => {
+                info!("🧅 {} upgraded to its onion service {}", origin, onion_host);
+                if let Some(state) = &self.app_state {
+                    state.add_log("info", format!("🧅 Upgraded {} to its onion service {}", origin, onion_host), "network").await;
+                }
+                upgraded
+            }
+            Err(e) => {
+                warn!("Onion service {} for {} failed, keeping the original response: {}", onion_host, origin, e);
+                if let Some(state) = &self.app_state {
+                    state.add_log("warn", format!("⚠️ Onion service for {} unreachable - used the regular site", origin), "network").await;
+                }
+                response
+            }
+        }
+    }
+    
+    /// Log third-party cookies a response tried to set, removed by
+    /// `Config::first_party_isolation`
+    async fn report_stripped_cookies(&self, uri: &hyper::Uri, first_party: &str, count: usize) {
+        if count == 0 {
+            return;
+        }
+        
+        let host = normalize_host(uri.host().unwrap_or_default());
+        info!("🍪 Stripped {} third-party cookie(s) for {} on {}", count, host, first_party);
+        if let Some(state) = &self.app_state {
+            let details = LogDetails {
+                url: Some(uri.to_string()),
+                domain: Some(host.clone()),
+                path: Some(uri.path().to_string()),
+                port: uri.port_u16(),
+                threat_type: Some("Third-Party Cookie".to_string()),
+                reason: Some(format!("{} cookie(s) stripped: {} is embedded in {}, a different site (first_party_isolation)", count, host, first_party)),
+                ..LogDetails::default()
+            };
+            state.add_log_with_details("info", format!("🍪 Stripped {} third-party cookie(s): {}", count, host), "tracker", Some(details)).await;
+        }
+    }
+    
+    /// Log a request that is an app's own DNS-over-HTTPS lookup, returning
+    /// the response that blocks it when `Config::block_app_doh` is on
+    async fn check_app_doh(
+        &self,
+        method: &Method,
+        host: &str,
+        port: u16,
+        path: &str,
+        headers: &hyper::HeaderMap,
+    ) -> Option<Response<Full<Bytes>>> {
+        let endpoint = app_doh::detect(&self.config.doh_endpoints, host, path, headers)?;
+        let block = self.report_app_doh(method, host, port, path, &endpoint).await;
+        
+        block.then(|| {
+            let accept = headers.get(hyper::header::ACCEPT).and_then(|v| v.to_str().ok());
+            let detail = BlockDetail {
+                host,
+                rule: "block_app_doh",
+                message: "DNS-over-HTTPS blocked by Privacy Suite - use the system resolver",
+            };
+            self.block_response("App DNS-over-HTTPS", detail, accept)
+        })
+    }
+    
+    /// Log and count an app DoH lookup matched by `endpoint`. Returns whether
+    /// to block it.
+    async fn report_app_doh(&self, method: &Method, host: &str, port: u16, path: &str, endpoint: &str) -> bool {
+        let full_url = format!("{}{}", host, path);
+        let learning = self.config.block_app_doh && self.config.learning_mode;
+        let block = self.config.block_app_doh && !learning;
+        
+        let (level, message) = if block {
+            ("warn", format!("🚫 Blocked app DNS-over-HTTPS: {}", full_url))
+        } else if learning {
+            ("info", format!("🎓 Would block (app DoH {}): {} {}", endpoint, method, full_url))
+        } else {
+            ("info", format!("🔍 App DNS-over-HTTPS detected: {}", full_url))
+        };
+        if block {
+            warn!("{} ({})", message, endpoint);
+        } else {
+            info!("{} ({})", message, endpoint);
+        }
+        
+        if let Some(state) = &self.app_state {
+            let details = LogDetails {
+                url: Some(full_url.clone()),
+                domain: Some(host.to_string()),
+                path: (!path.is_empty()).then(|| path.to_string()),
+                port: Some(port),
+                method: Some(method.to_string()),
+                threat_type: Some("App DNS-over-HTTPS".to_string()),
+                reason: Some(format!("Matched DoH endpoint {} - the app resolves names bypassing the suite's DNS settings", endpoint)),
+                ..LogDetails::default()
+            };
+            if block {
+                state.counters.requests_blocked.inc();
+                state.hosts.record_blocked(host);
+            } else if learning {
+                state.counters.would_block.inc();
+            }
+            state.add_log_with_details(level, message, "dns", Some(details)).await;
+        }
+        
+        block
+    }
+    
+    /// `Protections::check`, except that a host allowed for now from a block
+    /// page gets past allowlist mode and the tracker lists (not the leak
+    /// protections). Also returns the temporary allow that was used, if any.
+    fn check_protections(&self, host: &str, port: u16) -> (Option<BlockedBy>, Option<AllowScope>) {
+        if self.protections.lists_block(host) {
+            if let Some(scope) = self.tracker_blocker.take_temporary_allow(host) {
+                return (self.protections.check_leaks(host, port), Some(scope));
+            }
+        }
+        (self.protections.check(host, port), None)
+    }
+    
+    async fn report_temporary_allow(&self, host: &str, scope: AllowScope) {
+        let scope = match scope {
+            AllowScope::Once => "once",
+            AllowScope::Session => "for this session",
+        };
+        info!("🔓 Temporarily allowed {} ({})", host, scope);
+        if let Some(state) = &self.app_state {
+            state.add_log("info", format!("🔓 Allowed {} {} - normally blocked", host, scope), "tracker").await;
+        }
+    }
+    
+    /// Learning mode: log and count the first rule that would have blocked a request
+    async fn report_would_block(
+        &self,
+        host: &str,
+        port: u16,
+        full_url: &str,
+        method: &str,
+        filter: Option<&request_filter::RequestFilter>,
+    ) {
+        let (category, rule) = match filter {
+            Some(filter) => ("security", format!("request filter {}", filter.describe())),
+            None => match self.protections.would_block(host, port) {
+                Some(blocked_by) => (blocked_by.log_category(), self.protections.rule(blocked_by, host)),
+                None => return,
+            },
+        };
+        info!("🎓 Learning mode - would block {} {} ({})", method, full_url, rule);
+        
+        if let Some(state) = &self.app_state {
+            let details = LogDetails {
+                url: Some(full_url.to_string()),
+                domain: Some(host.to_string()),
+                port: Some(port),
+                method: Some(method.to_string()),
+                threat_type: Some("Would Block".to_string()),
+                reason: Some(format!("Learning mode: matched {} but forwarded anyway", rule)),
+                ..LogDetails::default()
+            };
+            state.counters.would_block.inc();
+            state.add_log_with_details("info", format!("🎓 Would block ({}): {} {}", rule, method, full_url), category, Some(details)).await;
+        }
+    }
+    
+    /// Log and count circuits the Tor layer rebuilt after repeated failures
+    /// or retired for age since this was last called. Each change is
+    /// reported once, by whichever request notices it first, however many
+    /// were in flight when it happened.
+    async fn report_circuit_changes(&self) {
+        let (rebuilt, rotated) = {
+            let mut reported = self.reported_circuits.lock().unwrap_or_else(|e| e.into_inner());
+            let (rebuilds, rotations) = (self.transport.circuit_rebuilds(), self.transport.circuit_rotations());
+            let changes = (rebuilds.saturating_sub(reported.0), rotations.saturating_sub(reported.1));
+            *reported = (rebuilds, rotations);
+            changes
+        };
+        if rebuilt == 0 && rotated == 0 {
+            return;
+        }
+        
+        // A new circuit is a new identity, so don't carry the old fingerprint over
+        self.fingerprint.rotate_unless_pinned();
+        
+        if let Some(state) = &self.app_state {
+            if rebuilt > 0 {
+                state.counters.circuit_rebuilds.add(rebuilt);
+                state.add_log(
+                    "warn",
+                    "🔄 Repeated failures across hosts - built a new Tor circuit and retried".to_string(),
+                    "network",
+                ).await;
+            }
+            if rotated > 0 {
+                state.add_log(
+                    "info",
+                    format!("⏱️ Tor circuit reached its max age ({}s) - new streams use a fresh circuit", self.config.max_circuit_age_secs),
+                    "network",
+                ).await;
+            }
+        }
+    }
+    
+    /// Answer a request whose upstream response hit `Config::max_response_bytes`
+    async fn response_too_large(&self, uri: &hyper::Uri, limit: usize) -> Response<Full<Bytes>> {
+        warn!("🚫 Dropped oversized response from {}: {}", uri, PrivacyError::ResponseTooLarge { limit });
+        if let Some(state) = &self.app_state {
+            let details = LogDetails {
+                url: Some(uri.to_string()),
+                domain: uri.host().map(|h| h.to_string()),
+                path: Some(uri.path().to_string()),
+                port: uri.port_u16(),
+                reason: Some(format!("Upstream response exceeded the {} byte limit (max_response_bytes)", limit)),
+                ..LogDetails::default()
+            };
+            state.counters.requests_dropped.inc();
+            state.add_log_with_details("error", format!("🚫 Response too large, dropped: {}", uri), "network", Some(details)).await;
+        }
+        
+        Response::builder()
+            .status(502)
+            .body(Full::new(Bytes::from(format!(
+                "Bad gateway: the response was larger than the {} byte limit set by Privacy Suite",
+                limit
+            ))))
+            .unwrap()
+    }
+    
+    /// Open a CONNECT tunnel for `client` to `host:port`: through Tor, or
+    /// for private and local hosts directly (or not at all) per
+    /// `private_host_refusal`
     pub async fn connect_through_tor(
         &self,
         host: &str,
         port: u16,
-    ) -> Result<arti_client::DataStream, Box<dyn std::error::Error + Send + Sync>> {
+        client: Option<IpAddr>,
+    ) -> Result<Box<dyn TransportStream>> {
         info!("🔐 Opening HTTPS tunnel to {}:{} via Tor", host, port);
         
         if let Some(state) = &self.app_state {
             state.add_log("info", format!("🔐 Opening tunnel to {}:{}", host, port), "network").await;
         }
         
-        self.tor.connect_stream(host, port).await
+        // HTTPS tunnels bypass `route_request`, so allowlist mode is enforced here too
+        let normalized_host = normalize_host(host);
+        let allowed_for_now = (!self.protections.is_allowlisted(&normalized_host))
+            .then(|| self.tracker_blocker.take_temporary_allow(&normalized_host))
+            .flatten();
+        if let Some(scope) = allowed_for_now {
+            self.report_temporary_allow(&normalized_host, scope).await;
+        } else if !self.protections.is_allowlisted(&normalized_host) {
+            let target = format!("{}:{}", normalized_host, port);
+            if self.config.learning_mode {
+                self.report_would_block(&normalized_host, port, &target, "CONNECT", None).await;
+            } else {
+                warn!("🚫 Blocked tunnel to non-allowlisted host: {}", normalized_host);
+                if let Some(state) = &self.app_state {
+                    state.counters.requests_blocked.inc();
+                    state.add_log("warn", format!("🚫 Blocked (not allowlisted): {}", target), "security").await;
+                    state.hosts.record_blocked(&normalized_host);
+                }
+                return Err(PrivacyError::Blocked(format!("{} is not on the allowlist", normalized_host)));
+            }
+        }
+        
+        // Only the host of a tunnel shows, so DoH endpoints are matched on it alone
+        if let Some(endpoint) = app_doh::detect_host(&self.config.doh_endpoints, &normalized_host) {
+            if self.report_app_doh(&Method::CONNECT, &normalized_host, port, "", &endpoint).await {
+                return Err(PrivacyError::Blocked(format!("{}:{}: app DNS-over-HTTPS (block_app_doh)", normalized_host, port)));
+            }
+        }
+        
+        // Tunnels wait out a reconnect like plain requests do
+        if !self.kill_switch.allow_or_hold(Some(&normalized_host)).await {
+            let target = format!("{}:{}", normalized_host, port);
+            warn!("🚫 Kill switch: Blocking tunnel to {} (Tor disconnected)", target);
+            if let Some(state) = &self.app_state {
+                let details = LogDetails {
+                    domain: Some(normalized_host.clone()),
+                    port: Some(port),
+                    method: Some("CONNECT".to_string()),
+                    client_ip: client.map(|ip| ip.to_string()),
+                    threat_type: Some("Kill Switch Block".to_string()),
+                    reason: Some("Tor connection lost - blocking traffic to prevent IP leaks".to_string()),
+                    ..LogDetails::default()
+                };
+                state.add_log_with_details("error", "🚫 Kill switch blocked tunnel - Tor disconnected!".to_string(), "security", Some(details)).await;
+                state.counters.requests_blocked.inc();
+                state.counters.security_threats_detected.inc();
+            }
+            return Err(PrivacyError::KillSwitch(format!("{}: Tor disconnected", target)));
+        }
+        
+        // No exit can reach these, and asking one reveals what is on the local network
+        if kill_switch::is_lan_destination(&normalized_host) {
+            let target = format!("{}:{}", normalized_host, port);
+            if let Some((rule, message)) = private_host_refusal(&self.config, &normalized_host, port, client) {
+                self.report_private_host_refused(&normalized_host, port, &target, "CONNECT", client, rule).await;
+                return Err(PrivacyError::Blocked(format!("{}: {}", target, message)));
+            }
+            info!("🏠 Private host {} - tunneling directly instead of through Tor", target);
+            let connect_host = normalized_host.trim_start_matches('[').trim_end_matches(']');
+            return Ok(self.local.connect_stream(connect_host, port).await?.stream);
+        }
+        
+        let connect_host = self.connect_host(host).await?;
+        let opened = match &self.connect_coalescer {
+            Some(coalescer) => coalescer.open(&connect_host, port, || self.transport.connect_stream(&connect_host, port)).await,
+            None => self.transport.connect_stream(&connect_host, port).await,
+        };
+        self.report_circuit_changes().await;
+        let opened = opened?;
+        
+        if let Some(state) = &self.app_state {
+            self.publish_circuit(state, opened.circuit).await;
+        }
+        
+        Ok(opened.stream)
+    }
+    
+    /// Report the circuit a tunnel got, then place its exit in the
+    /// background: the lookup may itself go over Tor, and the tunnel
+    /// shouldn't wait on it. The guard and middle relays aren't looked up.
+    async fn publish_circuit(&self, state: &ApiState, circuit: Option<Vec<CircuitHop>>) {
+        let exit = circuit
+            .as_ref()
+            .and_then(|hops| hops.iter().find(|hop| hop.role == "exit" && hop.country.is_none()))
+            .and_then(|exit| Some((exit.relay.clone(), exit.address?)));
+        state.set_circuit(circuit).await;
+        
+        let (Some(geoip), Some((relay, address))) = (self.geoip.clone(), exit) else {
+            return;
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Some(country) = geoip.country(address).await {
+                state.locate_exit(&relay, country).await;
+            }
+        });
+    }
+    
+    /// The 403 for a blocked request: a block page for browsers, the plain
+    /// `detail.message` for other clients
+    fn block_response(&self, category: &str, detail: BlockDetail<'_>, accept: Option<&str>) -> Response<Full<Bytes>> {
+        let scheme = if self.config.api_tls { "https" } else { "http" };
+        let api_base = format!("{}://127.0.0.1:{}", scheme, self.config.api_port);
+        block_page::response(category, detail, accept, &api_base)
+    }
+    
+    /// The kill switch guarding this router's requests
+    pub fn kill_switch(&self) -> &KillSwitch {
+        &self.kill_switch
     }
     
     /// Get statistics about blocked trackers
@@ -393,13 +1698,559 @@ impl Router {
         selected
     }
     
+    /// Forward an onion-encrypted request along a custom-network route.
+    ///
+    /// The custom network has no relay protocol yet, so this always fails
+    /// closed rather than pretending the request was delivered.
     async fn send_through_route(
         &self,
-        _encrypted_request: Vec<u8>,
+        encrypted_request: Vec<u8>,
         route: &[&Node],
-    ) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error + Send + Sync>> {
-        info!("Request routed through: {:?}", route);
+    ) -> Result<Response<Full<Bytes>>> {
+        let hops: Vec<&str> = route.iter().map(|node| node.address.as_str()).collect();
+        warn!(
+            "Custom routing backend is experimental - not sending {} byte onion request via {:?}",
+            encrypted_request.len(),
+            hops
+        );
+        
+        if let Some(state) = &self.app_state {
+            state.add_log("warn", "⚠️ Custom routing backend is experimental and cannot deliver requests - switch routing_backend to Tor".to_string(), "network").await;
+        }
+        
+        Err(PrivacyError::Config("Custom routing backend is not available: no relay nodes implemented".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protections(configure: impl FnOnce(&mut Config)) -> (Protections, TrackerBlocker) {
+        let mut config = Config::default();
+        configure(&mut config);
+        let tracker_blocker = TrackerBlocker::new();
+        (Protections::from_config(&config, &tracker_blocker).unwrap(), tracker_blocker)
+    }
+
+    #[test]
+    fn test_enabled_protections_block() {
+        let (protections, tracker_blocker) = protections(|_| {});
+
+        assert_eq!(protections.check("[2001:db8::1]", 443), Some(BlockedBy::Ipv6));
+        assert_eq!(protections.check("stun.l.google.com", 3478), Some(BlockedBy::WebRtc));
+        assert_eq!(protections.check("doubleclick.net", 443), Some(BlockedBy::Tracker));
+        assert_eq!(protections.check("example.com", 443), None);
+        assert_eq!(tracker_blocker.total_blocked(), 1);
+    }
+
+    #[test]
+    fn test_would_block_counts_nothing() {
+        let (protections, tracker_blocker) = protections(|config| config.block_direct_ip = true);
+
+        for (host, port) in [("[2001:db8::1]", 443), ("stun.l.google.com", 3478), ("1.1.1.1", 443), ("doubleclick.net", 443), ("example.com", 443)] {
+            assert_eq!(protections.would_block(host, port), protections.check(host, port), "{}", host);
+        }
+        // Only the `check` calls counted
+        assert_eq!(protections.ipv6.as_ref().unwrap().get_blocked_count(), 1);
+        assert_eq!(tracker_blocker.total_blocked(), 1);
+    }
+
+    #[test]
+    fn test_disabled_protections_are_never_consulted() {
+        let (protections, tracker_blocker) = protections(|config| {
+            config.ipv6_protection = false;
+            config.webrtc_protection = false;
+            config.tracker_blocking = false;
+        });
+
+        assert!(protections.ipv6.is_none());
+        assert!(protections.webrtc.is_none());
+        assert!(protections.tracker_blocker.is_none());
+
+        assert_eq!(protections.check("[2001:db8::1]", 443), None);
+        assert_eq!(protections.check("stun.l.google.com", 3478), None);
+        assert_eq!(protections.check("doubleclick.net", 443), None);
+        // The shared blocker counts every block, so zero means it was never asked
+        assert_eq!(tracker_blocker.total_blocked(), 0);
+    }
+
+    #[test]
+    fn test_allowlist_mode_blocks_unlisted_hosts() {
+        let (protections, _) = protections(|config| {
+            config.mode = BrowsingMode::Allowlist;
+            config.allowed_domains = vec!["Example.com".to_string(), "wikipedia.org".to_string(), "doubleclick.net".to_string()];
+        });
+
+        assert_eq!(protections.check("example.com", 443), None);
+        assert_eq!(protections.check("www.example.com", 443), None);
+        assert_eq!(protections.check("en.wikipedia.org", 80), None);
+        assert_eq!(protections.check("news.example.org", 443), Some(BlockedBy::NotAllowlisted));
+        assert_eq!(protections.check("notexample.com", 443), Some(BlockedBy::NotAllowlisted));
+        // Allowlisting doesn't switch the other protections off
+        assert_eq!(protections.check("doubleclick.net", 443), Some(BlockedBy::Tracker));
+
+        // The default blocklist mode lets anything through that nothing else blocks
+        let (protections, _) = protections(|config| config.allowed_domains = vec!["example.com".to_string()]);
+        assert_eq!(protections.check("news.example.org", 443), None);
+    }
+
+    #[tokio::test]
+    async fn test_app_doh_blocked_but_same_host_allowed() {
+        let mut config = Config::default();
+        config.block_app_doh = true;
+        let state = ApiState::new(config.clone());
+        let router = Router::with_transport(config, Some(state.clone()), Arc::new(crate::transport::DirectTransport::new())).await.unwrap();
+        let headers = hyper::HeaderMap::new();
+        
+        let blocked = router.check_app_doh(&Method::GET, "1.1.1.1", 80, "/dns-query", &headers).await.unwrap();
+        assert_eq!(blocked.status(), 403);
+        assert!(router.check_app_doh(&Method::GET, "1.1.1.1", 80, "/", &headers).await.is_none());
+        assert_eq!(state.stats_snapshot().await.requests_blocked, 1);
+        let logs = state.logs.read().await.clone();
+        assert_eq!(logs.iter().filter(|log| log.category == "dns").count(), 1);
+        assert!(logs.iter().any(|log| log.category == "dns" && log.message.contains("1.1.1.1/dns-query")));
+        
+        // A tunnel to a DoH host is refused before anything is opened
+        let err = router.connect_through_tor("dns.google", 443, None).await.err().unwrap();
+        assert!(matches!(err, PrivacyError::Blocked(ref msg) if msg.contains("block_app_doh")), "{}", err);
+        assert_eq!(state.stats_snapshot().await.requests_blocked, 2);
+        
+        // Without block_app_doh the lookup is only reported
+        let state = ApiState::new(Config::default());
+        let router = Router::with_transport(Config::default(), Some(state.clone()), Arc::new(crate::transport::DirectTransport::new())).await.unwrap();
+        assert!(router.check_app_doh(&Method::GET, "1.1.1.1", 80, "/dns-query", &headers).await.is_none());
+        assert_eq!(state.stats_snapshot().await.requests_blocked, 0);
+        assert!(state.logs.read().await.iter().any(|log| log.category == "dns" && log.message.contains("detected")));
+    }
+
+    #[test]
+    fn test_lan_clients_never_reach_this_machine_directly() {
+        let lan_client: IpAddr = "192.168.1.20".parse().unwrap();
+        let local_client: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut config = Config::default();
+        assert_eq!(config.private_hosts, PrivateHostPolicy::Block);
+        assert!(private_host_refusal(&config, "nas.local", 80, Some(local_client)).is_some());
+        
+        config.private_hosts = PrivateHostPolicy::Bypass;
+        let rule = |host: &str, port: u16, client: Option<IpAddr>| private_host_refusal(&config, host, port, client).map(|(rule, _)| rule);
+        assert_eq!(rule("nas.local", 80, Some(lan_client)), None);
+        assert_eq!(rule("10.1.2.3", 443, Some(lan_client)), None);
+        assert_eq!(rule("localhost", 8080, Some(local_client)), None);
+        for host in ["localhost", "127.0.0.1", "[::1]", "0.0.0.0"] {
+            assert_eq!(rule(host, 8080, Some(lan_client)), Some("loopback"), "{}", host);
+            assert_eq!(rule(host, 8080, None), Some("loopback"), "{}", host);
+            assert_eq!(rule(host, config.api_port, Some(local_client)), Some("api_port"), "{}", host);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allowlist_mode_blocks_tunnels() {
+        let mut config = Config::default();
+        config.mode = BrowsingMode::Allowlist;
+        config.allowed_domains = vec!["example.com".to_string()];
+        let state = ApiState::new(config.clone());
+        let router = Router::with_transport(config, Some(state.clone()), Arc::new(crate::transport::DirectTransport::new())).await.unwrap();
+
+        let err = router.connect_through_tor("tracker.example.net", 443, None).await.err().unwrap();
+        assert!(matches!(err, PrivacyError::Blocked(_)), "{}", err);
+        assert_eq!(err.status_code(), 403);
+        assert_eq!(state.stats_snapshot().await.requests_blocked, 1);
+        let logs = state.logs.read().await.clone();
+        assert!(logs.iter().any(|log| log.category == "security" && log.message.contains("tracker.example.net")));
+    }
+
+    #[test]
+    fn test_default_exit_follows_home_country_and_preference() {
+        let mut config = Config::default();
+        config.home_country = Some("GB".to_string());
+        assert_eq!(Router::choose_default_exit(&config).as_deref(), Some("de"));
+        
+        config.exit_preference = exit_selection::ExitPreference::Fastest;
+        assert_eq!(Router::choose_default_exit(&config).as_deref(), Some("uk"));
+    }
+
+    #[test]
+    fn test_protections_toggle_independently() {
+        let (protections, _) = protections(|config| config.webrtc_protection = false);
+
+        assert_eq!(protections.check("stun.l.google.com", 3478), None);
+        assert_eq!(protections.check("[2001:db8::1]", 443), Some(BlockedBy::Ipv6));
+        assert_eq!(protections.check("doubleclick.net", 443), Some(BlockedBy::Tracker));
+    }
+
+    #[test]
+    fn test_ipv6_over_tor_never_covers_direct_connections() {
+        let (protections, _) = protections(|config| {
+            config.allow_ipv6_over_tor = true;
+            config.private_hosts = PrivateHostPolicy::Bypass;
+        });
+        assert_eq!(protections.check("[2001:db8::1]", 443), None);
+        // Served directly, so the connection would leave from this machine
+        assert_eq!(protections.check("[fd12:3456::1]", 8080), Some(BlockedBy::Ipv6));
+        assert_eq!(protections.check("[::1]", 8080), None);
+
+        // Asking an exit for a private host isn't a connection from here
+        let (protections, _) = protections(|config| config.allow_ipv6_over_tor = true);
+        assert_eq!(protections.check("[fd12:3456::1]", 443), None);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_detector_stays_quiet() {
+        let mut config = Config::default();
+        let state = ApiState::new(config.clone());
+        
+        config.security_detectors.insert("tracking_pattern".to_string(), false);
+        detect_security_risks(&config, &state, "example.com", "/track", "GET").await;
+        assert!(state.logs.read().await.is_empty());
+        
+        // Other detectors still run, and re-enabling brings tracking back
+        detect_security_risks(&config, &state, "example.com", "/login?password=x", "GET").await;
+        assert_eq!(state.logs.read().await.len(), 1);
+        config.security_detectors.insert("tracking_pattern".to_string(), true);
+        detect_security_risks(&config, &state, "example.com", "/track", "GET").await;
+        assert_eq!(state.logs.read().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_sensitive_request_escalates() {
+        let config = Config::default();
+        let state = ApiState::new(config.clone());
+        let replays = |logs: &[crate::web_api::LogEntry]| logs.iter().filter(|log| log.message.starts_with("🔁")).count();
+        
+        detect_security_risks(&config, &state, "api.example.com", "/me?token=abc", "GET").await;
+        assert_eq!(replays(&state.logs.read().await), 0);
+        detect_security_risks(&config, &state, "api.example.com", "/me?token=abc", "GET").await;
+        assert_eq!(replays(&state.logs.read().await), 1);
+        
+        // A different token is a different request
+        detect_security_risks(&config, &state, "api.example.com", "/me?token=def", "GET").await;
+        assert_eq!(replays(&state.logs.read().await), 1);
+    }
+
+    #[test]
+    fn test_socks_transport_needs_socks5_url() {
+        let config = Config::default();
+        assert!(Router::socks_transport("socks5://127.0.0.1:9050", &config).is_ok());
+        assert!(matches!(
+            Router::socks_transport("http://127.0.0.1:8118", &config),
+            Err(PrivacyError::Config(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_strict_exit_refuses_fallback() {
+        let no_exit = || async { Err(PrivacyError::ExitUnavailable { country: "ch".to_string(), reason: "no exit relay in country".to_string() }) };
+        
+        let state = ApiState::new(Config::default());
+        state.update_stats(|s| s.tor_connected = true).await;
+        let err = check_exit("ch", true, Some(&state), no_exit()).await.unwrap_err();
+        assert!(matches!(err, PrivacyError::ExitUnavailable { ref country, .. } if country == "ch"));
+        assert_eq!(err.log_category(), "network");
+        assert!(!state.stats.read().await.tor_connected);
+        let logs = state.logs.read().await.clone();
+        assert_eq!((logs[0].level.as_str(), logs[0].category.as_str()), ("error", "network"));
+        
+        // Without strict mode the restriction is dropped, with a warning
+        let state = ApiState::new(Config::default());
+        assert!(!check_exit("ch", false, Some(&state), no_exit()).await.unwrap());
+        assert_eq!(state.logs.read().await[0].level, "warn");
+        assert!(check_exit("ch", true, Some(&state), async { Ok(()) }).await.unwrap());
+        
+        // A probe that failed for some other reason says nothing about the country
+        let state = ApiState::new(Config::default());
+        let timed_out = async { Err(PrivacyError::TorConnect("circuit timed out".to_string())) };
+        assert!(check_exit("ch", false, Some(&state), timed_out).await.unwrap());
+        assert!(state.logs.read().await.is_empty());
+        
+        // An external SOCKS5 proxy can't honor the choice at all
+        let mut config = Config::default();
+        config.strict_exit = true;
+        config.socks_transport = Some("socks5://127.0.0.1:9050".to_string());
+        let state = ApiState::new(config.clone());
+        state.request_exit_country(Some("ch".to_string())).await;
+        assert!(matches!(Router::new(config, Some(state)).await, Err(PrivacyError::ExitUnavailable { .. })));
+    }
+
+    #[test]
+    fn test_onion_location_header() {
+        let response = crate::tor_network::parse_http_response(
+            b"HTTP/1.1 200 OK\r\nOnion-Location: http://exampleonionaddress.onion/news?id=1\r\n\r\n",
+        ).unwrap();
+        let onion = onion_location(response.headers()).unwrap();
+        assert_eq!(onion.host(), Some("exampleonionaddress.onion"));
+        assert_eq!(onion.path_and_query().unwrap().as_str(), "/news?id=1");
+        
+        let location = |value: &str| {
+            let mut headers = hyper::HeaderMap::new();
+            headers.insert("onion-location", value.parse().unwrap());
+            onion_location(&headers)
+        };
+        assert!(location("http://example.com/").is_none());
+        assert!(location("https://exampleonionaddress.onion/").is_none());
+        assert!(location("not a url").is_none());
+    }
+
+    /// Transport that builds a circuit (slowly) for a stream when none is
+    /// open yet, like arti, and counts the builds (as rebuilds too)
+    #[derive(Default)]
+    struct CircuitTransport {
+        circuit_open: std::sync::atomic::AtomicBool,
+        builds: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for CircuitTransport {
+        async fn connect_stream(&self, _host: &str, _port: u16) -> Result<crate::transport::OpenedStream> {
+            use std::sync::atomic::Ordering;
+            
+            if !self.circuit_open.load(Ordering::SeqCst) {
+                self.builds.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                self.circuit_open.store(true, Ordering::SeqCst);
+            }
+            let (stream, _) = tokio::io::duplex(64);
+            Ok(crate::transport::OpenedStream { stream: Box::new(stream), circuit: None })
+        }
+
+        async fn route_request(
+            &self,
+            _req: Request<hyper::body::Incoming>,
+            _fingerprint: &BrowserFingerprint,
+            _connect_host: &str,
+        ) -> Result<Response<Full<Bytes>>> {
+            unreachable!("only tunnels are opened")
+        }
+
+        async fn fetch(
+            &self,
+            _method: &Method,
+            _uri: &hyper::Uri,
+            _fingerprint: &BrowserFingerprint,
+            _connect_host: &str,
+        ) -> Result<Response<Full<Bytes>>> {
+            unreachable!("only tunnels are opened")
+        }
+
+        fn circuit_rebuilds(&self) -> u64 {
+            self.builds.load(std::sync::atomic::Ordering::SeqCst) as u64
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_tunnels_to_one_host_share_a_circuit() {
+        const TUNNELS: usize = 6;
+        
+        for coalesce_connects in [false, true] {
+            let mut config = Config::default();
+            config.coalesce_connects = coalesce_connects;
+            let transport = Arc::new(CircuitTransport::default());
+            let state = ApiState::new(config.clone());
+            let router = Router::with_transport(config, Some(state.clone()), transport.clone()).await.unwrap();
+            
+            let tunnels = (0..TUNNELS).map(|_| router.connect_through_tor("static.cdn.example", 443, None));
+            for opened in futures::future::join_all(tunnels).await {
+                assert!(opened.is_ok());
+            }
+            let builds = transport.builds.load(std::sync::atomic::Ordering::SeqCst);
+            if coalesce_connects {
+                assert_eq!(builds, 1);
+            } else {
+                assert_eq!(builds, TUNNELS, "every tunnel builds its own circuit without coalescing");
+            }
+            // Each build is counted once, not once per tunnel in flight when it happened
+            assert_eq!(state.counters.circuit_rebuilds.get(), builds as u64);
+        }
+    }
+
+    /// Transport that answers every fetch to a `.onion` host with "onion",
+    /// or refuses them all when `onion_up` is false
+    struct OnionTransport {
+        onion_up: bool,
+        fetched: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for OnionTransport {
+        async fn connect_stream(&self, host: &str, _port: u16) -> Result<crate::transport::OpenedStream> {
+            Err(PrivacyError::TorConnect(format!("no streams to {} in this test", host)))
+        }
+
+        async fn route_request(
+            &self,
+            _req: Request<hyper::body::Incoming>,
+            _fingerprint: &BrowserFingerprint,
+            _connect_host: &str,
+        ) -> Result<Response<Full<Bytes>>> {
+            unreachable!("only fetch is used")
+        }
+
+        async fn fetch(
+            &self,
+            method: &Method,
+            uri: &hyper::Uri,
+            _fingerprint: &BrowserFingerprint,
+            connect_host: &str,
+        ) -> Result<Response<Full<Bytes>>> {
+            self.fetched.lock().unwrap().push(format!("{} {} via {}", method, uri, connect_host));
+            if !self.onion_up {
+                return Err(PrivacyError::TorConnect("onion service unreachable".to_string()));
+            }
+            Ok(Response::new(Full::new(Bytes::from("onion"))))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_onion_location_is_followed() {
+        use http_body_util::BodyExt;
+        
+        let advertised = || {
+            Response::builder()
+                .header("Onion-Location", "http://exampleonionaddress.onion/page")
+                .body(Full::new(Bytes::from("clearnet")))
+                .unwrap()
+        };
+        let uri: hyper::Uri = "http://example.com/page".parse().unwrap();
+        let mut config = Config::default();
+        config.prefer_onion = true;
+        
+        for onion_up in [true, false] {
+            let state = ApiState::new(config.clone());
+            let transport = Arc::new(OnionTransport { onion_up, fetched: Default::default() });
+            let router = Router::with_transport(config.clone(), Some(state.clone()), transport.clone()).await.unwrap();
+            
+            let response = router.follow_onion_location(&Method::GET, &uri, advertised()).await;
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, if onion_up { "onion" } else { "clearnet" });
+            assert_eq!(
+                *transport.fetched.lock().unwrap(),
+                ["GET http://exampleonionaddress.onion/page via exampleonionaddress.onion"]
+            );
+            let logs = state.logs.read().await.clone();
+            assert!(logs.iter().any(|log| log.message.contains("example.com") && log.category == "network"));
+            
+            // Requests with side effects are never repeated elsewhere
+            router.follow_onion_location(&Method::POST, &uri, advertised()).await;
+            assert_eq!(transport.fetched.lock().unwrap().len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_page_for_browsers_plain_text_for_others() {
+        use http_body_util::BodyExt;
+        
+        let router = Router::with_transport(Config::default(), None, Arc::new(crate::transport::DirectTransport::new())).await.unwrap();
+        let detail = BlockDetail { host: "ads.<evil>.example", rule: "||ads.example^", message: "Tracker blocked by Privacy Suite" };
+        let body = |response: Response<Full<Bytes>>| async move {
+            String::from_utf8(response.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap()
+        };
+        
+        let browser = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+        let page = router.block_response("Tracker", detail, Some(browser));
+        assert_eq!(page.status(), 403);
+        assert_eq!(page.headers()[hyper::header::CONTENT_TYPE], "text/html; charset=utf-8");
+        let html = body(page).await;
+        assert!(html.contains("Tracker") && html.contains("||ads.example^"), "{}", html);
+        assert!(html.contains("ads.&lt;evil&gt;.example"), "host not escaped: {}", html);
+        assert!(html.contains(r#"action="http://127.0.0.1:3030/api/allowlist/temp?domain=ads.%3Cevil%3E.example&amp;scope=once""#), "{}", html);
+        assert!(html.contains("scope=session"));
+        
+        for accept in [None, Some("*/*"), Some("application/json"), Some("text/html;q=0, text/plain")] {
+            let plain = router.block_response("Tracker", detail, accept);
+            assert_eq!(plain.status(), 403);
+            assert_eq!(plain.headers()[hyper::header::CONTENT_TYPE], "text/plain; charset=utf-8");
+            assert_eq!(body(plain).await, "Tracker blocked by Privacy Suite", "{:?}", accept);
+        }
+    }
+    
+    async fn parse_response(raw: &[u8]) -> (hyper::HeaderMap, Bytes) {
+        use http_body_util::BodyExt;
+        
+        let response = crate::tor_network::parse_http_response(raw).unwrap();
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (parts.headers, body)
+    }
+
+    #[tokio::test]
+    async fn test_login_form_over_http_is_flagged() {
+        let page = b"HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\r\n\
+            <form action=\"/login\" method=\"post\"><input name=\"user\">\
+            <INPUT Type = 'Password' name=\"pass\"></form>";
+        let (headers, body) = parse_response(page).await;
+        assert_eq!(response_risks(&headers, &body), vec![LOGIN_FORM_OVER_HTTP]);
+        
+        // A plain page, or a password field mentioned outside an input tag, is fine
+        let (headers, body) = parse_response(b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<p>Forgot your type=password?</p><input type=\"text\">").await;
+        assert!(response_risks(&headers, &body).is_empty());
+        
+        // Only HTML bodies are inspected
+        let (headers, body) = parse_response(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\n<input type=password>").await;
+        assert!(response_risks(&headers, &body).is_empty());
+        
+        // Only the start of a page is searched
+        let mut late = vec![b' '; PASSWORD_SCAN_LIMIT];
+        late.extend_from_slice(b"<input type=password>");
+        assert!(!has_password_input(&late));
+        assert!(has_password_input(&late[PASSWORD_SCAN_LIMIT - 10..]));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_login_form_is_flagged() {
+        use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+        
+        let form = b"<form method=\"post\"><input name=\"user\"><input type=\"password\" name=\"pass\"></form>";
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(form).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let raw = [b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Encoding: gzip\r\n\r\n".as_slice(), &gzip].concat();
+        let (headers, body) = parse_response(&raw).await;
+        
+        // Flagged from a decoded copy, while the body stays as the server sent it
+        assert_eq!(response_risks(&headers, &body), vec![LOGIN_FORM_OVER_HTTP]);
+        assert_eq!(body.as_ref(), gzip.as_slice());
+        
+        // Both forms of deflate servers send
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(form).unwrap();
+        let mut raw_deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw_deflate.write_all(form).unwrap();
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert(hyper::header::CONTENT_TYPE, "text/html".parse().unwrap());
+        headers.insert(hyper::header::CONTENT_ENCODING, "deflate".parse().unwrap());
+        for body in [zlib.finish().unwrap(), raw_deflate.finish().unwrap()] {
+            assert_eq!(response_risks(&headers, &body), vec![LOGIN_FORM_OVER_HTTP]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_brotli_body_is_forwarded_untouched() {
+        // Not valid UTF-8, and it happens to contain a password input once "decoded" as text
+        let compressed: &[u8] = b"\x1b\xff\x00<input type=password>\x80\x03";
+        let raw = [b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Encoding: br\r\n\r\n".as_slice(), compressed].concat();
+        let (headers, body) = parse_response(&raw).await;
+        
+        assert_eq!(headers[hyper::header::CONTENT_ENCODING], "br");
+        assert_eq!(body.as_ref(), compressed);
+        assert!(response_risks(&headers, &body).is_empty());
+        
+        assert!(scannable_body(&headers, &body).is_none());
+        
+        let (headers, _) = parse_response(b"HTTP/1.1 200 OK\r\nContent-Encoding: identity\r\n\r\n").await;
+        assert!(content_encodings(&headers).is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_downgrade_signs_are_flagged() {
+        let (headers, body) = parse_response(
+            b"HTTP/1.1 200 OK\r\nStrict-Transport-Security: max-age=31536000\r\nSet-Cookie: sid=1; Path=/; Secure; HttpOnly\r\nContent-Length: 0\r\n\r\n",
+        ).await;
+        assert_eq!(response_risks(&headers, &body), vec![HSTS_OVER_HTTP, SECURE_COOKIE_OVER_HTTP]);
         
-        Ok(Response::new(Full::new(Bytes::from("Privacy Suite - Request Routed"))))
+        // "secure" in a cookie value is not the Secure attribute
+        let (headers, body) = parse_response(b"HTTP/1.1 200 OK\r\nSet-Cookie: mode=secure; Path=/\r\nContent-Length: 0\r\n\r\n").await;
+        assert!(response_risks(&headers, &body).is_empty());
     }
 }
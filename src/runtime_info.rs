@@ -0,0 +1,135 @@
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Addresses the running backend actually bound, kept next to the config file
+pub const RUNTIME_FILE: &str = "runtime.json";
+
+/// Serializes read-modify-write updates from the API and proxy tasks
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Where the proxy and web API are really listening, so the GUIs don't have
+/// to assume the default ports
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeInfo {
+    #[serde(default)]
+    pub proxy_addr: Option<SocketAddr>,
+    #[serde(default)]
+    pub api_addr: Option<SocketAddr>,
+    /// The web API serves HTTPS (`Config::api_tls`), with its certificate
+    /// in the same directory as this file
+    #[serde(default)]
+    pub api_tls: bool,
+}
+
+impl RuntimeInfo {
+    /// Read the runtime file in `dir`, if the backend has written one
+    pub fn load(dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(dir.join(RUNTIME_FILE)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Read the runtime file from the default `~/.privacy_suite` directory
+    pub fn load_default() -> Option<Self> {
+        Self::load(&dirs::home_dir()?.join(".privacy_suite"))
+    }
+
+    /// Base URL of the web API, falling back to the default port. Over
+    /// HTTPS the host is `localhost`, a name the certificate is issued for.
+    pub fn api_base(&self) -> String {
+        let port = self.api_addr.map_or(crate::config::DEFAULT_API_PORT, |addr| addr.port());
+        if self.api_tls {
+            format!("https://localhost:{}", port)
+        } else {
+            format!("http://127.0.0.1:{}", port)
+        }
+    }
+}
+
+/// Directory the runtime file for `config` lives in, or None for a config
+/// that was never loaded from disk
+fn runtime_dir(config: &Config) -> Option<PathBuf> {
+    config
+        .config_path()
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+}
+
+/// Record the address the proxy bound
+pub fn record_proxy(config: &Config, addr: SocketAddr) -> io::Result<()> {
+    update(config, |info| info.proxy_addr = Some(addr))
+}
+
+/// Record the address the web API bound, and whether it serves HTTPS
+pub fn record_api(config: &Config, addr: SocketAddr) -> io::Result<()> {
+    update(config, |info| {
+        info.api_addr = Some(addr);
+        info.api_tls = config.api_tls;
+    })
+}
+
+/// Delete the runtime file on shutdown, so no GUI goes looking for a
+/// backend at addresses nothing listens on any more
+pub fn remove(config: &Config) -> io::Result<()> {
+    let Some(dir) = runtime_dir(config) else {
+        return Ok(());
+    };
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    match fs::remove_file(dir.join(RUNTIME_FILE)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn update(config: &Config, change: impl FnOnce(&mut RuntimeInfo)) -> io::Result<()> {
+    let Some(dir) = runtime_dir(config) else {
+        return Ok(());
+    };
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut info = RuntimeInfo::load(&dir).unwrap_or_default();
+    change(&mut info);
+    let json = serde_json::to_string_pretty(&info).map_err(io::Error::other)?;
+
+    // Write then rename so a GUI polling the file never sees it half-written
+    fs::create_dir_all(&dir)?;
+    let tmp = dir.join(format!("{}.tmp", RUNTIME_FILE));
+    fs::write(&tmp, json)?;
+    fs::rename(&tmp, dir.join(RUNTIME_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_merge_into_one_file() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_runtime_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = Config::load_or_create_at(&dir.join("config.toml")).unwrap();
+
+        let proxy: SocketAddr = "0.0.0.0:8889".parse().unwrap();
+        let api: SocketAddr = "127.0.0.1:3031".parse().unwrap();
+        record_api(&config, api).unwrap();
+        record_proxy(&config, proxy).unwrap();
+
+        let info = RuntimeInfo::load(&dir).unwrap();
+        assert_eq!(info, RuntimeInfo { proxy_addr: Some(proxy), api_addr: Some(api), api_tls: false });
+        assert_eq!(info.api_base(), "http://127.0.0.1:3031");
+        assert_eq!(RuntimeInfo { api_tls: true, ..info }.api_base(), "https://localhost:3031");
+
+        // Configs that don't live on disk have nowhere to record to
+        record_api(&Config::default(), api).unwrap();
+
+        remove(&config).unwrap();
+        assert_eq!(RuntimeInfo::load(&dir), None);
+        remove(&config).unwrap();
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::config::DnsResolution;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// The Tor Project's checker: reports the address a request came from and
+/// whether that address is a Tor exit
+pub const TOR_CHECK_URL: &str = "https://check.torproject.org/api/ip";
+
+/// How long the request to `TOR_CHECK_URL` may take, circuit setup included
+pub const TOR_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// STUN server a WebRTC leak would go to
+pub const WEBRTC_TEST_TARGET: (&str, u16) = ("stun.l.google.com", 3478);
+
+/// IPv6 destination (a documentation address) an IPv6 leak would go to
+pub const IPV6_TEST_TARGET: (&str, u16) = ("[2001:db8::1]", 443);
+
+/// Answer from `TOR_CHECK_URL`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TorCheck {
+    #[serde(rename = "IsTor")]
+    pub is_tor: bool,
+    #[serde(rename = "IP")]
+    pub ip: IpAddr,
+}
+
+/// One line of the `/api/test` report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelfTestCheck {
+    /// Stable identifier: `tor`, `exit_ip`, `dns`, `webrtc` or `ipv6`
+    pub id: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl SelfTestCheck {
+    fn new(id: &str, passed: bool, detail: impl Into<String>) -> Self {
+        Self { id: id.to_string(), passed, detail: detail.into() }
+    }
+}
+
+/// Result of the "Am I protected?" self-test
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelfTestReport {
+    /// True when every check passed
+    pub passed: bool,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+/// What the self-test observed of the running suite
+#[derive(Debug, Clone)]
+pub struct SelfTestInputs {
+    pub tor_bootstrapped: bool,
+    /// Outcome of requesting `TOR_CHECK_URL` through the local proxy
+    pub tor_check: Result<TorCheck, String>,
+    /// Addresses of this machine. Only local interfaces are listed: asking a
+    /// service for the public address would itself bypass the protection.
+    pub local_ips: Vec<IpAddr>,
+    pub dns_resolution: DnsResolution,
+    /// Rule that blocks `WEBRTC_TEST_TARGET`, if any
+    pub webrtc_block: Option<String>,
+    /// Rule that blocks `IPV6_TEST_TARGET`, if any
+    pub ipv6_block: Option<String>,
+    /// IPv6 destinations are reached by the Tor exit, so they can't leak
+    pub allow_ipv6_over_tor: bool,
+}
+
+/// Turn what the self-test observed into its report
+pub fn evaluate(inputs: &SelfTestInputs) -> SelfTestReport {
+    let exit_ip = match &inputs.tor_check {
+        Ok(check) if !check.is_tor => {
+            SelfTestCheck::new("exit_ip", false, format!("Traffic leaves from {}, which is not a Tor exit", check.ip))
+        }
+        Ok(check) if inputs.local_ips.contains(&check.ip) => {
+            SelfTestCheck::new("exit_ip", false, format!("Traffic leaves from this machine's own address {}", check.ip))
+        }
+        Ok(check) => SelfTestCheck::new("exit_ip", true, format!("Traffic leaves from Tor exit {}", check.ip)),
+        Err(e) => SelfTestCheck::new("exit_ip", false, format!("Could not reach the Tor checker: {}", e)),
+    };
+
+    // The checker's hostname was looked up the same way every proxied hostname is
+    let dns = match (&inputs.tor_check, inputs.dns_resolution) {
+        (Err(_), _) => SelfTestCheck::new("dns", false, "Not tested - no request made it through the proxy"),
+        (Ok(_), DnsResolution::ExitDns) => SelfTestCheck::new("dns", true, "Hostnames are resolved by the Tor exit"),
+        (Ok(_), DnsResolution::LocalDoH) => SelfTestCheck::new("dns", true, "Hostnames are resolved with DNS-over-HTTPS through Tor"),
+    };
+
+    let webrtc = match &inputs.webrtc_block {
+        Some(rule) => SelfTestCheck::new("webrtc", true, format!("STUN requests are blocked ({})", rule)),
+        None => SelfTestCheck::new("webrtc", false, "STUN requests would go through - WebRTC can reveal the real IP"),
+    };
+
+    let ipv6 = match &inputs.ipv6_block {
+        Some(rule) => SelfTestCheck::new("ipv6", true, format!("IPv6 requests are blocked ({})", rule)),
+        None if inputs.allow_ipv6_over_tor => SelfTestCheck::new("ipv6", true, "IPv6 destinations are reached through the Tor exit"),
+        None => SelfTestCheck::new("ipv6", false, "IPv6 requests would go through unprotected"),
+    };
+
+    let checks = vec![
+        SelfTestCheck::new(
+            "tor",
+            inputs.tor_bootstrapped,
+            if inputs.tor_bootstrapped { "Bootstrapped" } else { "Not connected" },
+        ),
+        exit_ip,
+        dns,
+        webrtc,
+        ipv6,
+    ];
+    SelfTestReport { passed: checks.iter().all(|check| check.passed), checks }
+}
+
+/// Ask `TOR_CHECK_URL` where requests sent through the proxy at `proxy_port` come from
+pub async fn check_through_proxy(proxy_port: u16) -> Result<TorCheck, String> {
+    let proxy = reqwest::Proxy::all(format!("http://127.0.0.1:{}", proxy_port))
+        .map_err(|e| format!("Invalid proxy address: {}", e))?;
+    let client = reqwest::Client::builder()
+        .proxy(proxy)
+        .timeout(TOR_CHECK_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    client
+        .get(TOR_CHECK_URL)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Request failed: {}", e))?
+        .json::<TorCheck>()
+        .await
+        .map_err(|e| format!("Unexpected answer: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs() -> SelfTestInputs {
+        SelfTestInputs {
+            tor_bootstrapped: true,
+            tor_check: Ok(TorCheck { is_tor: true, ip: "198.51.100.7".parse().unwrap() }),
+            local_ips: vec!["192.168.1.20".parse().unwrap()],
+            dns_resolution: DnsResolution::ExitDns,
+            webrtc_block: Some("webrtc_protection".to_string()),
+            ipv6_block: Some("ipv6_protection".to_string()),
+            allow_ipv6_over_tor: false,
+        }
+    }
+
+    fn failed(report: &SelfTestReport) -> Vec<&str> {
+        report.checks.iter().filter(|check| !check.passed).map(|check| check.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_checker_answer_parsed() {
+        let check: TorCheck = serde_json::from_str(r#"{"IsTor":true,"IP":"198.51.100.7"}"#).unwrap();
+        assert_eq!(check, TorCheck { is_tor: true, ip: "198.51.100.7".parse().unwrap() });
+    }
+
+    #[test]
+    fn test_exit_must_be_tor_and_not_local() {
+        let mut leaking = inputs();
+        leaking.tor_check = Ok(TorCheck { is_tor: true, ip: "192.168.1.20".parse().unwrap() });
+        assert_eq!(failed(&evaluate(&leaking)), ["exit_ip"]);
+
+        leaking.tor_check = Ok(TorCheck { is_tor: false, ip: "203.0.113.9".parse().unwrap() });
+        let report = evaluate(&leaking);
+        assert_eq!(failed(&report), ["exit_ip"]);
+        assert!(report.checks[1].detail.contains("not a Tor exit"));
+
+        // Without an answer, DNS can't be vouched for either
+        leaking.tor_check = Err("timed out".to_string());
+        assert_eq!(failed(&evaluate(&leaking)), ["exit_ip", "dns"]);
+    }
+
+    #[test]
+    fn test_ipv6_over_tor_is_not_a_leak() {
+        let mut over_tor = inputs();
+        over_tor.ipv6_block = None;
+        assert_eq!(failed(&evaluate(&over_tor)), ["ipv6"]);
+        over_tor.allow_ipv6_over_tor = true;
+        assert!(evaluate(&over_tor).passed);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::config::Config;
+use crate::web_api::Stats;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Past sessions, kept next to the config file
+pub const HISTORY_FILE: &str = "history.json";
+
+/// Sessions remembered; the oldest are dropped beyond this
+pub const MAX_SESSIONS: usize = 200;
+
+/// What one connected session did, recorded when it ends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    /// RFC 3339 local time, e.g. `2026-10-14T09:30:00+02:00`
+    pub started_at: String,
+    pub ended_at: String,
+    pub duration_secs: u64,
+    pub total_requests: u64,
+    pub requests_blocked: u64,
+    pub trackers_blocked: u64,
+    pub webrtc_blocked: u64,
+    pub ipv6_blocked: u64,
+    pub bytes_transferred: u64,
+    #[serde(default)]
+    pub exit_country: Option<String>,
+}
+
+impl SessionSummary {
+    /// Summarize a session that ran for `duration_secs` and is ending now
+    pub fn ending_now(duration_secs: u64, stats: &Stats) -> Self {
+        let ended_at = chrono::Local::now();
+        let started_at = ended_at - chrono::Duration::seconds(duration_secs.min(i64::MAX as u64) as i64);
+        Self {
+            started_at: started_at.to_rfc3339(),
+            ended_at: ended_at.to_rfc3339(),
+            duration_secs,
+            total_requests: stats.total_requests,
+            requests_blocked: stats.requests_blocked,
+            trackers_blocked: stats.trackers_blocked,
+            webrtc_blocked: stats.webrtc_blocked,
+            ipv6_blocked: stats.ipv6_blocked,
+            bytes_transferred: stats.bytes_transferred,
+            exit_country: stats.exit_country.clone(),
+        }
+    }
+}
+
+/// The last `max_sessions` session summaries, oldest first, saved to
+/// `HISTORY_FILE` on every change when the config lives on disk
+#[derive(Clone)]
+pub struct SessionHistory {
+    sessions: Arc<Mutex<Vec<SessionSummary>>>,
+    path: Option<PathBuf>,
+    max_sessions: usize,
+}
+
+impl SessionHistory {
+    /// History kept only in memory
+    pub fn in_memory(max_sessions: usize) -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(Vec::new())),
+            path: None,
+            max_sessions: max_sessions.max(1),
+        }
+    }
+
+    /// History stored in `dir`, starting from whatever was saved there
+    pub fn load(dir: &Path, max_sessions: usize) -> Self {
+        let path = dir.join(HISTORY_FILE);
+        let mut sessions: Vec<SessionSummary> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        let max_sessions = max_sessions.max(1);
+        let excess = sessions.len().saturating_sub(max_sessions);
+        sessions.drain(..excess);
+
+        Self {
+            sessions: Arc::new(Mutex::new(sessions)),
+            path: Some(path),
+            max_sessions,
+        }
+    }
+
+    /// History next to `config`'s file, or in memory for a config that was
+    /// never loaded from disk
+    pub fn for_config(config: &Config) -> Self {
+        match config.config_path().parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => Self::load(dir, MAX_SESSIONS),
+            None => Self::in_memory(MAX_SESSIONS),
+        }
+    }
+
+    /// Add a finished session, dropping the oldest beyond the cap
+    pub fn record(&self, session: SessionSummary) -> io::Result<()> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.push(session);
+        let excess = sessions.len().saturating_sub(self.max_sessions);
+        sessions.drain(..excess);
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(&*sessions).map_err(io::Error::other)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write then rename so a crash never leaves a truncated history
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Recorded sessions, most recent first
+    pub fn recent(&self) -> Vec<SessionSummary> {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(total_requests: u64) -> SessionSummary {
+        let stats = Stats {
+            total_requests,
+            trackers_blocked: 3,
+            requests_blocked: 4,
+            bytes_transferred: 1_024,
+            exit_country: Some("de".to_string()),
+            ..Stats::default()
+        };
+        SessionSummary::ending_now(90, &stats)
+    }
+
+    #[test]
+    fn test_sessions_survive_reload() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_history_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let history = SessionHistory::load(&dir, 10);
+        history.record(session(1)).unwrap();
+        history.record(session(2)).unwrap();
+
+        let reloaded = SessionHistory::load(&dir, 10).recent();
+        assert_eq!(reloaded, history.recent());
+        let totals: Vec<u64> = reloaded.iter().map(|s| s.total_requests).collect();
+        assert_eq!(totals, [2, 1]);
+        assert_eq!(reloaded[0].exit_country.as_deref(), Some("de"));
+        assert_eq!((reloaded[0].duration_secs, reloaded[0].trackers_blocked, reloaded[0].bytes_transferred), (90, 3, 1_024));
+        assert!(reloaded[0].started_at < reloaded[0].ended_at);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_history_is_capped() {
+        let history = SessionHistory::in_memory(3);
+        for total_requests in 1..=5 {
+            history.record(session(total_requests)).unwrap();
+        }
+        let totals: Vec<u64> = history.recent().iter().map(|s| s.total_requests).collect();
+        assert_eq!(totals, [5, 4, 3]);
+    }
+}
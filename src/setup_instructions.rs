@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+
+/// Where a user is pointing at the proxy, as named by `/api/setup-instructions?os=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SetupPlatform {
+    Windows,
+    Macos,
+    Linux,
+    /// Firefox keeps its own proxy settings on every OS
+    Firefox,
+    Android,
+}
+
+impl SetupPlatform {
+    /// The OS this build runs on
+    pub fn current() -> Self {
+        if cfg!(target_os = "windows") {
+            SetupPlatform::Windows
+        } else if cfg!(target_os = "macos") {
+            SetupPlatform::Macos
+        } else {
+            SetupPlatform::Linux
+        }
+    }
+}
+
+/// Manual proxy setup for one platform, as returned by `/api/setup-instructions`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetupInstructions {
+    pub platform: SetupPlatform,
+    pub title: String,
+    pub steps: Vec<String>,
+    /// `ip:port` to enter on this machine
+    pub proxy: String,
+    /// `ip:port` other devices on the LAN enter, if they can reach the proxy
+    pub lan_proxy: Option<String>,
+    /// The steps numbered, one per line, for the GUI's Copy button
+    pub text: String,
+}
+
+/// Steps for pointing `platform` at the proxy on `proxy_port`. `lan_ip` is
+/// this machine's LAN address, or None when the proxy only listens locally.
+pub fn instructions(platform: SetupPlatform, proxy_port: u16, lan_ip: Option<IpAddr>) -> SetupInstructions {
+    let port = proxy_port.to_string();
+    let lan_proxy = lan_ip.map(|ip| SocketAddr::new(ip, proxy_port).to_string());
+    let other_devices = match &lan_proxy {
+        Some(lan_proxy) => format!("Other devices on your network use {} instead of 127.0.0.1:{}", lan_proxy, port),
+        None => "The proxy only accepts connections from this computer; set proxy_addr to 0.0.0.0 to share it with your network".to_string(),
+    };
+
+    let (title, mut steps) = match platform {
+        SetupPlatform::Windows => (
+            "Windows",
+            vec![
+                "Open Settings > Network & Internet > Proxy".to_string(),
+                "Under \"Manual proxy setup\", click Set up (or turn on \"Use a proxy server\")".to_string(),
+                format!("Enter 127.0.0.1 as the proxy IP address and {} as the port", port),
+                "Tick \"Don't use the proxy server for local (intranet) addresses\" and click Save".to_string(),
+            ],
+        ),
+        SetupPlatform::Macos => (
+            "macOS",
+            vec![
+                "Open System Settings > Network, select your connection and click Details".to_string(),
+                "Choose Proxies".to_string(),
+                format!("Turn on \"Web proxy (HTTP)\" and enter server 127.0.0.1, port {}", port),
+                format!("Turn on \"Secure web proxy (HTTPS)\" and enter server 127.0.0.1, port {}", port),
+                "Click OK".to_string(),
+            ],
+        ),
+        SetupPlatform::Linux => (
+            "Linux",
+            vec![
+                "GNOME: open Settings > Network > Network Proxy and choose Manual (KDE: System Settings > Network > Proxy)".to_string(),
+                format!("Set both the HTTP and HTTPS proxy to 127.0.0.1, port {}", port),
+                format!(
+                    "For terminal programs, run: export http_proxy=http://127.0.0.1:{0} https_proxy=http://127.0.0.1:{0}",
+                    port
+                ),
+            ],
+        ),
+        SetupPlatform::Firefox => (
+            "Firefox",
+            vec![
+                "Open Settings > General, scroll to Network Settings and click Settings...".to_string(),
+                "Choose \"Manual proxy configuration\"".to_string(),
+                format!("Enter 127.0.0.1 as the HTTP Proxy and {} as the port", port),
+                "Tick \"Also use this proxy for HTTPS\" and click OK".to_string(),
+            ],
+        ),
+        SetupPlatform::Android => {
+            let host = lan_ip.map_or_else(|| "this computer's LAN address".to_string(), |ip| ip.to_string());
+            (
+                "Android",
+                vec![
+                    "Connect the phone to the same Wi-Fi network as this computer".to_string(),
+                    "Open Settings > Network & internet > Internet and tap the gear next to your Wi-Fi network".to_string(),
+                    "Tap Edit (the pencil), then Advanced options".to_string(),
+                    format!("Set Proxy to Manual, the hostname to {} and the port to {}, then tap Save", host, port),
+                ],
+            )
+        }
+    };
+
+    let proxy = format!("127.0.0.1:{}", port);
+    match platform {
+        // The phone is always another device
+        SetupPlatform::Android if lan_proxy.is_none() => steps.insert(0, other_devices),
+        SetupPlatform::Android => {}
+        _ => steps.push(other_devices),
+    }
+    let text = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| format!("{}. {}", i + 1, step))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    SetupInstructions { platform, title: title.to_string(), steps, proxy, lan_proxy, text }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_platform_has_its_own_steps() {
+        let lan_ip: IpAddr = "192.168.1.20".parse().unwrap();
+        let expected = [
+            (SetupPlatform::Windows, "Network & Internet > Proxy"),
+            (SetupPlatform::Macos, "System Settings > Network"),
+            (SetupPlatform::Linux, "export http_proxy=http://127.0.0.1:8888"),
+            (SetupPlatform::Firefox, "Manual proxy configuration"),
+            (SetupPlatform::Android, "hostname to 192.168.1.20 and the port to 8888"),
+        ];
+
+        for (platform, marker) in expected {
+            let setup = instructions(platform, 8888, Some(lan_ip));
+            assert!(!setup.steps.is_empty());
+            assert!(setup.text.contains(marker), "{:?}: {}", platform, setup.text);
+            assert!(setup.text.starts_with("1. "));
+            assert_eq!(setup.lan_proxy.as_deref(), Some("192.168.1.20:8888"));
+        }
+
+        // Without a reachable LAN address the phone can't be pointed anywhere yet
+        let android = instructions(SetupPlatform::Android, 8888, None);
+        assert!(android.steps[0].contains("proxy_addr"), "{}", android.text);
+        assert!(!instructions(SetupPlatform::Windows, 8888, Some(lan_ip)).text.contains("proxy_addr"));
+    }
+}
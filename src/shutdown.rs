@@ -0,0 +1,125 @@
+use crate::error::PrivacyError;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Why the process is exiting. Each reason has its own exit code so a
+/// supervising script or service manager can tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// The user quit from the GUI (`/api/shutdown`) or closed the window
+    UserRequest,
+    /// Ctrl+C, or SIGTERM on Unix
+    SignalReceived,
+    /// Something the suite can't run without failed
+    FatalError,
+    /// A port the suite needs is taken, usually by another instance
+    PortConflict,
+}
+
+impl ShutdownReason {
+    /// Process exit code: 0 for a requested exit, 1 for a fatal error (the
+    /// same as `main` returning an error), 3 for a port conflict, and the
+    /// conventional 130 for an interrupt
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ShutdownReason::UserRequest => 0,
+            ShutdownReason::FatalError => 1,
+            ShutdownReason::PortConflict => 3,
+            ShutdownReason::SignalReceived => 130,
+        }
+    }
+
+    /// The reason to exit with after `e` stopped a required service
+    pub fn from_error(e: &(dyn std::error::Error + 'static)) -> Self {
+        match e.downcast_ref::<PrivacyError>() {
+            Some(PrivacyError::PortInUse { .. }) => ShutdownReason::PortConflict,
+            _ => ShutdownReason::FatalError,
+        }
+    }
+
+    /// Log the reason and end the process with its exit code
+    pub fn exit(self) -> ! {
+        info!("Exiting: {} (code {})", self, self.exit_code());
+        std::process::exit(self.exit_code())
+    }
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ShutdownReason::UserRequest => "shutdown requested by the user",
+            ShutdownReason::SignalReceived => "termination signal received",
+            ShutdownReason::FatalError => "fatal error",
+            ShutdownReason::PortConflict => "port already in use",
+        })
+    }
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM on Unix
+pub async fn termination_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
+}
+
+/// Wait until the process should stop: `signal` resolving, or a background
+/// task reporting a fatal failure on `fatal`
+pub async fn wait_for_shutdown(
+    signal: impl Future<Output = io::Result<()>>,
+    fatal: &mut mpsc::UnboundedReceiver<ShutdownReason>,
+) -> ShutdownReason {
+    tokio::select! {
+        result = signal => match result {
+            Ok(()) => ShutdownReason::SignalReceived,
+            Err(e) => {
+                error!("Failed to listen for termination signals: {}", e);
+                ShutdownReason::FatalError
+            }
+        },
+        Some(reason) = fatal.recv() => reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_each_trigger_gets_its_reason_and_code() {
+        let (fatal_tx, mut fatal) = mpsc::unbounded_channel();
+
+        // Ctrl+C / SIGTERM
+        let reason = wait_for_shutdown(async { Ok(()) }, &mut fatal).await;
+        assert_eq!((reason, reason.exit_code()), (ShutdownReason::SignalReceived, 130));
+
+        // The web API couldn't bind its port
+        let port_in_use: Box<dyn std::error::Error + Send + Sync> = Box::new(PrivacyError::PortInUse { port: 3030 });
+        fatal_tx.send(ShutdownReason::from_error(&*port_in_use)).unwrap();
+        let reason = wait_for_shutdown(std::future::pending(), &mut fatal).await;
+        assert_eq!((reason, reason.exit_code()), (ShutdownReason::PortConflict, 3));
+
+        // Any other failure of a required service
+        let io_error: Box<dyn std::error::Error + Send + Sync> = Box::new(io::Error::other("disk full"));
+        fatal_tx.send(ShutdownReason::from_error(&*io_error)).unwrap();
+        let reason = wait_for_shutdown(std::future::pending(), &mut fatal).await;
+        assert_eq!((reason, reason.exit_code()), (ShutdownReason::FatalError, 1));
+
+        // Failing to install the signal handler is fatal too
+        let reason = wait_for_shutdown(async { Err(io::Error::other("no signals")) }, &mut fatal).await;
+        assert_eq!(reason, ShutdownReason::FatalError);
+
+        assert_eq!(ShutdownReason::UserRequest.exit_code(), 0);
+    }
+}
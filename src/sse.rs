@@ -0,0 +1,130 @@
+use futures::stream::{self, Stream};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+/// Frames a log stream holds for a client before it counts as too far behind
+pub const LOG_STREAM_BACKLOG: usize = 16;
+
+/// What a `queued` stream yields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame<T> {
+    Data(T),
+    /// The client fell too far behind and the stream ends here; it should
+    /// refetch the full state and reconnect
+    Resync,
+}
+
+/// Sample `produce` every `interval` for as long as the stream is alive.
+/// A client reading slower than that gets the latest value only: the
+/// frames in between are replaced, never queued.
+pub fn latest<T, F, Fut>(interval: Duration, mut produce: F) -> impl Stream<Item = T>
+where
+    T: Clone + Send + Sync + 'static,
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = T> + Send,
+{
+    let (tx, rx) = watch::channel(None);
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tx.closed() => return,
+                _ = tokio::time::sleep(interval) => {}
+            }
+            tx.send_replace(Some(produce().await));
+        }
+    });
+
+    stream::unfold(rx, |mut rx| async move {
+        rx.changed().await.ok()?;
+        let value = rx.borrow_and_update().clone()?;
+        Some((value, rx))
+    })
+}
+
+/// Sample `produce` every `interval`, queueing up to `backlog` frames for a
+/// slow client. Once the queue is full sampling stops, and the client's
+/// next read is `Frame::Resync`, ending the stream.
+pub fn queued<T, F, Fut>(interval: Duration, backlog: usize, mut produce: F) -> impl Stream<Item = Frame<T>>
+where
+    T: Send + 'static,
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = T> + Send,
+{
+    let (tx, rx) = mpsc::channel(backlog);
+    let lagged = Arc::new(AtomicBool::new(false));
+    let producer_lagged = lagged.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tx.closed() => return,
+                _ = tokio::time::sleep(interval) => {}
+            }
+            match tx.try_send(produce().await) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    producer_lagged.store(true, Ordering::Relaxed);
+                    return;
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => return,
+            }
+        }
+    });
+
+    stream::unfold(Some(rx), move |rx| {
+        let lagged = lagged.clone();
+        async move {
+            let mut rx = rx?;
+            // Stale frames still queued are skipped: the client resyncs anyway
+            if lagged.load(Ordering::Relaxed) {
+                return Some((Frame::Resync, None));
+            }
+            match rx.recv().await {
+                Some(value) => Some((Frame::Data(value), Some(rx))),
+                None if lagged.load(Ordering::Relaxed) => Some((Frame::Resync, None)),
+                None => None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::sync::atomic::AtomicU64;
+
+    fn counter() -> (Arc<AtomicU64>, impl FnMut() -> futures::future::Ready<u64> + Send + 'static) {
+        let count = Arc::new(AtomicU64::new(0));
+        let produced = count.clone();
+        (count, move || futures::future::ready(produced.fetch_add(1, Ordering::Relaxed) + 1))
+    }
+
+    #[tokio::test]
+    async fn test_slow_reader_gets_latest_frame_only() {
+        let (_, produce) = counter();
+        let mut frames = Box::pin(latest(Duration::from_millis(1), produce));
+
+        let first = frames.next().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let second = frames.next().await.unwrap();
+        assert!(second > first + 1, "intermediate frames were queued: {} then {}", first, second);
+        assert!(frames.next().await.unwrap() > second);
+    }
+
+    #[tokio::test]
+    async fn test_stalled_reader_is_told_to_resync() {
+        let (count, produce) = counter();
+        let mut frames = Box::pin(queued(Duration::from_millis(1), 4, produce));
+
+        assert_eq!(frames.next().await, Some(Frame::Data(1)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(frames.next().await, Some(Frame::Resync));
+        assert_eq!(frames.next().await, None);
+
+        // Sampling stopped at the full queue instead of buffering on
+        assert_eq!(count.load(Ordering::Relaxed), 1 + 4 + 1);
+    }
+}
@@ -1,21 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use tracing::{info, error};
+use tracing::{info, warn, error};
 
 /// System proxy configuration for Windows
 pub struct SystemProxy {
     original_state: Option<ProxyState>,
+    backup_path: Option<PathBuf>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 struct ProxyState {
     enabled: bool,
     server: String,
 }
 
+/// Location of the on-disk copy of the original proxy settings
+///
+/// Written on `enable` and removed once the settings are restored, so a file
+/// left behind means the previous run exited without cleaning up.
+pub fn default_backup_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".privacy_suite").join("proxy_backup.json"))
+}
+
+/// Restore proxy settings from a backup left behind by a crashed or killed run
+///
+/// Returns `Ok(true)` if a backup was found and restored.
+pub fn restore_leftover_backup(path: &Path) -> Result<bool, String> {
+    restore_backup_with(path, SystemProxy::apply_state)
+}
+
+fn restore_backup_with(
+    path: &Path,
+    apply: impl FnOnce(&ProxyState) -> Result<(), String>,
+) -> Result<bool, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(format!("Failed to read proxy backup: {}", e)),
+    };
+
+    let state: ProxyState = serde_json::from_str(&contents)
+        .map_err(|e| format!("Corrupt proxy backup {}: {}", path.display(), e))?;
+
+    warn!("Found leftover proxy backup, restoring original settings...");
+    apply(&state)?;
+
+    fs::remove_file(path)
+        .map_err(|e| format!("Failed to remove proxy backup: {}", e))?;
+    info!("✓ Proxy settings from previous session restored");
+    Ok(true)
+}
+
+/// Install a panic hook that restores the original proxy settings when a
+/// panic is about to end the process
+///
+/// `Drop` does not run if the process aborts, so this uses the on-disk
+/// backup rather than any in-memory `SystemProxy`. Panics in tokio tasks
+/// are caught by the runtime and the suite keeps running, so those leave
+/// the proxy settings alone.
+pub fn install_restore_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        if let Some(path) = default_backup_path().filter(|_| panic_ends_process()) {
+            if let Err(e) = restore_leftover_backup(&path) {
+                error!("Failed to restore proxy settings after panic: {}", e);
+            }
+        }
+        previous(panic_info);
+    }));
+}
+
+/// Whether the panic being handled takes the process down: always with
+/// `panic = "abort"`, otherwise only on the main thread
+fn panic_ends_process() -> bool {
+    cfg!(panic = "abort") || std::thread::current().name() == Some("main")
+}
+
 impl SystemProxy {
     pub fn new() -> Self {
         Self {
             original_state: None,
+            backup_path: default_backup_path(),
         }
     }
 
@@ -23,13 +90,15 @@ impl SystemProxy {
     pub fn enable(&mut self, proxy_addr: &str) -> Result<(), String> {
         info!("Configuring system proxy...");
         
-        // Save current state first
-        self.original_state = Some(self.get_current_state()?);
+        // Save current state first, both in memory and on disk in case we crash
+        let original = self.get_current_state()?;
+        self.write_backup(&original)?;
+        self.original_state = Some(original);
         
         #[cfg(target_os = "windows")]
         {
             // Enable Windows system proxy
-            self.enable_windows(proxy_addr)?;
+            Self::enable_windows(proxy_addr)?;
             
             // Also notify browsers to refresh their proxy settings
             self.notify_browsers();
@@ -51,20 +120,57 @@ impl SystemProxy {
         #[cfg(target_os = "windows")]
         {
             if let Some(original) = &self.original_state {
-                if original.enabled {
-                    self.enable_windows(&original.server)?;
-                } else {
-                    self.disable_windows()?;
-                }
+                Self::apply_state(original)?;
+                self.remove_backup();
                 info!("✓ Original proxy settings restored");
                 Ok(())
             } else {
-                self.disable_windows()
+                Self::disable_windows()
+            }
+        }
+        
+        #[cfg(not(target_os = "windows"))]
+        {
+            self.remove_backup();
+            Ok(())
+        }
+    }
+
+    fn write_backup(&self, state: &ProxyState) -> Result<(), String> {
+        let Some(path) = &self.backup_path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create backup directory: {}", e))?;
+        }
+        let json = serde_json::to_string(state)
+            .map_err(|e| format!("Failed to serialize proxy state: {}", e))?;
+        fs::write(path, json)
+            .map_err(|e| format!("Failed to write proxy backup: {}", e))
+    }
+
+    fn remove_backup(&self) {
+        if let Some(path) = &self.backup_path {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Put the system proxy back into a previously captured state
+    fn apply_state(state: &ProxyState) -> Result<(), String> {
+        #[cfg(target_os = "windows")]
+        {
+            if state.enabled {
+                Self::enable_windows(&state.server)
+            } else {
+                Self::disable_windows()
             }
         }
         
         #[cfg(not(target_os = "windows"))]
         {
+            let _ = state;
             Ok(())
         }
     }
@@ -106,7 +212,7 @@ impl SystemProxy {
     }
 
     #[cfg(target_os = "windows")]
-    fn enable_windows(&self, proxy_addr: &str) -> Result<(), String> {
+    fn enable_windows(proxy_addr: &str) -> Result<(), String> {
         // Set proxy server
         let result1 = Command::new("reg")
             .args(&[
@@ -157,7 +263,7 @@ impl SystemProxy {
     }
 
     #[cfg(target_os = "windows")]
-    fn disable_windows(&self) -> Result<(), String> {
+    fn disable_windows() -> Result<(), String> {
         // Disable proxy
         let result = Command::new("reg")
             .args(&[
@@ -224,6 +330,49 @@ impl Drop for SystemProxy {
     }
 }
 
+/// Command-line flag passed to a backend instance relaunched by `relaunch_elevated`
+pub const RELAUNCHED_FLAG: &str = "--elevated-relaunch";
+
+/// Relaunch the backend with administrator privileges (shows a UAC prompt)
+///
+/// Returns an error if the user declines the prompt, in which case the
+/// current instance should keep running in manual-proxy mode.
+pub fn relaunch_elevated() -> Result<(), String> {
+    #[cfg(target_os = "windows")]
+    {
+        let exe = std::env::current_exe()
+            .map_err(|e| format!("Failed to locate backend executable: {}", e))?;
+        
+        info!("Requesting elevation for {}", exe.display());
+        
+        // Start-Process fails with a non-zero exit code if the UAC prompt is declined
+        let status = Command::new("powershell")
+            .args(&[
+                "-NoProfile",
+                "-Command",
+                &format!(
+                    "Start-Process -FilePath '{}' -ArgumentList '{}' -Verb RunAs -WindowStyle Hidden",
+                    exe.display(),
+                    RELAUNCHED_FLAG
+                ),
+            ])
+            .status()
+            .map_err(|e| format!("Failed to request elevation: {}", e))?;
+        
+        if !status.success() {
+            return Err("Administrator privileges were declined".to_string());
+        }
+        
+        info!("✓ Elevated backend instance launched");
+        Ok(())
+    }
+    
+    #[cfg(not(target_os = "windows"))]
+    {
+        Err("Elevated relaunch only supported on Windows".to_string())
+    }
+}
+
 /// Check if running with administrator privileges (required for system proxy)
 pub fn is_elevated() -> bool {
     #[cfg(target_os = "windows")]
@@ -253,3 +402,68 @@ pub fn is_elevated() -> bool {
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_backup_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("privacy_suite_test_{}_{}", name, std::process::id()))
+            .join("proxy_backup.json")
+    }
+
+    #[test]
+    fn test_task_panics_leave_the_proxy_alone() {
+        let worker = std::thread::Builder::new()
+            .name("tokio-runtime-worker".to_string())
+            .spawn(panic_ends_process)
+            .unwrap();
+        assert_eq!(worker.join().unwrap(), cfg!(panic = "abort"));
+    }
+
+    #[test]
+    fn test_leftover_backup_restored_on_launch() {
+        let path = temp_backup_path("leftover");
+
+        // Simulate a previous run that enabled the proxy and was then killed
+        let crashed = SystemProxy {
+            original_state: None,
+            backup_path: Some(path.clone()),
+        };
+        let original = ProxyState {
+            enabled: true,
+            server: "corp-proxy:3128".to_string(),
+        };
+        crashed.write_backup(&original).unwrap();
+        std::mem::forget(crashed);
+
+        // Record the restore instead of touching the real system settings
+        let mut restored = None;
+        let result = restore_backup_with(&path, |state| {
+            restored = Some(state.clone());
+            Ok(())
+        });
+        assert_eq!(result, Ok(true));
+        assert_eq!(restored, Some(original));
+        assert!(!path.exists());
+
+        // Nothing left to restore on the next launch
+        assert_eq!(restore_backup_with(&path, |_| panic!("no backup to apply")), Ok(false));
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_corrupt_backup_is_reported() {
+        let path = temp_backup_path("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "not json").unwrap();
+
+        assert!(restore_backup_with(&path, |_| Ok(())).is_err());
+        // The file is kept so the user can recover the settings by hand
+        assert!(path.exists());
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -1,131 +1,738 @@
-use arti_client::{TorClient, TorClientConfig};
-use hyper::{Request, Response, body::Bytes};
+use arti_client::config::onion_service::OnionServiceConfigBuilder;
+use arti_client::config::BoolOrAuto;
+use arti_client::{ErrorKind, HasKind, IsolationToken, StreamPrefs, TorClient, TorClientConfig, TorClientConfigBuilder};
+use async_trait::async_trait;
+use futures::StreamExt;
+use hyper::{header, HeaderMap, Method, Request, Response, Uri, Version, body::Bytes};
 use http_body_util::Full;
-use tracing::{info, error};
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{info, warn, error};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::error::{PrivacyError, Result};
 use crate::fingerprint::BrowserFingerprint;
+use crate::onion_service::{self, OnionServiceInfo, OnionServicePort};
+use crate::transport::{OpenedStream, Transport};
+use crate::upstream_proxy::{self, UpstreamProxy, UpstreamRuntime};
+use tor_cell::relaycell::msg::Connected;
+use tor_linkspec::{HasAddrs, HasRelayIds};
+use tor_proto::stream::IncomingStreamRequest;
+
+/// One relay in the Tor circuit carrying a stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitHop {
+    /// "guard", "middle" or "exit"
+    pub role: String,
+    /// Relay identity (fingerprints/address) as reported by arti
+    pub relay: String,
+    /// Relay country, when known
+    pub country: Option<String>,
+    /// Address the relay is reachable at, when arti reports one
+    #[serde(default)]
+    pub address: Option<IpAddr>,
+}
+
+/// Why `TorNetwork::send_request` failed, and whether the request had
+/// already gone out on a stream by then
+#[derive(Debug)]
+struct SendFailure {
+    error: PrivacyError,
+    request_sent: bool,
+}
+
+impl SendFailure {
+    fn unsent(error: PrivacyError) -> Self {
+        Self { error, request_sent: false }
+    }
+    
+    fn sent(error: PrivacyError) -> Self {
+        Self { error, request_sent: true }
+    }
+}
 
 #[derive(Clone)]
 pub struct TorNetwork {
-    client: Arc<TorClient<tor_rtcompat::PreferredRuntime>>,
+    client: Arc<TorClient<UpstreamRuntime>>,
+    prefs: StreamPrefs,
+    max_response_bytes: usize,
+    isolation: Arc<Mutex<CircuitLifetime>>,
+    failures: Arc<Mutex<FailureTracker>>,
+    circuit_rebuilds: Arc<AtomicU64>,
+    circuit_rotations: Arc<AtomicU64>,
+    keep_alive: bool,
+    pool: StreamPool<arti_client::DataStream>,
+    exit_relays: ExitRelays,
+    /// Whether arti can build circuits, from its bootstrap events
+    ready: tokio::sync::watch::Receiver<bool>,
+    /// Country set with `with_exit_country`, and whether streams use any
+    /// exit for now as none there could be reached (shared by clones)
+    exit_country: Option<(String, tor_geoip::CountryCode)>,
+    any_exit: Arc<AtomicBool>,
+}
+
+/// An onion service published by `TorNetwork::launch_onion_service`; it
+/// stays reachable until this is dropped
+pub struct HostedOnionService {
+    pub info: OnionServiceInfo,
+    _service: Arc<tor_hsservice::RunningOnionService>,
+    forwarder: tokio::task::AbortHandle,
+}
+
+impl Drop for HostedOnionService {
+    fn drop(&mut self) {
+        self.forwarder.abort();
+    }
+}
+
+/// How long an idle pooled stream is kept for the next request
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Idle streams kept per origin
+const POOL_MAX_PER_ORIGIN: usize = 4;
+
+/// Idle upstream connections kept open for HTTP keep-alive, by origin
+struct StreamPool<S> {
+    idle: Arc<Mutex<HashMap<(String, u16), Vec<(S, Instant)>>>>,
+}
+
+impl<S> Clone for StreamPool<S> {
+    fn clone(&self) -> Self {
+        Self { idle: self.idle.clone() }
+    }
+}
+
+impl<S> StreamPool<S> {
+    fn new() -> Self {
+        Self { idle: Arc::new(Mutex::new(HashMap::new())) }
+    }
+    
+    /// Take the most recently used idle stream to `host:port`, dropping expired ones
+    fn take(&self, host: &str, port: u16) -> Option<S> {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        let streams = idle.get_mut(&(host.to_string(), port))?;
+        streams.retain(|(_, since)| since.elapsed() < POOL_IDLE_TIMEOUT);
+        streams.pop().map(|(stream, _)| stream)
+    }
+    
+    fn put(&self, host: &str, port: u16, stream: S) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        let streams = idle.entry((host.to_string(), port)).or_default();
+        if streams.len() >= POOL_MAX_PER_ORIGIN {
+            streams.remove(0);
+        }
+        streams.push((stream, Instant::now()));
+    }
+    
+    fn clear(&self) {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+/// Whether the client asked to keep its connection open after this request
+pub fn wants_keep_alive(version: Version, headers: &HeaderMap) -> bool {
+    let connection: Vec<String> = [header::CONNECTION.as_str(), "proxy-connection"]
+        .iter()
+        .filter_map(|name| headers.get(*name))
+        .filter_map(|value| value.to_str().ok())
+        .map(|value| value.to_ascii_lowercase())
+        .collect();
+    
+    if connection.iter().any(|value| value.contains("close")) {
+        return false;
+    }
+    // HTTP/1.0 closes unless the client opts in
+    version != Version::HTTP_10 || connection.iter().any(|value| value.contains("keep-alive"))
+}
+
+/// Default `Config::max_circuit_age_secs`, Tor's own `MaxCircuitDirtiness`
+pub const DEFAULT_MAX_CIRCUIT_AGE: Duration = Duration::from_secs(600);
+
+/// Source of the current time, replaceable in tests
+pub type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// The isolation token new streams are opened with. Streams with different
+/// tokens never share a circuit, so replacing it moves new streams onto
+/// fresh circuits while open ones keep theirs.
+///
+/// The token is replaced on demand and once it is `max_age` old.
+pub struct CircuitLifetime {
+    token: IsolationToken,
+    created: Instant,
+    max_age: Duration,
+    clock: Clock,
+}
+
+impl CircuitLifetime {
+    /// A `max_age` of zero keeps circuits until they are renewed by hand
+    pub fn new(max_age: Duration) -> Self {
+        Self::with_clock(max_age, Arc::new(Instant::now))
+    }
+    
+    pub fn with_clock(max_age: Duration, clock: Clock) -> Self {
+        Self {
+            token: IsolationToken::new(),
+            created: clock(),
+            max_age,
+            clock,
+        }
+    }
+    
+    /// Put later streams on fresh circuits
+    pub fn renew(&mut self) {
+        self.token = IsolationToken::new();
+        self.created = (self.clock)();
+    }
+    
+    /// Token for a new stream, renewed first if the current circuits are too
+    /// old. The flag says whether that happened.
+    pub fn token_for_new_stream(&mut self) -> (IsolationToken, bool) {
+        let expired = !self.max_age.is_zero()
+            && (self.clock)().saturating_duration_since(self.created) >= self.max_age;
+        if expired {
+            self.renew();
+        }
+        (self.token, expired)
+    }
+}
+
+/// Default number of distinct hosts that must fail in a row before the circuit is rebuilt
+pub const DEFAULT_FAILURE_THRESHOLD: usize = 3;
+
+/// Spots a degraded circuit from consecutive failures.
+///
+/// Repeated failures to one host are probably that host's fault, so only
+/// failures to different hosts count; any success resets the streak.
+#[derive(Debug)]
+pub struct FailureTracker {
+    threshold: usize,
+    failed_hosts: HashSet<String>,
+}
+
+impl FailureTracker {
+    /// A threshold of 0 disables automatic rebuilds
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            failed_hosts: HashSet::new(),
+        }
+    }
+    
+    /// Record a failure, returning true when the circuit should be rebuilt
+    pub fn record_failure(&mut self, host: &str) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        
+        self.failed_hosts.insert(host.to_ascii_lowercase());
+        if self.failed_hosts.len() >= self.threshold {
+            self.failed_hosts.clear();
+            return true;
+        }
+        false
+    }
+    
+    pub fn record_success(&mut self) {
+        self.failed_hosts.clear();
+    }
+}
+
+/// Circuits tried for a stream before giving up on an exit `ExitRelays` permits
+const EXIT_RELAY_ATTEMPTS: usize = 5;
+
+/// Exit relays ruled out by fingerprint (`Config::exclude_exit_nodes`).
+///
+/// arti has no ExcludeExitNodes setting, so the exit of each new stream's
+/// circuit is only known once the stream is open: a stream that landed on
+/// an excluded exit is dropped for one on a fresh circuit, but that exit
+/// has already been asked for the destination. Pinning exits
+/// (`Config::allow_exit_nodes`) is refused: among thousands of exits a
+/// handful of fresh circuits would almost never land on a pinned one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExitRelays {
+    exclude: HashSet<String>,
+}
+
+impl ExitRelays {
+    pub fn new(exclude: &[String], allow: &[String]) -> Result<Self> {
+        if !allow.is_empty() {
+            return Err(PrivacyError::Config(
+                "allow_exit_nodes isn't supported: arti can't pin exit relays, pick an exit country instead".to_string(),
+            ));
+        }
+        let exclude = exclude
+            .iter()
+            .map(|fingerprint| {
+                parse_fingerprint(fingerprint).ok_or_else(|| {
+                    PrivacyError::Config(format!("Invalid exit relay fingerprint {:?}: expected 40 hex digits", fingerprint))
+                })
+            })
+            .collect::<Result<HashSet<_>>>()?;
+        Ok(Self { exclude })
+    }
+    
+    pub fn is_unrestricted(&self) -> bool {
+        self.exclude.is_empty()
+    }
+    
+    /// Whether a circuit may exit through the relay with `fingerprint`. An
+    /// exit arti didn't identify only passes when nothing is restricted.
+    pub fn permits(&self, fingerprint: Option<&str>) -> bool {
+        match fingerprint {
+            Some(fingerprint) => !self.exclude.contains(fingerprint),
+            None => self.is_unrestricted(),
+        }
+    }
+}
+
+/// A relay's RSA identity fingerprint in upper-case hex, accepting the
+/// `$`-prefixed and space-grouped forms relay lists show
+pub fn parse_fingerprint(text: &str) -> Option<String> {
+    let text = text.trim();
+    let hex: String = text.strip_prefix('$').unwrap_or(text).chars().filter(|c| *c != ' ').collect();
+    (hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit())).then(|| hex.to_ascii_uppercase())
+}
+
+/// Relays in every circuit arti builds: a guard, a middle and an exit
+pub const TOR_PATH_LENGTH: u8 = 3;
+
+/// Client configuration for circuits of `path_length` relays (`Config::tor_path_length`),
+/// keeping arti's state and cache in `state/` and `cache/` under `data_dir`
+/// (`Config::resolved_tor_data_dir`), which are created if missing.
+///
+/// arti has no setting for the path length: every exit circuit is a guard,
+/// a middle and an exit relay. Other lengths are refused rather than
+/// silently ignored; shorter ones would also let a single relay see both
+/// the client and its destination.
+pub fn client_config(path_length: u8, data_dir: &Path) -> Result<TorClientConfig> {
+    match path_length.cmp(&TOR_PATH_LENGTH) {
+        std::cmp::Ordering::Less => Err(PrivacyError::Config(format!(
+            "tor_path_length {} is too short: Tor circuits need at least {} relays",
+            path_length, TOR_PATH_LENGTH
+        ))),
+        std::cmp::Ordering::Greater => Err(PrivacyError::Config(format!(
+            "tor_path_length {} isn't supported: arti always builds {}-relay circuits",
+            path_length, TOR_PATH_LENGTH
+        ))),
+        std::cmp::Ordering::Equal => {
+            let (state_dir, cache_dir) = (data_dir.join("state"), data_dir.join("cache"));
+            for dir in [&state_dir, &cache_dir] {
+                std::fs::create_dir_all(dir)
+                    .map_err(|e| PrivacyError::Config(format!("Failed to create Tor data directory {}: {}", dir.display(), e)))?;
+            }
+            TorClientConfigBuilder::from_directories(state_dir, cache_dir)
+                .build()
+                .map_err(|e| PrivacyError::Config(format!("Invalid Tor client configuration: {}", e)))
+        }
+    }
+}
+
+/// Whether arti can run in this environment, as checked before connecting
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TorSupport {
+    Supported,
+    /// arti can't start here; `reason` says what to change
+    Unsupported { reason: String },
+}
+
+/// Decides whether Tor can start with a config (`probe_support`, replaceable in tests)
+pub type TorProbe = Arc<dyn Fn(&crate::config::Config) -> TorSupport + Send + Sync>;
+
+/// Check what arti needs from the environment before any bootstrap is
+/// attempted: the OS random number generator for its keys, and a writable
+/// data directory for its state and cache. Either missing (sandboxes,
+/// read-only containers) otherwise only shows up as an error deep inside
+/// the bootstrap.
+pub fn probe_support(config: &crate::config::Config) -> TorSupport {
+    use rand::RngCore;
+    
+    let mut sample = [0u8; 32];
+    if let Err(e) = rand::rngs::OsRng.try_fill_bytes(&mut sample) {
+        return TorSupport::Unsupported {
+            reason: format!(
+                "the system random number generator is unavailable ({}); a sandbox or container has to allow getrandom or /dev/urandom",
+                e
+            ),
+        };
+    }
+    
+    // The path length is a config choice checked on connect, not an environment limit
+    let data_dir = config.resolved_tor_data_dir();
+    if let Err(e) = client_config(TOR_PATH_LENGTH, &data_dir) {
+        return TorSupport::Unsupported { reason: format!("{}; set tor_data_dir to a writable directory", e) };
+    }
+    let probe = data_dir.join("state").join(".write_probe");
+    if let Err(e) = std::fs::write(&probe, b"").and_then(|_| std::fs::remove_file(&probe)) {
+        return TorSupport::Unsupported {
+            reason: format!("Tor data directory {} isn't writable ({}); set tor_data_dir to a writable directory", data_dir.display(), e),
+        };
+    }
+    TorSupport::Supported
 }
 
+/// Default cap on a buffered upstream response (see `Config::max_response_bytes`)
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 50 * 1024 * 1024;
+
 impl TorNetwork {
-    pub async fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    /// Bootstrap arti with `path_length`-relay circuits and its files in
+    /// `data_dir` (see `client_config`), reaching the Tor network through
+    /// `upstream` if given
+    pub async fn new(upstream: Option<UpstreamProxy>, path_length: u8, data_dir: &Path) -> Result<Self> {
         info!("Bootstrapping Tor connection...");
+        let config = client_config(path_length, data_dir)?;
+        
+        if let Some(upstream) = &upstream {
+            upstream_proxy::check_reachable(upstream)
+                .await
+                .map_err(PrivacyError::TorBootstrap)?;
+            info!("Reaching Tor network through upstream proxy {}", upstream);
+        }
         
-        // Create Tor client with default config
-        let config = TorClientConfig::default();
+        let runtime = upstream_proxy::runtime(upstream.clone())
+            .map_err(|e| PrivacyError::TorBootstrap(format!("Failed to create runtime: {}", e)))?;
         
         // Bootstrap connection to Tor network
         // This connects to directory servers and builds circuits
-        let client = TorClient::create_bootstrapped(config).await?;
+        let client = TorClient::with_runtime(runtime)
+            .config(config)
+            .create_bootstrapped()
+            .await
+            .map_err(|e| match &upstream {
+                Some(upstream) => PrivacyError::TorBootstrap(format!("via upstream proxy {}: {}", upstream, e)),
+                None => PrivacyError::TorBootstrap(e.to_string()),
+            })?;
         
         info!("Tor bootstrapped! Connected to network.");
+        let ready = watch_readiness(&client);
         
         Ok(Self {
             client: Arc::new(client),
+            // arti's defaults: exits may reach a name over IPv6, which never
+            // leaves from this machine; IPv6 literals are up to `Ipv6Protection`
+            prefs: StreamPrefs::new(),
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            isolation: Arc::new(Mutex::new(CircuitLifetime::new(DEFAULT_MAX_CIRCUIT_AGE))),
+            failures: Arc::new(Mutex::new(FailureTracker::new(DEFAULT_FAILURE_THRESHOLD))),
+            circuit_rebuilds: Arc::new(AtomicU64::new(0)),
+            circuit_rotations: Arc::new(AtomicU64::new(0)),
+            keep_alive: true,
+            pool: StreamPool::new(),
+            exit_relays: ExitRelays::default(),
+            ready,
+            exit_country: None,
+            any_exit: Arc::new(AtomicBool::new(false)),
         })
     }
     
-    pub async fn route_request(
+    /// Reuse Tor streams across requests to the same origin (HTTP keep-alive)
+    pub fn with_keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+    
+    /// Keep streams off exits `exit_relays` rules out
+    pub fn with_exit_relays(mut self, exit_relays: ExitRelays) -> Self {
+        self.exit_relays = exit_relays;
+        self
+    }
+    
+    /// Set how many distinct hosts must fail in a row before the circuit is rebuilt
+    pub fn with_failure_threshold(self, threshold: usize) -> Self {
+        *self.failures.lock().unwrap_or_else(|e| e.into_inner()) = FailureTracker::new(threshold);
+        self
+    }
+    
+    /// Stop opening streams on a circuit once it is `max_age` old (zero disables)
+    pub fn with_max_circuit_age(self, max_age: Duration) -> Self {
+        *self.isolation.lock().unwrap_or_else(|e| e.into_inner()) = CircuitLifetime::new(max_age);
+        self
+    }
+    
+    /// Retire the current circuits: later streams are built on fresh ones
+    pub fn new_identity(&self) {
+        self.isolation.lock().unwrap_or_else(|e| e.into_inner()).renew();
+        // Pooled streams still ride the old circuits
+        self.pool.clear();
+        self.failures.lock().unwrap_or_else(|e| e.into_inner()).record_success();
+        self.circuit_rebuilds.fetch_add(1, Ordering::Relaxed);
+        info!("🔄 New Tor identity - subsequent streams use fresh circuits");
+    }
+    
+    /// Preferences for a new stream, moving to fresh circuits if the current ones are too old
+    fn stream_prefs(&self) -> StreamPrefs {
+        let (token, expired) = self.isolation.lock().unwrap_or_else(|e| e.into_inner()).token_for_new_stream();
+        if expired {
+            // Idle pooled streams would keep the old circuits in use
+            self.pool.clear();
+            self.circuit_rotations.fetch_add(1, Ordering::Relaxed);
+            info!("⏱️ Tor circuit reached its max age - new streams use a fresh circuit");
+        }
+        
+        let mut prefs = self.prefs.clone();
+        prefs.set_isolation(token);
+        if self.any_exit.load(Ordering::Relaxed) {
+            prefs.any_exit_country();
+        }
+        prefs
+    }
+    
+    /// Track the outcome of a Tor operation. Returns true if it failed and
+    /// the circuit was rebuilt as a result, so the caller should retry once.
+    fn circuit_failed<T>(&self, result: &Result<T>, host: &str) -> bool {
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        match result {
+            Ok(_) => {
+                failures.record_success();
+                false
+            }
+            Err(PrivacyError::TorConnect(_)) | Err(PrivacyError::Io(_)) => {
+                if !failures.record_failure(host) {
+                    return false;
+                }
+                drop(failures);
+                warn!("⚠️ Repeated Tor failures across hosts - rebuilding circuit");
+                self.new_identity();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+    
+    /// Set the largest response `route_request` will buffer
+    pub fn with_max_response_bytes(mut self, max_response_bytes: usize) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+    
+    /// Allow streams to `.onion` addresses (arti refuses them by default)
+    pub fn with_onion_services(mut self, allow: bool) -> Self {
+        self.prefs.connect_to_onion_services(BoolOrAuto::Explicit(allow));
+        self
+    }
+    
+    /// Only use exits in `country` (a two-letter code, with the GUI's "uk" for GB)
+    pub fn with_exit_country(mut self, country: &str) -> Result<Self> {
+        let code = if country.eq_ignore_ascii_case("uk") { "gb" } else { country };
+        let code = code.parse::<tor_geoip::CountryCode>().map_err(|e| PrivacyError::ExitUnavailable {
+            country: country.to_string(),
+            reason: format!("not a valid country code ({})", e),
+        })?;
+        self.prefs.exit_country(code);
+        self.exit_country = Some((country.to_string(), code));
+        info!("Exit country restricted to {}", country);
+        Ok(self)
+    }
+    
+    /// Use any exit instead of the `with_exit_country` one, or go back to it
+    pub fn use_any_exit(&self, any: bool) {
+        self.any_exit.store(any, Ordering::Relaxed);
+    }
+    
+    /// Check that a stream can be built with the exit restriction, even
+    /// while `use_any_exit` is on. Only arti finding no usable exit in the
+    /// country is `ExitUnavailable`; any other failure says nothing about it.
+    pub async fn probe_exit(&self) -> Result<()> {
+        let mut prefs = self.stream_prefs();
+        if let Some((_, code)) = &self.exit_country {
+            prefs.exit_country(*code);
+        }
+        self.client
+            .connect_with_prefs(("check.torproject.org", 443), &prefs)
+            .await
+            .map(drop)
+            .map_err(|e| match e.kind() {
+                ErrorKind::NoExit => PrivacyError::ExitUnavailable {
+                    country: self.exit_country.as_ref().map(|(country, _)| country.clone()).unwrap_or_default(),
+                    reason: e.to_string(),
+                },
+                _ => PrivacyError::TorConnect(e.to_string()),
+            })
+    }
+    
+    async fn send_request(
         &self,
-        req: Request<hyper::body::Incoming>,
+        method: &Method,
+        uri: &Uri,
         fingerprint: &BrowserFingerprint,
-    ) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error + Send + Sync>> {
-        let uri = req.uri().clone();
-        let method = req.method().clone();
-        
+        connect_host: &str,
+        keep_alive: bool,
+    ) -> std::result::Result<Response<Full<Bytes>>, SendFailure> {
         info!("Routing {} {} through Tor", method, uri);
         
-        // Extract host and port
-        let host = uri.host().ok_or("No host in URI")?;
-        let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("https") { 443 } else { 80 });
+        let (port, request_data) = upstream_request(method, uri, fingerprint, keep_alive).map_err(SendFailure::unsent)?;
+        let request = request_data.as_bytes();
+        let head_request = method == Method::HEAD;
+        // Taken before the pool so an expired circuit's idle streams aren't reused
+        let prefs = self.stream_prefs();
         
-        // Get path with query
-        let path_and_query = uri.path_and_query()
-            .map(|pq| pq.as_str())
-            .unwrap_or("/");
+        // Reuse an idle stream to this origin when there is one
+        if keep_alive {
+            if let Some(mut stream) = self.pool.take(connect_host, port) {
+                info!("Sending request on pooled Tor stream to {}:{}", connect_host, port);
+                match exchange_keep_alive(&mut stream, request, self.max_response_bytes, head_request).await {
+                    Ok((response, reusable)) => {
+                        if reusable {
+                            self.pool.put(connect_host, port, stream);
+                        }
+                        return Ok(response);
+                    }
+                    // The origin may have closed the idle connection; retry on a new stream
+                    Err(PrivacyError::Io(e)) if method.is_idempotent() => {
+                        info!("Pooled stream to {}:{} was closed ({}), reconnecting", connect_host, port, e);
+                    }
+                    Err(e) => return Err(SendFailure::sent(e)),
+                }
+            }
+        }
         
-        info!("Connecting to {}:{} via Tor", host, port);
+        info!("Connecting to {}:{} via Tor", connect_host, port);
         
         // Connect through Tor
-        let mut stream = self.client
-            .connect((host, port))
-            .await
-            .map_err(|e| format!("Tor connection failed: {}", e))?;
-        
-        // Build proper HTTP/1.1 request with randomized fingerprint
-        let request_data = format!(
-            "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\nAccept-Language: {}\r\nAccept-Encoding: {}\r\nConnection: close\r\n\r\n",
-            method,
-            path_and_query,
-            host,
-            fingerprint.user_agent,
-            fingerprint.accept_language,
-            fingerprint.accept_encoding
-        );
+        let mut stream = self.connect_permitted(connect_host, port, prefs).await.map_err(SendFailure::unsent)?;
         
         info!("Sending request through Tor circuit...");
         
-        // Send through Tor stream
-        use tokio::io::{AsyncWriteExt, AsyncReadExt};
-        stream.write_all(request_data.as_bytes()).await?;
-        stream.flush().await?;
-        
-        // Read response with timeout
-        let mut response_bytes = Vec::new();
-        let read_result = tokio::time::timeout(
-            std::time::Duration::from_secs(30),
-            stream.read_to_end(&mut response_bytes)
-        ).await;
-        
-        match read_result {
-            Ok(Ok(_)) => {
-                info!("✓ Received response through Tor ({} bytes)", response_bytes.len());
-                
-                // Parse HTTP response
-                let response_str = String::from_utf8_lossy(&response_bytes);
-                
-                // Split headers and body
-                if let Some(body_start) = response_str.find("\r\n\r\n") {
-                    let headers_part = &response_str[..body_start];
-                    let body = &response_str[body_start + 4..];
-                    
-                    info!("Response headers: {}", headers_part.lines().next().unwrap_or("No status line"));
-                    info!("Body length: {} bytes", body.len());
-                    
-                    Ok(Response::new(Full::new(Bytes::from(body.to_string()))))
-                } else {
-                    // No proper HTTP response, return raw data
-                    Ok(Response::new(Full::new(Bytes::from(response_str.to_string()))))
-                }
-            }
-            Ok(Err(e)) => {
-                Err(format!("Failed to read response: {}", e).into())
+        if !keep_alive {
+            return exchange(stream, request, self.max_response_bytes).await.map_err(SendFailure::sent);
+        }
+        
+        let (response, reusable) = exchange_keep_alive(&mut stream, request, self.max_response_bytes, head_request)
+            .await
+            .map_err(SendFailure::sent)?;
+        if reusable {
+            self.pool.put(connect_host, port, stream);
+        }
+        Ok(response)
+    }
+    
+    async fn open_stream(&self, host: &str, port: u16) -> Result<arti_client::DataStream> {
+        info!("Establishing Tor stream to {}:{}", host, port);
+        
+        self.connect_permitted(host, port, self.stream_prefs()).await
+    }
+    
+    /// Open a stream with `prefs`, moving to fresh circuits until its exit is
+    /// one `self.exit_relays` permits
+    async fn connect_permitted(&self, host: &str, port: u16, mut prefs: StreamPrefs) -> Result<arti_client::DataStream> {
+        for _ in 0..EXIT_RELAY_ATTEMPTS {
+            let stream = self.client
+                .connect_with_prefs((host, port), &prefs)
+                .await
+                .map_err(|e| PrivacyError::TorConnect(format!("stream to {}:{}: {}", host, port, e)))?;
+            if self.exit_relays.is_unrestricted() {
+                return Ok(stream);
             }
-            Err(_) => {
-                Err("Request timeout after 30 seconds".into())
+            
+            let exit = Self::exit_fingerprint(&stream);
+            if self.exit_relays.permits(exit.as_deref()) {
+                return Ok(stream);
             }
+            info!("Exit relay {} is ruled out - trying a fresh circuit", exit.as_deref().unwrap_or("(unknown)"));
+            drop(stream);
+            self.isolation.lock().unwrap_or_else(|e| e.into_inner()).renew();
+            prefs = self.stream_prefs();
         }
+        Err(PrivacyError::TorConnect(format!(
+            "stream to {}:{}: every exit was ruled out by exclude_exit_nodes in {} circuits",
+            host, port, EXIT_RELAY_ATTEMPTS
+        )))
     }
     
-    pub async fn connect_stream(
-        &self,
-        host: &str,
-        port: u16,
-    ) -> Result<arti_client::DataStream, Box<dyn std::error::Error + Send + Sync>> {
-        info!("Establishing Tor stream to {}:{}", host, port);
+    /// RSA fingerprint of the exit of the circuit a stream is attached to
+    fn exit_fingerprint(stream: &arti_client::DataStream) -> Option<String> {
+        let circuit = stream.ctrl().circuit()?;
+        let path = circuit.path_ref();
+        let exit = path.hops().last()?.as_chan_target()?;
+        let identity = exit.rsa_identity()?;
+        Some(identity.as_bytes().iter().map(|b| format!("{:02X}", b)).collect())
+    }
+    
+    /// Describe the circuit a stream is attached to (None if it has no circuit yet)
+    pub fn circuit_path(stream: &arti_client::DataStream) -> Option<Vec<CircuitHop>> {
+        let circuit = stream.ctrl().circuit()?;
+        let path = circuit.path_ref();
+        let hops = path.hops();
+        let last = hops.len().saturating_sub(1);
         
-        let stream = self.client
-            .connect((host, port))
-            .await
-            .map_err(|e| format!("Tor stream connection failed: {}", e))?;
+        Some(
+            hops.iter()
+                .enumerate()
+                .map(|(i, hop)| CircuitHop {
+                    role: match i {
+                        0 => "guard",
+                        i if i == last => "exit",
+                        _ => "middle",
+                    }
+                    .to_string(),
+                    relay: hop.to_string(),
+                    country: None,
+                    address: hop
+                        .as_chan_target()
+                        .and_then(|target| target.addrs().first())
+                        .map(|addr| addr.ip()),
+                })
+                .collect(),
+        )
+    }
+    
+    /// Publish `service` as an onion service, forwarding inbound connections
+    /// on its virtual port to the local port
+    pub fn launch_onion_service(&self, service: OnionServicePort) -> Result<HostedOnionService> {
+        let failed = |e: String| PrivacyError::TorConnect(format!("onion service for local port {}: {}", service.local_port, e));
         
-        Ok(stream)
+        let nickname = service.nickname().parse().map_err(|e| failed(format!("{}", e)))?;
+        let config = OnionServiceConfigBuilder::default()
+            .nickname(nickname)
+            .build()
+            .map_err(|e| failed(e.to_string()))?;
+        let (running, rend_requests) = self.client
+            .launch_onion_service(config)
+            .map_err(|e| failed(e.to_string()))?;
+        let hostname = running
+            .onion_name()
+            .ok_or_else(|| failed("no onion address was generated".to_string()))?
+            .to_string();
+        
+        let forwarder = tokio::spawn(async move {
+            let mut streams = Box::pin(tor_hsservice::handle_rend_requests(rend_requests));
+            while let Some(request) = streams.next().await {
+                let wanted = matches!(request.request(), IncomingStreamRequest::Begin(begin) if begin.port() == service.virtual_port);
+                if !wanted {
+                    let _ = request.shutdown_circuit();
+                    continue;
+                }
+                
+                tokio::spawn(async move {
+                    let forwarded = match request.accept(Connected::new_empty()).await {
+                        Ok(stream) => onion_service::forward_to_local(stream, service.local_port).await,
+                        Err(e) => Err(std::io::Error::other(e)),
+                    };
+                    if let Err(e) = forwarded {
+                        warn!("Onion service stream to local port {} failed: {}", service.local_port, e);
+                    }
+                });
+            }
+        })
+        .abort_handle();
+        
+        info!("🧅 Onion service {}:{} -> 127.0.0.1:{}", hostname, service.virtual_port, service.local_port);
+        Ok(HostedOnionService {
+            info: OnionServiceInfo {
+                hostname,
+                virtual_port: service.virtual_port,
+                local_port: service.local_port,
+            },
+            _service: running,
+            forwarder,
+        })
     }
     
-    pub async fn check_connection(&self) -> Result<bool, Box<dyn std::error::Error>> {
+    pub async fn check_connection(&self) -> std::result::Result<bool, Box<dyn std::error::Error>> {
         // Test connection by fetching Tor check page
         info!("Testing Tor connection...");
         
@@ -145,3 +752,805 @@ impl TorNetwork {
         }
     }
 }
+
+#[async_trait]
+impl Transport for TorNetwork {
+    async fn connect_stream(&self, host: &str, port: u16) -> Result<OpenedStream> {
+        let mut result = self.open_stream(host, port).await;
+        if self.circuit_failed(&result, host) {
+            info!("Retrying stream to {}:{} on a fresh circuit", host, port);
+            result = self.open_stream(host, port).await;
+            self.circuit_failed(&result, host);
+        }
+        
+        let stream = result?;
+        Ok(OpenedStream {
+            circuit: Self::circuit_path(&stream),
+            stream: Box::new(stream),
+        })
+    }
+    
+    async fn route_request(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        fingerprint: &BrowserFingerprint,
+        connect_host: &str,
+    ) -> Result<Response<Full<Bytes>>> {
+        let uri = req.uri().clone();
+        let method = req.method().clone();
+        let keep_alive = self.keep_alive && wants_keep_alive(req.version(), req.headers());
+        
+        let result = self.send_request(&method, &uri, fingerprint, connect_host, keep_alive).await;
+        // A POST that reached the origin may have taken effect, so only a
+        // request that never got that far, or one safe to repeat, is resent
+        let resendable = method.is_idempotent() || result.as_ref().is_err_and(|failure| !failure.request_sent);
+        let result = result.map_err(|failure| failure.error);
+        if self.circuit_failed(&result, connect_host) && resendable {
+            info!("Retrying {} {} on a fresh circuit", method, uri);
+            let retry = self.send_request(&method, &uri, fingerprint, connect_host, keep_alive).await.map_err(|failure| failure.error);
+            self.circuit_failed(&retry, connect_host);
+            return retry;
+        }
+        result
+    }
+    
+    async fn fetch(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        fingerprint: &BrowserFingerprint,
+        connect_host: &str,
+    ) -> Result<Response<Full<Bytes>>> {
+        let result = self.send_request(method, uri, fingerprint, connect_host, false).await.map_err(|failure| failure.error);
+        self.circuit_failed(&result, connect_host);
+        result
+    }
+    
+    /// Number of times the circuit has been rebuilt (automatically or via `new_identity`)
+    fn circuit_rebuilds(&self) -> u64 {
+        self.circuit_rebuilds.load(Ordering::Relaxed)
+    }
+    
+    fn circuit_rotations(&self) -> u64 {
+        self.circuit_rotations.load(Ordering::Relaxed)
+    }
+    
+    fn ready_for_traffic(&self) -> Option<tokio::sync::watch::Receiver<bool>> {
+        Some(self.ready.clone())
+    }
+}
+
+/// Follow `client`'s bootstrap events: arti reports it is no longer ready
+/// for traffic when it loses the network (say, a dropped Wi-Fi) and ready
+/// again once it can build circuits, all within one session
+fn watch_readiness(client: &TorClient<UpstreamRuntime>) -> tokio::sync::watch::Receiver<bool> {
+    let (ready_tx, ready) = tokio::sync::watch::channel(true);
+    let mut events = client.bootstrap_events();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                status = events.next() => match status {
+                    Some(status) => {
+                        let now_ready = status.ready_for_traffic();
+                        ready_tx.send_if_modified(|ready| std::mem::replace(ready, now_ready) != now_ready);
+                    }
+                    None => break,
+                },
+                _ = ready_tx.closed() => break,
+            }
+        }
+    });
+    ready
+}
+
+/// Target port of `uri` and the HTTP/1.1 request to send for it, with the
+/// headers of the randomized `fingerprint`
+pub(crate) fn upstream_request(
+    method: &Method,
+    uri: &Uri,
+    fingerprint: &BrowserFingerprint,
+    keep_alive: bool,
+) -> Result<(u16, String)> {
+    // Extract host and port
+    let host = uri.host().ok_or_else(|| PrivacyError::Http("No host in URI".to_string()))?;
+    let default_port = if uri.scheme_str() == Some("https") { 443 } else { 80 };
+    let port = uri.port_u16().unwrap_or(default_port);
+    // The Host header names the port too, unless it is the scheme's default
+    let host_header = if port == default_port { host.to_string() } else { format!("{}:{}", host, port) };
+    
+    // Get path with query
+    let path_and_query = uri.path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    
+    // Client hints must match the User-Agent (Chrome sends them, Firefox doesn't)
+    let client_hints: String = fingerprint
+        .client_hint_headers()
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect();
+    
+    // Build proper HTTP/1.1 request with randomized fingerprint
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\n{}Accept: text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8\r\nAccept-Language: {}\r\nAccept-Encoding: {}\r\nConnection: {}\r\n\r\n",
+        method,
+        path_and_query,
+        host_header,
+        fingerprint.user_agent,
+        client_hints,
+        fingerprint.accept_language,
+        fingerprint.accept_encoding,
+        if keep_alive { "keep-alive" } else { "close" }
+    );
+    Ok((port, request))
+}
+
+/// Hop-by-hop headers that describe the upstream connection, not the content
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "te",
+    "trailer",
+    "upgrade",
+    "content-length",
+];
+
+/// Send a raw HTTP/1.1 request over `stream` and read back the full response.
+///
+/// Fails with `PrivacyError::ResponseTooLarge` as soon as more than
+/// `max_bytes` arrive.
+pub(crate) async fn exchange<S>(
+    mut stream: S,
+    request: &[u8],
+    max_bytes: usize,
+) -> Result<Response<Full<Bytes>>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(request).await?;
+    stream.flush().await?;
+    
+    // Read response with timeout
+    let read_result = tokio::time::timeout(
+        std::time::Duration::from_secs(30),
+        read_limited(&mut stream, max_bytes)
+    ).await;
+    
+    match read_result {
+        Ok(Ok(response_bytes)) => {
+            info!("✓ Received response through Tor ({} bytes)", response_bytes.len());
+            parse_http_response(&response_bytes)
+        }
+        Ok(Err(e)) => Err(e),
+        Err(_) => {
+            Err(PrivacyError::TorConnect("Request timeout after 30 seconds".to_string()))
+        }
+    }
+}
+
+/// Send a request on a persistent connection and read exactly one response.
+///
+/// Returns the response and whether the connection can carry another
+/// request. Fails with `PrivacyError::Io` if the connection was closed
+/// before any response arrived (e.g. an idle connection the origin dropped).
+pub(crate) async fn exchange_keep_alive<S>(
+    stream: &mut S,
+    request: &[u8],
+    max_bytes: usize,
+    head_request: bool,
+) -> Result<(Response<Full<Bytes>>, bool)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(request).await?;
+    stream.flush().await?;
+    
+    let read_result = tokio::time::timeout(
+        std::time::Duration::from_secs(30),
+        read_response(stream, max_bytes, head_request)
+    ).await;
+    
+    match read_result {
+        Ok(Ok((response_bytes, reusable))) => {
+            info!("✓ Received response through Tor ({} bytes, reusable: {})", response_bytes.len(), reusable);
+            Ok((parse_http_response(&response_bytes)?, reusable))
+        }
+        Ok(Err(e)) => Err(e),
+        Err(_) => {
+            Err(PrivacyError::TorConnect("Request timeout after 30 seconds".to_string()))
+        }
+    }
+}
+
+/// How the end of a response body is found
+enum BodyFraming {
+    Length(usize),
+    Chunked,
+    UntilEof,
+}
+
+/// Read one response, stopping at the end of its body rather than at EOF
+async fn read_response<S>(
+    stream: &mut S,
+    max_bytes: usize,
+    head_request: bool,
+) -> Result<(Vec<u8>, bool)>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut data = Vec::new();
+    
+    let header_len = loop {
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if read_more(stream, &mut data, max_bytes).await? == 0 {
+            return Err(if data.is_empty() {
+                std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "connection closed before a response").into()
+            } else {
+                PrivacyError::Http("Connection closed mid-headers".to_string())
+            });
+        }
+    };
+    
+    let (framing, close) = {
+        let mut headers = [httparse::EMPTY_HEADER; 128];
+        let mut response = httparse::Response::new(&mut headers);
+        response
+            .parse(&data[..header_len])
+            .map_err(|e| PrivacyError::Http(format!("Bad response head: {}", e)))?;
+        
+        let header = |name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .map(|h| String::from_utf8_lossy(h.value).to_ascii_lowercase())
+        };
+        let status = response.code.unwrap_or(200);
+        let connection = header("connection").unwrap_or_default();
+        let close = connection.contains("close")
+            || (response.version == Some(0) && !connection.contains("keep-alive"));
+        
+        let framing = if head_request || status / 100 == 1 || status == 204 || status == 304 {
+            BodyFraming::Length(0)
+        } else if header("transfer-encoding").is_some_and(|v| v.contains("chunked")) {
+            BodyFraming::Chunked
+        } else if let Some(len) = header("content-length").and_then(|v| v.trim().parse().ok()) {
+            BodyFraming::Length(len)
+        } else {
+            BodyFraming::UntilEof
+        };
+        (framing, close)
+    };
+    
+    match framing {
+        BodyFraming::Length(len) => {
+            let total = header_len + len;
+            if total > max_bytes {
+                return Err(PrivacyError::ResponseTooLarge { limit: max_bytes });
+            }
+            while data.len() < total {
+                if read_more(stream, &mut data, max_bytes).await? == 0 {
+                    return Err(PrivacyError::Http("Truncated response body".to_string()));
+                }
+            }
+            data.truncate(total);
+            Ok((data, !close))
+        }
+        BodyFraming::Chunked => loop {
+            if let Some(body_len) = chunked_message_len(&data[header_len..]) {
+                data.truncate(header_len + body_len);
+                return Ok((data, !close));
+            }
+            if read_more(stream, &mut data, max_bytes).await? == 0 {
+                return Err(PrivacyError::Http("Truncated chunked body".to_string()));
+            }
+        },
+        BodyFraming::UntilEof => {
+            while read_more(stream, &mut data, max_bytes).await? > 0 {}
+            Ok((data, false))
+        }
+    }
+}
+
+/// Append the next read to `data`, enforcing `max_bytes`; returns 0 at EOF
+async fn read_more<S>(stream: &mut S, data: &mut Vec<u8>, max_bytes: usize) -> Result<usize>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = [0u8; 16 * 1024];
+    let n = stream.read(&mut buf).await?;
+    if data.len() + n > max_bytes {
+        error!("✗ Response exceeded {} byte limit, aborting read", max_bytes);
+        return Err(PrivacyError::ResponseTooLarge { limit: max_bytes });
+    }
+    data.extend_from_slice(&buf[..n]);
+    Ok(n)
+}
+
+/// Length of a complete chunked body (including trailers), or None if more data is needed
+fn chunked_message_len(data: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    
+    loop {
+        let line_end = pos + data[pos..].windows(2).position(|w| w == b"\r\n")?;
+        let size_line = std::str::from_utf8(&data[pos..line_end]).ok()?;
+        let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+        pos = line_end + 2;
+        
+        if size == 0 {
+            // Trailers end with an empty line
+            loop {
+                let line_end = pos + data[pos..].windows(2).position(|w| w == b"\r\n")?;
+                let empty = line_end == pos;
+                pos = line_end + 2;
+                if empty {
+                    return Some(pos);
+                }
+            }
+        }
+        
+        pos += size + 2;
+        if pos > data.len() {
+            return None;
+        }
+    }
+}
+
+/// Read until EOF, giving up once the response is larger than `max_bytes`
+async fn read_limited<S>(
+    stream: &mut S,
+    max_bytes: usize,
+) -> Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut response_bytes = Vec::new();
+    let mut buf = [0u8; 16 * 1024];
+    
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(response_bytes);
+        }
+        if response_bytes.len() + n > max_bytes {
+            error!("✗ Response exceeded {} byte limit, aborting read", max_bytes);
+            return Err(PrivacyError::ResponseTooLarge { limit: max_bytes });
+        }
+        response_bytes.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Parse a raw HTTP/1.x response, keeping the body as raw bytes.
+///
+/// Only the header block is interpreted as text; the body is passed through
+/// untouched (apart from removing chunked framing) so images, PDFs and other
+/// binary downloads aren't corrupted.
+pub fn parse_http_response(raw: &[u8]) -> Result<Response<Full<Bytes>>> {
+    let header_end = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos,
+        // No proper HTTP response, return raw data
+        None => return Ok(Response::new(Full::new(Bytes::copy_from_slice(raw)))),
+    };
+    
+    let head = String::from_utf8_lossy(&raw[..header_end]);
+    let body = &raw[header_end + 4..];
+    
+    let mut lines = head.lines();
+    let status_line = lines.next().unwrap_or("No status line");
+    let status: u16 = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(200);
+    
+    let mut builder = Response::builder().status(status);
+    let mut chunked = false;
+    let mut content_type = None;
+    
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim();
+            let value = value.trim();
+            
+            if name.eq_ignore_ascii_case("transfer-encoding") && value.to_ascii_lowercase().contains("chunked") {
+                chunked = true;
+            }
+            if name.eq_ignore_ascii_case("content-type") {
+                content_type = Some(value.to_string());
+            }
+            if HOP_BY_HOP_HEADERS.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+                continue;
+            }
+            
+            builder = builder.header(name, value);
+        }
+    }
+    
+    let body = if chunked {
+        decode_chunked(body)?
+    } else {
+        body.to_vec()
+    };
+    
+    info!(
+        "Response: {} ({}, {} bytes)",
+        status_line,
+        content_type.as_deref().unwrap_or("no content type"),
+        body.len()
+    );
+    
+    Ok(builder.body(Full::new(Bytes::from(body)))?)
+}
+
+/// Remove `Transfer-Encoding: chunked` framing from a body
+fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+    let malformed = || PrivacyError::Http("Malformed chunked body".to_string());
+    let mut decoded = Vec::with_capacity(data.len());
+    
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(malformed)?;
+        let size_line = std::str::from_utf8(&data[..line_end]).map_err(|_| malformed())?;
+        let size_hex = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_hex, 16).map_err(|_| malformed())?;
+        data = &data[line_end + 2..];
+        
+        if size == 0 {
+            break;
+        }
+        if data.len() < size {
+            return Err(PrivacyError::Http("Truncated chunked body".to_string()));
+        }
+        
+        decoded.extend_from_slice(&data[..size]);
+        data = data.get(size + 2..).unwrap_or(&[]);
+    }
+    
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// A valid 1x1 PNG (contains bytes that are not valid UTF-8)
+    const PNG: [u8; 70] = [
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d,
+        0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+        0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4, 0x89, 0x00, 0x00, 0x00,
+        0x0d, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0xf8, 0xcf, 0xd0, 0xf0,
+        0x1f, 0x00, 0x06, 0x00, 0x02, 0x7f, 0x21, 0x02, 0x73, 0x3b, 0x00, 0x00,
+        0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+    ];
+
+    /// Serve a single canned response on a local port
+    async fn mock_upstream(response: Vec<u8>) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            socket.write_all(&response).await.unwrap();
+        });
+        
+        addr
+    }
+
+    async fn fetch_body(upstream: std::net::SocketAddr) -> (hyper::http::response::Parts, Vec<u8>) {
+        use http_body_util::BodyExt;
+        
+        let stream = TcpStream::connect(upstream).await.unwrap();
+        let request = b"GET /pixel.png HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let response = exchange(stream, request, DEFAULT_MAX_RESPONSE_BYTES).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes().to_vec();
+        (parts, bytes)
+    }
+
+    fn sha256(data: &[u8]) -> Vec<u8> {
+        ring::digest::digest(&ring::digest::SHA256, data).as_ref().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_png_body_is_byte_identical() {
+        let mut upstream_response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+            PNG.len()
+        ).into_bytes();
+        upstream_response.extend_from_slice(&PNG);
+        
+        let (parts, body) = fetch_body(mock_upstream(upstream_response).await).await;
+        
+        assert_eq!(parts.status, 200);
+        assert_eq!(parts.headers["content-type"], "image/png");
+        assert_eq!(sha256(&body), sha256(&PNG));
+    }
+
+    #[tokio::test]
+    async fn test_chunked_png_body_is_byte_identical() {
+        let (first, second) = PNG.split_at(30);
+        let mut upstream_response = b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for chunk in [first, second] {
+            upstream_response.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            upstream_response.extend_from_slice(chunk);
+            upstream_response.extend_from_slice(b"\r\n");
+        }
+        upstream_response.extend_from_slice(b"0\r\n\r\n");
+        
+        let (parts, body) = fetch_body(mock_upstream(upstream_response).await).await;
+        
+        assert!(parts.headers.get("transfer-encoding").is_none());
+        assert_eq!(sha256(&body), sha256(&PNG));
+    }
+
+    #[tokio::test]
+    async fn test_response_size_guard_fires() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap();
+        
+        // Keep streaming well past the limit; the reader must stop on its own
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            let _ = socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\r\n").await;
+            let chunk = [0u8; 8192];
+            for _ in 0..64 {
+                if socket.write_all(&chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+        
+        let stream = TcpStream::connect(upstream).await.unwrap();
+        let request = b"GET /big HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let err = exchange(stream, request, 64 * 1024).await.unwrap_err();
+        
+        assert!(matches!(err, PrivacyError::ResponseTooLarge { limit } if limit == 64 * 1024));
+    }
+
+    #[tokio::test]
+    async fn test_two_keep_alive_requests_share_one_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap();
+        
+        // Answer every request on the first connection, then refuse any other
+        let accepted = Arc::new(AtomicU64::new(0));
+        let server_accepted = accepted.clone();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            server_accepted.fetch_add(1, Ordering::SeqCst);
+            let bodies = [&b"first"[..], &b"second"[..]];
+            for body in bodies {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n", body.len());
+                socket.write_all(head.as_bytes()).await.unwrap();
+                socket.write_all(body).await.unwrap();
+            }
+            while listener.accept().await.is_ok() {
+                server_accepted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        
+        let pool = StreamPool::new();
+        pool.put("localhost", upstream.port(), TcpStream::connect(upstream).await.unwrap());
+        
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n";
+        for expected in ["first", "second"] {
+            use http_body_util::BodyExt;
+            
+            let mut stream = pool.take("localhost", upstream.port()).expect("stream was returned to the pool");
+            let (response, reusable) = exchange_keep_alive(&mut stream, request, DEFAULT_MAX_RESPONSE_BYTES, false).await.unwrap();
+            assert!(reusable);
+            pool.put("localhost", upstream.port(), stream);
+            
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, expected);
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_connection_close_and_chunked_framing() {
+        let mut chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n".to_vec();
+        chunked.extend_from_slice(b"5\r\nhello\r\n0\r\n\r\n");
+        let mut stream = TcpStream::connect(mock_upstream(chunked).await).await.unwrap();
+        
+        let (response, reusable) = exchange_keep_alive(&mut stream, b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n", 1024, false).await.unwrap();
+        assert!(!reusable, "the origin asked to close the connection");
+        assert_eq!(response.status(), 200);
+        
+        let mut headers = HeaderMap::new();
+        assert!(wants_keep_alive(Version::HTTP_11, &headers));
+        assert!(!wants_keep_alive(Version::HTTP_10, &headers));
+        headers.insert(header::CONNECTION, "Close".parse().unwrap());
+        assert!(!wants_keep_alive(Version::HTTP_11, &headers));
+        
+        assert_eq!(chunked_message_len(b"5\r\nhello\r\n0\r\n"), None);
+        assert_eq!(chunked_message_len(b"5\r\nhello\r\n0\r\nX-Trailer: 1\r\n\r\n"), Some(29));
+    }
+
+    #[test]
+    fn test_host_header_keeps_a_non_default_port() {
+        let fingerprint = BrowserFingerprint::random();
+        let host_line = |uri: &str| {
+            let (port, request) = upstream_request(&Method::GET, &uri.parse().unwrap(), &fingerprint, false).unwrap();
+            (port, request.lines().nth(1).unwrap().to_string())
+        };
+        assert_eq!(host_line("http://example.com/"), (80, "Host: example.com".to_string()));
+        assert_eq!(host_line("http://example.com:80/"), (80, "Host: example.com".to_string()));
+        assert_eq!(host_line("http://example.com:8080/"), (8080, "Host: example.com:8080".to_string()));
+        assert_eq!(host_line("http://[::1]:8080/"), (8080, "Host: [::1]:8080".to_string()));
+    }
+
+    #[test]
+    fn test_preserves_status_code() {
+        let response = parse_http_response(b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\n\r\nmissing").unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[test]
+    fn test_repeated_failures_across_hosts_trigger_rebuild() {
+        let mut tracker = FailureTracker::new(3);
+        
+        // The same dead host failing over and over is not the circuit's fault
+        for _ in 0..5 {
+            assert!(!tracker.record_failure("down.example"));
+        }
+        assert!(!tracker.record_failure("a.example"));
+        assert!(tracker.record_failure("b.example"));
+        
+        // The streak starts over after a rebuild
+        assert!(!tracker.record_failure("c.example"));
+    }
+    
+    #[test]
+    fn test_success_resets_failure_streak() {
+        let mut tracker = FailureTracker::new(2);
+        assert!(!tracker.record_failure("a.example"));
+        tracker.record_success();
+        assert!(!tracker.record_failure("b.example"));
+        assert!(tracker.record_failure("c.example"));
+        
+        let mut disabled = FailureTracker::new(0);
+        for host in ["a", "b", "c", "d"] {
+            assert!(!disabled.record_failure(host));
+        }
+    }
+    
+    #[tokio::test]
+    #[ignore = "bootstraps a real Tor client"]
+    async fn test_onion_service_descriptor_created() {
+        let data_dir = std::env::temp_dir().join(format!("privacy_suite_onion_tor_{}", std::process::id()));
+        let tor = TorNetwork::new(None, TOR_PATH_LENGTH, &data_dir).await.unwrap();
+        let hosted = tor.launch_onion_service(OnionServicePort { local_port: 8080, virtual_port: 80 }).unwrap();
+        
+        let hostname = &hosted.info.hostname;
+        // v3 addresses are 56 base32 characters
+        assert!(hostname.ends_with(".onion") && hostname.len() == 62, "{}", hostname);
+        assert_eq!((hosted.info.virtual_port, hosted.info.local_port), (80, 8080));
+    }
+    
+    #[test]
+    fn test_path_length_reaches_client_config() {
+        let data_dir = std::env::temp_dir().join(format!("privacy_suite_path_length_{}", std::process::id()));
+        assert!(client_config(TOR_PATH_LENGTH, &data_dir).is_ok());
+        
+        let err = client_config(2, &data_dir).err().unwrap();
+        assert!(matches!(err, PrivacyError::Config(ref msg) if msg.contains("too short")), "{}", err);
+        let err = client_config(5, &data_dir).err().unwrap();
+        assert!(matches!(err, PrivacyError::Config(ref msg) if msg.contains("always builds 3-relay")), "{}", err);
+    }
+    
+    #[test]
+    fn test_exit_relay_fingerprints_are_validated() {
+        let excluded = "$0011223344556677889900AABBCCDDEEFF001122";
+        let mut config = crate::config::Config::default();
+        config.exclude_exit_nodes = vec![excluded.to_string(), "a0b1 c2d3 e4f5 a6b7 c8d9 e0f1 a2b3 c4d5 e6f7 a8b9".to_string()];
+        
+        let exit_relays = ExitRelays::new(&config.exclude_exit_nodes, &config.allow_exit_nodes).unwrap();
+        assert!(!exit_relays.permits(Some("0011223344556677889900AABBCCDDEEFF001122")));
+        assert!(!exit_relays.permits(Some("A0B1C2D3E4F5A6B7C8D9E0F1A2B3C4D5E6F7A8B9")));
+        assert!(exit_relays.permits(Some("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF")));
+        // An unidentified exit can't be checked
+        assert!(!exit_relays.permits(None));
+        assert!(ExitRelays::default().permits(None));
+        
+        // Pinning is refused outright rather than retried into the ground
+        config.allow_exit_nodes = vec![excluded.to_string()];
+        let err = ExitRelays::new(&[], &config.allow_exit_nodes).err().unwrap();
+        assert!(matches!(err, PrivacyError::Config(ref msg) if msg.contains("allow_exit_nodes isn't supported")), "{}", err);
+        
+        for invalid in ["", "0011223344", "$0011223344556677889900AABBCCDDEEFF00112G", "exit.example.com"] {
+            let err = ExitRelays::new(&[invalid.to_string()], &[]).err().unwrap();
+            assert!(matches!(err, PrivacyError::Config(ref msg) if msg.contains("Invalid exit relay fingerprint")), "{}", err);
+        }
+    }
+    
+    #[test]
+    fn test_configured_data_dir_is_created() {
+        let data_dir = std::env::temp_dir().join(format!("privacy_suite_tor_data_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let mut config = crate::config::Config::default();
+        config.tor_data_dir = Some(data_dir.clone());
+        assert_eq!(config.resolved_tor_data_dir(), data_dir);
+        
+        client_config(TOR_PATH_LENGTH, &config.resolved_tor_data_dir()).unwrap();
+        assert!(data_dir.join("state").is_dir());
+        assert!(data_dir.join("cache").is_dir());
+        
+        // Unset, it sits next to the config file
+        config.tor_data_dir = None;
+        assert!(config.resolved_tor_data_dir().ends_with(".privacy_suite/tor"));
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+    
+    #[test]
+    fn test_probe_rejects_an_unusable_data_dir() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_tor_probe_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = crate::config::Config::default();
+        config.tor_data_dir = Some(dir.join("tor"));
+        assert_eq!(probe_support(&config), TorSupport::Supported);
+        
+        // A file where the data directory should be
+        std::fs::write(dir.join("blocked"), b"").unwrap();
+        config.tor_data_dir = Some(dir.join("blocked"));
+        match probe_support(&config) {
+            TorSupport::Unsupported { reason } => assert!(reason.contains("tor_data_dir"), "{}", reason),
+            TorSupport::Supported => panic!("a file can't hold Tor's state"),
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[test]
+    fn test_aged_circuit_not_reused_for_new_streams() {
+        let start = Instant::now();
+        let offset = Arc::new(Mutex::new(Duration::ZERO));
+        let clock_offset = offset.clone();
+        let clock: Clock = Arc::new(move || start + *clock_offset.lock().unwrap());
+        let advance = |by: Duration| *offset.lock().unwrap() += by;
+        
+        let mut lifetime = CircuitLifetime::with_clock(Duration::from_secs(600), clock.clone());
+        let (first, expired) = lifetime.token_for_new_stream();
+        assert!(!expired);
+        advance(Duration::from_secs(599));
+        assert_eq!(lifetime.token_for_new_stream(), (first, false));
+        
+        // Past its age, the next stream gets a fresh circuit, which is then reused
+        advance(Duration::from_secs(1));
+        let (second, expired) = lifetime.token_for_new_stream();
+        assert!(expired);
+        assert_ne!(second, first);
+        advance(Duration::from_secs(300));
+        assert_eq!(lifetime.token_for_new_stream(), (second, false));
+        
+        // A manual renewal restarts the clock
+        advance(Duration::from_secs(299));
+        lifetime.renew();
+        let (third, _) = lifetime.token_for_new_stream();
+        assert_ne!(third, second);
+        advance(Duration::from_secs(599));
+        assert_eq!(lifetime.token_for_new_stream(), (third, false));
+        
+        let mut unlimited = CircuitLifetime::with_clock(Duration::ZERO, clock);
+        let (token, _) = unlimited.token_for_new_stream();
+        advance(Duration::from_secs(100_000));
+        assert_eq!(unlimited.token_for_new_stream(), (token, false));
+    }
+}
@@ -0,0 +1,21 @@
+use std::future::Future;
+
+tokio::task_local! {
+    static TRACE_ID: String;
+}
+
+/// A short random id for one proxied request or tunnel
+pub fn new_id() -> String {
+    format!("{:08x}", rand::random::<u32>())
+}
+
+/// Run `f` with `trace_id` attached to every log entry it writes, through
+/// the router and Tor network alike
+pub async fn scope<F: Future>(trace_id: String, f: F) -> F::Output {
+    TRACE_ID.scope(trace_id, f).await
+}
+
+/// Trace id of the request being handled on this task, if any
+pub fn current() -> Option<String> {
+    TRACE_ID.try_with(|id| id.clone()).ok()
+}
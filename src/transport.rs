@@ -0,0 +1,328 @@
+use async_trait::async_trait;
+use hyper::{Method, Request, Response, Uri, body::Bytes};
+use http_body_util::Full;
+use std::io::{self, Error as IoError, ErrorKind};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tracing::info;
+use crate::error::{PrivacyError, Result};
+use crate::fingerprint::BrowserFingerprint;
+use crate::tor_network::{self, CircuitHop};
+
+/// A byte stream opened by a `Transport`
+pub trait TransportStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> TransportStream for T {}
+
+/// A stream to a destination, with the circuit carrying it when the transport has one
+pub struct OpenedStream {
+    pub stream: Box<dyn TransportStream>,
+    pub circuit: Option<Vec<CircuitHop>>,
+}
+
+/// How the router reaches destinations: the built-in Tor client, an
+/// external SOCKS5 proxy, or (in tests) a direct connection
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Open a raw stream to `host:port`, e.g. for a CONNECT tunnel
+    async fn connect_stream(&self, host: &str, port: u16) -> Result<OpenedStream>;
+
+    /// Send an HTTP request and buffer the response. `connect_host` is what
+    /// to connect to (the hostname, or an address already resolved locally);
+    /// the request's own host is still used for the Host header.
+    async fn route_request(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        fingerprint: &BrowserFingerprint,
+        connect_host: &str,
+    ) -> Result<Response<Full<Bytes>>>;
+
+    /// Send a bodiless `method` request for `uri` and buffer the response, e.g.
+    /// to repeat a request at a site's onion address
+    async fn fetch(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        fingerprint: &BrowserFingerprint,
+        connect_host: &str,
+    ) -> Result<Response<Full<Bytes>>>;
+
+    /// Number of times the transport rebuilt its circuit after failures
+    fn circuit_rebuilds(&self) -> u64 {
+        0
+    }
+
+    /// Number of times new streams moved to a fresh circuit because the old
+    /// one reached `Config::max_circuit_age_secs`
+    fn circuit_rotations(&self) -> u64 {
+        0
+    }
+
+    /// Whether the transport can carry traffic right now, for one that can
+    /// lose the network without the session ending; None if it can't
+    fn ready_for_traffic(&self) -> Option<watch::Receiver<bool>> {
+        None
+    }
+}
+
+/// Open a new stream with `transport` for a request and read the whole response
+async fn send_buffered<T: Transport + ?Sized>(
+    transport: &T,
+    method: &Method,
+    uri: &Uri,
+    fingerprint: &BrowserFingerprint,
+    connect_host: &str,
+    max_response_bytes: usize,
+) -> Result<Response<Full<Bytes>>> {
+    let (port, request) = tor_network::upstream_request(method, uri, fingerprint, false)?;
+    let opened = transport.connect_stream(connect_host, port).await?;
+    tor_network::exchange(opened.stream, request.as_bytes(), max_response_bytes).await
+}
+
+/// Connects straight to the destination from this machine.
+///
+/// Offers no anonymity at all, so it is never selected from the config; the
+/// router only uses it for private and local hosts no exit could reach
+/// (`PrivateHostPolicy::Bypass`), and it lets the router and proxy be
+/// exercised without Tor.
+#[derive(Clone)]
+pub struct DirectTransport {
+    max_response_bytes: usize,
+}
+
+impl DirectTransport {
+    pub fn new() -> Self {
+        Self { max_response_bytes: tor_network::DEFAULT_MAX_RESPONSE_BYTES }
+    }
+
+    /// Set the largest response `route_request` will buffer
+    pub fn with_max_response_bytes(mut self, max_response_bytes: usize) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+}
+
+impl Default for DirectTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Transport for DirectTransport {
+    async fn connect_stream(&self, host: &str, port: u16) -> Result<OpenedStream> {
+        let stream = TcpStream::connect((host, port)).await?;
+        Ok(OpenedStream { stream: Box::new(stream), circuit: None })
+    }
+
+    async fn route_request(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        fingerprint: &BrowserFingerprint,
+        connect_host: &str,
+    ) -> Result<Response<Full<Bytes>>> {
+        send_buffered(self, req.method(), req.uri(), fingerprint, connect_host, self.max_response_bytes).await
+    }
+
+    async fn fetch(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        fingerprint: &BrowserFingerprint,
+        connect_host: &str,
+    ) -> Result<Response<Full<Bytes>>> {
+        send_buffered(self, method, uri, fingerprint, connect_host, self.max_response_bytes).await
+    }
+}
+
+/// Sends everything through an external SOCKS5 proxy, such as a system Tor
+/// daemon on `127.0.0.1:9050`, instead of the built-in Tor client.
+///
+/// Hostnames are passed to the proxy unresolved, so DNS happens on the
+/// proxy's side just like with `DnsResolution::ExitDns`.
+#[derive(Clone)]
+pub struct Socks5Transport {
+    proxy_host: String,
+    proxy_port: u16,
+    max_response_bytes: usize,
+}
+
+impl Socks5Transport {
+    pub fn new(proxy_host: impl Into<String>, proxy_port: u16) -> Self {
+        Self {
+            proxy_host: proxy_host.into(),
+            proxy_port,
+            max_response_bytes: tor_network::DEFAULT_MAX_RESPONSE_BYTES,
+        }
+    }
+
+    /// Set the largest response `route_request` will buffer
+    pub fn with_max_response_bytes(mut self, max_response_bytes: usize) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+}
+
+#[async_trait]
+impl Transport for Socks5Transport {
+    async fn connect_stream(&self, host: &str, port: u16) -> Result<OpenedStream> {
+        info!("Connecting to {}:{} via SOCKS5 proxy {}:{}", host, port, self.proxy_host, self.proxy_port);
+
+        let mut stream = TcpStream::connect((self.proxy_host.as_str(), self.proxy_port))
+            .await
+            .map_err(|e| PrivacyError::TorConnect(format!("SOCKS5 proxy {}:{} unreachable: {}", self.proxy_host, self.proxy_port, e)))?;
+        socks5_connect(&mut stream, host, port)
+            .await
+            .map_err(|e| PrivacyError::TorConnect(format!("SOCKS5 connect to {}:{}: {}", host, port, e)))?;
+
+        Ok(OpenedStream { stream: Box::new(stream), circuit: None })
+    }
+
+    async fn route_request(
+        &self,
+        req: Request<hyper::body::Incoming>,
+        fingerprint: &BrowserFingerprint,
+        connect_host: &str,
+    ) -> Result<Response<Full<Bytes>>> {
+        send_buffered(self, req.method(), req.uri(), fingerprint, connect_host, self.max_response_bytes).await
+    }
+
+    async fn fetch(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        fingerprint: &BrowserFingerprint,
+        connect_host: &str,
+    ) -> Result<Response<Full<Bytes>>> {
+        send_buffered(self, method, uri, fingerprint, connect_host, self.max_response_bytes).await
+    }
+}
+
+/// SOCKS5 CONNECT to `host:port`, sending hostnames as domain names (ATYP 3)
+async fn socks5_connect<S>(stream: &mut S, host: &str, port: u16) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Greeting: version 5, one method, "no authentication"
+    stream.write_all(&[0x05, 0x01, 0x00]).await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice != [0x05, 0x00] {
+        return Err(IoError::new(ErrorKind::PermissionDenied, "SOCKS5 proxy requires authentication"));
+    }
+
+    let mut request = vec![0x05, 0x01, 0x00];
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(std::net::IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let name = u8::try_from(host.len())
+                .map_err(|_| IoError::new(ErrorKind::InvalidInput, "hostname too long for SOCKS5"))?;
+            request.push(0x03);
+            request.push(name);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0x00 {
+        let reason = match reply[1] {
+            0x02 => "not allowed by ruleset",
+            0x03 => "network unreachable",
+            0x04 => "host unreachable",
+            0x05 => "connection refused",
+            _ => "general failure",
+        };
+        return Err(IoError::new(ErrorKind::ConnectionRefused, format!("SOCKS5 error: {}", reason)));
+    }
+
+    // Skip the bound address the proxy reports back
+    let addr_len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        }
+        _ => return Err(IoError::new(ErrorKind::InvalidData, "Malformed SOCKS5 reply")),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Minimal SOCKS5 server that records the requested target and then
+    /// connects to `destination`, whatever was asked for
+    async fn socks5_server(destination: std::net::SocketAddr) -> (std::net::SocketAddr, tokio::sync::oneshot::Receiver<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+
+        tokio::spawn(async move {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            client.read_exact(&mut greeting).await.unwrap();
+            client.write_all(&[0x05, 0x00]).await.unwrap();
+
+            let mut head = [0u8; 5];
+            client.read_exact(&mut head).await.unwrap();
+            assert_eq!(head[3], 0x03, "hostname should not be resolved locally");
+            let mut target = vec![0u8; head[4] as usize + 2];
+            client.read_exact(&mut target).await.unwrap();
+            tx.send(target).unwrap();
+
+            let mut upstream = TcpStream::connect(destination).await.unwrap();
+            client.write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 0]).await.unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+        });
+
+        (addr, rx)
+    }
+
+    #[tokio::test]
+    async fn test_socks5_transport_tunnels_by_hostname() {
+        let destination = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let destination_addr = destination.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = destination.accept().await.unwrap();
+            socket.write_all(b"hello from upstream").await.unwrap();
+        });
+        let (proxy, target) = socks5_server(destination_addr).await;
+
+        let transport = Socks5Transport::new("127.0.0.1", proxy.port());
+        let mut opened = transport.connect_stream("example.onion", 8080).await.unwrap();
+        let mut received = String::new();
+        opened.stream.read_to_string(&mut received).await.unwrap();
+
+        assert_eq!(received, "hello from upstream");
+        assert!(opened.circuit.is_none());
+        assert_eq!(target.await.unwrap(), [b"example.onion".as_slice(), &8080u16.to_be_bytes()].concat());
+    }
+
+    #[tokio::test]
+    async fn test_socks5_transport_reports_unreachable_proxy() {
+        let unused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = unused.local_addr().unwrap().port();
+        drop(unused);
+
+        let err = Socks5Transport::new("127.0.0.1", port).connect_stream("example.com", 80).await.err().unwrap();
+        assert!(matches!(err, PrivacyError::TorConnect(ref msg) if msg.contains("unreachable")), "{}", err);
+    }
+}
@@ -0,0 +1,359 @@
+use crate::config::{Config, TrustedNetworkMode};
+use crate::web_api::ApiState;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::info;
+
+/// How often the watcher looks for a network change
+pub const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What identifies the network the machine is on
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkIdentity {
+    /// Name of the Wi-Fi network, if on Wi-Fi
+    pub ssid: Option<String>,
+    pub gateway: Option<IpAddr>,
+    /// Hardware address of the gateway, lowercase and colon-separated
+    pub gateway_mac: Option<String>,
+}
+
+/// Whether the current network is trusted, as shown by `/api/diagnostics`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustEvaluation {
+    pub identity: NetworkIdentity,
+    pub trusted: bool,
+    /// Entry of `trusted_ssids` or `trusted_gateways` that matched
+    pub matched: Option<String>,
+    pub mode: TrustedNetworkMode,
+}
+
+/// Normalize a hardware address to lowercase, colon-separated form
+fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_ascii_lowercase().replace('-', ":")
+}
+
+/// Check `identity` against the trusted SSIDs and gateways in `config`.
+/// Gateway entries are either an IP address or a MAC address; the MAC is
+/// the stronger match, as home routers all share a handful of addresses.
+///
+/// Anyone can name a hotspot after a trusted SSID, so once `trusted_ssids`
+/// are set a Wi-Fi network is trusted only when its SSID is listed and its
+/// gateway's MAC matches a `trusted_gateways` entry too. Gateway entries
+/// alone still decide off Wi-Fi.
+pub fn evaluate(config: &Config, identity: NetworkIdentity) -> TrustEvaluation {
+    let by_mac = |trusted: &&String| identity.gateway_mac.as_deref() == Some(normalize_mac(trusted).as_str());
+    let by_gateway = |trusted: &&String| match trusted.trim().parse::<IpAddr>() {
+        Ok(ip) => identity.gateway == Some(ip),
+        Err(_) => by_mac(trusted),
+    };
+    let matched = match &identity.ssid {
+        Some(ssid) if !config.trusted_ssids.is_empty() => config
+            .trusted_ssids
+            .iter()
+            .find(|trusted| *trusted == ssid)
+            .filter(|_| config.trusted_gateways.iter().any(|trusted| by_mac(&trusted))),
+        _ => config.trusted_gateways.iter().find(by_gateway),
+    }
+    .cloned();
+
+    TrustEvaluation { trusted: matched.is_some(), matched, identity, mode: config.trusted_network_mode }
+}
+
+/// What the suite should do about a change of network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustAction {
+    /// Disconnect from Tor and let traffic go out directly
+    Disconnect,
+    /// Stay on Tor but turn the kill switch off
+    RelaxKillSwitch,
+    /// Connect if not connected, and turn the kill switch back on if it was relaxed
+    Engage { restore_kill_switch: bool },
+    Nothing,
+}
+
+/// Tracks whether the machine is on a trusted network, so protection is
+/// changed once per move between trusted and untrusted networks and the
+/// user's own toggles in between are left alone
+#[derive(Debug, Default)]
+pub struct TrustTracker {
+    trusted: Option<bool>,
+    /// The kill switch was on before a trusted network relaxed it
+    relaxed_kill_switch: bool,
+}
+
+impl TrustTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_evaluation(&mut self, evaluation: &TrustEvaluation, connected: bool, kill_switch_on: bool) -> TrustAction {
+        if self.trusted == Some(evaluation.trusted) {
+            return TrustAction::Nothing;
+        }
+        self.trusted = Some(evaluation.trusted);
+
+        if evaluation.trusted {
+            return match evaluation.mode {
+                TrustedNetworkMode::Off if connected => TrustAction::Disconnect,
+                TrustedNetworkMode::Relaxed if kill_switch_on => {
+                    self.relaxed_kill_switch = true;
+                    TrustAction::RelaxKillSwitch
+                }
+                _ => TrustAction::Nothing,
+            };
+        }
+
+        let restore_kill_switch = std::mem::take(&mut self.relaxed_kill_switch);
+        if connected && !restore_kill_switch {
+            TrustAction::Nothing
+        } else {
+            TrustAction::Engage { restore_kill_switch }
+        }
+    }
+}
+
+/// Follow network changes for as long as the app runs, relaxing protection
+/// on trusted networks and engaging it on every other one
+pub async fn watch(state: ApiState) {
+    let mut tracker = TrustTracker::new();
+    info!("📶 Trusted networks enabled");
+
+    loop {
+        let identity = tokio::task::spawn_blocking(current_identity).await.unwrap_or_default();
+        let evaluation = evaluate(&*state.config.read().await, identity);
+
+        let connected = state.is_connected().await;
+        let kill_switch_on = match &state.kill_switch {
+            Some(kill_switch) => kill_switch.get_stats().await.active,
+            None => false,
+        };
+        let action = tracker.on_evaluation(&evaluation, connected, kill_switch_on);
+        state.apply_trust(evaluation, action).await;
+
+        tokio::time::sleep(NETWORK_CHECK_INTERVAL).await;
+    }
+}
+
+/// Identity of the network the machine is on now. Fields that can't be
+/// read stay empty, which never matches a trusted network.
+pub fn current_identity() -> NetworkIdentity {
+    let gateway = default_gateway();
+    NetworkIdentity {
+        ssid: current_ssid(),
+        gateway,
+        gateway_mac: gateway.and_then(gateway_mac),
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let mut command = std::process::Command::new(program);
+    command.args(args);
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    let output = command.output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Gateway of the default route in `/proc/net/route`
+pub fn parse_proc_route(table: &str) -> Option<IpAddr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        // Stored in host (little-endian) order
+        (gateway != 0).then(|| IpAddr::from(gateway.to_le_bytes()))
+    })
+}
+
+/// Hardware address `/proc/net/arp` lists for `ip`
+pub fn parse_proc_arp(table: &str, ip: IpAddr) -> Option<String> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let mac = *fields.get(3)?;
+        (fields.first()?.parse::<IpAddr>().ok()? == ip && mac != "00:00:00:00:00:00").then(|| normalize_mac(mac))
+    })
+}
+
+/// SSID in the output of `netsh wlan show interfaces`
+pub fn parse_netsh_ssid(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "SSID").then(|| value.trim().to_string()).filter(|ssid| !ssid.is_empty())
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn default_gateway() -> Option<IpAddr> {
+    parse_proc_route(&std::fs::read_to_string("/proc/net/route").ok()?)
+}
+
+#[cfg(target_os = "linux")]
+fn gateway_mac(gateway: IpAddr) -> Option<String> {
+    parse_proc_arp(&std::fs::read_to_string("/proc/net/arp").ok()?, gateway)
+}
+
+#[cfg(target_os = "linux")]
+fn current_ssid() -> Option<String> {
+    command_output("iwgetid", &["-r"])
+        .map(|ssid| ssid.trim().to_string())
+        .filter(|ssid| !ssid.is_empty())
+}
+
+#[cfg(target_os = "windows")]
+fn default_gateway() -> Option<IpAddr> {
+    // "0.0.0.0  0.0.0.0  <gateway>  <interface>  <metric>"
+    command_output("route", &["print", "-4", "0.0.0.0"])?.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["0.0.0.0", "0.0.0.0", gateway, ..] => gateway.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn gateway_mac(gateway: IpAddr) -> Option<String> {
+    // "  192.168.1.1           aa-bb-cc-dd-ee-ff     dynamic"
+    let ip = gateway.to_string();
+    command_output("arp", &["-a", &ip])?.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [address, mac, ..] if *address == ip => Some(normalize_mac(mac)),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn current_ssid() -> Option<String> {
+    parse_netsh_ssid(&command_output("netsh", &["wlan", "show", "interfaces"])?)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn default_gateway() -> Option<IpAddr> {
+    // "    gateway: 192.168.1.1"
+    command_output("route", &["-n", "get", "default"])?
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("gateway:")?.trim().parse().ok())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn gateway_mac(gateway: IpAddr) -> Option<String> {
+    // "? (192.168.1.1) at aa:bb:cc:dd:ee:ff on en0 ifscope [ethernet]"
+    let output = command_output("arp", &["-n", &gateway.to_string()])?;
+    let mac = output.split(" at ").nth(1)?.split_whitespace().next()?;
+    mac.contains(':').then(|| normalize_mac(mac))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn current_ssid() -> Option<String> {
+    // "Current Wi-Fi Network: HomeNet"
+    let output = command_output("networksetup", &["-getairportnetwork", "en0"])?;
+    output
+        .trim()
+        .split_once("Network: ")
+        .map(|(_, ssid)| ssid.to_string())
+        .filter(|ssid| !ssid.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(mode: TrustedNetworkMode) -> Config {
+        let mut config = Config::default();
+        config.trusted_ssids = vec!["HomeNet".to_string()];
+        config.trusted_gateways = vec!["AA-BB-CC-DD-EE-FF".to_string(), "10.0.0.1".to_string()];
+        config.trusted_network_mode = mode;
+        config
+    }
+
+    fn on_wifi(ssid: &str) -> NetworkIdentity {
+        NetworkIdentity {
+            ssid: Some(ssid.to_string()),
+            gateway: "192.168.1.1".parse().ok(),
+            gateway_mac: Some("aa:bb:cc:dd:ee:ff".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_trust_decided_by_ssid_or_gateway() {
+        let config = config(TrustedNetworkMode::Off);
+
+        let home = evaluate(&config, on_wifi("HomeNet"));
+        assert!(home.trusted);
+        assert_eq!(home.matched.as_deref(), Some("HomeNet"));
+        assert!(!evaluate(&config, on_wifi("homenet")).trusted);
+        assert!(!evaluate(&config, on_wifi("CoffeeShop")).trusted);
+
+        // A hotspot named after the trusted network, behind another router
+        let evil_twin = NetworkIdentity { gateway_mac: Some("11:22:33:44:55:66".to_string()), ..on_wifi("HomeNet") };
+        assert!(!evaluate(&config, evil_twin).trusted);
+        // With trusted SSIDs set, a trusted gateway alone doesn't do on Wi-Fi
+        let office_wifi = NetworkIdentity { gateway: "10.0.0.1".parse().ok(), gateway_mac: None, ..on_wifi("Office") };
+        assert!(!evaluate(&config, office_wifi).trusted);
+
+        let wired = NetworkIdentity { ssid: None, ..on_wifi("HomeNet") };
+        assert_eq!(evaluate(&config, wired).matched.as_deref(), Some("AA-BB-CC-DD-EE-FF"));
+        let office = NetworkIdentity { ssid: None, gateway: "10.0.0.1".parse().ok(), gateway_mac: None };
+        assert!(evaluate(&config, office).trusted);
+
+        // Without trusted SSIDs, gateways decide on Wi-Fi as well
+        let mut by_gateway = config.clone();
+        by_gateway.trusted_ssids.clear();
+        assert!(evaluate(&by_gateway, on_wifi("CoffeeShop")).trusted);
+
+        // Nothing detected is never trusted
+        assert!(!evaluate(&config, NetworkIdentity::default()).trusted);
+    }
+
+    #[test]
+    fn test_protection_changes_only_when_trust_changes() {
+        let config = config(TrustedNetworkMode::Off);
+        let home = evaluate(&config, on_wifi("HomeNet"));
+        let cafe = evaluate(&config, on_wifi("CoffeeShop"));
+        let mut tracker = TrustTracker::new();
+
+        assert_eq!(tracker.on_evaluation(&home, true, true), TrustAction::Disconnect);
+        // Connecting by hand at home sticks
+        assert_eq!(tracker.on_evaluation(&home, true, true), TrustAction::Nothing);
+        assert_eq!(tracker.on_evaluation(&cafe, false, true), TrustAction::Engage { restore_kill_switch: false });
+        assert_eq!(tracker.on_evaluation(&cafe, false, true), TrustAction::Nothing);
+
+        // Relaxed mode gives the kill switch back on leaving
+        let config = self::config(TrustedNetworkMode::Relaxed);
+        let home = evaluate(&config, on_wifi("HomeNet"));
+        let cafe = evaluate(&config, on_wifi("CoffeeShop"));
+        let mut tracker = TrustTracker::new();
+        assert_eq!(tracker.on_evaluation(&home, true, true), TrustAction::RelaxKillSwitch);
+        assert_eq!(tracker.on_evaluation(&cafe, true, false), TrustAction::Engage { restore_kill_switch: true });
+        assert_eq!(tracker.on_evaluation(&home, true, true), TrustAction::RelaxKillSwitch);
+        // Already off when arriving: nothing to give back
+        let mut tracker = TrustTracker::new();
+        assert_eq!(tracker.on_evaluation(&home, true, false), TrustAction::Nothing);
+        assert_eq!(tracker.on_evaluation(&cafe, true, false), TrustAction::Nothing);
+    }
+
+    #[test]
+    fn test_platform_output_parsed() {
+        let route = "Iface\tDestination\tGateway \tFlags\n\
+                     wlan0\t0000A8C0\t00000000\t0001\n\
+                     wlan0\t00000000\t0101A8C0\t0003\n";
+        assert_eq!(parse_proc_route(route), "192.168.1.1".parse().ok());
+
+        let arp = "IP address       HW type     Flags       HW address            Mask     Device\n\
+                   192.168.1.1      0x1         0x2         AA:BB:CC:DD:EE:FF     *        wlan0\n";
+        assert_eq!(parse_proc_arp(arp, "192.168.1.1".parse().unwrap()).as_deref(), Some("aa:bb:cc:dd:ee:ff"));
+
+        let netsh = "    Name                   : Wi-Fi\n    BSSID                  : aa:bb:cc:dd:ee:ff\n    SSID                   : Home: 5G\n";
+        assert_eq!(parse_netsh_ssid(netsh).as_deref(), Some("Home: 5G"));
+    }
+}
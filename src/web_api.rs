@@ -1,50 +1,262 @@
 use axum::{
-    extract::State,
+    extract::{Path as UrlPath, Query, State},
+    http::{header, StatusCode},
     response::sse::{Event, Sse},
+    response::IntoResponse,
     routing::{get, post, put},
     Json, Router,
 };
-use futures::stream::{Stream, self};
+use futures::stream::{Stream, StreamExt};
+use futures::future;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::Infallible;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::sync::RwLock;
 use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
+use tracing::{info, warn};
+use crate::api_tls;
+use crate::blocklist::{self, AllowScope, BlockCheck, BlocklistFormat, ImportSummary, RefreshSummary, Subscription, TemporaryAllowlist, TrackerBlocker};
+use crate::fingerprint::{BrowserFingerprint, SessionFingerprint};
+use crate::host_stats::{HostStats, HostTracker, MAX_TRACKED_HOSTS};
+use crate::idle_timeout::IdleTimer;
 use crate::kill_switch::KillSwitch;
-use crate::proxy::ProxyServer;
-use crate::config::Config;
+use crate::lan_addresses::{self, NetworkInfo};
+use crate::network::{Node, NodeRegistry};
+use crate::onion_service::OnionServiceInfo;
+use crate::proxy::{self, ConnectionRegistry, ProxyServer};
+use crate::rate_limit::{ClientThroughput, RateLimiter};
+use crate::runtime_info;
+use crate::replay_detection::{ReplayTracker, MAX_TRACKED_URLS, REPLAY_WINDOW};
+use crate::self_test::{self, SelfTestInputs, SelfTestReport, TorCheck};
+use crate::session_history::{SessionHistory, SessionSummary};
+use crate::setup_instructions::{self, SetupInstructions, SetupPlatform};
+use crate::sse;
+use crate::shutdown::ShutdownReason;
+use crate::dns;
+use crate::exit_selection;
+use crate::config::{BrowsingMode, Config, DnsResolution, LogLevel, ProtectionSettings, DEFAULT_PROXY_PORT};
+use crate::tor_network::{self, CircuitHop, TorProbe, TorSupport};
+use crate::trace;
+use crate::trusted_networks::{TrustAction, TrustEvaluation};
+use crate::webhook::{Webhook, WebhookEvent};
 use crate::system_proxy::{self as sys_proxy, SystemProxy};
 use crate::system_proxy;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// What `/api/stats` reports. The counters are kept in `StatCounters` and
+/// copied in by `ApiState::stats_snapshot`; `ApiState::stats` holds the rest.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Stats {
     pub tor_connected: bool,
     pub kill_switch_active: bool,
     pub requests_blocked: u64,
+    pub requests_dropped: u64,
     pub trackers_blocked: u64,
     pub webrtc_blocked: u64,
     pub ipv6_blocked: u64,
     pub total_requests: u64,
     pub proxy_running: bool,
     pub auto_proxy_enabled: bool,
+    /// Same as `session_uptime_seconds`, kept for existing clients
     pub uptime_seconds: u64,
+    /// Seconds connected in the current session, 0 while disconnected
+    pub session_uptime_seconds: u64,
+    /// Seconds since the app started
+    pub app_uptime_seconds: u64,
+    /// When the current session connected (RFC 3339), None while disconnected
+    #[serde(default)]
+    pub connected_since: Option<String>,
     pub security_threats_detected: u64,
     pub exit_country: Option<String>,
+    /// The exit relay was located in another country than the one requested
+    #[serde(default)]
+    pub exit_country_mismatch: bool,
+    pub is_admin: bool,
+    pub bytes_transferred: u64,
+    pub active_connections: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub circuit_rebuilds: u64,
+    /// Relays per Tor circuit (`Config::tor_path_length`)
+    pub tor_path_length: u8,
+    /// Requests learning mode let through that would otherwise have been blocked
+    pub would_block: u64,
+    /// Response header lines removed by `Config::scrub_response_headers`
+    pub headers_scrubbed: u64,
+    /// Why the proxy last failed to start or stopped unexpectedly
+    pub proxy_error: Option<String>,
+    /// Current transfer rate of each active LAN client
+    #[serde(default)]
+    pub client_throughput: Vec<ClientThroughput>,
+    /// Where the connection is in its lifecycle; `proxy_error` says why it failed
+    #[serde(default)]
+    pub connection_state: ConnectionState,
+}
+
+/// A stats counter bumped without taking the `Stats` lock
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+    
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+    
+    /// Take one off, stopping at zero
+    pub fn dec(&self) {
+        let _ = self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+    
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+    
+    pub fn set(&self, n: u64) {
+        self.0.store(n, Ordering::Relaxed);
+    }
+}
+
+/// The numeric fields of `Stats`, updated from the request path on every
+/// request. Atomics keep those bumps from queueing behind each other (and
+/// behind readers) on the `Stats` write lock.
+#[derive(Debug, Default)]
+pub struct StatCounters {
+    pub requests_blocked: Counter,
+    pub requests_dropped: Counter,
+    pub trackers_blocked: Counter,
+    pub webrtc_blocked: Counter,
+    pub ipv6_blocked: Counter,
+    pub total_requests: Counter,
+    pub security_threats_detected: Counter,
+    pub bytes_transferred: Counter,
+    pub active_connections: Counter,
+    pub cache_hits: Counter,
+    pub cache_misses: Counter,
+    pub circuit_rebuilds: Counter,
+    pub would_block: Counter,
+    pub headers_scrubbed: Counter,
+}
+
+impl StatCounters {
+    /// Copy the current counts into `stats`
+    pub fn fill(&self, stats: &mut Stats) {
+        stats.requests_blocked = self.requests_blocked.get();
+        stats.requests_dropped = self.requests_dropped.get();
+        stats.trackers_blocked = self.trackers_blocked.get();
+        stats.webrtc_blocked = self.webrtc_blocked.get();
+        stats.ipv6_blocked = self.ipv6_blocked.get();
+        stats.total_requests = self.total_requests.get();
+        stats.security_threats_detected = self.security_threats_detected.get();
+        stats.bytes_transferred = self.bytes_transferred.get();
+        stats.active_connections = self.active_connections.get();
+        stats.cache_hits = self.cache_hits.get();
+        stats.cache_misses = self.cache_misses.get();
+        stats.circuit_rebuilds = self.circuit_rebuilds.get();
+        stats.would_block = self.would_block.get();
+        stats.headers_scrubbed = self.headers_scrubbed.get();
+    }
+    
+    /// Zero the per-session counts when a new session starts. Open
+    /// connections are counted as they close, so they are left alone.
+    pub fn reset_session(&self) {
+        for counter in [
+            &self.requests_blocked,
+            &self.requests_dropped,
+            &self.trackers_blocked,
+            &self.webrtc_blocked,
+            &self.ipv6_blocked,
+            &self.total_requests,
+            &self.security_threats_detected,
+            &self.cache_hits,
+            &self.cache_misses,
+            &self.circuit_rebuilds,
+            &self.would_block,
+            &self.headers_scrubbed,
+        ] {
+            counter.set(0);
+        }
+    }
+}
+
+/// Lifecycle of a proxy session, from the connect request until it stops
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    #[default]
+    Disconnected,
+    /// Tor is bootstrapping
+    Connecting,
+    Connected,
+    /// Bootstrap failed, timed out, or the proxy stopped with an error
+    Failed,
+    /// Tor can't run in this environment (`Stats::proxy_error` says why), so
+    /// connecting is refused and the system proxy is left alone
+    Unsupported,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LogEntry {
+    /// Increases with every new entry, for looking one up via `/api/logs/{id}`
+    #[serde(default)]
+    pub id: u64,
     pub timestamp: String,
     pub level: String,
     pub message: String,
     pub category: String, // "tracker", "webrtc", "ipv6", "general", "network", "security"
     #[serde(skip_serializing_if = "Option::is_none")]
     pub details: Option<LogDetails>,
+    /// Number of identical events this entry stands for
+    #[serde(default = "default_log_count")]
+    pub count: u64,
+    /// When the first of the collapsed events was logged
+    #[serde(skip)]
+    first_seen: Option<std::time::Instant>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+fn default_log_count() -> u64 {
+    1
+}
+
+/// Identical threat events within this window collapse into one entry
+const LOG_DEDUP_WINDOW: Duration = Duration::from_secs(60);
+
+/// Repeats after which a collapsed entry is raised one severity level
+const LOG_ESCALATION_COUNT: u64 = 10;
+
+/// Events are identical when category, threat type and domain all match
+fn log_dedup_key<'a>(category: &'a str, details: Option<&'a LogDetails>) -> Option<(&'a str, &'a str, &'a str)> {
+    let details = details?;
+    Some((category, details.threat_type.as_deref()?, details.domain.as_deref()?))
+}
+
+/// Entries kept in the events buffer (`/api/events`)
+const MAX_EVENTS: usize = 500;
+
+/// Whether a log entry belongs in the events timeline: `general` entries
+/// (startup, config changes, connecting and disconnecting) and anything at
+/// warn or above. Info entries in the other categories are logged per request
+/// or tunnel and would bury those.
+fn is_event(level: &str, category: &str) -> bool {
+    category == "general" || matches!(level, "warn" | "error")
+}
+
+fn escalate_level(level: &str) -> String {
+    match level {
+        "info" => "warn",
+        "warn" => "error",
+        other => other,
+    }
+    .to_string()
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct LogDetails {
     pub url: Option<String>,
     pub domain: Option<String>,
@@ -55,46 +267,111 @@ pub struct LogDetails {
     pub threat_type: Option<String>,
     pub reason: Option<String>,
     pub request_headers: Option<Vec<String>>,
+    /// Shared by every entry logged while handling one proxied request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
 }
 
+/// Upper bound on `/api/shutdown` cleanup before the process exits anyway
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before exiting so the HTTP response reaches the GUI
+const EXIT_DELAY: Duration = Duration::from_millis(500);
+
+/// Ends the process once shutdown cleanup is done (replaceable in tests)
+pub type ExitHook = Arc<dyn Fn(ShutdownReason) + Send + Sync>;
+
 #[derive(Clone)]
 pub struct ApiState {
     pub stats: Arc<RwLock<Stats>>,
+    pub counters: Arc<StatCounters>,
     pub logs: Arc<RwLock<Vec<LogEntry>>>,
+    /// The lifecycle and security entries of `logs`, kept longer because
+    /// request noise doesn't push them out
+    pub events: Arc<RwLock<Vec<LogEntry>>>,
+    /// Id the next new log entry gets
+    next_log_id: Arc<AtomicU64>,
     pub start_time: std::time::Instant,
     pub connected_time: Arc<RwLock<Option<std::time::Instant>>>,
     pub total_connected_duration: Arc<RwLock<u64>>,
     pub kill_switch: Option<KillSwitch>,
-    pub config: Arc<Config>,
+    pub config: Arc<RwLock<Config>>,
     pub proxy_handle: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
     pub system_proxy: Arc<RwLock<SystemProxy>>,
+    pub circuit: Arc<RwLock<Option<Vec<CircuitHop>>>>,
+    pub connections: ConnectionRegistry,
+    pub rate_limiter: RateLimiter,
+    pub tracker_blocker: TrackerBlocker,
+    pub node_registry: Option<Arc<NodeRegistry>>,
+    pub fingerprint: SessionFingerprint,
+    pub exit: ExitHook,
+    pub hosts: HostTracker,
+    pub history: SessionHistory,
+    /// Onion services published by the running session
+    pub onion_services: Arc<RwLock<Vec<OnionServiceInfo>>>,
+    /// Recent URLs carrying credentials, for the `request_replay` detector
+    pub replay_tracker: ReplayTracker,
+    /// Latest verdict of the trusted networks watcher, if it is running
+    pub trusted_network: Arc<RwLock<Option<TrustEvaluation>>>,
+    /// Exit country the user picked, None on "Auto". `Stats::exit_country`
+    /// shows the same, or on "Auto" where the exit actually is.
+    requested_exit: Arc<RwLock<Option<String>>>,
+    /// `Config::log_levels`, read on every log entry; a std lock so logging
+    /// never waits behind a config write
+    log_levels: Arc<std::sync::RwLock<HashMap<String, LogLevel>>>,
+    /// Time since the last proxied request, for `Config::idle_disconnect_secs`
+    pub idle: IdleTimer,
+    /// Where security errors are posted (`Config::webhook_url`)
+    pub webhook: Option<Webhook>,
+    /// Checks arti can run here before a session starts
+    tor_probe: TorProbe,
+    /// Verdict of the last `tor_probe`, None until it first ran
+    pub tor_support: Arc<RwLock<Option<TorSupport>>>,
 }
 
 impl ApiState {
     pub fn new(config: Config) -> Self {
+        let tracker_blocker = TrackerBlocker::for_config(&config);
+        let rate_limiter = RateLimiter::new(config.rate_limit_kbps);
+        let history = SessionHistory::for_config(&config);
+        let log_levels = Arc::new(std::sync::RwLock::new(config.log_levels.clone()));
+        let webhook = Webhook::for_config(&config);
+        
         Self {
             stats: Arc::new(RwLock::new(Stats {
-                tor_connected: false,
-                kill_switch_active: false,
-                requests_blocked: 0,
-                trackers_blocked: 0,
-                webrtc_blocked: 0,
-                ipv6_blocked: 0,
-                total_requests: 0,
-                proxy_running: false,
-                auto_proxy_enabled: false,
-                uptime_seconds: 0,
-                security_threats_detected: 0,
-                exit_country: None,
+                is_admin: sys_proxy::is_elevated(),
+                tor_path_length: config.tor_path_length,
+                ..Stats::default()
             })),
+            counters: Arc::new(StatCounters::default()),
             logs: Arc::new(RwLock::new(Vec::new())),
+            events: Arc::new(RwLock::new(Vec::new())),
+            next_log_id: Arc::new(AtomicU64::new(1)),
             start_time: std::time::Instant::now(),
             connected_time: Arc::new(RwLock::new(None)),
             total_connected_duration: Arc::new(RwLock::new(0)),
             kill_switch: None,
-            config: Arc::new(config),
+            config: Arc::new(RwLock::new(config)),
             proxy_handle: Arc::new(RwLock::new(None)),
             system_proxy: Arc::new(RwLock::new(SystemProxy::new())),
+            circuit: Arc::new(RwLock::new(None)),
+            connections: ConnectionRegistry::new(),
+            rate_limiter,
+            tracker_blocker,
+            node_registry: None,
+            fingerprint: SessionFingerprint::new(BrowserFingerprint::random()),
+            exit: Arc::new(|reason: ShutdownReason| reason.exit()),
+            hosts: HostTracker::new(MAX_TRACKED_HOSTS),
+            history,
+            onion_services: Arc::new(RwLock::new(Vec::new())),
+            replay_tracker: ReplayTracker::new(REPLAY_WINDOW, MAX_TRACKED_URLS),
+            trusted_network: Arc::new(RwLock::new(None)),
+            requested_exit: Arc::new(RwLock::new(None)),
+            log_levels,
+            idle: IdleTimer::new(),
+            webhook,
+            tor_probe: Arc::new(tor_network::probe_support),
+            tor_support: Arc::new(RwLock::new(None)),
         }
     }
     
@@ -103,30 +380,148 @@ impl ApiState {
         self
     }
     
+    pub fn with_tor_probe(mut self, tor_probe: TorProbe) -> Self {
+        self.tor_probe = tor_probe;
+        self
+    }
+    
+    pub fn with_webhook(mut self, webhook: Webhook) -> Self {
+        self.webhook = Some(webhook);
+        self
+    }
+    
     pub fn with_system_proxy(mut self, system_proxy: Arc<RwLock<SystemProxy>>) -> Self {
         self.system_proxy = system_proxy;
         self
     }
+    
+    pub fn with_node_registry(mut self, node_registry: Arc<NodeRegistry>) -> Self {
+        self.node_registry = Some(node_registry);
+        self
+    }
+    
+    pub fn with_idle_timer(mut self, idle: IdleTimer) -> Self {
+        self.idle = idle;
+        self
+    }
+    
+    pub fn with_exit_hook(mut self, exit: impl Fn(ShutdownReason) + Send + Sync + 'static) -> Self {
+        self.exit = Arc::new(exit);
+        self
+    }
 
+    /// Whether `category`'s configured verbosity keeps an entry at `level`
+    fn log_level_allows(&self, level: &str, category: &str) -> bool {
+        let log_levels = self.log_levels.read().unwrap_or_else(|e| e.into_inner());
+        match log_levels.get(category) {
+            Some(LogLevel::Off) => false,
+            Some(threshold) => LogLevel::of_entry(level) >= *threshold,
+            None => true,
+        }
+    }
+    
+    /// Change how verbose `category` is in the in-app log, from now on
+    pub fn set_log_level(&self, category: &str, level: LogLevel) -> HashMap<String, LogLevel> {
+        let mut log_levels = self.log_levels.write().unwrap_or_else(|e| e.into_inner());
+        log_levels.insert(category.to_string(), level);
+        log_levels.clone()
+    }
+    
     pub async fn add_log(&self, level: &str, message: String, category: &str) {
         self.add_log_with_details(level, message, category, None).await;
     }
 
+    /// Record a log entry. `Config::log_levels` only decides whether it
+    /// shows in the in-app log: the events timeline and the webhook get it
+    /// either way.
     pub async fn add_log_with_details(&self, level: &str, message: String, category: &str, details: Option<LogDetails>) {
+        let keep = self.log_level_allows(level, category);
+        let now = std::time::Instant::now();
+        let timestamp = chrono::Local::now().format("%H:%M:%S%.3f").to_string();
+        let details = match trace::current() {
+            Some(trace_id) => Some(LogDetails { trace_id: Some(trace_id), ..details.unwrap_or_default() }),
+            None => details,
+        };
         let mut logs = self.logs.write().await;
-        logs.push(LogEntry {
-            timestamp: chrono::Local::now().format("%H:%M:%S%.3f").to_string(),
+        
+        // Collapse repeats of a recent identical threat event instead of flooding the log
+        if let Some(key) = log_dedup_key(category, details.as_ref()) {
+            let mut events = self.events.write().await;
+            // An entry the in-app log leaves out can only repeat one in the events
+            let recent_in = if keep { &mut *logs } else { &mut *events };
+            let recent = recent_in.iter_mut().rev().find(|log| {
+                log_dedup_key(&log.category, log.details.as_ref()) == Some(key)
+                    && log.first_seen.is_some_and(|first| now.duration_since(first) < LOG_DEDUP_WINDOW)
+            });
+            if let Some(entry) = recent {
+                entry.count += 1;
+                entry.timestamp = timestamp;
+                if entry.count == LOG_ESCALATION_COUNT {
+                    entry.level = escalate_level(&entry.level);
+                    // A repeat only notifies once it escalates into a qualifying entry
+                    if entry.level != level {
+                        self.notify_webhook(entry);
+                    }
+                }
+                let entry = entry.clone();
+                match events.iter_mut().rev().find(|event| event.id == entry.id) {
+                    Some(event) => *event = entry,
+                    // A per-request entry joins the events once it escalates
+                    None if is_event(&entry.level, &entry.category) => {
+                        events.push(entry);
+                        if events.len() > MAX_EVENTS {
+                            events.remove(0);
+                        }
+                    }
+                    None => {}
+                }
+                return;
+            }
+        }
+        
+        let entry = LogEntry {
+            id: self.next_log_id.fetch_add(1, Ordering::Relaxed),
+            timestamp,
             level: level.to_string(),
             message,
             category: category.to_string(),
             details,
-        });
+            count: 1,
+            first_seen: Some(now),
+        };
+        self.notify_webhook(&entry);
+        if is_event(level, category) {
+            let mut events = self.events.write().await;
+            events.push(entry.clone());
+            if events.len() > MAX_EVENTS {
+                events.remove(0);
+            }
+        }
+        if !keep {
+            return;
+        }
+        logs.push(entry);
         // Keep only last 2000 logs for detailed tracking
         if logs.len() > 2000 {
             logs.remove(0);
         }
     }
 
+    fn notify_webhook(&self, entry: &LogEntry) {
+        let Some(webhook) = &self.webhook else {
+            return;
+        };
+        if Webhook::qualifies(&entry.level, &entry.category) {
+            webhook.notify(WebhookEvent {
+                event: entry.message.clone(),
+                timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+                threat_type: entry.details.as_ref().and_then(|details| details.threat_type.clone()),
+                host: entry.details.as_ref().and_then(|details| details.domain.clone()),
+            });
+        }
+    }
+    
+    /// Change the non-counter fields of the stats; counts go through `counters`
     pub async fn update_stats<F>(&self, f: F)
     where
         F: FnOnce(&mut Stats),
@@ -134,26 +529,659 @@ impl ApiState {
         let mut stats = self.stats.write().await;
         f(&mut *stats);
         
-        // Calculate only connected session duration
-        if let Some(connected_since) = *self.connected_time.read().await {
-            stats.uptime_seconds = connected_since.elapsed().as_secs();
+        let connected_since = *self.connected_time.read().await;
+        self.set_uptimes(&mut stats, connected_since);
+    }
+    
+    /// Fill in how long the connected session (if any) and the app have been running
+    fn set_uptimes(&self, stats: &mut Stats, connected_since: Option<std::time::Instant>) {
+        let session = connected_since.map_or(0, |since| since.elapsed().as_secs());
+        stats.session_uptime_seconds = session;
+        stats.uptime_seconds = session;
+        stats.app_uptime_seconds = self.start_time.elapsed().as_secs();
+        // `Instant`s have no wall-clock time, so work back from now
+        stats.connected_since = connected_since.map(|since| {
+            let elapsed = chrono::Duration::from_std(since.elapsed()).unwrap_or_default();
+            (chrono::Local::now() - elapsed).to_rfc3339_opts(chrono::SecondsFormat::Millis, false)
+        });
+    }
+
+    /// Re-fetch the enabled tracker lists, keeping the current set on failure
+    pub async fn refresh_blocklist(&self) -> Result<RefreshSummary, String> {
+        self.reload_blocklist(None).await
+    }
+    
+    /// Reload the local (`file://`) tracker lists at `changed`, keeping
+    /// what the other lists contributed last time
+    pub async fn reload_local_lists(&self, changed: &[PathBuf]) -> Result<RefreshSummary, String> {
+        let changed: Vec<String> = self
+            .config
+            .read()
+            .await
+            .enabled_tracker_lists()
+            .into_iter()
+            .filter(|url| blocklist::local_list_path(url).is_some_and(|path| changed.contains(&path)))
+            .collect();
+        self.reload_blocklist(Some(&changed)).await
+    }
+    
+    /// Refresh the tracker lists, only those in `changed` if given
+    async fn reload_blocklist(&self, changed: Option<&[String]>) -> Result<RefreshSummary, String> {
+        let (tracker_lists, checksums) = {
+            let config = self.config.read().await;
+            (config.enabled_tracker_lists(), config.tracker_list_checksums.clone())
+        };
+        let result = match changed {
+            Some(changed) => self.tracker_blocker.refresh_changed(&tracker_lists, &checksums, changed).await,
+            None => self.tracker_blocker.refresh_verified(&tracker_lists, &checksums).await,
+        };
+        match result {
+            Ok(summary) => {
+                for url in &summary.rejected {
+                    self.add_log("warn", format!(
+                        "🛡️ Blocklist {} failed checksum verification - keeping the last verified version",
+                        url
+                    ), "security").await;
+                }
+                self.add_log("info", format!(
+                    "🔄 Blocklist updated: +{} / -{} domains ({} total)",
+                    summary.added, summary.removed, summary.total
+                ), "tracker").await;
+                Ok(summary)
+            }
+            Err(e) => {
+                self.add_log("warn", format!("⚠️ Blocklist update failed, keeping current list: {}", e), "tracker").await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Pick the exit country for the next connection (None for "Auto")
+    pub async fn request_exit_country(&self, country: Option<String>) {
+        *self.requested_exit.write().await = country.clone();
+        self.update_stats(|s| s.exit_country = country).await;
+    }
+    
+    pub async fn requested_exit_country(&self) -> Option<String> {
+        self.requested_exit.read().await.clone()
+    }
+    
+    /// Record the circuit used by the most recent Tor stream. With the exit
+    /// left on "Auto", the exit relay's country (if GeoIP placed it) becomes
+    /// the reported exit country; with one picked, an exit located elsewhere
+    /// is flagged (`warn_exit_mismatch`).
+    pub async fn set_circuit(&self, path: Option<Vec<CircuitHop>>) {
+        let located = path
+            .as_ref()
+            .and_then(|hops| hops.iter().find(|hop| hop.role == "exit"))
+            .and_then(|exit| exit.country.clone());
+        let requested = self.requested_exit_country().await;
+        let warn_exit_mismatch = self.config.read().await.warn_exit_mismatch;
+        let mismatch = match (&requested, &located) {
+            (Some(requested), Some(located)) => warn_exit_mismatch && !exit_selection::same_country(requested, located),
+            _ => false,
+        };
+        
+        let mut newly_mismatched = false;
+        self.update_stats(|s| {
+            if let (None, Some(country)) = (&requested, &located) {
+                s.exit_country = Some(country.clone());
+            }
+            newly_mismatched = mismatch && !s.exit_country_mismatch;
+            s.exit_country_mismatch = mismatch;
+        })
+        .await;
+        *self.circuit.write().await = path;
+        
+        if let (true, Some(requested), Some(located)) = (newly_mismatched, &requested, &located) {
+            self.add_log("warn", format!(
+                "🌍 Exit relay is in {} although {} was requested - traffic leaves from another jurisdiction",
+                located, requested
+            ), "security").await;
+        }
+    }
+    
+    /// Fill in the exit country once GeoIP has placed `exit_relay`, unless a
+    /// newer circuit replaced the one it exits in the meantime
+    pub async fn locate_exit(&self, exit_relay: &str, country: String) {
+        let mut path = self.circuit.read().await.clone();
+        let Some(exit) = path.iter_mut().flatten().find(|hop| hop.role == "exit" && hop.relay == exit_relay) else {
+            return;
+        };
+        exit.country = Some(country);
+        self.set_circuit(path).await;
+    }
+    
+    pub async fn set_onion_services(&self, services: Vec<OnionServiceInfo>) {
+        *self.onion_services.write().await = services;
+    }
+
+    /// Sever everything immediately: engage the kill switch, abort all client
+    /// connections, drop Tor circuits and flush DNS caches.
+    ///
+    /// Leaves the suite disconnected (the system proxy keeps pointing at the
+    /// stopped proxy, so apps fail closed) until the user reconnects. Safe to
+    /// call repeatedly.
+    pub async fn panic(&self) -> PanicReport {
+        let (connections_aborted, proxy_stopped) = self.sever().await;
+        // The suite's own DNS caches went with the proxy's router
+        let dns_flushed = match dns::flush_system_cache() {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("DNS cache not flushed: {}", e);
+                false
+            }
+        };
+        
+        self.add_log_with_details(
+            "error",
+            format!("🚨 PANIC: all traffic severed ({} connections aborted) - reconnect to resume", connections_aborted),
+            "security",
+            Some(LogDetails {
+                threat_type: Some("Panic Button".to_string()),
+                reason: Some("User-triggered emergency disconnect".to_string()),
+                ..LogDetails::default()
+            }),
+        ).await;
+        
+        PanicReport {
+            kill_switch_engaged: self.kill_switch.is_some(),
+            connections_aborted,
+            proxy_stopped,
+            dns_flushed,
+        }
+    }
+
+    /// Engage the kill switch, abort client connections and stop the proxy.
+    ///
+    /// Returns how many connections were aborted and whether a proxy was running.
+    async fn sever(&self) -> (usize, bool) {
+        if let Some(ref ks) = self.kill_switch {
+            ks.set_enabled(true).await;
+            ks.set_tor_status(false).await;
+        }
+        
+        let connections_aborted = self.connections.abort_all();
+        
+        // Dropping the proxy task drops its Tor client and retires its circuits
+        let proxy_stopped = if let Some(handle) = self.proxy_handle.write().await.take() {
+            handle.abort();
+            true
+        } else {
+            false
+        };
+        self.set_circuit(None).await;
+        self.end_session().await;
+        
+        self.update_stats(|s| {
+            s.connection_state = ConnectionState::Disconnected;
+            s.kill_switch_active = true;
+            s.proxy_running = false;
+            s.tor_connected = false;
+        }).await;
+        self.counters.active_connections.set(0);
+        
+        (connections_aborted, proxy_stopped)
+    }
+    
+    /// Stop timing the connected session, adding it to the total and to the
+    /// history. Also called on shutdown, so a session still open is recorded.
+    pub async fn end_session(&self) {
+        // "Allow for this session" lasts exactly this long
+        self.tracker_blocker.clear_temporary();
+        // The session's Tor client is gone, whatever the router last saw
+        if let Some(ref ks) = self.kill_switch {
+            ks.set_tor_status(false).await;
+        }
+        let Some(connected_since) = self.connected_time.write().await.take() else {
+            return;
+        };
+        let duration = connected_since.elapsed().as_secs();
+        *self.total_connected_duration.write().await += duration;
+        // They were unpublished along with the session's Tor client
+        self.set_onion_services(Vec::new()).await;
+        
+        let summary = SessionSummary::ending_now(duration, &self.stats_snapshot().await);
+        if let Err(e) = self.history.record(summary) {
+            warn!("Failed to save session history: {}", e);
+        }
+    }
+    
+    /// Whether a proxy session is running or starting
+    pub async fn is_connected(&self) -> bool {
+        self.proxy_handle
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+    
+    /// Drop the session before the machine sleeps or locks, so nothing leaks
+    /// while the network reconfigures
+    pub async fn disconnect_for_sleep(&self, reason: &str) {
+        let (connections_aborted, _) = self.sever().await;
+        info!("💤 {} - disconnected ({} connections aborted)", reason, connections_aborted);
+        self.add_log("warn", format!("💤 {} - kill switch engaged and circuits closed", reason), "security").await;
+    }
+    
+    /// Drop a session nobody has used for `idle`: kill switch engaged,
+    /// circuits closed and, with the rights to do so, the system proxy restored
+    pub async fn disconnect_for_idle(&self, idle: Duration) {
+        let (connections_aborted, _) = self.sever().await;
+        let mut proxy_restored = false;
+        if sys_proxy::is_elevated() {
+            match self.system_proxy.write().await.disable() {
+                Ok(_) => {
+                    self.update_stats(|s| s.auto_proxy_enabled = false).await;
+                    proxy_restored = true;
+                }
+                Err(e) => warn!("Failed to restore system proxy after idle disconnect: {}", e),
+            }
+        }
+        info!("⏱️ Idle for {}s - disconnected ({} connections aborted)", idle.as_secs(), connections_aborted);
+        let restored = if proxy_restored { " and system proxy restored" } else { "" };
+        self.add_log(
+            "warn",
+            format!("⏱️ No requests for {}s - disconnected, kill switch engaged{}", idle.as_secs(), restored),
+            "security",
+        ).await;
+    }
+    
+    /// Record the trusted networks watcher's latest `evaluation` and carry out `action`
+    pub async fn apply_trust(&self, evaluation: TrustEvaluation, action: TrustAction) {
+        let network = evaluation.matched.clone().unwrap_or_else(|| "untrusted network".to_string());
+        *self.trusted_network.write().await = Some(evaluation);
+        
+        match action {
+            TrustAction::Disconnect => {
+                self.add_log("warn", format!("📶 On trusted network {} - disconnecting from Tor", network), "security").await;
+                toggle_connection(State(self.clone()), Json(ConnectionToggle { connect: false, exit_country: None })).await;
+            }
+            TrustAction::RelaxKillSwitch => {
+                if let Some(ref kill_switch) = self.kill_switch {
+                    kill_switch.set_enabled(false).await;
+                    self.update_stats(|s| s.kill_switch_active = false).await;
+                }
+                self.add_log("warn", format!("📶 On trusted network {} - kill switch off", network), "security").await;
+            }
+            TrustAction::Engage { restore_kill_switch } => {
+                self.add_log("info", format!("📶 On {} - engaging protection", network), "security").await;
+                if restore_kill_switch {
+                    if let Some(ref kill_switch) = self.kill_switch {
+                        kill_switch.set_enabled(true).await;
+                        self.update_stats(|s| s.kill_switch_active = true).await;
+                    }
+                }
+                if !self.is_connected().await {
+                    let exit_country = self.requested_exit_country().await;
+                    toggle_connection(State(self.clone()), Json(ConnectionToggle { connect: true, exit_country })).await;
+                }
+            }
+            TrustAction::Nothing => {}
+        }
+    }
+    
+    /// Start a fresh session on new circuits after waking up
+    pub async fn reconnect_after_sleep(&self) {
+        self.add_log("info", "☀️ Resumed - reconnecting on fresh circuits".to_string(), "general").await;
+        let exit_country = self.requested_exit_country().await;
+        toggle_connection(State(self.clone()), Json(ConnectionToggle { connect: true, exit_country })).await;
+    }
+
+    /// Undo everything the suite changed on the system so the process can exit
+    /// without leaving the user offline.
+    ///
+    /// Gives up after `SHUTDOWN_TIMEOUT`; the report then shows the steps that
+    /// did finish.
+    pub async fn shutdown(&self) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+        
+        report.completed = tokio::time::timeout(SHUTDOWN_TIMEOUT, self.restore_system(&mut report))
+            .await
+            .is_ok();
+        if !report.completed {
+            report.errors.push(format!("Cleanup did not finish within {} seconds", SHUTDOWN_TIMEOUT.as_secs()));
+        }
+        
+        report
+    }
+    
+    async fn restore_system(&self, report: &mut ShutdownReport) {
+        // Restore the system proxy first: pointing at a dead port is what leaves apps offline
+        if sys_proxy::is_elevated() {
+            let system_proxy = self.system_proxy.clone().write_owned().await;
+            let restored = tokio::task::spawn_blocking(move || system_proxy.disable())
+                .await
+                .unwrap_or_else(|e| Err(format!("Proxy restore task failed: {}", e)));
+            match restored {
+                Ok(()) => report.system_proxy_restored = true,
+                Err(e) => report.errors.push(format!("Failed to restore system proxy: {}", e)),
+            }
         } else {
-            stats.uptime_seconds = 0;
+            // Never set without elevation
+            report.system_proxy_restored = true;
+        }
+        self.update_stats(|s| s.auto_proxy_enabled = !report.system_proxy_restored).await;
+        
+        // The kill switch blocks in-process only, so there are no firewall rules to remove yet
+        if let Some(ref ks) = self.kill_switch {
+            ks.set_enabled(false).await;
+            report.kill_switch_disabled = true;
+        }
+        
+        // Tear down client connections and the proxy task, which owns the Tor client and its circuits
+        report.connections_closed = self.connections.abort_all();
+        let handle = self.proxy_handle.write().await.take();
+        if let Some(handle) = handle {
+            handle.abort();
+            let _ = handle.await;
+            report.proxy_stopped = true;
         }
+        self.set_circuit(None).await;
+        self.end_session().await;
+        if let Err(e) = runtime_info::remove(&*self.config.read().await) {
+            report.errors.push(format!("Failed to remove {}: {}", runtime_info::RUNTIME_FILE, e));
+        }
+        
+        self.update_stats(|s| {
+            s.connection_state = ConnectionState::Disconnected;
+            s.kill_switch_active = false;
+            s.proxy_running = false;
+            s.tor_connected = false;
+        }).await;
+        self.counters.active_connections.set(0);
+    }
+
+    /// Current stats with up-to-date session and app uptimes
+    pub async fn stats_snapshot(&self) -> Stats {
+        let mut stats = self.stats.read().await.clone();
+        self.counters.fill(&mut stats);
+        let connected_since = *self.connected_time.read().await;
+        self.set_uptimes(&mut stats, connected_since);
+        stats.client_throughput = self.rate_limiter.throughput();
+        stats
     }
 }
 
 async fn get_stats(State(state): State<ApiState>) -> Json<Stats> {
-    let mut stats = state.stats.read().await.clone();
+    Json(state.stats_snapshot().await)
+}
+
+/// One line of the `/api/diagnostics` checklist
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DiagnosticCheck {
+    /// Stable identifier, e.g. `tor` or `kill_switch`
+    pub id: String,
+    /// Whether this protection is working right now
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl DiagnosticCheck {
+    fn new(id: &str, ok: bool, detail: impl Into<String>) -> Self {
+        Self { id: id.to_string(), ok, detail: detail.into() }
+    }
+}
+
+/// Live status of every subsystem in one place, for the GUI's health checklist
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Diagnostics {
+    /// True when every check passes
+    pub healthy: bool,
+    pub checks: Vec<DiagnosticCheck>,
+    /// Whether the current network is trusted; None unless `trusted_ssids`
+    /// or `trusted_gateways` are set
+    pub trusted_network: Option<TrustEvaluation>,
+    /// Whether Tor can run here at all; None until it was first checked
+    #[serde(default)]
+    pub tor_support: Option<TorSupport>,
+}
+
+impl ApiState {
+    /// Check whether Tor can run here, at startup and before every connect.
+    /// While it can't, the suite stays disconnected in
+    /// `ConnectionState::Unsupported` with the reason in `proxy_error`.
+    pub async fn check_tor_support(&self) -> TorSupport {
+        let config = self.config.read().await.clone();
+        let support = (self.tor_probe)(&config);
+        let previous = self.tor_support.write().await.replace(support.clone());
+        if previous.as_ref() == Some(&support) {
+            return support;
+        }
+        
+        match &support {
+            TorSupport::Unsupported { reason } => {
+                warn!("Tor can't run here: {}", reason);
+                self.add_log("error", format!("🚫 Tor can't run on this system: {}", reason), "general").await;
+                self.update_stats(|s| {
+                    s.connection_state = ConnectionState::Unsupported;
+                    s.proxy_error = Some(format!("Tor unsupported: {}", reason));
+                }).await;
+            }
+            TorSupport::Supported if previous.is_some() => {
+                self.add_log("info", "✅ Tor can run on this system again".to_string(), "general").await;
+                self.update_stats(|s| {
+                    s.connection_state = ConnectionState::Disconnected;
+                    s.proxy_error = None;
+                }).await;
+            }
+            TorSupport::Supported => {}
+        }
+        support
+    }
     
-    // Calculate only connected session duration (not total app uptime)
-    if let Some(connected_since) = *state.connected_time.read().await {
-        stats.uptime_seconds = connected_since.elapsed().as_secs();
-    } else {
-        stats.uptime_seconds = 0;
+    pub async fn diagnostics(&self) -> Diagnostics {
+        let stats = self.stats_snapshot().await;
+        let config = self.config.read().await.clone();
+        let kill_switch = match &self.kill_switch {
+            Some(kill_switch) => Some(kill_switch.get_stats().await),
+            None => None,
+        };
+        let blocklist_size = self.tracker_blocker.blocklist_size();
+        
+        let checks = vec![
+            DiagnosticCheck::new(
+                "tor",
+                stats.tor_connected,
+                match (stats.tor_connected, &stats.exit_country) {
+                    (true, Some(country)) => format!("Bootstrapped, exiting in {}", country),
+                    (true, None) => "Bootstrapped".to_string(),
+                    (false, _) => stats.proxy_error.clone().unwrap_or_else(|| "Not connected".to_string()),
+                },
+            ),
+            DiagnosticCheck::new(
+                "kill_switch",
+                kill_switch.as_ref().is_some_and(|k| k.active),
+                match &kill_switch {
+                    Some(k) if k.active => format!("Engaged ({} requests blocked)", k.blocked_requests),
+                    Some(_) => "Disabled - traffic may leak if Tor fails".to_string(),
+                    None => "Not running".to_string(),
+                },
+            ),
+            DiagnosticCheck::new(
+                "system_proxy",
+                stats.auto_proxy_enabled,
+                if stats.auto_proxy_enabled { "Applied" } else { "Not applied - only apps configured manually are protected" },
+            ),
+            DiagnosticCheck::new(
+                "elevation",
+                stats.is_admin,
+                if stats.is_admin { "Running as administrator" } else { "Not elevated - the system proxy may not apply" },
+            ),
+            DiagnosticCheck::new(
+                "ipv6_protection",
+                config.ipv6_protection,
+                if config.ipv6_protection { "Active" } else { "Disabled in config" },
+            ),
+            DiagnosticCheck::new(
+                "blocklist",
+                config.tracker_blocking && blocklist_size > 0,
+                if config.tracker_blocking {
+                    format!("{} domains", blocklist_size)
+                } else {
+                    "Tracker blocking disabled in config".to_string()
+                },
+            ),
+            DiagnosticCheck::new(
+                "dns",
+                true,
+                match config.dns_resolution {
+                    DnsResolution::ExitDns => "Resolved by the Tor exit".to_string(),
+                    DnsResolution::LocalDoH => "Encrypted DNS over Tor".to_string(),
+                },
+            ),
+        ];
+        
+        let trusted_network = self.trusted_network.read().await.clone();
+        let tor_support = self.tor_support.read().await.clone();
+        Diagnostics { healthy: checks.iter().all(|check| check.ok), checks, trusted_network, tor_support }
+    }
+}
+
+async fn get_diagnostics(State(state): State<ApiState>) -> Json<Diagnostics> {
+    Json(state.diagnostics().await)
+}
+
+impl ApiState {
+    /// End-to-end "Am I protected?" check: a request through the running
+    /// proxy to the Tor checker, plus the leak protections' test cases
+    pub async fn self_test(&self) -> SelfTestReport {
+        let proxy_port = self.config.read().await.proxy_port().unwrap_or(DEFAULT_PROXY_PORT);
+        self.self_test_with(self_test::check_through_proxy(proxy_port)).await
     }
     
-    Json(stats)
+    /// `self_test` with `tor_check` standing in for the request to the checker
+    pub async fn self_test_with(
+        &self,
+        tor_check: impl std::future::Future<Output = Result<TorCheck, String>>,
+    ) -> SelfTestReport {
+        let stats = self.stats_snapshot().await;
+        let config = self.config.read().await.clone();
+        let tor_bootstrapped = stats.tor_connected && stats.proxy_running;
+        
+        let tor_check = if tor_bootstrapped {
+            tor_check.await
+        } else {
+            Err("Tor is not connected".to_string())
+        };
+        let (webrtc_host, webrtc_port) = self_test::WEBRTC_TEST_TARGET;
+        let (ipv6_host, ipv6_port) = self_test::IPV6_TEST_TARGET;
+        
+        let report = self_test::evaluate(&SelfTestInputs {
+            tor_bootstrapped,
+            tor_check,
+            local_ips: lan_addresses::lan_addresses(true).into_iter().map(|address| address.ip).collect(),
+            dns_resolution: config.dns_resolution,
+            webrtc_block: crate::routing::blocking_rule(&config, &self.tracker_blocker, webrtc_host, webrtc_port),
+            ipv6_block: crate::routing::blocking_rule(&config, &self.tracker_blocker, ipv6_host, ipv6_port),
+            allow_ipv6_over_tor: config.allow_ipv6_over_tor,
+        });
+        
+        let failed: Vec<&str> = report.checks.iter().filter(|check| !check.passed).map(|check| check.id.as_str()).collect();
+        if failed.is_empty() {
+            self.add_log("info", "✅ Self-test passed - traffic is protected".to_string(), "security").await;
+        } else {
+            self.add_log("warn", format!("⚠️ Self-test failed: {}", failed.join(", ")), "security").await;
+        }
+        report
+    }
+}
+
+async fn run_self_test(State(state): State<ApiState>) -> Json<SelfTestReport> {
+    Json(state.self_test().await)
+}
+
+/// LAN addresses other devices can point their proxy settings at
+async fn get_network_info(State(state): State<ApiState>) -> Json<NetworkInfo> {
+    let config = state.config.read().await.clone();
+    let proxy_port = config.proxy_port().unwrap_or(DEFAULT_PROXY_PORT);
+    Json(NetworkInfo::new(lan_addresses::lan_addresses(config.allow_ipv6_over_tor), proxy_port))
+}
+
+#[derive(Deserialize)]
+struct SetupInstructionsQuery {
+    os: Option<SetupPlatform>,
+}
+
+/// Step-by-step manual proxy setup for `os` (this machine's OS by default)
+async fn get_setup_instructions(
+    State(state): State<ApiState>,
+    Query(query): Query<SetupInstructionsQuery>,
+) -> Json<SetupInstructions> {
+    let config = state.config.read().await.clone();
+    let proxy_port = config.proxy_port().unwrap_or(DEFAULT_PROXY_PORT);
+    // Other devices can only connect when the proxy listens beyond loopback
+    let bind_ip = config
+        .proxy_addr()
+        .rsplit_once(':')
+        .and_then(|(host, _)| host.trim_matches(|c| c == '[' || c == ']').parse::<std::net::IpAddr>().ok());
+    let lan_ip = if bind_ip.is_some_and(|ip| ip.is_loopback()) {
+        None
+    } else {
+        lan_addresses::lan_addresses(false).first().map(|address| address.ip)
+    };
+    Json(setup_instructions::instructions(query.os.unwrap_or_else(SetupPlatform::current), proxy_port, lan_ip))
+}
+
+/// Onion services the running session publishes, with their `.onion` hostnames
+async fn get_onion_services(State(state): State<ApiState>) -> Json<Vec<OnionServiceInfo>> {
+    Json(state.onion_services.read().await.clone())
+}
+
+/// Past sessions, most recent first
+async fn get_history(State(state): State<ApiState>) -> Json<Vec<SessionSummary>> {
+    Json(state.history.recent())
+}
+
+/// Render stats in the Prometheus text exposition format
+pub fn render_metrics(stats: &Stats) -> String {
+    let metrics: [(&str, &str, &str, u64); 17] = [
+        ("dul_requests_total", "counter", "Total requests handled by the proxy", stats.total_requests),
+        ("dul_requests_blocked_total", "counter", "Requests blocked by any protection", stats.requests_blocked),
+        ("dul_requests_dropped_total", "counter", "Requests dropped because the response was too large", stats.requests_dropped),
+        ("dul_trackers_blocked_total", "counter", "Requests blocked as known trackers", stats.trackers_blocked),
+        ("dul_webrtc_blocked_total", "counter", "WebRTC/STUN requests blocked", stats.webrtc_blocked),
+        ("dul_ipv6_blocked_total", "counter", "IPv6 requests blocked", stats.ipv6_blocked),
+        ("dul_security_threats_total", "counter", "Security threats detected", stats.security_threats_detected),
+        ("dul_bytes_transferred_total", "counter", "Bytes transferred through the proxy", stats.bytes_transferred),
+        ("dul_cache_hits_total", "counter", "GET responses served from the response cache", stats.cache_hits),
+        ("dul_cache_misses_total", "counter", "Cacheable GETs that had to be fetched", stats.cache_misses),
+        ("dul_circuit_rebuilds_total", "counter", "Tor circuits rebuilt after repeated failures", stats.circuit_rebuilds),
+        ("dul_would_block_total", "counter", "Requests learning mode let through instead of blocking", stats.would_block),
+        ("dul_headers_scrubbed_total", "counter", "Response headers removed by the header scrubber", stats.headers_scrubbed),
+        ("dul_active_connections", "gauge", "Currently open client connections", stats.active_connections),
+        ("dul_uptime_seconds", "gauge", "Seconds connected in the current session", stats.uptime_seconds),
+        ("dul_tor_connected", "gauge", "Whether Tor is connected (1) or not (0)", stats.tor_connected as u64),
+        ("dul_kill_switch_active", "gauge", "Whether the kill switch is active (1) or not (0)", stats.kill_switch_active as u64),
+    ];
+    
+    let mut output = String::new();
+    for (name, metric_type, help, value) in metrics {
+        output.push_str(&format!("# HELP {} {}\n", name, help));
+        output.push_str(&format!("# TYPE {} {}\n", name, metric_type));
+        output.push_str(&format!("{} {}\n", name, value));
+    }
+    output
+}
+
+async fn get_metrics(State(state): State<ApiState>) -> impl IntoResponse {
+    let stats = state.stats_snapshot().await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_metrics(&stats),
+    )
+}
+
+#[derive(Serialize)]
+struct CircuitInfo {
+    path: Option<Vec<CircuitHop>>,
+    exit_country: Option<String>,
+}
+
+async fn get_circuit(State(state): State<ApiState>) -> Json<CircuitInfo> {
+    let path = state.circuit.read().await.clone();
+    let exit_country = state.stats.read().await.exit_country.clone();
+    Json(CircuitInfo { path, exit_country })
 }
 
 async fn get_logs(State(state): State<ApiState>) -> Json<Vec<LogEntry>> {
@@ -161,6 +1189,53 @@ async fn get_logs(State(state): State<ApiState>) -> Json<Vec<LogEntry>> {
     Json(logs)
 }
 
+/// Lifecycle and security events only, without per-request entries
+async fn get_events(State(state): State<ApiState>) -> Json<Vec<LogEntry>> {
+    let events = state.events.read().await.clone();
+    Json(events)
+}
+
+/// One log entry with its full details, e.g. why a request was blocked
+async fn get_log(State(state): State<ApiState>, UrlPath(id): UrlPath<u64>) -> Result<Json<LogEntry>, StatusCode> {
+    let logs = state.logs.read().await;
+    // Ids increase along the list, so search it like a sorted one
+    match logs.binary_search_by_key(&id, |log| log.id) {
+        Ok(index) => Ok(Json(logs[index].clone())),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn get_log_levels(State(state): State<ApiState>) -> Json<HashMap<String, LogLevel>> {
+    Json(state.config.read().await.log_levels.clone())
+}
+
+#[derive(Deserialize)]
+struct LogLevelRequest {
+    category: String,
+    level: LogLevel,
+}
+
+/// Change how verbose one in-app log category is, without a restart
+async fn set_log_level(
+    State(state): State<ApiState>,
+    Json(request): Json<LogLevelRequest>,
+) -> Result<Json<HashMap<String, LogLevel>>, StatusCode> {
+    let category = request.category.trim();
+    if category.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let log_levels = state.set_log_level(category, request.level);
+    let saved = {
+        let mut config = state.config.write().await;
+        config.log_levels = log_levels.clone();
+        config.save().map_err(|e| e.to_string())
+    };
+    if let Err(e) = saved {
+        state.add_log("warn", format!("⚠️ Log level changed but could not be saved: {}", e), "general").await;
+    }
+    Ok(Json(log_levels))
+}
+
 #[derive(Deserialize)]
 struct LogFilter {
     category: Option<String>,
@@ -195,18 +1270,12 @@ async fn get_filtered_logs(
 async fn stats_stream(
     State(state): State<ApiState>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let stream = stream::unfold(state, |state| async move {
-        tokio::time::sleep(Duration::from_millis(100)).await;
-        let mut stats = state.stats.read().await.clone();
-        // Calculate only connected session duration
-        if let Some(connected_since) = *state.connected_time.read().await {
-            stats.uptime_seconds = connected_since.elapsed().as_secs();
-        } else {
-            stats.uptime_seconds = 0;
-        }
-        let event = Event::default().json_data(stats).ok()?;
-        Some((Ok(event), state))
+    // A slow client skips straight to the newest stats
+    let stats = sse::latest(Duration::from_millis(100), move || {
+        let state = state.clone();
+        async move { state.stats_snapshot().await }
     });
+    let stream = stats.filter_map(|stats| future::ready(Event::default().json_data(stats).ok().map(Ok)));
 
     Sse::new(stream).keep_alive(
         axum::response::sse::KeepAlive::new()
@@ -215,15 +1284,39 @@ async fn stats_stream(
     )
 }
 
+/// SSE event for a frame of a log stream. A `resync` event names the
+/// endpoint to refetch before reconnecting.
+fn log_stream_event(frame: sse::Frame<Vec<LogEntry>>, resync_from: &str) -> Option<Event> {
+    match frame {
+        sse::Frame::Data(entries) => Event::default().json_data(entries).ok(),
+        sse::Frame::Resync => Some(Event::default().event("resync").data(resync_from)),
+    }
+}
+
 async fn logs_stream(
     State(state): State<ApiState>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let stream = stream::unfold(state, |state| async move {
-        tokio::time::sleep(Duration::from_millis(200)).await;
-        let logs = state.logs.read().await.clone();
-        let event = Event::default().json_data(logs).ok()?;
-        Some((Ok(event), state))
+    let logs = sse::queued(Duration::from_millis(200), sse::LOG_STREAM_BACKLOG, move || {
+        let state = state.clone();
+        async move { state.logs.read().await.clone() }
+    });
+    let stream = logs.filter_map(|frame| future::ready(log_stream_event(frame, "/api/logs").map(Ok)));
+
+    Sse::new(stream).keep_alive(
+        axum::response::sse::KeepAlive::new()
+            .interval(Duration::from_secs(1))
+            .text("keep-alive-text"),
+    )
+}
+
+async fn events_stream(
+    State(state): State<ApiState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = sse::queued(Duration::from_millis(200), sse::LOG_STREAM_BACKLOG, move || {
+        let state = state.clone();
+        async move { state.events.read().await.clone() }
     });
+    let stream = events.filter_map(|frame| future::ready(log_stream_event(frame, "/api/events").map(Ok)));
 
     Sse::new(stream).keep_alive(
         axum::response::sse::KeepAlive::new()
@@ -253,38 +1346,518 @@ async fn toggle_kill_switch(
         state.add_log("info", log_msg, "general").await;
     }
     
-    let mut stats = state.stats.read().await.clone();
-    // Calculate only connected session duration
-    if let Some(connected_since) = *state.connected_time.read().await {
-        stats.uptime_seconds = connected_since.elapsed().as_secs();
-    } else {
-        stats.uptime_seconds = 0;
-    }
-    Json(stats)
+    Json(state.stats_snapshot().await)
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ShutdownReport {
+    /// The system proxy was restored, or was never changed
+    pub system_proxy_restored: bool,
+    pub kill_switch_disabled: bool,
+    pub connections_closed: usize,
+    pub proxy_stopped: bool,
+    /// Every step finished within the shutdown timeout
+    pub completed: bool,
+    pub errors: Vec<String>,
 }
 
 async fn shutdown(
     State(state): State<ApiState>,
-) -> Json<bool> {
+) -> Json<ShutdownReport> {
     state.add_log("info", "Shutdown requested from GUI".to_string(), "general").await;
     
-    // Disable kill switch
-    if let Some(ref ks) = state.kill_switch {
-        ks.set_enabled(false).await;
+    let report = state.shutdown().await;
+    if report.errors.is_empty() {
+        state.add_log("info", "✅ System settings restored - exiting".to_string(), "general").await;
+    } else {
+        warn!("Shutdown cleanup incomplete: {}", report.errors.join("; "));
+        state.add_log("error", format!("⚠️ Exiting with incomplete cleanup: {}", report.errors.join("; ")), "general").await;
+    }
+    
+    // Cleanup is done; exit once the response has had time to reach the GUI
+    let exit = state.exit.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(EXIT_DELAY).await;
+        exit(ShutdownReason::UserRequest);
+    });
+    
+    Json(report)
+}
+
+#[derive(Serialize)]
+struct BlocklistRefreshResponse {
+    success: bool,
+    summary: Option<RefreshSummary>,
+    error: Option<String>,
+}
+
+async fn refresh_blocklist(State(state): State<ApiState>) -> Json<BlocklistRefreshResponse> {
+    match state.refresh_blocklist().await {
+        Ok(summary) => Json(BlocklistRefreshResponse {
+            success: true,
+            summary: Some(summary),
+            error: None,
+        }),
+        Err(e) => Json(BlocklistRefreshResponse {
+            success: false,
+            summary: None,
+            error: Some(e),
+        }),
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FingerprintState {
+    pub fingerprint: BrowserFingerprint,
+    pub pinned: bool,
+}
+
+impl FingerprintState {
+    fn of(session: &SessionFingerprint) -> Self {
+        Self {
+            fingerprint: session.current(),
+            pinned: session.is_pinned(),
+        }
+    }
+}
+
+async fn get_fingerprint(State(state): State<ApiState>) -> Json<FingerprintState> {
+    Json(FingerprintState::of(&state.fingerprint))
+}
+
+/// Pin `fingerprint`, or roll a new random one with `reroll`
+#[derive(Deserialize)]
+struct FingerprintChange {
+    fingerprint: Option<BrowserFingerprint>,
+    #[serde(default)]
+    reroll: bool,
+}
+
+#[derive(Serialize)]
+struct FingerprintChangeResponse {
+    success: bool,
+    state: FingerprintState,
+    error: Option<String>,
+}
+
+/// Pin or reroll the session fingerprint; applies to the next request
+async fn update_fingerprint(
+    State(state): State<ApiState>,
+    Json(change): Json<FingerprintChange>,
+) -> Json<FingerprintChangeResponse> {
+    let result = match (change.fingerprint, change.reroll) {
+        (Some(fingerprint), false) => state.fingerprint.pin(fingerprint).map(|_| "📌 Browser fingerprint pinned"),
+        (None, true) => {
+            state.fingerprint.reroll();
+            Ok("🎲 Browser fingerprint rerolled")
+        }
+        _ => Err("Send either a fingerprint to pin or reroll: true".to_string()),
+    };
+    
+    let error = match result {
+        Ok(message) => {
+            state.add_log("info", format!("{}: {}", message, state.fingerprint.current().user_agent), "general").await;
+            None
+        }
+        Err(e) => Some(e),
+    };
+    
+    Json(FingerprintChangeResponse {
+        success: error.is_none(),
+        state: FingerprintState::of(&state.fingerprint),
+        error,
+    })
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct NodeScore {
+    pub address: String,
+    pub reputation: f32,
+    pub banned: bool,
+    pub latency_ms: Option<u64>,
+}
+
+impl From<Node> for NodeScore {
+    fn from(node: Node) -> Self {
+        Self {
+            banned: node.is_banned(),
+            address: node.address,
+            reputation: node.reputation,
+            latency_ms: node.latency_ms,
+        }
+    }
+}
+
+/// Current reputation of every registered custom-network node
+async fn get_nodes(State(state): State<ApiState>) -> Json<Vec<NodeScore>> {
+    let nodes = match &state.node_registry {
+        Some(registry) => registry.all_nodes().unwrap_or_else(|e| {
+            warn!("Failed to read node registry: {}", e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+    Json(nodes.into_iter().map(NodeScore::from).collect())
+}
+
+#[derive(Deserialize)]
+struct HostsQuery {
+    limit: Option<usize>,
+}
+
+/// Destination hosts seen this session, heaviest bandwidth users first
+async fn get_hosts(State(state): State<ApiState>, Query(query): Query<HostsQuery>) -> Json<Vec<HostStats>> {
+    Json(state.hosts.top(query.limit.unwrap_or(MAX_TRACKED_HOSTS)))
+}
+
+/// Merge a hosts-file or Adblock list from the request body into the blocklist
+async fn import_blocklist(State(state): State<ApiState>, body: String) -> Json<ImportSummary> {
+    let summary = state.tracker_blocker.import(&body);
+    state.add_log("info", format!(
+        "📥 Imported {:?} blocklist: {} domains, {} new ({} total)",
+        summary.format, summary.parsed, summary.added, summary.total
+    ), "tracker").await;
+    Json(summary)
+}
+
+#[derive(Deserialize)]
+struct BlocklistExportQuery {
+    format: Option<BlocklistFormat>,
+}
+
+/// The current blocklist as a hosts file (the default) or an Adblock list
+async fn export_blocklist(
+    State(state): State<ApiState>,
+    Query(query): Query<BlocklistExportQuery>,
+) -> impl IntoResponse {
+    let format = query.format.unwrap_or(BlocklistFormat::Hosts);
+    let filename = match format {
+        BlocklistFormat::Hosts => "attachment; filename=\"blocklist.hosts\"",
+        BlocklistFormat::Adblock => "attachment; filename=\"blocklist.txt\"",
+    };
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8"), (header::CONTENT_DISPOSITION, filename)],
+        state.tracker_blocker.export(format),
+    )
+}
+
+#[derive(Deserialize)]
+struct BlocklistCheckQuery {
+    domain: String,
+}
+
+/// Report whether a domain would be blocked and which rule decides it
+async fn check_blocklist(
+    State(state): State<ApiState>,
+    Query(query): Query<BlocklistCheckQuery>,
+) -> Json<BlockCheck> {
+    let domain = blocklist::normalize_host(query.domain.trim());
+    Json(state.tracker_blocker.check(&domain))
+}
+
+#[derive(Deserialize)]
+struct TemporaryAllowQuery {
+    domain: String,
+    scope: AllowScope,
+}
+
+/// Let a blocked domain through once or until disconnect, e.g. from a block
+/// page's buttons. The saved allowlist is left alone.
+async fn allow_temporarily(
+    State(state): State<ApiState>,
+    Query(query): Query<TemporaryAllowQuery>,
+) -> Result<Json<TemporaryAllowlist>, StatusCode> {
+    let domain = blocklist::normalize_host(query.domain.trim());
+    if domain.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let allowed = state.tracker_blocker.allow_temporarily(&domain, query.scope);
+    let scope = match query.scope {
+        AllowScope::Once => "for one request",
+        AllowScope::Session => "until disconnect",
+    };
+    state.add_log("info", format!("🔓 {} allowed {}", domain, scope), "tracker").await;
+    Ok(Json(allowed))
+}
+
+async fn get_temporary_allowlist(State(state): State<ApiState>) -> Json<TemporaryAllowlist> {
+    Json(state.tracker_blocker.temporary_allowlist())
+}
+
+async fn list_subscriptions(state: &ApiState) -> Vec<Subscription> {
+    let config = state.config.read().await;
+    state.tracker_blocker.subscriptions(&config.tracker_lists, &config.disabled_tracker_lists)
+}
+
+/// Log (rather than fail on) a config that couldn't be saved after a subscription change
+async fn save_subscriptions(state: &ApiState, saved: Result<(), String>) {
+    if let Err(e) = saved {
+        state.add_log("warn", format!("⚠️ Blocklist subscriptions changed but could not be saved: {}", e), "tracker").await;
+    }
+}
+
+async fn get_subscriptions(State(state): State<ApiState>) -> Json<Vec<Subscription>> {
+    Json(list_subscriptions(&state).await)
+}
+
+#[derive(Deserialize)]
+struct SubscriptionRequest {
+    url: String,
+}
+
+/// Subscribe to a tracker list; it is loaded on the next blocklist refresh
+async fn add_subscription(
+    State(state): State<ApiState>,
+    Json(request): Json<SubscriptionRequest>,
+) -> Result<Json<Vec<Subscription>>, StatusCode> {
+    let url = request.url.trim();
+    if !["https://", "http://", "file://"].iter().any(|scheme| url.starts_with(scheme)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let saved = {
+        let mut config = state.config.write().await;
+        if !config.add_tracker_list(url) {
+            return Err(StatusCode::CONFLICT);
+        }
+        config.save().map_err(|e| e.to_string())
+    };
+    save_subscriptions(&state, saved).await;
+    state.add_log("info", format!("➕ Subscribed to blocklist {}", url), "tracker").await;
+    Ok(Json(list_subscriptions(&state).await))
+}
+
+/// Unsubscribe from a tracker list; its domains go on the next blocklist refresh
+async fn remove_subscription(
+    State(state): State<ApiState>,
+    Json(request): Json<SubscriptionRequest>,
+) -> Result<Json<Vec<Subscription>>, StatusCode> {
+    let saved = {
+        let mut config = state.config.write().await;
+        if !config.remove_tracker_list(&request.url) {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        config.save().map_err(|e| e.to_string())
+    };
+    save_subscriptions(&state, saved).await;
+    state.add_log("info", format!("➖ Unsubscribed from blocklist {}", request.url), "tracker").await;
+    Ok(Json(list_subscriptions(&state).await))
+}
+
+#[derive(Deserialize)]
+struct SubscriptionToggle {
+    url: String,
+    enabled: bool,
+}
+
+/// Switch a tracker list on or off; takes effect on the next blocklist refresh
+async fn toggle_subscription(
+    State(state): State<ApiState>,
+    Json(toggle): Json<SubscriptionToggle>,
+) -> Result<Json<Vec<Subscription>>, StatusCode> {
+    let saved = {
+        let mut config = state.config.write().await;
+        if !config.set_tracker_list_enabled(&toggle.url, toggle.enabled) {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        config.save().map_err(|e| e.to_string())
+    };
+    save_subscriptions(&state, saved).await;
+    state.add_log("info", format!("⚙️ Blocklist {} {}", toggle.url, if toggle.enabled { "enabled" } else { "disabled" }), "tracker").await;
+    Ok(Json(list_subscriptions(&state).await))
+}
+
+/// Protections to change; omitted fields keep their current value
+#[derive(Deserialize)]
+struct ProtectionUpdate {
+    webrtc_protection: Option<bool>,
+    ipv6_protection: Option<bool>,
+    canvas_protection: Option<bool>,
+    tracker_blocking: Option<bool>,
+    security_detection: Option<bool>,
+    #[serde(default)]
+    security_detectors: HashMap<String, bool>,
+    mode: Option<BrowsingMode>,
+    /// Replaces the whole allowed-domain list
+    allowed_domains: Option<Vec<String>>,
+}
+
+impl ProtectionUpdate {
+    /// Apply the update, returning any detector names that don't exist
+    fn apply(&self, settings: &mut ProtectionSettings) -> Vec<String> {
+        let fields = [
+            (self.webrtc_protection, &mut settings.webrtc_protection),
+            (self.ipv6_protection, &mut settings.ipv6_protection),
+            (self.canvas_protection, &mut settings.canvas_protection),
+            (self.tracker_blocking, &mut settings.tracker_blocking),
+            (self.security_detection, &mut settings.security_detection),
+        ];
+        for (update, value) in fields {
+            if let Some(enabled) = update {
+                *value = enabled;
+            }
+        }
+        if let Some(mode) = self.mode {
+            settings.mode = mode;
+        }
+        if let Some(ref allowed_domains) = self.allowed_domains {
+            settings.allowed_domains = allowed_domains
+                .iter()
+                .map(|domain| blocklist::normalize_host(domain.trim()))
+                .filter(|domain| !domain.is_empty())
+                .collect();
+        }
+        
+        let mut unknown = Vec::new();
+        for (name, enabled) in &self.security_detectors {
+            match settings.security_detectors.get_mut(name) {
+                Some(value) => *value = *enabled,
+                None => unknown.push(name.clone()),
+            }
+        }
+        unknown
+    }
+}
+
+async fn get_config(State(state): State<ApiState>) -> Json<ProtectionSettings> {
+    Json(state.config.read().await.protections())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigLocation {
+    /// The config.toml the backend loaded
+    pub path: std::path::PathBuf,
+    /// The directory holding it, for opening in a file manager
+    pub dir: Option<std::path::PathBuf>,
+}
+
+/// Where the config file is, for users editing advanced settings by hand
+async fn get_config_path(State(state): State<ApiState>) -> Json<ConfigLocation> {
+    let path = state.config.read().await.config_path().to_path_buf();
+    let dir = path.parent().map(Path::to_path_buf);
+    Json(ConfigLocation { path, dir })
+}
+
+/// Change individual protections; they take effect on the next connection
+async fn update_config(
+    State(state): State<ApiState>,
+    Json(update): Json<ProtectionUpdate>,
+) -> Json<ProtectionSettings> {
+    let (settings, unknown, saved) = {
+        let mut config = state.config.write().await;
+        let mut settings = config.protections();
+        let unknown = update.apply(&mut settings);
+        config.set_protections(settings.clone());
+        (settings, unknown, config.save())
+    };
+    
+    if !unknown.is_empty() {
+        state.add_log("warn", format!("⚠️ Ignored unknown security detectors: {}", unknown.join(", ")), "general").await;
+    }
+    if let Err(e) = saved {
+        state.add_log("warn", format!("⚠️ Protection settings changed but could not be saved: {}", e), "general").await;
+    }
+    state.add_log("info", format!(
+        "⚙️ Protections updated (WebRTC: {}, IPv6: {}, canvas: {}, trackers: {}, security detection: {}) - applies on next connection",
+        on_off(settings.webrtc_protection),
+        on_off(settings.ipv6_protection),
+        on_off(settings.canvas_protection),
+        on_off(settings.tracker_blocking),
+        on_off(settings.security_detection),
+    ), "general").await;
+    if settings.mode == BrowsingMode::Allowlist {
+        state.add_log("info", format!("⚙️ Allowlist mode: only {} allowed domains are reachable", settings.allowed_domains.len()), "general").await;
+    }
+    
+    let mut disabled: Vec<&str> = settings
+        .security_detectors
+        .iter()
+        .filter(|(_, enabled)| !**enabled)
+        .map(|(name, _)| name.as_str())
+        .collect();
+    if !disabled.is_empty() {
+        disabled.sort_unstable();
+        state.add_log("info", format!("⚙️ Security detectors off: {}", disabled.join(", ")), "general").await;
     }
     
-    // Disable system proxy
+    Json(settings)
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled { "on" } else { "off" }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PanicReport {
+    pub kill_switch_engaged: bool,
+    pub connections_aborted: usize,
+    pub proxy_stopped: bool,
+    pub dns_flushed: bool,
+}
+
+async fn panic_button(State(state): State<ApiState>) -> Json<PanicReport> {
+    Json(state.panic().await)
+}
+
+#[derive(Serialize)]
+struct ElevationResponse {
+    elevated: bool,
+    relaunching: bool,
+    message: String,
+}
+
+async fn request_elevation(
+    State(state): State<ApiState>,
+) -> Json<ElevationResponse> {
     if sys_proxy::is_elevated() {
-        let _ = state.system_proxy.write().await.disable();
+        return Json(ElevationResponse {
+            elevated: true,
+            relaunching: false,
+            message: "Already running with administrator privileges".to_string(),
+        });
     }
     
-    // Stop proxy
-    if let Some(handle) = state.proxy_handle.write().await.take() {
-        handle.abort();
-    }
+    state.add_log("info", "🔐 Requesting administrator privileges for system-wide protection...".to_string(), "general").await;
+    
+    // Blocks until the UAC prompt is answered
+    let result = tokio::task::spawn_blocking(sys_proxy::relaunch_elevated)
+        .await
+        .unwrap_or_else(|e| Err(format!("Elevation task failed: {}", e)));
     
-    // Exit the process
-    std::process::exit(0);
+    match result {
+        Ok(()) => {
+            state.add_log("info", "✅ Elevated instance starting - handing over to it".to_string(), "general").await;
+            
+            // Let the response reach the GUI, then release port 3030 for the elevated instance
+            let exit_state = state.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                
+                if let Some(ref ks) = exit_state.kill_switch {
+                    ks.set_enabled(false).await;
+                }
+                if let Some(handle) = exit_state.proxy_handle.write().await.take() {
+                    handle.abort();
+                }
+                
+                (exit_state.exit)(ShutdownReason::UserRequest);
+            });
+            
+            Json(ElevationResponse {
+                elevated: false,
+                relaunching: true,
+                message: "Restarting with administrator privileges".to_string(),
+            })
+        }
+        Err(e) => {
+            state.add_log("warn", format!("⚠️ Elevation not granted ({}) - continuing in manual proxy mode", e), "general").await;
+            
+            Json(ElevationResponse {
+                elevated: false,
+                relaunching: false,
+                message: e,
+            })
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -315,20 +1888,16 @@ async fn change_exit_country(
         "Auto (Random)"
     };
     
-    state.update_stats(|s| s.exit_country = change.country.clone()).await;
+    state.request_exit_country(change.country.clone()).await;
     state.add_log("info", format!("🌍 Exit location changed to: {}", country_name), "network").await;
     
+    // The recorded path no longer reflects the requested exit
+    state.set_circuit(None).await;
+    
     // Tor circuit restart would be implemented here
     // For now, we just update the preference for the next connection
     
-    let mut stats = state.stats.read().await.clone();
-    // Calculate only connected session duration
-    if let Some(connected_since) = *state.connected_time.read().await {
-        stats.uptime_seconds = connected_since.elapsed().as_secs();
-    } else {
-        stats.uptime_seconds = 0;
-    }
-    Json(stats)
+    Json(state.stats_snapshot().await)
 }
 
 #[derive(Deserialize)]
@@ -348,14 +1917,13 @@ async fn toggle_connection(
         
         if is_already_running || has_handle {
             state.add_log("warn", "Already connected or connecting...".to_string(), "general").await;
-            let mut stats = state.stats.read().await.clone();
-            // Calculate only connected session duration
-            if let Some(connected_since) = *state.connected_time.read().await {
-                stats.uptime_seconds = connected_since.elapsed().as_secs();
-            } else {
-                stats.uptime_seconds = 0;
-            }
-            return Json(stats);
+            return Json(state.stats_snapshot().await);
+        }
+        
+        // Nothing is started (or pointed at the proxy) when Tor can't run
+        if let TorSupport::Unsupported { reason } = state.check_tor_support().await {
+            state.add_log("warn", format!("⚠️ Not connecting - Tor can't run on this system: {}", reason), "general").await;
+            return Json(state.stats_snapshot().await);
         }
         
         // Start connection
@@ -378,15 +1946,30 @@ async fn toggle_connection(
                 _ => country.as_str(),
             };
             state.add_log("info", format!("🌍 Exit location set to: {}", country_name), "network").await;
-            state.update_stats(|s| s.exit_country = Some(country.clone())).await;
         } else {
             state.add_log("info", "🌍 Exit location: Auto (Random)".to_string(), "network").await;
-            state.update_stats(|s| s.exit_country = None).await;
         }
+        state.request_exit_country(toggle.exit_country.clone()).await;
+        
+        let mut config = state.config.read().await.clone();
+        
+        // Claim the proxy port before touching the system proxy, so it points at the port really bound
+        let listener = match bind_proxy_port(&state, &mut config).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                state.add_log("error", format!("Failed to start proxy: {}", e), e.log_category()).await;
+                state.update_stats(|s| {
+                    s.connection_state = ConnectionState::Failed;
+                    s.proxy_error = Some(e.to_string());
+                }).await;
+                return Json(state.stats_snapshot().await);
+            }
+        };
+        let proxy_port = config.proxy_port().unwrap_or(DEFAULT_PROXY_PORT);
         
         // Configure system proxy if running as admin
         if sys_proxy::is_elevated() {
-            let proxy_addr = (*state.config).proxy_addr();
+            let proxy_addr = config.proxy_addr();
             match state.system_proxy.write().await.enable(&proxy_addr) {
                 Ok(_) => {
                     state.add_log("info", "✅ System proxy configured - all apps will be protected".to_string(), "general").await;
@@ -396,58 +1979,17 @@ async fn toggle_connection(
                     state.add_log("warn", format!("Failed to configure system proxy: {}", e), "general").await;
                 }
             }
+        } else {
+            state.add_log("info", format!("ℹ️ Manual proxy mode - configure apps to use port {}", proxy_port), "general").await;
         }
         
+        state.update_stats(|s| s.connection_state = ConnectionState::Connecting).await;
+        // A new session starts out active
+        state.idle.touch();
         let proxy_state = state.clone();
-        let config = (*state.config).clone();
-        
-        let handle = tokio::spawn(async move {
-            match ProxyServer::new(config.clone(), Some(proxy_state.clone())).await {
-                Ok(proxy) => {
-                    proxy_state.add_log("info", "✅ Connected to Tor! Using 6,000+ volunteer nodes".into(), "general").await;
-                    proxy_state.add_log("info", "🌐 Proxy listening on all network interfaces (0.0.0.0:8888)".into(), "network").await;
-                    proxy_state.add_log("info", "📱 Other devices can connect using your LAN IP:8888".into(), "network").await;
-                    
-                    // Reset counters for new session
-                    proxy_state.update_stats(|s| {
-                        s.proxy_running = true;
-                        s.tor_connected = true;
-                        s.requests_blocked = 0;
-                        s.trackers_blocked = 0;
-                        s.webrtc_blocked = 0;
-                        s.ipv6_blocked = 0;
-                        s.total_requests = 0;
-                        s.uptime_seconds = 0;
-                        s.security_threats_detected = 0;
-                    }).await;
-                    
-                    // Start tracking connected time for this session
-                    *proxy_state.connected_time.write().await = Some(std::time::Instant::now());
-                    *proxy_state.total_connected_duration.write().await = 0;
-                    
-                    info!("✅ Privacy Suite proxy is running!");
-                    proxy_state.add_log("info", "✅ All systems operational - Privacy Suite is LIVE".to_string(), "general").await;
-                    
-                    let _ = proxy.run().await;
-                    
-                    // Stop tracking connected time and add to total
-                    if let Some(connected_since) = proxy_state.connected_time.write().await.take() {
-                        let session_duration = connected_since.elapsed().as_secs();
-                        *proxy_state.total_connected_duration.write().await += session_duration;
-                    }
-                    
-                    proxy_state.update_stats(|s| {
-                        s.proxy_running = false;
-                        s.tor_connected = false;
-                    }).await;
-                    
-                    proxy_state.add_log("info", "Proxy stopped".to_string(), "general").await;
-                }
-                Err(e) => {
-                    proxy_state.add_log("error", format!("Failed to start proxy: {}", e), "general").await;
-                }
-            }
-        });
+        let connect_timeout = Duration::from_secs(config.connect_timeout_secs);
+        let bootstrap = ProxyServer::with_listener(listener, config.clone(), Some(proxy_state.clone()));
+        let handle = tokio::spawn(run_session(proxy_state, bootstrap, connect_timeout, config, proxy_port));
         
         *state.proxy_handle.write().await = Some(handle);
         
@@ -470,12 +2012,14 @@ async fn toggle_connection(
         }
         
         if let Some(handle) = state.proxy_handle.write().await.take() {
-            // Clear connected duration
-            *state.connected_time.write().await = None;
+            // Record the session, then clear connected duration
+            state.end_session().await;
             *state.total_connected_duration.write().await = 0;
             
             handle.abort();
+            state.set_circuit(None).await;
             state.update_stats(|s| {
+                s.connection_state = ConnectionState::Disconnected;
                 s.proxy_running = false;
                 s.tor_connected = false;
                 s.uptime_seconds = 0;
@@ -486,15 +2030,179 @@ async fn toggle_connection(
         }
     }
     
-    let mut stats = state.stats.read().await.clone();
-    stats.uptime_seconds = state.start_time.elapsed().as_secs();
-    Json(stats)
+    Json(state.stats_snapshot().await)
+}
+
+/// Bootstrap the proxy and run it until it stops, keeping
+/// `Stats::connection_state` in step. Bootstrapping fails after `connect_timeout`.
+async fn run_session(
+    proxy_state: ApiState,
+    bootstrap: impl std::future::Future<Output = crate::error::Result<ProxyServer>>,
+    connect_timeout: Duration,
+    config: Config,
+    proxy_port: u16,
+) {
+    proxy_state.update_stats(|s| s.connection_state = ConnectionState::Connecting).await;
+    let bootstrapped = tokio::time::timeout(connect_timeout, bootstrap)
+        .await
+        .unwrap_or_else(|_| Err(crate::error::PrivacyError::TorBootstrap(format!(
+            "timed out after {}s (connect_timeout_secs)",
+            connect_timeout.as_secs()
+        ))));
+    
+    match bootstrapped {
+        Ok(proxy) => {
+            proxy_state.add_log("info", "✅ Connected to Tor! Using 6,000+ volunteer nodes".into(), "general").await;
+            proxy_state.add_log("info", format!("🌐 Proxy listening on {}", config.proxy_addr()), "network").await;
+            proxy_state.add_log("info", format!("📱 Other devices can connect using your LAN IP:{}", proxy_port), "network").await;
+            
+            // Reset counters for new session
+            proxy_state.update_stats(|s| {
+                s.connection_state = ConnectionState::Connected;
+                s.proxy_running = true;
+                s.tor_connected = true;
+                s.proxy_error = None;
+                s.uptime_seconds = 0;
+            }).await;
+            proxy_state.counters.reset_session();
+            proxy_state.hosts.clear();
+            
+            // Start tracking connected time for this session
+            *proxy_state.connected_time.write().await = Some(std::time::Instant::now());
+            *proxy_state.total_connected_duration.write().await = 0;
+            
+            info!("✅ Privacy Suite proxy is running!");
+            proxy_state.add_log("info", "✅ All systems operational - Privacy Suite is LIVE".to_string(), "general").await;
+            
+            let stopped = proxy.run().await;
+            if let Err(ref e) = stopped {
+                proxy_state.add_log("error", format!("❌ Proxy stopped: {}", e), e.log_category()).await;
+                // The session's Tor client went with it
+                proxy_state.add_log("error", "🔌 Tor disconnected - the session ended unexpectedly, reconnect to restore protection".to_string(), "security").await;
+            }
+            
+            // Stop tracking connected time and add to total
+            proxy_state.end_session().await;
+            
+            proxy_state.update_stats(|s| {
+                s.proxy_running = false;
+                s.tor_connected = false;
+                match stopped {
+                    Ok(()) => s.connection_state = ConnectionState::Disconnected,
+                    Err(e) => {
+                        s.connection_state = ConnectionState::Failed;
+                        s.proxy_error = Some(e.to_string());
+                    }
+                }
+            }).await;
+            
+            proxy_state.add_log("info", "Proxy stopped".to_string(), "general").await;
+        }
+        Err(e) => {
+            proxy_state.add_log("error", format!("Failed to start proxy: {}", e), e.log_category()).await;
+            if let Some(ref ks) = proxy_state.kill_switch {
+                ks.set_tor_status(false).await;
+            }
+            proxy_state.update_stats(|s| {
+                s.connection_state = ConnectionState::Failed;
+                s.tor_connected = false;
+                s.proxy_error = Some(e.to_string());
+            }).await;
+        }
+    }
+}
+
+/// Bind the proxy listener, moving `config` to the fallback port if the configured
+/// one is taken, and record the bound address for the GUIs
+async fn bind_proxy_port(state: &ApiState, config: &mut Config) -> crate::error::Result<tokio::net::TcpListener> {
+    let addr: std::net::SocketAddr = config.proxy_addr().parse()?;
+    let listener = proxy::bind_with_fallback(addr).await?;
+    let bound = listener.local_addr()?;
+    
+    if bound.port() != addr.port() {
+        state.add_log("warn", format!("⚠️ Port {} is in use - proxy moved to port {}", addr.port(), bound.port()), "network").await;
+        config.set_proxy_port(bound.port());
+    }
+    if let Err(e) = runtime_info::record_proxy(config, bound) {
+        warn!("Failed to write {}: {}", runtime_info::RUNTIME_FILE, e);
+    }
+    Ok(listener)
+}
+
+/// One command in a `/api/batch` request, named by its `op` field
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum BatchCommand {
+    GetStats,
+    GetLogs,
+    FilterLogs(LogFilter),
+    GetCircuit,
+    GetConfig,
+    UpdateConfig(ProtectionUpdate),
+    ToggleKillswitch(KillSwitchToggle),
+    ToggleConnection(ConnectionToggle),
+    SetExitCountry(ExitCountryChange),
+}
+
+#[derive(Debug, Serialize)]
+struct BatchResult {
+    success: bool,
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+}
+
+impl BatchResult {
+    fn failed(error: String) -> Self {
+        Self { success: false, result: None, error: Some(error) }
+    }
+}
+
+/// Run several commands in order in a single round trip.
+///
+/// Each command gets its own result; an invalid one fails on its own
+/// without stopping the rest of the batch.
+async fn run_batch(
+    State(state): State<ApiState>,
+    Json(commands): Json<Vec<serde_json::Value>>,
+) -> Json<Vec<BatchResult>> {
+    let mut results = Vec::with_capacity(commands.len());
+    
+    for command in commands {
+        let command = match serde_json::from_value::<BatchCommand>(command) {
+            Ok(command) => command,
+            Err(e) => {
+                results.push(BatchResult::failed(format!("Invalid command: {}", e)));
+                continue;
+            }
+        };
+        
+        let state = State(state.clone());
+        let result = match command {
+            BatchCommand::GetStats => serde_json::to_value(get_stats(state).await.0),
+            BatchCommand::GetLogs => serde_json::to_value(get_logs(state).await.0),
+            BatchCommand::FilterLogs(filter) => serde_json::to_value(get_filtered_logs(state, Json(filter)).await.0),
+            BatchCommand::GetCircuit => serde_json::to_value(get_circuit(state).await.0),
+            BatchCommand::GetConfig => serde_json::to_value(get_config(state).await.0),
+            BatchCommand::UpdateConfig(update) => serde_json::to_value(update_config(state, Json(update)).await.0),
+            BatchCommand::ToggleKillswitch(toggle) => serde_json::to_value(toggle_kill_switch(state, Json(toggle)).await.0),
+            BatchCommand::ToggleConnection(toggle) => serde_json::to_value(toggle_connection(state, Json(toggle)).await.0),
+            BatchCommand::SetExitCountry(change) => serde_json::to_value(change_exit_country(state, Json(change)).await.0),
+        };
+        
+        results.push(match result {
+            Ok(value) => BatchResult { success: true, result: Some(value), error: None },
+            Err(e) => BatchResult::failed(format!("Could not encode result: {}", e)),
+        });
+    }
+    
+    Json(results)
 }
 
 pub async fn start_web_api(
     state: ApiState,
     port: u16,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let state_config = state.config.clone();
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
@@ -503,21 +2211,889 @@ pub async fn start_web_api(
     let app = Router::new()
         .route("/api/stats", get(get_stats))
         .route("/api/stats/stream", get(stats_stream))
+        .route("/api/circuit", get(get_circuit))
         .route("/api/logs", get(get_logs))
         .route("/api/logs/filter", post(get_filtered_logs))
         .route("/api/logs/stream", get(logs_stream))
+        .route("/api/logs/:id", get(get_log))
+        .route("/api/log-level", get(get_log_levels).put(set_log_level))
+        .route("/api/events", get(get_events))
+        .route("/api/events/stream", get(events_stream))
         .route("/api/killswitch", put(toggle_kill_switch))
         .route("/api/connection", post(toggle_connection))
         .route("/api/exit-country", put(change_exit_country))
+        .route("/api/batch", post(run_batch))
         .route("/api/shutdown", post(shutdown))
+        .route("/api/panic", post(panic_button))
+        .route("/api/blocklist/refresh", post(refresh_blocklist))
+        .route("/api/blocklist/check", get(check_blocklist))
+        .route("/api/blocklist/import", post(import_blocklist))
+        .route("/api/blocklist/export", get(export_blocklist))
+        .route("/api/allowlist/temp", get(get_temporary_allowlist).post(allow_temporarily))
+        .route(
+            "/api/subscriptions",
+            get(get_subscriptions).post(add_subscription).delete(remove_subscription).put(toggle_subscription),
+        )
+        .route("/api/elevate", post(request_elevation))
+        .route("/api/config", get(get_config).put(update_config))
+        .route("/api/config-path", get(get_config_path))
+        .route("/api/nodes", get(get_nodes))
+        .route("/api/hosts", get(get_hosts))
+        .route("/api/diagnostics", get(get_diagnostics))
+        .route("/api/test", post(run_self_test))
+        .route("/api/history", get(get_history))
+        .route("/api/onion-services", get(get_onion_services))
+        .route("/api/network-info", get(get_network_info))
+        .route("/api/setup-instructions", get(get_setup_instructions))
+        .route("/api/fingerprint", get(get_fingerprint).put(update_fingerprint))
+        .route("/metrics", get(get_metrics))
         .layer(cors)
         .with_state(state);
 
-    let addr = format!("127.0.0.1:{}", port);
-    info!("🌐 Web API listening on http://{}", addr);
+    // The certificate lives next to the config file
+    let tls_dir = {
+        let config = state_config.read().await;
+        config
+            .api_tls
+            .then(|| config.config_path().parent().map(Path::to_path_buf).unwrap_or_default())
+    };
+
+    let listener = proxy::bind_listener(std::net::SocketAddr::from(([127, 0, 0, 1], port))).await?;
+    let addr = listener.local_addr()?;
+    if let Err(e) = runtime_info::record_api(&*state_config.read().await, addr) {
+        warn!("Failed to write {}: {}", runtime_info::RUNTIME_FILE, e);
+    }
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    match tls_dir {
+        Some(dir) => {
+            let tls_config = api_tls::load_or_generate(&dir)?;
+            info!("🔒 Web API listening on https://{}", addr);
+            api_tls::serve(listener, app, tls_config).await?;
+        }
+        None => {
+            info!("🌐 Web API listening on http://{}", addr);
+            axum::serve(listener, app).await?;
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn threat(domain: &str) -> LogDetails {
+        LogDetails {
+            url: Some(format!("http://{}/", domain)),
+            domain: Some(domain.to_string()),
+            path: Some("/".to_string()),
+            method: Some("GET".to_string()),
+            threat_type: Some("Unencrypted connection".to_string()),
+            reason: Some("HTTP connection detected - data transmitted in plain text".to_string()),
+            ..LogDetails::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cleans_up_before_exit() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        
+        /// Flags the proxy task as torn down when it is dropped
+        struct DropFlag(Arc<AtomicBool>);
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+        
+        let proxy_dropped = Arc::new(AtomicBool::new(false));
+        let kill_switch = KillSwitch::new();
+        kill_switch.set_enabled(true).await;
+        
+        // Snapshot what cleanup had done at the moment exit was requested
+        let (exit_tx, mut exit_rx) = tokio::sync::mpsc::unbounded_channel();
+        let stats = Arc::new(RwLock::new(Stats { kill_switch_active: true, proxy_running: true, ..Stats::default() }));
+        let exit_stats = stats.clone();
+        let exit_dropped = proxy_dropped.clone();
+        let mut state = ApiState::new(Config::default())
+            .with_kill_switch(kill_switch.clone())
+            .with_exit_hook(move |reason| {
+                let stats = exit_stats.try_read().map(|s| (s.kill_switch_active, s.proxy_running)).ok();
+                let _ = exit_tx.send((reason, exit_dropped.load(Ordering::SeqCst), stats));
+            });
+        state.stats = stats;
+        
+        let flag = DropFlag(proxy_dropped.clone());
+        *state.proxy_handle.write().await = Some(tokio::spawn(async move {
+            let _flag = flag;
+            std::future::pending::<()>().await
+        }));
+        
+        let Json(report) = shutdown(State(state.clone())).await;
+        assert!(report.completed);
+        assert!(report.system_proxy_restored);
+        assert!(report.kill_switch_disabled);
+        assert!(report.proxy_stopped);
+        assert!(report.errors.is_empty());
+        
+        // The response is returned first; exit follows with every step already done
+        assert!(exit_rx.try_recv().is_err());
+        let (reason, dropped_at_exit, stats_at_exit) = exit_rx.recv().await.unwrap();
+        assert_eq!((reason, reason.exit_code()), (ShutdownReason::UserRequest, 0));
+        assert!(dropped_at_exit);
+        assert_eq!(stats_at_exit, Some((false, false)));
+        assert!(!kill_switch.get_stats().await.active);
+        assert!(state.proxy_handle.read().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batch_reports_errors_per_command() {
+        let kill_switch = KillSwitch::new();
+        kill_switch.set_enabled(false).await;
+        let state = ApiState::new(Config::default()).with_kill_switch(kill_switch.clone());
+        
+        let commands = serde_json::json!([
+            { "op": "toggle_killswitch", "enabled": true },
+            { "op": "launch_missiles" },
+            { "op": "toggle_killswitch" },
+            { "op": "get_stats" },
+            { "enabled": true },
+        ]);
+        let Json(results) = run_batch(State(state), Json(serde_json::from_value(commands).unwrap())).await;
+        
+        assert_eq!(results.len(), 5);
+        assert!(results[0].success);
+        assert_eq!(results[0].result.as_ref().unwrap()["kill_switch_active"], true);
+        
+        assert!(!results[1].success);
+        assert!(results[1].error.as_ref().unwrap().contains("launch_missiles"));
+        assert!(!results[2].success, "missing `enabled` is an error for that command only");
+        assert!(!results[4].success, "a command without an op is rejected");
+        
+        // Later commands still run, and see the effect of earlier ones
+        assert!(results[3].success);
+        assert_eq!(results[3].result.as_ref().unwrap()["kill_switch_active"], true);
+        assert!(kill_switch.get_stats().await.active);
+    }
+
+    #[tokio::test]
+    async fn test_web_api_reports_port_in_use() {
+        let taken = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+        
+        let err = start_web_api(ApiState::new(Config::default()), port).await.unwrap_err();
+        let err = err.downcast::<crate::error::PrivacyError>().expect("bind failures are PrivacyErrors");
+        assert!(matches!(*err, crate::error::PrivacyError::PortInUse { port: p } if p == port));
+    }
+    
+    #[tokio::test]
+    async fn test_runtime_file_has_bound_addresses() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_web_api_runtime_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = Config::load_or_create_at(&dir.join("config.toml")).unwrap();
+        let state = ApiState::new(config);
+        
+        // Port 0 makes the OS pick, so only the runtime file knows the real port
+        tokio::spawn(start_web_api(state.clone(), 0));
+        let info = loop {
+            if let Some(info) = runtime_info::RuntimeInfo::load(&dir) {
+                break info;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+        let api_addr = info.api_addr.unwrap();
+        assert_ne!(api_addr.port(), 0);
+        let stats: Stats = reqwest::get(format!("{}/api/stats", info.api_base())).await.unwrap().json().await.unwrap();
+        assert!(!stats.proxy_running);
+        
+        // A taken proxy port moves the proxy and is recorded
+        let taken = tokio::net::TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let mut config = state.config.read().await.clone();
+        config.set_proxy_port(taken.local_addr().unwrap().port());
+        let listener = bind_proxy_port(&state, &mut config).await.unwrap();
+        let proxy_addr = runtime_info::RuntimeInfo::load(&dir).unwrap().proxy_addr.unwrap();
+        assert_eq!(proxy_addr, listener.local_addr().unwrap());
+        assert_eq!(config.proxy_port(), Some(proxy_addr.port()));
+        assert_eq!(runtime_info::RuntimeInfo::load(&dir).unwrap().api_addr, Some(api_addr));
+        
+        // Shutting down leaves no addresses behind for a GUI to try
+        state.shutdown().await;
+        assert_eq!(runtime_info::RuntimeInfo::load(&dir), None);
+        
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[tokio::test]
+    async fn test_fingerprint_get_pin_and_reroll() {
+        let state = ApiState::new(Config::default());
+        let Json(initial) = get_fingerprint(State(state.clone())).await;
+        assert!(!initial.pinned);
+        assert_eq!(initial.fingerprint.user_agent, state.fingerprint.current().user_agent);
+        
+        let firefox = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:121.0) Gecko/20100101 Firefox/121.0";
+        let pinned = BrowserFingerprint {
+            user_agent: firefox.to_string(),
+            client_hints: None,
+            ..BrowserFingerprint::random()
+        };
+        let change = FingerprintChange { fingerprint: Some(pinned.clone()), reroll: false };
+        let Json(response) = update_fingerprint(State(state.clone()), Json(change)).await;
+        assert!(response.success && response.state.pinned);
+        assert_eq!(response.state.fingerprint.user_agent, firefox);
+        
+        // A pin survives a new connection
+        state.fingerprint.rotate_unless_pinned();
+        assert_eq!(state.fingerprint.current().user_agent, firefox);
+        
+        // Hints that contradict the UA are refused and leave the pin in place
+        let inconsistent = BrowserFingerprint {
+            client_hints: crate::fingerprint::ClientHints::from_user_agent(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+            ),
+            ..pinned
+        };
+        let change = FingerprintChange { fingerprint: Some(inconsistent), reroll: false };
+        let Json(response) = update_fingerprint(State(state.clone()), Json(change)).await;
+        assert!(!response.success && response.error.is_some());
+        assert_eq!(response.state.fingerprint.user_agent, firefox);
+        
+        let change = FingerprintChange { fingerprint: None, reroll: true };
+        let Json(response) = update_fingerprint(State(state.clone()), Json(change)).await;
+        assert!(response.success && !response.state.pinned);
+    }
+    
+    #[tokio::test]
+    async fn test_identical_threat_events_collapse_into_one_entry() {
+        let state = ApiState::new(Config::default());
+        
+        for _ in 0..100 {
+            state.add_log_with_details("info", "⚠️ Unencrypted HTTP: example.com".to_string(), "security", Some(threat("example.com"))).await;
+        }
+        state.add_log_with_details("info", "⚠️ Unencrypted HTTP: other.example".to_string(), "security", Some(threat("other.example"))).await;
+        // Plain messages without threat details are never collapsed
+        state.add_log("info", "Privacy Suite starting...".to_string(), "general").await;
+        state.add_log("info", "Privacy Suite starting...".to_string(), "general").await;
+        
+        let logs = state.logs.read().await;
+        assert_eq!(logs.len(), 4);
+        assert_eq!(logs[0].count, 100);
+        assert_eq!(logs[0].level, "warn", "repeated events are escalated");
+        assert_eq!(logs[1].count, 1);
+        assert_eq!(logs[1].level, "info");
+        assert_eq!(logs[1].details.as_ref().unwrap().url.as_deref(), Some("http://other.example/"));
+    }
+
+    #[tokio::test]
+    async fn test_connect_is_an_event_but_requests_are_not() {
+        let config = Config::default();
+        let state = ApiState::new(config.clone());
+        let router = crate::routing::Router::with_transport(config.clone(), Some(state.clone()), Arc::new(crate::transport::DirectTransport::new())).await.unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = ProxyServer::with_router(listener, &config, router, Some(state.clone()));
+        let session = tokio::spawn(run_session(state.clone(), async { Ok(proxy) }, Duration::from_secs(5), config, 0));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        
+        // What the router logs for every request
+        let request = LogDetails { threat_type: None, ..threat("example.com") };
+        state.add_log_with_details("info", "🌐 example.com/".to_string(), "network", Some(request)).await;
+        state.add_log_with_details("warn", "🚫 Blocked tracker: doubleclick.net/".to_string(), "tracker", Some(threat("doubleclick.net"))).await;
+        
+        let Json(events) = get_events(State(state.clone())).await;
+        assert!(events.iter().any(|event| event.message.contains("Connected to Tor")));
+        assert!(events.iter().any(|event| event.message.contains("Blocked tracker")));
+        assert!(!events.iter().any(|event| event.category == "network" && event.level == "info"));
+        let logs = state.logs.read().await.clone();
+        assert!(logs.iter().any(|log| log.message == "🌐 example.com/"));
+        assert!(events.len() < logs.len());
+        
+        session.abort();
+    }
+
+    #[tokio::test]
+    async fn test_per_request_info_entries_stay_out_of_events() {
+        let state = ApiState::new(Config::default());
+        
+        let cookie = LogDetails { threat_type: Some("Third-Party Cookie".to_string()), ..threat("doubleclick.net") };
+        state.add_log("info", "🔐 Establishing encrypted Tor connection...".to_string(), "general").await;
+        state.add_log_with_details("info", "🍪 Stripped 2 third-party cookie(s): doubleclick.net".to_string(), "tracker", Some(cookie)).await;
+        state.add_log("info", "🔓 Allowed ads.example.com for one request - normally blocked".to_string(), "tracker").await;
+        state.add_log_with_details("warn", "🚫 Blocked tracker: doubleclick.net".to_string(), "tracker", Some(threat("doubleclick.net"))).await;
+        
+        let Json(events) = get_events(State(state.clone())).await;
+        let messages: Vec<&str> = events.iter().map(|event| event.message.as_str()).collect();
+        assert_eq!(messages, ["🔐 Establishing encrypted Tor connection...", "🚫 Blocked tracker: doubleclick.net"]);
+        
+        // Until it repeats often enough to be escalated to a warning
+        for _ in 0..LOG_ESCALATION_COUNT {
+            state.add_log_with_details("info", "⚠️ Unencrypted HTTP: example.com".to_string(), "security", Some(threat("example.com"))).await;
+        }
+        let Json(events) = get_events(State(state.clone())).await;
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].level, "warn");
+        assert_eq!(events[2].count, LOG_ESCALATION_COUNT);
+    }
+    
+    #[tokio::test]
+    async fn test_suppressed_category_is_not_logged() {
+        let state = ApiState::new(Config::default());
+        let set = |category: &str, level| {
+            set_log_level(State(state.clone()), Json(LogLevelRequest { category: category.to_string(), level }))
+        };
+        set("network", LogLevel::Off).await.unwrap();
+        set("tracker", LogLevel::Warn).await.unwrap();
+        let Json(log_levels) = set("security", LogLevel::Debug).await.unwrap();
+        assert_eq!(log_levels.len(), 3);
+        assert_eq!(state.config.read().await.log_levels, log_levels);
+        let before = state.logs.read().await.len();
+        
+        state.add_log("info", "🌐 example.com/".to_string(), "network").await;
+        state.add_log("error", "❌ Connection failed".to_string(), "network").await;
+        state.add_log("info", "➕ Subscribed to blocklist".to_string(), "tracker").await;
+        state.add_log_with_details("warn", "🚫 Blocked tracker: doubleclick.net".to_string(), "tracker", Some(threat("doubleclick.net"))).await;
+        state.add_log("info", "🔍 Checked request".to_string(), "security").await;
+        state.add_log("info", "unchanged".to_string(), "general").await;
+        
+        let logs = state.logs.read().await.clone();
+        let messages: Vec<&str> = logs[before..].iter().map(|log| log.message.as_str()).collect();
+        assert_eq!(messages, ["🚫 Blocked tracker: doubleclick.net", "🔍 Checked request", "unchanged"]);
+        // Events and the webhook don't go by the in-app log's levels
+        let events = state.events.read().await.clone();
+        assert!(events.iter().any(|event| event.message == "❌ Connection failed"));
+        assert!(events.iter().any(|event| event.message == "unchanged"));
+        assert!(!events.iter().any(|event| event.message == "🌐 example.com/"));
+        
+        assert_eq!(set(" ", LogLevel::Info).await.err(), Some(StatusCode::BAD_REQUEST));
+    }
+    
+    #[tokio::test]
+    async fn test_log_lookup_by_id() {
+        let state = ApiState::new(Config::default());
+        state.add_log("info", "Privacy Suite starting...".to_string(), "general").await;
+        state.add_log_with_details("warn", "🚫 Blocked tracker: doubleclick.net".to_string(), "tracker", Some(threat("doubleclick.net"))).await;
+        state.add_log("info", "✅ Connected".to_string(), "network").await;
+        
+        let ids: Vec<u64> = state.logs.read().await.iter().map(|log| log.id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+        
+        let Json(entry) = get_log(State(state.clone()), UrlPath(2)).await.unwrap();
+        assert_eq!(entry.category, "tracker");
+        let details = entry.details.unwrap();
+        assert_eq!(details.threat_type.as_deref(), Some("Unencrypted connection"));
+        assert!(details.reason.is_some());
+        
+        assert_eq!(get_log(State(state.clone()), UrlPath(99)).await.err(), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_self_test_passes_and_partially_fails() {
+        let exit = || async { Ok(TorCheck { is_tor: true, ip: "198.51.100.7".parse().unwrap() }) };
+        let state = ApiState::new(Config::default());
+        
+        // Nothing is asked of the checker before Tor is up
+        let report = state.self_test_with(exit()).await;
+        assert!(!report.passed);
+        assert!(!report.checks[0].passed);
+        assert!(report.checks[1].detail.contains("Tor is not connected"));
+        
+        state.update_stats(|s| {
+            s.tor_connected = true;
+            s.proxy_running = true;
+        }).await;
+        let report = state.self_test_with(exit()).await;
+        let ids: Vec<&str> = report.checks.iter().map(|check| check.id.as_str()).collect();
+        assert_eq!(ids, ["tor", "exit_ip", "dns", "webrtc", "ipv6"]);
+        assert!(report.passed, "{:?}", report);
+        
+        // With WebRTC protection off, only that check fails
+        state.config.write().await.webrtc_protection = false;
+        let report = state.self_test_with(exit()).await;
+        assert!(!report.passed);
+        let failed: Vec<&str> = report.checks.iter().filter(|check| !check.passed).map(|check| check.id.as_str()).collect();
+        assert_eq!(failed, ["webrtc"]);
+        assert!(state.logs.read().await.last().unwrap().message.contains("Self-test failed: webrtc"));
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics_report_each_subsystem() {
+        let mut config = Config::default();
+        config.ipv6_protection = false;
+        config.tracker_blocking = false;
+        let state = ApiState::new(config);
+        state.update_stats(|s| s.is_admin = false).await;
+        
+        let Json(diagnostics) = get_diagnostics(State(state.clone())).await;
+        let ids: Vec<&str> = diagnostics.checks.iter().map(|check| check.id.as_str()).collect();
+        assert_eq!(ids, ["tor", "kill_switch", "system_proxy", "elevation", "ipv6_protection", "blocklist", "dns"]);
+        let ok = |id: &str| diagnostics.checks.iter().find(|check| check.id == id).unwrap().ok;
+        assert!(!diagnostics.healthy);
+        assert!(!ok("tor") && !ok("kill_switch") && !ok("system_proxy") && !ok("elevation"));
+        assert!(!ok("ipv6_protection") && !ok("blocklist"));
+        assert!(ok("dns"));
+        assert_eq!(diagnostics.trusted_network, None);
+        
+        // Bringing subsystems up flips their checks
+        let state = state.with_kill_switch(KillSwitch::new());
+        state.update_stats(|s| {
+            s.tor_connected = true;
+            s.exit_country = Some("de".to_string());
+        }).await;
+        let diagnostics = state.diagnostics().await;
+        let tor = &diagnostics.checks[0];
+        assert!(tor.ok && tor.detail.contains("de"));
+        assert!(diagnostics.checks[1].ok);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_tor_keeps_the_suite_disconnected() {
+        let supported = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let probe_supported = supported.clone();
+        let probe: TorProbe = Arc::new(move |_: &Config| {
+            if probe_supported.load(Ordering::SeqCst) {
+                TorSupport::Supported
+            } else {
+                TorSupport::Unsupported { reason: "no entropy source".to_string() }
+            }
+        });
+        let state = ApiState::new(Config::default()).with_tor_probe(probe);
+        
+        assert!(matches!(state.check_tor_support().await, TorSupport::Unsupported { .. }));
+        let Json(stats) = toggle_connection(State(state.clone()), Json(ConnectionToggle { connect: true, exit_country: None })).await;
+        assert!(state.proxy_handle.read().await.is_none());
+        assert_eq!(stats.connection_state, ConnectionState::Unsupported);
+        assert!(!stats.proxy_running && !stats.tor_connected && !stats.auto_proxy_enabled);
+        assert!(stats.proxy_error.as_deref().is_some_and(|e| e.contains("no entropy source")));
+        
+        let diagnostics = state.diagnostics().await;
+        assert_eq!(diagnostics.tor_support, Some(TorSupport::Unsupported { reason: "no entropy source".to_string() }));
+        let tor = &diagnostics.checks[0];
+        assert!(!tor.ok && tor.detail.contains("no entropy source"), "{:?}", tor);
+        let logs = state.logs.read().await.clone();
+        assert_eq!(logs.iter().filter(|log| log.level == "error" && log.message.contains("no entropy source")).count(), 1);
+        
+        // Fixing the environment clears the state on the next check
+        supported.store(true, Ordering::SeqCst);
+        assert_eq!(state.check_tor_support().await, TorSupport::Supported);
+        let stats = state.stats_snapshot().await;
+        assert_eq!(stats.connection_state, ConnectionState::Disconnected);
+        assert_eq!(stats.proxy_error, None);
+    }
+    
+    #[tokio::test]
+    async fn test_ended_session_appears_in_history() {
+        let state = ApiState::new(Config::default());
+        // Nothing is recorded while no session is running
+        state.end_session().await;
+        assert!(get_history(State(state.clone())).await.0.is_empty());
+        
+        *state.connected_time.write().await = Some(std::time::Instant::now());
+        state.counters.total_requests.set(7);
+        state.counters.trackers_blocked.set(2);
+        state.update_stats(|s| s.exit_country = Some("se".to_string())).await;
+        state.panic().await;
+        
+        let Json(history) = get_history(State(state.clone())).await;
+        assert_eq!(history.len(), 1);
+        assert_eq!((history[0].total_requests, history[0].trackers_blocked), (7, 2));
+        assert_eq!(history[0].exit_country.as_deref(), Some("se"));
+        assert!(state.connected_time.read().await.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_counter_updates_are_not_lost() {
+        let state = ApiState::new(Config::default());
+        
+        let tasks: Vec<_> = (0..32)
+            .map(|task| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    for _ in 0..1_000 {
+                        state.counters.total_requests.inc();
+                        state.counters.bytes_transferred.add(3);
+                        state.counters.active_connections.inc();
+                        state.counters.active_connections.dec();
+                    }
+                    // Flag updates still go through the lock alongside the counters
+                    state.update_stats(|s| s.kill_switch_active = task % 2 == 0).await;
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        
+        let stats = state.stats_snapshot().await;
+        assert_eq!(stats.total_requests, 32_000);
+        assert_eq!(stats.bytes_transferred, 96_000);
+        assert_eq!(stats.active_connections, 0);
+        
+        // A new session starts from zero, except for connections still open
+        state.counters.active_connections.inc();
+        state.counters.reset_session();
+        let stats = state.stats_snapshot().await;
+        assert_eq!((stats.total_requests, stats.active_connections), (0, 1));
+        state.counters.active_connections.dec();
+        state.counters.active_connections.dec();
+        assert_eq!(state.counters.active_connections.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_session_and_app_uptime_are_consistent() {
+        let mut state = ApiState::new(Config::default());
+        state.start_time = std::time::Instant::now() - Duration::from_secs(100);
+        
+        // Disconnected: only the app has been running
+        let stats = get_stats(State(state.clone())).await.0;
+        assert_eq!((stats.session_uptime_seconds, stats.uptime_seconds), (0, 0));
+        assert_eq!(stats.app_uptime_seconds, 100);
+        
+        *state.connected_time.write().await = Some(std::time::Instant::now() - Duration::from_secs(5));
+        state.update_stats(|s| s.proxy_running = true).await;
+        let stored = state.stats.read().await.clone();
+        let polled = get_stats(State(state.clone())).await.0;
+        let streamed = state.stats_snapshot().await;
+        // Connecting again while connected answers with the current stats
+        let toggle = ConnectionToggle { connect: true, exit_country: None };
+        let toggled = toggle_connection(State(state.clone()), Json(toggle)).await.0;
+        for stats in [stored, polled, streamed, toggled] {
+            assert_eq!((stats.session_uptime_seconds, stats.uptime_seconds), (5, 5));
+            assert_eq!(stats.app_uptime_seconds, 100);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connected_since_follows_the_session() {
+        let state = ApiState::new(Config::default());
+        assert_eq!(state.stats_snapshot().await.connected_since, None);
+        
+        *state.connected_time.write().await = Some(std::time::Instant::now() - Duration::from_secs(90));
+        *state.proxy_handle.write().await = Some(tokio::spawn(std::future::pending()));
+        let stats = get_stats(State(state.clone())).await.0;
+        let since = chrono::DateTime::parse_from_rfc3339(stats.connected_since.as_deref().unwrap()).unwrap();
+        let ago = chrono::Local::now().signed_duration_since(since).num_seconds();
+        assert!((89..=91).contains(&ago), "connected {}s ago", ago);
+        
+        state.panic().await;
+        assert_eq!(state.stats_snapshot().await.connected_since, None);
+        assert_eq!(state.stats.read().await.connected_since, None);
+    }
+    
+    #[tokio::test]
+    async fn test_config_path_is_the_loaded_file() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_config_path_{}", std::process::id()));
+        let config_file = dir.join("config.toml");
+        let state = ApiState::new(Config::load_or_create_at(&config_file).unwrap());
+        
+        let Json(location) = get_config_path(State(state)).await;
+        assert_eq!(location.path, config_file);
+        assert!(location.path.is_file());
+        assert_eq!(location.dir.as_deref(), Some(dir.as_path()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_subscriptions_change_blocklist_on_refresh() {
+        let dir = std::env::temp_dir().join(format!("privacy_suite_subscriptions_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let list_url = |name: &str, domain: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("||{}^\n", domain)).unwrap();
+            format!("file://{}", path.display())
+        };
+        let (one, two) = (list_url("one.txt", "one.example.com"), list_url("two.txt", "two.example.net"));
+        
+        let mut config = Config::default();
+        config.tracker_lists.clear();
+        let state = ApiState::new(config);
+        let request = |url: &str| Json(SubscriptionRequest { url: url.to_string() });
+        let blocked = |domain: &str| state.tracker_blocker.check(domain).blocked;
+        
+        add_subscription(State(state.clone()), request(&one)).await.unwrap();
+        let Json(subscriptions) = add_subscription(State(state.clone()), request(&two)).await.unwrap();
+        assert_eq!(subscriptions.len(), 2);
+        assert!(subscriptions.iter().all(|s| s.enabled && s.last_updated.is_none()));
+        assert_eq!(add_subscription(State(state.clone()), request(&one)).await.err(), Some(StatusCode::CONFLICT));
+        assert_eq!(add_subscription(State(state.clone()), request("ftp://lists.example")).await.err(), Some(StatusCode::BAD_REQUEST));
+        
+        state.refresh_blocklist().await.unwrap();
+        assert!(blocked("one.example.com") && blocked("two.example.net"));
+        let Json(subscriptions) = get_subscriptions(State(state.clone())).await;
+        assert!(subscriptions.iter().all(|s| s.domain_count == Some(1) && s.last_updated.is_some()));
+        
+        // Disabling keeps the subscription, and its domains until the next refresh
+        let toggle = Json(SubscriptionToggle { url: two.clone(), enabled: false });
+        let Json(subscriptions) = toggle_subscription(State(state.clone()), toggle).await.unwrap();
+        assert_eq!(subscriptions.iter().map(|s| s.enabled).collect::<Vec<_>>(), [true, false]);
+        assert!(blocked("two.example.net"));
+        state.refresh_blocklist().await.unwrap();
+        assert!(blocked("one.example.com") && !blocked("two.example.net"));
+        
+        let toggle = Json(SubscriptionToggle { url: two.clone(), enabled: true });
+        toggle_subscription(State(state.clone()), toggle).await.unwrap();
+        let Json(subscriptions) = remove_subscription(State(state.clone()), request(&one)).await.unwrap();
+        assert_eq!(subscriptions.iter().map(|s| s.url.as_str()).collect::<Vec<_>>(), [two.as_str()]);
+        assert_eq!(remove_subscription(State(state.clone()), request(&one)).await.err(), Some(StatusCode::NOT_FOUND));
+        state.refresh_blocklist().await.unwrap();
+        assert!(!blocked("one.example.com") && blocked("two.example.net"));
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_located_exit_fills_auto_exit_country() {
+        let state = ApiState::new(Config::default());
+        let hop = |role: &str, country: Option<&str>| CircuitHop {
+            role: role.to_string(),
+            relay: format!("{} relay", role),
+            country: country.map(str::to_string),
+            address: Some("198.51.100.7".parse().unwrap()),
+        };
+        
+        state.set_circuit(Some(vec![hop("guard", Some("de")), hop("middle", None), hop("exit", Some("se"))])).await;
+        assert_eq!(state.stats_snapshot().await.exit_country.as_deref(), Some("se"));
+        assert_eq!(state.circuit.read().await.as_ref().unwrap().len(), 3);
+        
+        // Located after the fact, and only while the circuit is still current
+        state.set_circuit(Some(vec![hop("guard", None), hop("exit", None)])).await;
+        state.locate_exit("exit relay", "fi".to_string()).await;
+        assert_eq!(state.stats_snapshot().await.exit_country.as_deref(), Some("fi"));
+        state.locate_exit("some older exit", "us".to_string()).await;
+        assert_eq!(state.stats_snapshot().await.exit_country.as_deref(), Some("fi"));
+        
+        // A country the user picked is kept
+        state.request_exit_country(Some("ch".to_string())).await;
+        state.set_circuit(Some(vec![hop("exit", Some("nl"))])).await;
+        assert_eq!(state.stats_snapshot().await.exit_country.as_deref(), Some("ch"));
+    }
+    
+    #[tokio::test]
+    async fn test_exit_located_elsewhere_is_flagged() {
+        let state = ApiState::new(Config::default());
+        let exit_in = |country: &str| Some(vec![CircuitHop {
+            role: "exit".to_string(),
+            relay: "exit relay".to_string(),
+            country: Some(country.to_string()),
+            address: Some("198.51.100.7".parse().unwrap()),
+        }]);
+        let mismatch_events = |logs: &[LogEntry]| logs.iter().filter(|log| log.message.contains("although")).count();
+        
+        state.request_exit_country(Some("uk".to_string())).await;
+        state.set_circuit(exit_in("gb")).await;
+        assert!(!state.stats_snapshot().await.exit_country_mismatch);
+        
+        state.set_circuit(exit_in("nl")).await;
+        state.set_circuit(exit_in("nl")).await;
+        assert!(state.stats_snapshot().await.exit_country_mismatch);
+        let logs = state.logs.read().await.clone();
+        assert_eq!(mismatch_events(&logs), 1);
+        assert_eq!(logs.last().unwrap().category, "security");
+        
+        // Back in the requested country
+        state.set_circuit(exit_in("gb")).await;
+        assert!(!state.stats_snapshot().await.exit_country_mismatch);
+        
+        // Nothing to compare against on "Auto"
+        state.request_exit_country(None).await;
+        state.set_circuit(exit_in("nl")).await;
+        assert!(!state.stats_snapshot().await.exit_country_mismatch);
+        assert_eq!(mismatch_events(&state.logs.read().await), 1);
+    }
+
+    #[tokio::test]
+    async fn test_onion_services_listed_while_connected() {
+        let state = ApiState::new(Config::default());
+        assert!(get_onion_services(State(state.clone())).await.0.is_empty());
+        
+        // What the router reports after publishing `Config::onion_services`
+        *state.connected_time.write().await = Some(std::time::Instant::now());
+        let service = OnionServiceInfo {
+            hostname: format!("{}.onion", "a".repeat(56)),
+            virtual_port: 80,
+            local_port: 8080,
+        };
+        state.set_onion_services(vec![service.clone()]).await;
+        assert_eq!(get_onion_services(State(state.clone())).await.0, [service]);
+        
+        state.panic().await;
+        assert!(get_onion_services(State(state)).await.0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_state_follows_bootstrap() {
+        use crate::error::PrivacyError;
+        
+        let config = Config::default();
+        let state = ApiState::new(config.clone());
+        assert_eq!(state.stats_snapshot().await.connection_state, ConnectionState::Disconnected);
+        
+        // A bootstrap that takes a moment, then succeeds
+        let transport = Arc::new(crate::transport::DirectTransport::new());
+        let router = crate::routing::Router::with_transport(config.clone(), Some(state.clone()), transport).await.unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = ProxyServer::with_router(listener, &config, router, Some(state.clone()));
+        let bootstrap = async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(proxy)
+        };
+        let session = tokio::spawn(run_session(state.clone(), bootstrap, Duration::from_secs(5), config.clone(), 0));
+        
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let stats = state.stats_snapshot().await;
+        assert_eq!(stats.connection_state, ConnectionState::Connecting);
+        assert!(!stats.tor_connected && !stats.proxy_running);
+        
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let stats = state.stats_snapshot().await;
+        assert_eq!(stats.connection_state, ConnectionState::Connected);
+        assert!(stats.tor_connected && stats.proxy_running);
+        
+        *state.proxy_handle.write().await = Some(session);
+        let Json(stats) = toggle_connection(State(state.clone()), Json(ConnectionToggle { connect: false, exit_country: None })).await;
+        assert_eq!(stats.connection_state, ConnectionState::Disconnected);
+        
+        // A bootstrap error, and one that never finishes, both end in Failed with a reason
+        let failing = async { Err(PrivacyError::TorBootstrap("no consensus".to_string())) };
+        run_session(state.clone(), failing, Duration::from_secs(5), config.clone(), 0).await;
+        let stats = state.stats_snapshot().await;
+        assert_eq!(stats.connection_state, ConnectionState::Failed);
+        assert!(stats.proxy_error.as_deref().is_some_and(|e| e.contains("no consensus")));
+        
+        run_session(state.clone(), std::future::pending(), Duration::from_millis(50), config, 0).await;
+        let stats = state.stats_snapshot().await;
+        assert_eq!(stats.connection_state, ConnectionState::Failed);
+        assert!(stats.proxy_error.as_deref().is_some_and(|e| e.contains("timed out")));
+    }
+
+    #[tokio::test]
+    async fn test_config_update_changes_only_given_protections() {
+        let state = ApiState::new(Config::default());
+        let update = ProtectionUpdate {
+            webrtc_protection: Some(false),
+            ipv6_protection: None,
+            canvas_protection: None,
+            tracker_blocking: Some(false),
+            security_detection: None,
+            security_detectors: HashMap::from([
+                ("tracking_pattern".to_string(), false),
+                ("no_such_detector".to_string(), false),
+            ]),
+            mode: Some(BrowsingMode::Allowlist),
+            allowed_domains: Some(vec![" Example.com ".to_string(), "".to_string()]),
+        };
+        
+        let Json(updated) = update_config(State(state.clone()), Json(update)).await;
+        assert!(!updated.webrtc_protection && !updated.tracker_blocking);
+        assert!(updated.ipv6_protection && updated.canvas_protection && updated.security_detection);
+        assert!(!updated.security_detectors["tracking_pattern"]);
+        assert!(updated.security_detectors["credential_leak"]);
+        assert!(!updated.security_detectors.contains_key("no_such_detector"));
+        assert_eq!(updated.mode, BrowsingMode::Allowlist);
+        assert_eq!(updated.allowed_domains, ["example.com"]);
+        
+        let Json(current) = get_config(State(state.clone())).await;
+        assert_eq!(current, updated);
+        assert!(!state.config.read().await.tracker_blocking);
+        assert!(!state.config.read().await.security_detector_enabled("tracking_pattern"));
+    }
+
+    #[tokio::test]
+    async fn test_panic_severs_connections_and_engages_kill_switch() {
+        let kill_switch = KillSwitch::new();
+        kill_switch.set_enabled(false).await;
+        kill_switch.set_tor_status(true).await;
+        let state = ApiState::new(Config::default()).with_kill_switch(kill_switch.clone());
+        
+        let tunnel = tokio::spawn(async {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+        });
+        let id = state.connections.next_id();
+        state.connections.insert(id, &tunnel);
+        
+        let report = state.panic().await;
+        assert_eq!(report.connections_aborted, 1);
+        assert!(report.kill_switch_engaged);
+        // Only Windows has a system cache to flush
+        assert_eq!(report.dns_flushed, cfg!(target_os = "windows"));
+        assert!(tunnel.await.unwrap_err().is_cancelled());
+        assert!(state.connections.is_empty());
+        
+        let ks_stats = kill_switch.get_stats().await;
+        assert!(ks_stats.active);
+        assert!(!ks_stats.tor_connected);
+        assert!(!kill_switch.should_allow_traffic().await);
+        
+        // Idempotent: a second press finds nothing left to sever
+        let report = state.panic().await;
+        assert_eq!(report.connections_aborted, 0);
+        assert!(!report.proxy_stopped);
+        
+        let security_events = state.logs.read().await
+            .iter()
+            .filter(|log| log.category == "security")
+            .count();
+        assert_eq!(security_events, 2);
+    }
+    
+    #[tokio::test]
+    async fn test_session_allow_is_cleared_on_disconnect() {
+        let state = ApiState::new(Config::default());
+        let allow = |domain: &str, scope| {
+            allow_temporarily(State(state.clone()), Query(TemporaryAllowQuery { domain: domain.to_string(), scope }))
+        };
+        assert_eq!(allow("  ", AllowScope::Session).await.unwrap_err(), StatusCode::BAD_REQUEST);
+        
+        allow("doubleclick.net", AllowScope::Session).await.unwrap();
+        let Json(temporary) = allow("Google-Analytics.com", AllowScope::Once).await.unwrap();
+        assert!(temporary.session.contains("doubleclick.net"));
+        assert!(temporary.once.contains("google-analytics.com"));
+        assert_eq!(state.tracker_blocker.take_temporary_allow("ads.doubleclick.net"), Some(AllowScope::Session));
+        assert_eq!(state.tracker_blocker.take_temporary_allow("ads.doubleclick.net"), Some(AllowScope::Session));
+        
+        state.panic().await;
+        let Json(temporary) = get_temporary_allowlist(State(state.clone())).await;
+        assert_eq!(temporary, TemporaryAllowlist::default());
+        assert_eq!(state.tracker_blocker.take_temporary_allow("doubleclick.net"), None);
+    }
+
+    #[test]
+    fn test_metrics_format() {
+        let stats = Stats {
+            total_requests: 42,
+            trackers_blocked: 7,
+            tor_connected: true,
+            ..Stats::default()
+        };
+        let output = render_metrics(&stats);
+        
+        let mut samples = std::collections::HashMap::new();
+        for line in output.lines() {
+            if line.starts_with('#') {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                assert!(parts.len() >= 3 && (parts[1] == "HELP" || parts[1] == "TYPE"));
+                assert!(parts[2].starts_with("dul_"));
+                continue;
+            }
+            let (name, value) = line.split_once(' ').expect("sample line has a value");
+            samples.insert(name.to_string(), value.parse::<f64>().expect("value is numeric"));
+        }
+        
+        for name in [
+            "dul_requests_total",
+            "dul_requests_blocked_total",
+            "dul_requests_dropped_total",
+            "dul_trackers_blocked_total",
+            "dul_webrtc_blocked_total",
+            "dul_ipv6_blocked_total",
+            "dul_security_threats_total",
+            "dul_bytes_transferred_total",
+            "dul_active_connections",
+            "dul_uptime_seconds",
+        ] {
+            assert!(samples.contains_key(name), "missing metric {}", name);
+        }
+        assert_eq!(samples["dul_requests_total"], 42.0);
+        assert_eq!(samples["dul_trackers_blocked_total"], 7.0);
+        assert_eq!(samples["dul_tor_connected"], 1.0);
+        assert!(output.contains("# TYPE dul_active_connections gauge"));
+    }
+}
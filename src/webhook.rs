@@ -0,0 +1,214 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use crate::config::{Config, WebhookFormat};
+
+/// Most notifications sent per `RATE_WINDOW`; the rest are dropped
+const MAX_PER_WINDOW: usize = 10;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Tries per notification before it is given up
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubled for each one after
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One notification, posted as is in `WebhookFormat::Json`.
+///
+/// Only what identifies the event goes to the third party: never the URL,
+/// path or headers of the request, which for the credential-in-URL
+/// detector hold the credential itself.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEvent {
+    /// The log message
+    pub event: String,
+    /// RFC 3339
+    pub timestamp: String,
+    pub threat_type: Option<String>,
+    pub host: Option<String>,
+}
+
+/// Posts security errors to `Config::webhook_url`. Requests go out
+/// directly rather than through Tor, so a kill switch block still gets
+/// reported while Tor is down.
+#[derive(Clone)]
+pub struct Webhook {
+    url: String,
+    format: WebhookFormat,
+    client: reqwest::Client,
+    /// When each notification of the current window was sent
+    sent: Arc<Mutex<VecDeque<Instant>>>,
+    retry_delay: Duration,
+}
+
+impl Webhook {
+    pub fn new(url: impl Into<String>, format: WebhookFormat) -> Self {
+        Self {
+            url: url.into(),
+            format,
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            sent: Arc::new(Mutex::new(VecDeque::new())),
+            retry_delay: RETRY_DELAY,
+        }
+    }
+
+    /// The configured webhook, None when `webhook_url` is unset or blank
+    pub fn for_config(config: &Config) -> Option<Self> {
+        let url = config.webhook_url.as_deref().map(str::trim).filter(|url| !url.is_empty())?;
+        Some(Self::new(url, config.webhook_format))
+    }
+
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Whether a log entry is worth a notification
+    pub fn qualifies(level: &str, category: &str) -> bool {
+        level == "error" && category == "security"
+    }
+
+    /// Post `event` in the background. Returns false if the rate limit dropped it.
+    pub fn notify(&self, event: WebhookEvent) -> bool {
+        if !self.take_slot(Instant::now()) {
+            warn!("Webhook rate limit reached, not sending: {}", event.event);
+            return false;
+        }
+        let webhook = self.clone();
+        tokio::spawn(async move {
+            webhook.send(&event).await;
+        });
+        true
+    }
+
+    fn take_slot(&self, now: Instant) -> bool {
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        while sent.front().is_some_and(|first| now.duration_since(*first) >= RATE_WINDOW) {
+            sent.pop_front();
+        }
+        if sent.len() >= MAX_PER_WINDOW {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+
+    fn body(&self, event: &WebhookEvent) -> serde_json::Value {
+        match self.format {
+            WebhookFormat::Json => serde_json::to_value(event).unwrap_or_default(),
+            WebhookFormat::Chat => {
+                let line = format!("Privacy Suite: {} ({})", event.event, event.timestamp);
+                serde_json::json!({ "text": line, "content": line })
+            }
+        }
+    }
+
+    /// Post `event`, retrying on network errors, 429 and 5xx. Returns whether it was delivered.
+    pub async fn send(&self, event: &WebhookEvent) -> bool {
+        let body = self.body(event);
+        let mut delay = self.retry_delay;
+        for attempt in 1..=MAX_ATTEMPTS {
+            match self.client.post(&self.url).json(&body).send().await {
+                Ok(response) if response.status().is_success() => return true,
+                Ok(response) if response.status().is_client_error() && response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    warn!("Webhook refused the notification: {}", response.status());
+                    return false;
+                }
+                Ok(response) => warn!("Webhook attempt {}/{} failed: {}", attempt, MAX_ATTEMPTS, response.status()),
+                Err(e) => warn!("Webhook attempt {}/{} failed: {}", attempt, MAX_ATTEMPTS, e),
+            }
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web_api::{ApiState, LogDetails};
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_only_security_errors_reach_the_webhook() {
+        // The receiver fails the first post to make the notifier retry
+        let (tx, mut rx) = mpsc::unbounded_channel::<serde_json::Value>();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let receiver = Router::new()
+            .route(
+                "/hook",
+                post(|State((tx, calls)): State<(mpsc::UnboundedSender<serde_json::Value>, Arc<AtomicUsize>)>, Json(body): Json<serde_json::Value>| async move {
+                    tx.send(body).unwrap();
+                    if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        StatusCode::NO_CONTENT
+                    }
+                }),
+            )
+            .with_state((tx, calls.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+        let webhook = Webhook::new(url, WebhookFormat::Json).with_retry_delay(Duration::from_millis(10));
+        let state = ApiState::new(Config::default()).with_webhook(webhook);
+        state.add_log("info", "✅ Connected to Tor".to_string(), "network").await;
+        state.add_log("warn", "⚠️ Tracker list could not be fetched".to_string(), "security").await;
+        state.add_log("error", "❌ Connection failed".to_string(), "network").await;
+        let details = LogDetails {
+            url: Some("https://evil.example/login?password=hunter2".to_string()),
+            domain: Some("evil.example".to_string()),
+            path: Some("/login?password=hunter2".to_string()),
+            threat_type: Some("Password in URL".to_string()),
+            request_headers: Some(vec!["Cookie: session=secret".to_string()]),
+            ..LogDetails::default()
+        };
+        state.add_log_with_details("error", "⚠️ SECURITY: Password in URL - evil.example".to_string(), "security", Some(details)).await;
+
+        for _ in 0..2 {
+            let body = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+            assert_eq!(body["event"], "⚠️ SECURITY: Password in URL - evil.example");
+            assert_eq!(body["host"], "evil.example");
+            assert_eq!(body["threat_type"], "Password in URL");
+            assert!(chrono::DateTime::parse_from_rfc3339(body["timestamp"].as_str().unwrap()).is_ok(), "{}", body);
+            let posted = body.to_string();
+            assert!(!posted.contains("hunter2") && !posted.contains("secret"), "{}", posted);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_chat_format_and_rate_limit() {
+        let webhook = Webhook::new("http://127.0.0.1:9/hook", WebhookFormat::Chat);
+        let event = WebhookEvent {
+            event: "🚫 Kill switch blocked request - Tor disconnected!".to_string(),
+            timestamp: "2026-01-01T12:00:00.000+00:00".to_string(),
+            threat_type: None,
+            host: None,
+        };
+        let body = webhook.body(&event);
+        assert_eq!(body["text"], body["content"]);
+        assert!(body["text"].as_str().unwrap().contains("Kill switch blocked request"));
+
+        let start = Instant::now();
+        assert!((0..MAX_PER_WINDOW).all(|_| webhook.take_slot(start)));
+        assert!(!webhook.take_slot(start + Duration::from_secs(1)));
+        assert!(webhook.take_slot(start + RATE_WINDOW));
+    }
+}
@@ -1,5 +1,72 @@
+use http_body_util::Full;
+use hyper::{body::Bytes, header, header::HeaderName, HeaderMap, Response};
+use regex::Regex;
+use std::sync::Arc;
 use tracing::{info, warn};
-use std::net::IpAddr;
+use crate::request_filter::glob_match;
+
+/// STUN/TURN servers blocked unless `Config::stun_servers` says otherwise
+pub const DEFAULT_STUN_SERVERS: [&str; 11] = [
+    "stun.l.google.com",
+    "stun1.l.google.com",
+    "stun2.l.google.com",
+    "stun3.l.google.com",
+    "stun4.l.google.com",
+    "stun.cloudflare.com",
+    "stun.services.mozilla.com",
+    "stun.stunprotocol.org",
+    "stun.voip.blackberry.com",
+    "stun.voipbuster.com",
+    "global.stun.twilio.com",
+];
+
+pub fn default_stun_servers() -> Vec<String> {
+    DEFAULT_STUN_SERVERS.iter().map(|server| server.to_string()).collect()
+}
+
+/// One compiled entry of the STUN server list
+#[derive(Debug, Clone)]
+enum StunMatcher {
+    /// Plain name, matched anywhere in the host
+    Substring(String),
+    /// `*`/`?` glob over the whole host, e.g. `*.stun.example.com`
+    Wildcard(String),
+    /// `/regex/`
+    Regex(Regex),
+}
+
+impl StunMatcher {
+    fn parse(entry: &str) -> Result<Self, String> {
+        let entry = entry.trim();
+        if let Some(pattern) = entry.strip_prefix('/').and_then(|rest| rest.strip_suffix('/')) {
+            return Regex::new(&format!("(?i){}", pattern))
+                .map(StunMatcher::Regex)
+                .map_err(|e| format!("invalid STUN server regex {}: {}", entry, e));
+        }
+        if entry.is_empty() {
+            return Err("empty STUN server entry".to_string());
+        }
+        let entry = entry.to_ascii_lowercase();
+        if entry.contains(['*', '?']) {
+            Ok(StunMatcher::Wildcard(entry))
+        } else {
+            Ok(StunMatcher::Substring(entry))
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            StunMatcher::Substring(name) => host.contains(name.as_str()),
+            StunMatcher::Wildcard(glob) => glob_match(glob, host),
+            StunMatcher::Regex(regex) => regex.is_match(host),
+        }
+    }
+}
+
+/// Compile STUN server entries (names, wildcards and `/regex/`), failing on the first invalid one
+fn compile_stun_servers(entries: &[String]) -> Result<Vec<StunMatcher>, String> {
+    entries.iter().map(|entry| StunMatcher::parse(entry)).collect()
+}
 
 /// WebRTC Leak Protection
 /// 
@@ -8,6 +75,8 @@ use std::net::IpAddr;
 #[derive(Clone)]
 pub struct WebRtcProtection {
     enabled: bool,
+    inject_headers: bool,
+    stun_servers: Arc<Vec<StunMatcher>>,
 }
 
 impl WebRtcProtection {
@@ -15,46 +84,49 @@ impl WebRtcProtection {
         if enabled {
             info!("🛡️ WebRTC leak protection enabled");
         }
-        Self { enabled }
-    }
-
-    /// Check if a request is a WebRTC STUN/TURN request that should be blocked
-    pub fn should_block_request(&self, host: &str, _port: u16) -> bool {
-        if !self.enabled {
-            return false;
+        Self {
+            enabled,
+            inject_headers: true,
+            stun_servers: Arc::new(
+                DEFAULT_STUN_SERVERS.iter().map(|server| StunMatcher::Substring(server.to_string())).collect(),
+            ),
         }
+    }
 
-        // Block common STUN/TURN servers
-        let stun_servers = [
-            "stun.l.google.com",
-            "stun1.l.google.com",
-            "stun2.l.google.com",
-            "stun3.l.google.com",
-            "stun4.l.google.com",
-            "stun.cloudflare.com",
-            "stun.services.mozilla.com",
-            "stun.stunprotocol.org",
-            "stun.voip.blackberry.com",
-            "stun.voipbuster.com",
-            "global.stun.twilio.com",
-        ];
+    /// Block these STUN servers instead of `DEFAULT_STUN_SERVERS`. Entries are
+    /// names (matched anywhere in the host), wildcards such as
+    /// `*.stun.example.com`, or `/regex/`; an invalid entry is an error.
+    pub fn with_stun_servers(mut self, entries: &[String]) -> Result<Self, String> {
+        self.stun_servers = Arc::new(compile_stun_servers(entries)?);
+        Ok(self)
+    }
 
-        for stun_host in &stun_servers {
-            if host.contains(stun_host) {
-                warn!("🚫 Blocked WebRTC STUN request to {}", host);
-                return true;
-            }
-        }
+    /// Set whether protection headers are added to proxied HTML responses
+    pub fn with_header_injection(mut self, inject_headers: bool) -> Self {
+        self.inject_headers = inject_headers;
+        self
+    }
 
-        // Block direct IP connections (often used for WebRTC)
-        if host.parse::<IpAddr>().is_ok() {
-            warn!("🚫 Blocked direct IP connection attempt: {}", host);
+    /// Check if a request is a WebRTC STUN/TURN request that should be blocked
+    pub fn should_block_request(&self, host: &str, port: u16) -> bool {
+        if self.would_block_request(host, port) {
+            warn!("🚫 Blocked WebRTC STUN request to {}", host.to_ascii_lowercase());
             return true;
         }
 
         false
     }
 
+    /// Like `should_block_request`, without logging a block
+    pub fn would_block_request(&self, host: &str, _port: u16) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let host = host.to_ascii_lowercase();
+        self.stun_servers.iter().any(|server| server.matches(&host))
+    }
+
     /// Generate headers to disable WebRTC in browser
     pub fn get_protection_headers(&self) -> Vec<(&'static str, String)> {
         if !self.enabled {
@@ -69,11 +141,71 @@ impl WebRtcProtection {
         ]
     }
 
+    /// Add the protection headers to an HTML document response.
+    ///
+    /// An existing `Permissions-Policy` from the origin is merged rather than
+    /// replaced, so any directives it already sets are kept.
+    pub fn apply_to_response(&self, response: &mut Response<Full<Bytes>>) {
+        if !self.inject_headers || !is_html(response.headers()) {
+            return;
+        }
+
+        for (name, value) in self.get_protection_headers() {
+            let value = match response.headers().get(name).and_then(|v| v.to_str().ok()) {
+                Some(existing) if name == "Permissions-Policy" => merge_permissions_policy(existing, &value),
+                Some(_) => continue,
+                None => value,
+            };
+
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), value.parse()) {
+                response.headers_mut().insert(name, value);
+            }
+        }
+    }
+
     pub fn is_enabled(&self) -> bool {
         self.enabled
     }
 }
 
+fn is_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|ct| ct.trim_start().to_ascii_lowercase().starts_with("text/html"))
+        .unwrap_or(false)
+}
+
+/// Merge our Permissions-Policy directives into the origin's.
+///
+/// Origin directives are kept in order; a feature we restrict is set to the
+/// empty allowlist `()`, which is never less strict than what the origin set.
+fn merge_permissions_policy(existing: &str, ours: &str) -> String {
+    let parse = |policy: &str| -> Vec<(String, String)> {
+        policy
+            .split(',')
+            .filter_map(|directive| {
+                let (feature, allowlist) = directive.split_once('=')?;
+                Some((feature.trim().to_ascii_lowercase(), allowlist.trim().to_string()))
+            })
+            .collect()
+    };
+
+    let mut merged = parse(existing);
+    for (feature, allowlist) in parse(ours) {
+        match merged.iter_mut().find(|(f, _)| *f == feature) {
+            Some(entry) => entry.1 = allowlist,
+            None => merged.push((feature, allowlist)),
+        }
+    }
+
+    merged
+        .iter()
+        .map(|(feature, allowlist)| format!("{}={}", feature, allowlist))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,10 +218,85 @@ mod tests {
     }
 
     #[test]
-    fn test_blocks_direct_ips() {
+    fn test_wildcard_and_regex_stun_servers() {
+        let entries = vec![
+            "*.stun.example.com".to_string(),
+            "/^turn[0-9]+\\.relay\\.example\\.net$/".to_string(),
+            "stun.plain.example".to_string(),
+        ];
+        let protection = WebRtcProtection::new(true).with_stun_servers(&entries).unwrap();
+
+        assert!(protection.should_block_request("eu1.stun.example.com", 3478));
+        assert!(protection.should_block_request("A.B.STUN.Example.com", 3478));
+        assert!(!protection.should_block_request("stun.example.com", 3478));
+        assert!(protection.should_block_request("turn42.relay.example.net", 443));
+        assert!(!protection.should_block_request("turn.relay.example.net", 443));
+        assert!(protection.should_block_request("stun.plain.example", 3478));
+        // The configured list replaces the built-in one
+        assert!(!protection.should_block_request("stun.l.google.com", 3478));
+    }
+
+    #[test]
+    fn test_invalid_stun_pattern_rejected() {
+        let err = WebRtcProtection::new(true)
+            .with_stun_servers(&["/stun(/".to_string()])
+            .err()
+            .unwrap();
+        assert!(err.contains("invalid STUN server regex /stun(/"), "{}", err);
+        assert!(WebRtcProtection::new(true).with_stun_servers(&[" ".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_allows_direct_ips() {
+        // Direct IP blocking is handled separately by DirectIpProtection
+        let protection = WebRtcProtection::new(true);
+        assert!(!protection.should_block_request("192.168.1.1", 443));
+        assert!(!protection.should_block_request("1.1.1.1", 443));
+    }
+
+    fn response_with_type(content_type: &str) -> Response<Full<Bytes>> {
+        Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Full::new(Bytes::new()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_injects_policy_into_html_only() {
         let protection = WebRtcProtection::new(true);
-        assert!(protection.should_block_request("192.168.1.1", 443));
-        assert!(protection.should_block_request("8.8.8.8", 53));
+
+        let mut html = response_with_type("text/html; charset=utf-8");
+        protection.apply_to_response(&mut html);
+        assert_eq!(
+            html.headers()["permissions-policy"],
+            "camera=(), microphone=(), geolocation=()"
+        );
+
+        let mut image = response_with_type("image/png");
+        protection.apply_to_response(&mut image);
+        assert!(image.headers().get("permissions-policy").is_none());
+
+        let mut disabled = response_with_type("text/html");
+        WebRtcProtection::new(true)
+            .with_header_injection(false)
+            .apply_to_response(&mut disabled);
+        assert!(disabled.headers().get("permissions-policy").is_none());
+    }
+
+    #[test]
+    fn test_merges_existing_policy() {
+        let protection = WebRtcProtection::new(true);
+        let mut html = Response::builder()
+            .header(header::CONTENT_TYPE, "text/html")
+            .header("Permissions-Policy", "usb=(), camera=(self)")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+
+        protection.apply_to_response(&mut html);
+        assert_eq!(
+            html.headers()["permissions-policy"],
+            "usb=(), camera=(), microphone=(), geolocation=()"
+        );
     }
 
     #[test]